@@ -0,0 +1,147 @@
+//! ## Random RE generators
+//! Strategies for property tests. They build random REs from a grammar for each syntax, random junk for the
+//! parsers to reject, and random texts to search. They are kept apart from the tests themselves so any harness
+//! (comparing the parsers with each other, or the engine with another one) can use them.
+
+use proptest::prelude::*;
+use proptest::sample::select;
+
+/// Repetition suffixes, possibly lazy
+pub(crate) fn repetition() -> impl Strategy<Value = String> {
+    (
+        select(vec!["", "", "*", "+", "?", "{2}", "{1,}", "{0,3}", "{2,3}"]),
+        any::<bool>(),
+    )
+        .prop_map(|(reps, lazy)| {
+            if lazy && !reps.is_empty() {
+                format!("{}?", reps)
+            } else {
+                reps.to_string()
+            }
+        })
+}
+
+/// Special characters, the same in both syntaxes
+fn special() -> impl Strategy<Value = String> {
+    select(vec![".", "\\d", "\\l", "\\u", "\\a", "\\w", "\\x", "\\o"]).prop_map(String::from)
+}
+
+/// Ranges, the same in both syntaxes
+fn range() -> impl Strategy<Value = String> {
+    (
+        any::<bool>(),
+        prop::collection::vec(select(vec!["a", "b", "x-z", "0-9", "\\d", "\\w"]), 1..4),
+    )
+        .prop_map(|(not, items)| format!("[{}{}]", if not { "^" } else { "" }, items.concat()))
+}
+
+/// A traditional RE: a sequence of units, possibly anchored at either end
+pub(crate) fn traditional_re() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        "[abcxyz01 ]{1,3}",
+        special(),
+        range(),
+        Just("\\n".to_string()),
+    ];
+    let unit = (leaf, repetition())
+        .prop_map(|(leaf, reps)| leaf + &reps)
+        .prop_recursive(3, 24, 4, |inner| {
+            prop_oneof![
+                (
+                    select(vec!["", "?", "?<n>", "?<name>", "?<n+>"]),
+                    prop::collection::vec(inner.clone(), 1..4),
+                    repetition()
+                )
+                    .prop_map(|(prefix, units, reps)| format!(
+                        "\\({}{}\\){}",
+                        prefix,
+                        units.concat(),
+                        reps
+                    )),
+                (inner.clone(), inner).prop_map(|(a, b)| format!("{}\\|{}", a, b)),
+            ]
+        });
+    (
+        any::<bool>(),
+        prop::collection::vec(unit, 1..4),
+        any::<bool>(),
+    )
+        .prop_map(|(front, units, back)| {
+            format!(
+                "{}{}{}",
+                if front { "^" } else { "" },
+                units.concat(),
+                if back { "$" } else { "" }
+            )
+        })
+}
+
+/// The trailing name and repetition of an alternative syntax unit, in either order
+fn alt_suffix() -> impl Strategy<Value = String> {
+    (
+        select(vec!["", "", "<>", "<n>", "<name>"]),
+        repetition(),
+        any::<bool>(),
+    )
+        .prop_map(|(name, reps, name_first)| {
+            if name_first {
+                format!("{}{}", name, reps)
+            } else {
+                format!("{}{}", reps, name)
+            }
+        })
+}
+
+/// A text unit in the alternative syntax, in each of its four forms
+fn alt_text() -> impl Strategy<Value = String> {
+    let item = (prop_oneof!["[abcxyz01]", special(), range()], repetition())
+        .prop_map(|(item, reps)| item + &reps);
+    (
+        0..4usize,
+        any::<bool>(),
+        prop::collection::vec(item, 1..4),
+        alt_suffix(),
+    )
+        .prop_map(|(form, caseless, items, suffix)| {
+            let text = format!("{}{}", if caseless { "\\c" } else { "" }, items.concat());
+            match form {
+                0 => format!("\"{}\"{}", text, suffix),
+                1 => format!("'{}'{}", text, suffix),
+                2 => format!("txt({}){}", text, suffix),
+                // bare text ends at whitespace, so it cannot have a suffix
+                _ => format!("{} ", text),
+            }
+        })
+}
+
+/// An alternative syntax RE: a sequence of units, possibly anchored at the front
+pub(crate) fn alternative_re() -> impl Strategy<Value = String> {
+    let unit = alt_text().prop_recursive(3, 24, 4, |inner| {
+        (
+            select(vec!["and", "or"]),
+            prop::collection::vec(inner, 1..4),
+            alt_suffix(),
+        )
+            .prop_map(|(kind, units, suffix)| format!("{}({}){}", kind, units.join(" "), suffix))
+    });
+    (any::<bool>(), prop::collection::vec(unit, 1..4))
+        .prop_map(|(front, units)| format!("{}{}", if front { "^" } else { "" }, units.join(" ")))
+}
+
+/// Random strings made from the characters that mean something to the parsers, most of which are not legal REs
+pub(crate) fn junk_re() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        select(vec![
+            "a", "b", "\\", "(", ")", "|", "*", "+", "?", "{", "}", ",", "1", "[", "]", "^", "-",
+            "$", ".", "<", ">", "\"", "'", " ", "d", "c", "and(", "or(", "txt(", "get(", "any(",
+            "none(",
+        ]),
+        0..16,
+    )
+    .prop_map(|parts| parts.concat())
+}
+
+/// Texts to search. They are never empty, since **Input::init_text()** reads stdin for an empty string
+pub(crate) fn text() -> impl Strategy<Value = String> {
+    "[abcxyz01 \nAé]{1,24}"
+}
@@ -12,20 +12,399 @@
 //! While this can help in writing complex regular expressions or in understanding how the parser and walker work, it was mainly
 //! as an exercise in Rust.
 
-use crate::regexp::{set_trace, Report};
+use crate::regexp::options::with_walk_options;
+use crate::regexp::syntax::syntax_summary;
+use crate::regexp::trace::{attempt_tree, read_events, Attempt};
+use crate::regexp::{
+    find_iter, parse_tree_opts, replace_all, search_lines, set_trace, set_trace_format,
+    set_trace_writer, Error, MatchEvent, Report, SearchDriver, SearchOptions, TraceFormat, Warning,
+};
 use crate::tree::*;
-use crate::walk::{walk_tree, Input};
+use crate::walk::{
+    allocation_count, backtrack_count, reset_step_count, start_count, step_count, walk_tree,
+    walk_tree_until, Input, Path, WalkOptions,
+};
 use crate::Config;
 use core::fmt::Debug;
+use std::cell::RefCell;
 use std::io;
+use std::io::BufRead;
+use std::io::IsTerminal;
 use std::io::Write;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering::Acquire, Ordering::Release};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
 const PROMPT: &str = "> ";
+/// prompt for continuation lines
+const CONT_PROMPT: &str = "... ";
+/// how deep **source** commands can be nested, so a script that sources itself stops
+const MAX_SOURCE_DEPTH: usize = 16;
+/// prompt between the pages of a long list of results
+const PAGER_PROMPT: &str = "[Enter] next, q quit, NUMBER jump: ";
+/// the terminal height used when neither $LINES nor the terminal gives one
+const DEFAULT_TERMINAL_HEIGHT: usize = 24;
+
+/// Set when a command fails, so **source** can stop at the first command that does. It is cleared before each
+/// command is run.
+static COMMAND_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Prints the message for a command that could not do what it was asked, in println!()-like fashion, and marks the
+/// command as failed
+macro_rules! fail {
+    ($($arg:tt)*) => {{
+        COMMAND_FAILED.store(true, Release);
+        println!($($arg)*);
+    }};
+}
+
+/// Where interactive input comes from. If the **readline** feature is enabled and stdin is a terminal lines are
+/// read through *rustyline*, which provides line editing, history and tab completion. Otherwise it falls back to plain stdin.
+enum LineReader {
+    #[cfg(feature = "readline")]
+    Editor(Box<rustyline::Editor<CommandHelper, rustyline::history::DefaultHistory>>),
+    Plain,
+}
+
+impl LineReader {
+    fn new() -> LineReader {
+        #[cfg(feature = "readline")]
+        if io::stdin().is_terminal() {
+            if let Ok(mut editor) = rustyline::Editor::new() {
+                editor.set_helper(Some(CommandHelper::default()));
+                return LineReader::Editor(Box::new(editor));
+            }
+        }
+        LineReader::Plain
+    }
+
+    /// reads a line, without the trailing newline. Returns None at end of input.
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        match self {
+            #[cfg(feature = "readline")]
+            LineReader::Editor(editor) => match editor.readline(prompt) {
+                Ok(line) => Some(line),
+                // ^C abandons the current line
+                Err(rustyline::error::ReadlineError::Interrupted) => Some("".to_string()),
+                Err(_) => None,
+            },
+            LineReader::Plain => read_plain_line(&mut io::stdin().lock(), prompt),
+        }
+    }
+
+    #[cfg_attr(not(feature = "readline"), allow(unused_variables))]
+    fn add_history(&mut self, line: &str) {
+        match self {
+            #[cfg(feature = "readline")]
+            LineReader::Editor(editor) => {
+                let _ = editor.add_history_entry(line);
+            }
+            LineReader::Plain => (),
+        }
+    }
+
+    #[cfg_attr(not(feature = "readline"), allow(unused_variables))]
+    fn set_names(&mut self, names: CompletionNames) {
+        match self {
+            #[cfg(feature = "readline")]
+            LineReader::Editor(editor) => {
+                if let Some(helper) = editor.helper_mut() {
+                    helper.names = names;
+                }
+            }
+            LineReader::Plain => (),
+        }
+    }
+}
+
+/// Tab completion for the *rustyline* editor. The RE and text names change as the session goes on, so they are
+/// passed in after each command; everything else is fixed or global.
+#[cfg(feature = "readline")]
+#[derive(Default)]
+struct CommandHelper {
+    names: CompletionNames,
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::completion::Completer for CommandHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos, &self.names))
+    }
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::hint::Hinter for CommandHelper {
+    type Hint = String;
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::highlight::Highlighter for CommandHelper {}
+
+#[cfg(feature = "readline")]
+impl rustyline::validate::Validator for CommandHelper {}
+
+#[cfg(feature = "readline")]
+impl rustyline::Helper for CommandHelper {}
+
+/// The names of the stored REs and texts, used for completion
+#[derive(Default)]
+pub(crate) struct CompletionNames {
+    pub(crate) res: Vec<String>,
+    pub(crate) texts: Vec<String>,
+}
+
+/// Finds the completions for the word ending at **pos** in **line**. Returns the byte position where the word
+/// being completed starts, which is where the completions go, and the candidates. What is offered depends on what
+/// comes before: commands, subcommands, option values, RE and text names, definition names after "get(" or
+/// "defs show", and file paths after "use(", "text file", "search file", "save" and "load".
+pub(crate) fn complete(line: &str, pos: usize, names: &CompletionNames) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let mut words = split_command(before);
+    // the cursor is either at the end of a word or after whitespace, where an empty word is started
+    let (start, partial) = match words.last() {
+        Some((_, range)) if range.end == pos => {
+            let (word, range) = words.pop().unwrap();
+            (range.start, word)
+        }
+        _ => (pos, "".to_string()),
+    };
+    // definition names and files inside an RE, which take priority when the cursor is inside the parens
+    let raw = &before[start..];
+    let marker = ["get(", "use("]
+        .iter()
+        .filter_map(|marker| raw.rfind(marker).map(|at| (at, *marker)))
+        .max();
+    if let Some((at, marker)) = marker {
+        let arg_start = start + at + marker.len();
+        let arg = &before[arg_start..];
+        if !arg.contains(')') {
+            let candidates = if marker == "get(" {
+                matching(def_names().iter().map(String::as_str), arg)
+            } else {
+                complete_path(arg)
+            };
+            return (arg_start, candidates);
+        }
+    }
+    let words: Vec<&str> = words.iter().map(|(word, _)| word.as_str()).collect();
+    let cmd = words
+        .first()
+        .map_or("", |word| get_command(&COMMANDS, word));
+    let subcmd = |candidates: &'static [&str]| get_command(candidates, words[1]);
+    let re_names = names.res.iter().map(String::as_str);
+    let text_names = names.texts.iter().map(String::as_str);
+    let candidates = match (words.len(), cmd) {
+        (0, _) => matching(COMMANDS.into_iter(), &partial),
+        (_, "search") if words[1..].contains(&"file") => complete_path(&partial),
+        (_, "save") | (_, "load") | (_, "source") => complete_path(&partial),
+        (_, "diff") if words.len() < 3 => matching(re_names, &partial),
+        (1, "regexp") => matching(RE_SUBCOMMANDS.into_iter(), &partial),
+        (1, "text") => matching(TEXT_SUBCOMMANDS.into_iter(), &partial),
+        (1, "defs") => matching(DEFS_SUBCOMMANDS.into_iter(), &partial),
+        (1, "syntax") => matching(["traditional", "alternative"].into_iter(), &partial),
+        (1, "set") => matching(OPTIONS.into_iter(), &partial),
+        (1, "search") => matching(SEARCH_WORDS.into_iter(), &partial),
+        // search NUMBER all
+        (2, "search") if words[1].parse::<usize>().is_ok() => {
+            matching(SEARCH_WORDS.into_iter(), &partial)
+        }
+        (2, "regexp") => match subcmd(&RE_SUBCOMMANDS) {
+            "name" | "use" => matching(re_names, &partial),
+            _ => Vec::new(),
+        },
+        (2, "text") => match subcmd(&TEXT_SUBCOMMANDS) {
+            "name" | "use" => matching(text_names, &partial),
+            "file" => complete_path(&partial),
+            _ => Vec::new(),
+        },
+        (2, "defs") => match subcmd(&DEFS_SUBCOMMANDS) {
+            "show" | "delete" => matching(def_names().iter().map(String::as_str), &partial),
+            "load" => complete_path(&partial),
+            _ => Vec::new(),
+        },
+        (2, "set") => match subcmd(&OPTIONS) {
+            "parser" => matching(PARSER_VALUES.into_iter(), &partial),
+            "caseless" | "warnings" | "optimize" | "strict" | "lenient" | "auto" | "multiline" => {
+                matching(ON_OFF[0..2].iter().copied(), &partial)
+            }
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    (start, candidates)
+}
+
+/// the candidates that start with **partial**, sorted
+fn matching<'a>(candidates: impl Iterator<Item = &'a str>, partial: &str) -> Vec<String> {
+    let mut found: Vec<String> = candidates
+        .filter(|candidate| candidate.starts_with(partial))
+        .map(|candidate| candidate.to_string())
+        .collect();
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// the paths that start with **partial**. Directories get a trailing '/' so completion can continue into them,
+/// and hidden files are only offered if **partial** names them.
+fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, file) = match partial.rfind('/') {
+        Some(at) => (&partial[..=at], &partial[at + 1..]),
+        None => ("", partial),
+    };
+    let entries = match std::fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut found: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(file) || (name.starts_with('.') && !file.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+/// The reader is global, like the **Input** struct, so that prompts from anywhere (such as **get_response()**) share
+/// the same history
+static LINE_READER: Lazy<Mutex<LineReader>> = Lazy::new(|| Mutex::new(LineReader::new()));
+
+/// reads a line of user input, returning None at end of input
+fn read_line(prompt: &str) -> Option<String> {
+    LINE_READER.lock().unwrap().read_line(prompt)
+}
+
+/// adds a line to the history available through up-arrow
+fn add_history(line: &str) {
+    LINE_READER.lock().unwrap().add_history(line);
+}
+
+/// updates the RE and text names offered by tab completion
+fn set_completion_names(names: CompletionNames) {
+    LINE_READER.lock().unwrap().set_names(names);
+}
+
+/// prints the prompt and reads a line from **reader** with the newline removed. Returns None at end of input.
+fn read_plain_line<R: BufRead>(reader: &mut R, prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    std::io::stdout().flush().unwrap();
+    let mut buffer = String::new();
+    match reader.read_line(&mut buffer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            if buffer.ends_with('\n') {
+                buffer.pop();
+                if buffer.ends_with('\r') {
+                    buffer.pop();
+                }
+            }
+            Some(buffer)
+        }
+    }
+}
 
 /// holds a RE, which consists of a string and instructions on what parser to use
+#[derive(Serialize, Deserialize, Clone)]
 struct RegExp {
     re: String,
     alt_parser: bool,
+    /// optional name, so the RE can be recalled without knowing its position in the history
+    #[serde(default)]
+    name: Option<String>,
+    /// the tree from the last time the RE was parsed, so searching with it again does not parse it again
+    #[serde(skip)]
+    tree: RefCell<Option<CachedTree>>,
+}
+
+/// A parsed RE, along with everything that went into making it so it can tell when it is out of date
+#[derive(Clone)]
+struct CachedTree {
+    re: String,
+    alt_parser: bool,
+    /// the options that change the tree: optimize, strict, caseless and lenient
+    options: (bool, bool, bool, bool),
+    /// the def table generation when it was parsed, since changing a definition changes the trees that use it
+    defs_generation: usize,
+    tree: Node,
+}
+
+/// holds a search text, with an optional name like **RegExp**
+#[derive(Serialize, Deserialize, Clone)]
+struct Text {
+    text: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// The RE and text stacks can both have named entries, this lets the naming code be shared
+trait Slot {
+    fn name_mut(&mut self) -> &mut Option<String>;
+    fn name(&self) -> &Option<String>;
+}
+
+impl Slot for RegExp {
+    fn name_mut(&mut self) -> &mut Option<String> {
+        &mut self.name
+    }
+    fn name(&self) -> &Option<String> {
+        &self.name
+    }
+}
+
+impl Slot for Text {
+    fn name_mut(&mut self) -> &mut Option<String> {
+        &mut self.name
+    }
+    fn name(&self) -> &Option<String> {
+        &self.name
+    }
+}
+
+/// Gives the current (last) entry of a stack a name. A name can only be used once, so if another entry already has
+/// it it is taken away from that one. Returns the history number of the entry that lost the name.
+fn name_slot<T: Slot>(stack: &mut [T], name: &str) -> Option<usize> {
+    let previous = find_slot(stack, name);
+    if let Some(num) = previous {
+        let len = stack.len();
+        *stack[len - 1 - num].name_mut() = None;
+    }
+    if let Some(current) = stack.last_mut() {
+        *current.name_mut() = Some(name.to_string());
+    }
+    previous
+}
+
+/// Finds the history number (0 is the current entry) of the entry with the given name
+fn find_slot<T: Slot>(stack: &[T], name: &str) -> Option<usize> {
+    stack
+        .iter()
+        .rev()
+        .position(|slot| slot.name().as_deref() == Some(name))
+}
+
+/// formats an entry's name for the history lists
+fn slot_label<T: Slot>(slot: &T) -> String {
+    match slot.name() {
+        Some(name) => format!("[{}] ", name),
+        None => "".to_string(),
+    }
 }
 
 impl Debug for RegExp {
@@ -44,30 +423,46 @@ impl Debug for RegExp {
 }
 
 impl RegExp {
-    /// Guesses the type of a regular expression and gets confirmation of its choice from the user
-    fn guess_type(maybe_re: &String) -> Option<RegExp> {
-        let (prompt, dflt) = if maybe_re.contains("and(")
+    /// constructor, with no name
+    fn new(re: String, alt_parser: bool) -> RegExp {
+        RegExp {
+            re,
+            alt_parser,
+            name: None,
+            tree: RefCell::default(),
+        }
+    }
+
+    /// Guesses the syntax of a regular expression from the constructs in it: Some(true) for the alternative syntax,
+    /// Some(false) for the traditional one, and None if it does not look like either
+    fn looks_alternative(maybe_re: &str) -> Option<bool> {
+        if maybe_re.contains("and(")
             || maybe_re.contains("or(")
             || maybe_re.contains("get(")
+            || maybe_re.contains("any(")
+            || maybe_re.contains("none(")
         {
-            ("This looks like an alternative RE type. It is t[raditional], [alternative], c[ancel]", 1)
+            Some(true)
         } else if maybe_re.contains('\\') || maybe_re.contains('*') || maybe_re.contains('+') {
-            (
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Guesses the type of a regular expression and gets confirmation of its choice from the user
+    fn guess_type(maybe_re: &str) -> Option<RegExp> {
+        let (prompt, dflt) = match RegExp::looks_alternative(maybe_re) {
+            Some(true) => ("This looks like an alternative RE type. It is t[raditional], [alternative], c[ancel]", 1),
+            Some(false) => (
                 "This looks like a traditional RE type: [traditional], a[lternative], c[ancel]",
                 0,
-            )
-        } else {
-            ("This doesn't look like a regular expression. It is: t[raditional], a[lternative], [cancel]", 2)
+            ),
+            None => ("This doesn't look like a regular expression. It is: t[raditional], a[lternative], [cancel]", 2),
         };
         match get_response(prompt, vec!["traditional", "alternative", "cancel"], dflt) {
-            "alternative" => Some(RegExp {
-                re: maybe_re.to_string(),
-                alt_parser: true,
-            }),
-            "traditional" => Some(RegExp {
-                re: maybe_re.to_string(),
-                alt_parser: false,
-            }),
+            "alternative" => Some(RegExp::new(maybe_re.to_string(), true)),
+            "traditional" => Some(RegExp::new(maybe_re.to_string(), false)),
             _ => None,
         }
     }
@@ -78,18 +473,151 @@ pub(crate) struct Interactive {
     /// the list of regular expressions, last one is the current value
     res: Vec<RegExp>,
     /// the list of target strings, last one is the current value
-    texts: Vec<String>,
-    // Interesting: I stored cmd_parse_tree here to avoid having to recompute it each time, and found
-    // it caused a borrow violation. Moving it out and passing it as an extra parameter to where it is used
-    // removed the problem. Lesson learned.
-    // the tree used to parse the user commands, parsed from CMD_PARSE_RE, to save it from having to be reparsed every time
-    // cmd_parse_tree: Node,
+    texts: Vec<Text>,
+    /// the files used in the last file search, so it can be repeated after changing the RE
+    files: Vec<String>,
+    /// if set the session is loaded from the default file at startup and saved there at exit
+    persist: bool,
+    /// settings managed by the **set** command
+    options: Options,
+    /// the matches found by the last **search all**, copied out so they outlive later searches
+    last_matches: Vec<FoundMatch>,
+    /// the matches found by the last **search file**, for the **results** command
+    last_results: Vec<FileHit>,
+    /// the number of **source** commands being run, one inside the other
+    source_depth: usize,
+}
+
+/// How running a script with **source** ended
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ScriptEnd {
+    /// every command was run
+    Finished,
+    /// a command failed, or the file could not be read
+    Failed,
+    /// a command ended the session
+    Quit,
+}
+
+/// A match kept from a **search all**, for **text from-match** and **text from-line**
+struct FoundMatch {
+    /// the text matched
+    text: String,
+    /// the line (or lines) the match is in
+    line: String,
+}
+
+/// A match kept from a **search file**. Everything shown about it is copied out while its file is loaded, so it can
+/// be shown again after the files are closed.
+struct FileHit {
+    /// the file the match is in
+    file: String,
+    /// the line it starts in, counting from 1
+    line: usize,
+    /// the text matched, as the list shows it
+    text: String,
+    /// the full report, with the named units and their positions, as **Report::display()** shows it
+    detail: String,
+}
+
+impl FileHit {
+    /// the line for the hit in the list of results, where it is number NUMBER
+    fn summary(&self, number: usize) -> String {
+        format!("{}: {}:{}: {}", number, self.file, self.line, self.text)
+    }
+}
+
+/// Splits a list of LEN items into pages of HEIGHT, for showing long results a screenful at a time. Each call to
+/// **next()** gives the range of the items on the next page, and **jump()** changes where the next page starts.
+pub(crate) struct Pages {
+    len: usize,
+    height: usize,
+    /// the first item of the next page
+    next: usize,
+}
+
+impl Pages {
+    /// constructor, for LEN items shown HEIGHT (at least 1) at a time
+    pub(crate) fn new(len: usize, height: usize) -> Pages {
+        Pages {
+            len,
+            height: height.max(1),
+            next: 0,
+        }
+    }
+
+    /// makes the next page start at item NUMBER, counting from 1 as the list does. A number past the end starts it
+    /// at the last item.
+    pub(crate) fn jump(&mut self, number: usize) {
+        self.next = number.saturating_sub(1).min(self.len.saturating_sub(1));
+    }
+
+    /// true once every item has been on a page
+    pub(crate) fn done(&self) -> bool {
+        self.next >= self.len
+    }
+}
+
+impl Iterator for Pages {
+    type Item = Range<usize>;
+    fn next(&mut self) -> Option<Range<usize>> {
+        if self.done() {
+            return None;
+        }
+        let start = self.next;
+        self.next = (start + self.height).min(self.len);
+        Some(start..self.next)
+    }
+}
+
+/// What the user asked for at the **PAGER_PROMPT**
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PagerKey {
+    /// show the next page
+    Next,
+    /// stop showing the list
+    Quit,
+    /// go to the item with this number
+    Jump(usize),
 }
 
-/// a RE used to parse the command line each time it is entered. It breaks the command line
-/// up into words so it can be parsed. The Report objects do maintain the whitespace in the
-/// original string so the actual entered regular expressions are not lost.
-const CMD_PARSE_ALT_RE: &str = r"^and('\w*' '[^\w]'+<words>)+";
+impl PagerKey {
+    /// reads a response to the prompt: nothing for the next page, "q" to quit or a number to jump to, None for
+    /// anything else
+    pub(crate) fn parse(response: &str) -> Option<PagerKey> {
+        match response.trim() {
+            "" => Some(PagerKey::Next),
+            "q" | "quit" => Some(PagerKey::Quit),
+            number => number.parse().ok().filter(|&n| n > 0).map(PagerKey::Jump),
+        }
+    }
+}
+
+/// The number of lines the terminal shows, from $LINES or the terminal itself, or **DEFAULT_TERMINAL_HEIGHT**
+fn terminal_height() -> usize {
+    if let Some(lines) = std::env::var("LINES")
+        .ok()
+        .and_then(|lines| lines.parse().ok())
+    {
+        if lines > 0 {
+            return lines;
+        }
+    }
+    #[cfg(unix)]
+    {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_row > 0
+        {
+            return size.ws_row as usize;
+        }
+    }
+    DEFAULT_TERMINAL_HEIGHT
+}
+
+/// a RE that breaks a command line up into words. Commands used to be parsed by running it on each line entered,
+/// now it is only used by the **split** command to show the program parsing its own input.
+pub(crate) const CMD_PARSE_ALT_RE: &str = r"^and('\w*' '[^\w]'+<words>)+";
 
 /// help text to display
 const HELP_TEXT: &str = r"
@@ -100,7 +628,8 @@ string.
 
 Commands are in general of the form CMD [SUBCMD [DATA]], though it will try to guess the 
 meaning of ambiguous commands. The commands and subcommands can be abbreviated with the 
-first couple unique letters.
+first couple unique letters. Words are separated by whitespace, and names and file names
+containing spaces can be put in single or double quotes. REs and texts are taken exactly as typed.
 
 The commands are:
  - regexp:         display the current active regular expression
@@ -108,6 +637,8 @@ The commands are:
                    keyword is optional, if not given the program usually will guess what the text
                    is, and ask for confirmation
  - regexp history: lists the most recent regular expressions
+ - regexp name NAME: gives the current regular expression a name. A name can only belong to one regular expression
+ - regexp use NAME: makes the regular expression with the given name the current one
  - regexp list:    same as 're history'
  - regexp NUMBER:  sets the NUMBERth item on the history list to be the current regular expression
  - regular pop [n]:pops off (deletes) the nth re from the list. Defaults to 0 (the current RE)
@@ -115,44 +646,224 @@ The commands are:
  - text TEXT:      sets new search text
  - text list:      same as 'text history'
  - text history:   lists the most recent regular expressions
- - text NUMBER:    sets the NUMBERth item on the history list to be the current text
+ - text NUMBER:    sets the NUMBERth item on the history list to be the current text. If there is no
+                   such item, or anything follows the number, the input is taken as the new text instead
+ - text name NAME: gives the current text a name. A name can only belong to one text
+ - text use NAME:  makes the text with the given name the current one
  - text pop [n]:   pops off (deletes) the nth tex string from memory. Defauls to 0 (the current text string)
+ - text file PATH: reads the contents of the file PATH in as the new search text
+ - text from-match [N]: uses the text of the Nth match (default 1) found by the last search all as the new search text
+ - text from-line [N]: the same, but uses the whole line the match is in
  - search :        performs a RE search using the current RE and the current text. 
  - search NAME1 [NAME2...]: performs a RE search using the current RE and the current text, report only on units with the given names
+                   (and the units containing them)
  - search * :      performs a RE search using the current RE and the current text, report on all named units
  - search NUMBER:  performs a RE search using the current RE and the current text setting debug level to NUMBER to examine the path.
                    This can be combined with search for name.
- - tree [NUMBER]:  displays the parse tree for the current regular expression. Optional **NUMBER** sets the trace level
-                   to see how the parse is performed.
+ - search [NUMBER] all: finds every match of the current RE in the current text, numbering them and showing named units
+ - search [NUMBER] count: prints the number of matches of the current RE in the current text
+ - search file [PATH...]: searches the files for the current RE, printing each match numbered, with its file and
+                   line number. If no PATH is given the files from the last file search are used again. When there
+                   are more than fit on the screen they are shown a screenful at a time: Enter shows the next, q
+                   stops, and a NUMBER jumps to that match
+ - results:        shows the matches from the last search file again
+ - results NUMBER: shows the NUMBERth match from the last search file in full, with its named units and positions
+ - walk [NUMBER]:  performs a search like **search**, but dumps the successful path instead of the report. Optional
+                   **NUMBER** sets the trace level for the walk.
+ - walk json FILE: performs a walk, writing the trace to FILE as one JSON event per line, then reads it back
+                   and prints the tree of attempts the walk made
+ - tree [NUMBER] [depth N] [filter NAME]: displays the parse tree for the current regular expression, followed by any
+                   warnings. Optional **NUMBER** sets the trace level to see how the parse is performed. depth N prints
+                   only the top N levels and filter NAME only the subtrees under the units named NAME
+ - tree json:      prints the parse tree for the current regular expression as JSON, the same as --tree-json
+ - tree expanded [notes]: prints the current regular expression in the alternative syntax with its definitions
+                   written out in place, the same as --expanded. With notes the expanded definitions are marked
+ - explain:        describes the current regular expression in plain English, the same as --explain, followed by
+                   any warnings as notes if warnings are on
+ - replace TEMPLATE: prints the current text with every match of the current RE replaced by TEMPLATE. In TEMPLATE
+                   $name or ${name} is the named unit, $0 the whole match, $N the Nth recorded unit, and $$ is '$'
+ - replace! TEMPLATE: the same as replace, but the result becomes the new current text
+ - bench [COUNT]:  times parsing the current RE and searching the current text COUNT times (default 100), and
+                   shows the number of start positions, steps, backtracks, and allocations the search takes,
+                   and the most text held in memory
+ - diff RE1 RE2:   finds all matches of two REs from the history (by number or name) in the current text and
+                   shows the matches found by only one of them and by both, noting named units that differ
+ - defs [list]:    lists the defined snippet names with their definitions
+ - defs show NAME: displays the parse tree for the definition of NAME
+ - defs delete NAME: removes the definition of NAME
+ - defs clear:     removes all definitions
+ - defs load [FILE]: loads definitions from FILE, the same as use(FILE) in an RE. Defaults to ~/.regexp, and builtin loads the builtin definitions
+ - set:            lists the session options
+ - set OPTION VALUE: sets a session option. The options are:
+                   parser guess|traditional|alternative: parser for an RE entered without a keyword (default guess)
+                   trace NUMBER: trace level for search, walk and tree when none is given (default 0)
+                   caseless on|off: ignore case in the characters of the RE (default off)
+                   warnings on|off: show warnings with the tree command (default on)
+                   optimize on|off: simplify the parse tree (default on)
+                   strict on|off: make unknown escapes like \q an error instead of a warning (default off)
+                   lenient on|off: take text left over after the RE is complete, like a stray closing paren,
+                   as literal text with a warning instead of an error (default off)
+                   auto on|off: while a regexp command is continued over several lines with '\\', close what
+                   has been typed so far and preview its first match in the current text (default off)
+                   timeout SECONDS|off: abandon a search or walk that runs longer than this (default off)
+                   multiline on|off: let matches in the current text run past the end of a line. When off each
+                   line is searched on its own, with '^' and '$' matching at its ends (default on)
+                   There is no color option since nothing is printed in color, and no step budget: the walk does
+                   not count steps against a limit, so a timeout is the way to bound a search
+ - split LINE:     shows how LINE is broken into words, both by the command splitter and by the RE the
+                   program once used to parse its own commands
+ - syntax [traditional|alternative]: prints a summary of the syntax, with examples. The default is the
+                   syntax of the current RE
+ - save [FILE]:    saves the REs, texts, definitions and options to FILE (default ~/.regexp_session)
+ - load [FILE]:    replaces the REs, texts and options with those saved in FILE (default ~/.regexp_session) and adds its definitions
+ - source [-k] FILE: runs the commands in FILE as if they were typed, showing each one before its output. Lines
+                   starting with '#' are skipped. It stops at the first command that fails unless -k is given
  - help:           displays this help
  - ?:              displays this help
 ";
 
 /// The commands for the main loop
-const COMMANDS: [&str; 9] = [
-    "regexp", "text", "search", "tree", "walk", "quit", "exit", "help", "?",
+const COMMANDS: [&str; 22] = [
+    "regexp", "text", "search", "results", "tree", "explain", "walk", "replace", "replace!",
+    "bench", "diff", "defs", "set", "split", "syntax", "save", "load", "source", "quit", "exit",
+    "help", "?",
+];
+
+/// default file for **save** and **load**, in the user's home directory
+const SESSION_FILE: &str = ".regexp_session";
+
+/// The contents of a saved session: the RE and text stacks and the def table
+#[derive(Serialize, Deserialize, Default)]
+struct Session {
+    res: Vec<RegExp>,
+    texts: Vec<Text>,
+    /// definitions as text, to be reparsed when loaded
+    defs: Vec<String>,
+    #[serde(default)]
+    options: Options,
+}
+
+/// Session settings, changed with the **set** command
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub(crate) struct Options {
+    /// parser for an RE entered without "traditional" or "alternative": "guess" asks the user
+    pub(crate) parser: String,
+    /// trace level used by **search**, **walk** and **tree** when none is given
+    pub(crate) trace: usize,
+    /// ignore case in all the characters of the RE
+    pub(crate) caseless: bool,
+    /// show warnings with the **tree** command
+    pub(crate) warnings: bool,
+    /// simplify the tree after parsing
+    pub(crate) optimize: bool,
+    /// make unknown escapes an error instead of a warning
+    pub(crate) strict: bool,
+    /// take text left over after the RE is complete as literal text instead of an error
+    pub(crate) lenient: bool,
+    /// preview the match of an RE after each continuation line while it is being entered
+    pub(crate) auto: bool,
+    /// abandon a search of the current text that takes longer than this many seconds
+    pub(crate) timeout: Option<f64>,
+    /// let matches in the current text run past the end of a line
+    pub(crate) multiline: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            parser: "guess".to_string(),
+            trace: 0,
+            caseless: false,
+            warnings: true,
+            optimize: true,
+            strict: false,
+            lenient: false,
+            auto: false,
+            timeout: None,
+            multiline: true,
+        }
+    }
+}
+
+/// the option names accepted by **set**
+const OPTIONS: [&str; 10] = [
+    "parser",
+    "trace",
+    "caseless",
+    "warnings",
+    "optimize",
+    "strict",
+    "lenient",
+    "auto",
+    "timeout",
+    "multiline",
+];
+/// the values for the **parser** option
+const PARSER_VALUES: [&str; 3] = ["guess", "traditional", "alternative"];
+/// the values for on/off options, the first two are the ones offered by completion
+const ON_OFF: [&str; 6] = ["on", "off", "true", "false", "yes", "no"];
+/// subcommands of **regexp**
+const RE_SUBCOMMANDS: [&str; 7] = [
+    "pop",
+    "history",
+    "list",
+    "traditional",
+    "alternative",
+    "name",
+    "use",
 ];
+/// subcommands of **text**
+const TEXT_SUBCOMMANDS: [&str; 9] = [
+    "pop",
+    "history",
+    "list",
+    "set",
+    "file",
+    "name",
+    "use",
+    "from-match",
+    "from-line",
+];
+/// subcommands of **defs**
+const DEFS_SUBCOMMANDS: [&str; 5] = ["list", "show", "delete", "clear", "load"];
+/// keywords that can follow **search**
+const SEARCH_WORDS: [&str; 3] = ["all", "count", "file"];
 /// Used to check for continuation lines
 const SLASH_BYTE: u8 = 92;
 impl Interactive {
     /// constructor for the session object
     pub(crate) fn new(config: Config) -> Interactive {
-        let mut res = Vec::<RegExp>::new();
+        let mut interactive = Interactive {
+            res: Vec::<RegExp>::new(),
+            texts: Vec::<Text>::new(),
+            files: Vec::<String>::new(),
+            persist: config.persist,
+            options: Options {
+                warnings: !config.no_warnings,
+                optimize: !config.no_optimize,
+                strict: config.strict,
+                ..Options::default()
+            },
+            last_matches: Vec::new(),
+            last_results: Vec::new(),
+            source_depth: 0,
+        };
+        if config.persist {
+            let path = session_path("");
+            if std::path::Path::new(&path).exists() {
+                interactive.report_load(&path);
+            }
+        }
         if !config.re.is_empty() {
-            res.push(RegExp {
-                re: config.re.to_string(),
-                alt_parser: config.alt_parser(),
-            });
+            interactive
+                .res
+                .push(RegExp::new(config.re.to_string(), config.alt_parser()));
         }
-        let mut texts = Vec::<String>::new();
         if !config.text.is_empty() {
-            texts.push(config.text);
+            interactive.push_text(config.text);
         };
-        Interactive {
-            res,
-            texts,
-            //                      cmd_parse_tree: parse_tree(CMD_PARSE_ALT_RE, true).unwrap()
-        }
+        interactive
     }
 
     /// gets the current RE, or None
@@ -161,113 +872,470 @@ impl Interactive {
     }
 
     /// gets the current search text, or None
-    fn text(&self) -> Option<&String> {
-        self.texts.last()
+    pub(crate) fn text(&self) -> Option<&String> {
+        self.texts.last().map(|text| &text.text)
+    }
+
+    /// gets the tree for an RE, applying the session options. The tree is kept with the RE, and only parsed again if
+    /// the RE, the options that affect it, or the definitions have changed since.
+    fn parse_re(&self, re: &RegExp) -> Result<Node, Error> {
+        let options = (
+            self.options.optimize,
+            self.options.strict,
+            self.options.caseless,
+            self.options.lenient,
+        );
+        if let Some(cached) = re.tree.borrow().as_ref() {
+            if cached.re == re.re
+                && cached.alt_parser == re.alt_parser
+                && cached.options == options
+                && cached.defs_generation == defs_generation()
+            {
+                return Ok(cached.tree.clone());
+            }
+        }
+        let tree = self.parse_re_with_warnings(re)?.0;
+        *re.tree.borrow_mut() = Some(CachedTree {
+            re: re.re.clone(),
+            alt_parser: re.alt_parser,
+            options,
+            defs_generation: defs_generation(),
+            tree: tree.clone(),
+        });
+        Ok(tree)
+    }
+
+    /// parses an RE into a tree, applying the session options, and also returns any warnings
+    fn parse_re_with_warnings(&self, re: &RegExp) -> Result<(Node, Vec<Warning>), Error> {
+        parse_tree_opts(
+            &re.re,
+            &SearchOptions::new()
+                .alt_parser(re.alt_parser)
+                .optimize(self.options.optimize)
+                .strict(self.options.strict)
+                .lenient(self.options.lenient)
+                .caseless(self.options.caseless),
+        )
+    }
+
+    /// builds the preview printed by the **auto** option for PARTIAL, the lines typed so far of a command being
+    /// continued. Only **regexp** commands are previewed: their RE is closed with **auto_close()** and searched for in
+    /// the current text, and the preview is the first match or the parse error. A definition in the partial RE
+    /// would be added to the def table, so the table is put back afterward.
+    pub(crate) fn preview(&self, partial: &str) -> Option<String> {
+        let words = Words::new(partial);
+        if get_command(&COMMANDS, words.word(0)) != "regexp" {
+            return None;
+        }
+        let (re, alt_parser) = match get_command(&RE_SUBCOMMANDS, words.word(1)) {
+            subcmd @ ("alternative" | "traditional") => (
+                input_substring(&words, 2, usize::MAX),
+                subcmd == "alternative",
+            ),
+            "unrecognized" => {
+                let re = input_substring(&words, 1, usize::MAX);
+                let alt_parser = match self.options.parser.as_str() {
+                    "guess" => RegExp::looks_alternative(&re) == Some(true),
+                    parser => parser == "alternative",
+                };
+                (re, alt_parser)
+            }
+            _ => return None,
+        };
+        let closing = auto_close(&re, alt_parser);
+        let generation = defs_generation();
+        let saved_defs = def_sources();
+        let parsed =
+            self.parse_re_with_warnings(&RegExp::new(format!("{}{}", re, closing), alt_parser));
+        if defs_generation() != generation {
+            clear_defs();
+            for def in saved_defs.iter() {
+                let _ = load_defs(def);
+            }
+        }
+        let result = match (parsed, self.text()) {
+            (Err(err), _) => format!("Error parsing RE: {}", err.msg),
+            (Ok(_), None) => "No current text".to_string(),
+            (Ok((tree, _)), Some(text)) => match Input::init_text(text, false) {
+                Err(msg) => msg.to_string(),
+                Ok(_) => match walk_tree(&tree, 0) {
+                    Err(msg) => format!("Error: {}", msg),
+                    Ok(None) => "No match".to_string(),
+                    Ok(Some(path)) => format!("{:?}", Report::new(&path).matched),
+                },
+            },
+        };
+        Some(if closing.is_empty() {
+            format!("preview: {}", result)
+        } else {
+            format!("preview (closed with {}): {}", closing, result)
+        })
+    }
+
+    /// the current session options
+    pub(crate) fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// runs F, a search of the current text, with the **timeout** option in force
+    fn with_timeout<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut walk = WalkOptions::new();
+        if let Some(secs) = self.options.timeout {
+            walk = walk.timeout(Duration::from_secs_f64(secs));
+        }
+        with_walk_options(&SearchOptions::new().walk(walk), f)
+    }
+
+    /// finds the first match of NODE in the current text. With the **multiline** option off each line is searched on
+    /// its own, so a match cannot run past the end of the line it starts in.
+    fn first_match<'a>(&self, node: &'a Node) -> Result<Option<Path<'a>>, Error> {
+        self.with_timeout(|| {
+            if self.options.multiline {
+                return walk_tree(node, 0);
+            }
+            let mut start = 0;
+            loop {
+                let end = Input::line_end(start);
+                if let Some(path) = walk_tree_until(node, start, end)? {
+                    return Ok(Some(path));
+                }
+                if end >= Input::len() {
+                    return Ok(None);
+                }
+                start = end + 1;
+            }
+        })
+    }
+
+    /// sets the session option **name** to **value**. On error the message lists the valid choices.
+    pub(crate) fn set_option(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let on_off = |value: &str| match get_command(&ON_OFF, value) {
+            "on" | "true" | "yes" => Ok(true),
+            "off" | "false" | "no" => Ok(false),
+            _ => Err(format!("Value for {} must be on or off", name)),
+        };
+        match get_command(&OPTIONS, name) {
+            "parser" => match get_command(&PARSER_VALUES, value) {
+                "unrecognized" | "ambiguous" | "" => {
+                    return Err(
+                        "Value for parser must be guess, traditional or alternative".to_string()
+                    )
+                }
+                parser => self.options.parser = parser.to_string(),
+            },
+            "trace" => match value.parse::<usize>() {
+                Ok(trace) => self.options.trace = trace,
+                Err(_) => return Err("Value for trace must be a number".to_string()),
+            },
+            "caseless" => self.options.caseless = on_off(value)?,
+            "warnings" => self.options.warnings = on_off(value)?,
+            "optimize" => self.options.optimize = on_off(value)?,
+            "strict" => self.options.strict = on_off(value)?,
+            "lenient" => self.options.lenient = on_off(value)?,
+            "auto" => self.options.auto = on_off(value)?,
+            "timeout" => match value.parse::<f64>() {
+                Ok(secs) if secs > 0.0 && secs.is_finite() => self.options.timeout = Some(secs),
+                _ if get_command(&ON_OFF, value) == "off" => self.options.timeout = None,
+                _ => {
+                    return Err("Value for timeout must be a number of seconds, or off".to_string())
+                }
+            },
+            "multiline" => self.options.multiline = on_off(value)?,
+            _ => {
+                return Err(format!(
+                    "Unknown option {}, options are: {}",
+                    name,
+                    OPTIONS.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// executes a **set** command: with no arguments lists the options, otherwise sets one
+    fn do_set(&mut self, words: &Words) {
+        match words.len() {
+            1 => {
+                println!("parser:   {}", self.options.parser);
+                println!("trace:    {}", self.options.trace);
+                println!("caseless: {}", on_off_str(self.options.caseless));
+                println!("warnings: {}", on_off_str(self.options.warnings));
+                println!("optimize: {}", on_off_str(self.options.optimize));
+                println!("strict:   {}", on_off_str(self.options.strict));
+                println!("lenient:  {}", on_off_str(self.options.lenient));
+                println!("auto:     {}", on_off_str(self.options.auto));
+                match self.options.timeout {
+                    Some(secs) => println!("timeout:  {}s", secs),
+                    None => println!("timeout:  off"),
+                }
+                println!("multiline: {}", on_off_str(self.options.multiline));
+            }
+            3 => {
+                if let Err(msg) = self.set_option(words.word(1), words.word(2)) {
+                    fail!("{}", msg);
+                }
+            }
+            _ => fail!("set [OPTION VALUE], options are: {}", OPTIONS.join(", ")),
+        }
+    }
+
+    /// makes **text** the current search text
+    fn push_text(&mut self, text: String) {
+        self.texts.push(Text { text, name: None });
+    }
+
+    /// the names of the stored REs and texts, for tab completion
+    fn completion_names(&self) -> CompletionNames {
+        CompletionNames {
+            res: self.res.iter().filter_map(|re| re.name.clone()).collect(),
+            texts: self
+                .texts
+                .iter()
+                .filter_map(|text| text.name.clone())
+                .collect(),
+        }
     }
 
     /// starts up the interactive session
     pub(crate) fn run(&mut self) {
-        let stdin = io::stdin();
+        self.seed_history();
+        set_completion_names(self.completion_names());
         let mut buffer = String::new();
         // used to signal continuation line
         let mut cont = false;
-        let cmd_parse_tree = parse_tree(CMD_PARSE_ALT_RE, true).unwrap();
-        loop {
-            if !cont {
-                buffer = "".to_string();
-            }
-            self.prompt(cont);
-            cont = false;
-            match stdin.read_line(&mut buffer) {
-                Ok(0) => {
-                    break;
-                }
-                Ok(1) => (),
-                Ok(_x) => {
-                    let len = buffer.len();
-                    // handle line continuation
-                    if let [SLASH_BYTE, _cr] = buffer.as_bytes()[len - 2..] {
-                        cont = true;
-                        buffer.remove(len - 2);
-                    } else {
-                        buffer.remove(len - 1);
-                        if !self.do_command(&buffer, &cmd_parse_tree) {
-                            break;
-                        }
+        while let Some(line) = read_line(if cont { CONT_PROMPT } else { PROMPT }) {
+            // handle line continuation
+            if let Some(line) = line.strip_suffix('\\') {
+                buffer.push_str(line);
+                if self.options.auto {
+                    if let Some(preview) = self.preview(&buffer) {
+                        println!("{}", preview);
                     }
                 }
-                Err(_msg) => {
-                    break;
-                }
+                buffer.push('\n');
+                cont = true;
+                continue;
+            }
+            cont = false;
+            buffer.push_str(&line);
+            let command = std::mem::take(&mut buffer);
+            if command.is_empty() {
+                continue;
+            }
+            add_history(&command);
+            if !self.do_command(&command) {
+                break;
             }
+            set_completion_names(self.completion_names());
         }
         println!("exit");
+        self.end();
     }
 
-    /// prints out the session prompt - maybe in the future it will want to display some information here
-    fn prompt(&mut self, cont: bool) {
-        print!("{}", if cont { "... " } else { "> " });
-        std::io::stdout().flush().unwrap();
+    /// ends the session, saving it if it persists
+    pub(crate) fn end(&self) {
+        if self.persist {
+            if let Err(msg) = self.save(&session_path("")) {
+                println!("{}", msg);
+            }
+        }
     }
 
-    /// parses the entered string to get a command, and call **execute_command()** to do it. Return *false* to exit.
-    fn do_command(&mut self, input: &str, cmd_parse_tree: &Node) -> bool {
-        if let Err(msg) = Input::init_text(input, false) {
-            println!("{}", msg);
-            return false;
+    /// Runs the commands in the file **path** as if they were typed at the prompt, printing each one after the
+    /// prompt so the output reads like a transcript of the session. Lines ending in '\\' are continued as at the
+    /// prompt, and blank lines and lines starting with '#' are skipped. Unless **keep_going** is set the script stops
+    /// at the first command that fails.
+    pub(crate) fn source(&mut self, path: &str, keep_going: bool) -> ScriptEnd {
+        let script = match std::fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(err) => {
+                fail!("Error reading {}: {}", path, err);
+                return ScriptEnd::Failed;
+            }
+        };
+        if self.source_depth >= MAX_SOURCE_DEPTH {
+            fail!("Scripts are nested more than {} deep", MAX_SOURCE_DEPTH);
+            return ScriptEnd::Failed;
         }
-        match walk_tree(cmd_parse_tree, 0) {
-            Ok(Some(path)) => {
-                let report = Report::new(&path);
-                let words = report.get_by_name("words");
-                self.execute_command(&words)
+        self.source_depth += 1;
+        let mut end = ScriptEnd::Finished;
+        let mut buffer = String::new();
+        let mut first_line = 0;
+        for (num, line) in script.lines().enumerate() {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if buffer.is_empty() {
+                if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                    continue;
+                }
+                first_line = num + 1;
+                println!("{}{}", PROMPT, line);
+            } else {
+                println!("{}{}", CONT_PROMPT, line);
             }
-            Ok(None) => true,
-            Err(msg) => {
-                println!("{}", msg);
-                true
+            if let Some(line) = line.strip_suffix('\\') {
+                buffer.push_str(line);
+                buffer.push('\n');
+                continue;
+            }
+            buffer.push_str(line);
+            let command = std::mem::take(&mut buffer);
+            if !self.do_command(&command) {
+                end = ScriptEnd::Quit;
+                break;
+            }
+            if COMMAND_FAILED.load(Acquire) && !keep_going {
+                println!("Stopped at line {} of {}", first_line, path);
+                end = ScriptEnd::Failed;
+                break;
+            }
+        }
+        // a continued line at the end of the file is run as it is
+        if !buffer.is_empty() {
+            if !self.do_command(&buffer) {
+                end = ScriptEnd::Quit;
+            } else if COMMAND_FAILED.load(Acquire) && !keep_going {
+                println!("Stopped at line {} of {}", first_line, path);
+                end = ScriptEnd::Failed;
             }
         }
+        self.source_depth -= 1;
+        // the source command fails with the script, so an enclosing script stops too
+        COMMAND_FAILED.store(end == ScriptEnd::Failed, Release);
+        end
+    }
+
+    /// puts commands to recreate the stored REs and texts on the line history, so they can be recalled with up-arrow
+    fn seed_history(&self) {
+        for re in self.res.iter() {
+            add_history(&format!(
+                "regexp {} {}",
+                if re.alt_parser {
+                    "alternative"
+                } else {
+                    "traditional"
+                },
+                re.re
+            ));
+        }
+        for text in self.texts.iter() {
+            add_history(&format!("text set {}", text.text));
+        }
+    }
+
+    /// splits the entered string into words to get a command, and call **execute_command()** to do it. Return
+    /// *false* to exit.
+    pub(crate) fn do_command(&mut self, input: &str) -> bool {
+        COMMAND_FAILED.store(false, Release);
+        self.execute_command(&Words::new(input))
+    }
+
+    /// true if the last command run by **do_command()** failed
+    pub(crate) fn command_failed(&self) -> bool {
+        COMMAND_FAILED.load(Acquire)
     }
 
     /// executes the user commands
-    fn execute_command(&mut self, words: &Vec<&Report>) -> bool {
+    fn execute_command(&mut self, words: &Words) -> bool {
         if !words.is_empty() {
-            match Input::apply(|input| get_command(&COMMANDS, words[0].string(input))) {
+            match get_command(&COMMANDS, words.word(0)) {
                 "regexp" => self.do_re(words),
                 "text" => self.do_text(words),
                 "search" => self.do_search(words),
+                "results" => self.do_results(words),
+                "walk" if words.word(1) == "json" => {
+                    if words.len() == 3 {
+                        let _ = self.walk_json(words.word(2));
+                    } else {
+                        fail!("walk json FILE");
+                    }
+                }
+                "walk" => match int_arg(words, 1, self.options.trace) {
+                    Some(trace) => {
+                        let _ = self.walk(trace);
+                    }
+                    None => fail!("walk [NUMBER]"),
+                },
                 "help" | "?" => println!("{}", HELP_TEXT),
                 "quit" => {
                     return false;
                 }
                 "exit" => {
-                    return get_response("Really exit?", vec!["yes", "no"], 0) == "no";
+                    return get_response("Really exit?", vec!["yes", "no"], 0) != "yes";
                 }
                 "tree" => self.do_tree(words),
-                "unrecognized" => println!("unrecognized command"),
-                "ambiguous" => println!("ambiguous command"),
-                _ => (),
-            }
-        }
-        true
-    }
-
+                "explain" => self.do_explain(),
+                "replace" => {
+                    let _ = self.do_replace(words, false);
+                }
+                "replace!" => {
+                    let _ = self.do_replace(words, true);
+                }
+                "diff" => {
+                    if words.len() == 3 {
+                        let _ = self.diff(words.word(1), words.word(2));
+                    } else {
+                        fail!("diff RE1 RE2: each RE is a history number or name");
+                    }
+                }
+                "defs" => do_defs(words),
+                "set" => self.do_set(words),
+                "split" => split_demo(&input_substring(words, 1, usize::MAX)),
+                "syntax" => self.do_syntax(words),
+                "bench" => match int_arg(words, 1, 100) {
+                    Some(count) if count > 0 => {
+                        let _ = self.bench(count);
+                    }
+                    _ => fail!("bench [COUNT]"),
+                },
+                "save" => {
+                    let path = session_path(&rest_arg(words, 1));
+                    match self.save(&path) {
+                        Ok(()) => println!("Session saved to {}", path),
+                        Err(msg) => fail!("{}", msg),
+                    }
+                }
+                "load" => self.report_load(&session_path(&rest_arg(words, 1))),
+                "source" => {
+                    let keep_going = words.word(1) == "-k";
+                    let path = rest_arg(words, if keep_going { 2 } else { 1 });
+                    if path.is_empty() {
+                        fail!("source [-k] FILE");
+                    } else if self.source(&path, keep_going) == ScriptEnd::Quit {
+                        return false;
+                    }
+                }
+                "unrecognized" => fail!("unrecognized command"),
+                "ambiguous" => fail!("ambiguous command"),
+                _ => (),
+            }
+        }
+        true
+    }
+
+    /// executes a **syntax** command, printing the summary of the syntax given, or else that of the current RE
+    fn do_syntax(&self, words: &Words) {
+        let syntax = words.word(1);
+        let alt = match syntax {
+            "" => self.re().is_some_and(|re| re.alt_parser),
+            _ if "alternative".starts_with(syntax) => true,
+            _ if "traditional".starts_with(syntax) => false,
+            _ => {
+                fail!("syntax [traditional|alternative]");
+                return;
+            }
+        };
+        print!("{}", syntax_summary(alt));
+    }
+
     /// executes a **regexp** command
-    fn do_re(&mut self, words: &Vec<&Report>) {
+    fn do_re(&mut self, words: &Words) {
         let len = self.res.len();
         let subcmd = if words.len() > 1 {
-            Input::apply(|input| {
-                get_command(
-                    &["pop", "history", "list", "traditional", "alternative"],
-                    words[1].string(input),
-                )
-                .to_string()
-            })
+            get_command(&RE_SUBCOMMANDS, words.word(1))
         } else {
-            "".to_string()
+            ""
         };
-        match subcmd.as_str() {
+        match subcmd {
             "" => {
                 if let Some(re) = self.re() {
                     println!("current RE: {:?}", re);
@@ -278,7 +1346,7 @@ impl Interactive {
             "pop" => {
                 if let Some(num) = int_arg(words, 2, 0) {
                     if num >= len {
-                        println!(
+                        fail!(
                             "Only {} regular expression stored, value between 0 and {}",
                             len,
                             len - 1
@@ -292,7 +1360,7 @@ impl Interactive {
                         }
                     }
                 } else {
-                    println!("regexp pop [number]");
+                    fail!("regexp pop [number]");
                 }
             }
             "history" | "list" => {
@@ -300,46 +1368,73 @@ impl Interactive {
                     println!("No saved REs");
                 } else {
                     for i in 0..len {
-                        println!("  {}: {:?}", i, self.res[len - i - 1]);
+                        let re = &self.res[len - i - 1];
+                        println!("  {}: {}{:?}", i, slot_label(re), re);
                     }
                 }
             }
             "alternative" | "traditional" => {
                 if words.len() == 2 {
-                    println!("'re {}' requires regular expression", subcmd);
+                    fail!("'re {}' requires regular expression", subcmd);
                 } else {
-                    self.res.push(RegExp {
-                        re: input_substring(words, 2, 1000),
-                        alt_parser: subcmd == "alternative",
-                    });
+                    self.res.push(RegExp::new(
+                        input_substring(words, 2, 1000),
+                        subcmd == "alternative",
+                    ));
                 }
             }
-            "ambiguous" => println!("ambiguous subcommand"),
-            _ => {
-                if let Some(num) = int_arg(words, 1, 0) {
-                    // can't be default of 0, we know there is text in position 1
-                    if num >= len {
-                        println!("There are only {} REs stored", len);
-                    } else {
-                        let re = self.res.remove(len - 1 - num);
-                        println!("Using {:?}", re);
-                        self.res.push(re);
+            "name" => {
+                let name = words.word(2).to_string();
+                if name.is_empty() {
+                    fail!("regexp name NAME");
+                } else if self.res.is_empty() {
+                    fail!("No current RE to name");
+                } else if let Some(num) = name_slot(&mut self.res, &name) {
+                    if num > 0 {
+                        println!("Name {} moved from RE {}", name, num);
                     }
-                } else if let Some(re) = RegExp::guess_type(&input_substring(words, 1, 1000)) {
+                }
+            }
+            "use" => match find_slot(&self.res, words.word(2)) {
+                Some(num) => {
+                    let re = self.res.remove(len - 1 - num);
+                    println!("Using {:?}", re);
+                    self.res.push(re);
+                }
+                None => fail!("No RE with that name"),
+            },
+            "ambiguous" => fail!("ambiguous subcommand"),
+            _ => {
+                if let Some(num) = history_index(words, len) {
+                    let re = self.res.remove(len - 1 - num);
+                    println!("Using RE {} from the history: {:?}", num, re);
+                    self.res.push(re);
+                } else if words.len() == 2 && int_arg(words, 1, 0).is_some() {
+                    // a bare number is almost never meant as an RE, so don't guess
+                    fail!(
+                        "There are only {} REs stored, to search for the number use \"regexp traditional {}\"",
+                        len,
+                        words.word(1)
+                    );
+                } else if let Some(re) = match self.options.parser.as_str() {
+                    "guess" => RegExp::guess_type(&input_substring(words, 1, 1000)),
+                    parser => Some(RegExp::new(
+                        input_substring(words, 1, 1000),
+                        parser == "alternative",
+                    )),
+                } {
                     self.res.push(re);
                 } else {
-                    println!("Unrecognized re subcommand");
+                    fail!("Unrecognized re subcommand");
                 }
             }
         }
     }
 
     /// executes a *text* command
-    fn do_text(&mut self, words: &Vec<&Report>) {
+    fn do_text(&mut self, words: &Words) {
         let subcmd = if words.len() > 1 {
-            Input::apply(|input| {
-                get_command(&["pop", "history", "list", "set"], words[1].string(input))
-            })
+            get_command(&TEXT_SUBCOMMANDS, words.word(1))
         } else {
             ""
         };
@@ -355,102 +1450,334 @@ impl Interactive {
             "pop" => {
                 if let Some(num) = int_arg(words, 2, 0) {
                     if num >= len {
-                        println!("Only {} texts stored, value between 0 and {}", len, len - 1);
+                        fail!("Only {} texts stored, value between 0 and {}", len, len - 1);
                     } else {
                         let _ = self.texts.remove(len - 1 - num);
-                        if let Some(re) = self.re() {
-                            println!("current text is {:?}", re);
+                        if let Some(text) = self.text() {
+                            println!("current text is {:?}", text);
                         } else {
                             println!("No stored texts");
                         }
                     }
                 } else {
-                    println!("text pop [number]");
+                    fail!("text pop [number]");
+                }
+            }
+            "set" => self.push_text(input_substring(words, 2, 1000)),
+            "name" => {
+                let name = words.word(2).to_string();
+                if name.is_empty() {
+                    fail!("text name NAME");
+                } else if self.texts.is_empty() {
+                    fail!("No current text to name");
+                } else if let Some(num) = name_slot(&mut self.texts, &name) {
+                    if num > 0 {
+                        println!("Name {} moved from text {}", name, num);
+                    }
+                }
+            }
+            "use" => match find_slot(&self.texts, words.word(2)) {
+                Some(num) => {
+                    let text = self.texts.remove(len - 1 - num);
+                    println!("Using {:?}", text.text);
+                    self.texts.push(text);
+                }
+                None => fail!("No text with that name"),
+            },
+            "file" => {
+                let filename = rest_arg(words, 2);
+                if filename.is_empty() {
+                    fail!("text file PATH");
+                } else {
+                    match std::fs::read_to_string(&filename) {
+                        Ok(text) => self.push_text(text),
+                        Err(err) => fail!("Error reading file {}: {}", filename, err),
+                    }
+                }
+            }
+            "from-match" | "from-line" => {
+                let count = self.last_matches.len();
+                match int_arg(words, 2, 1) {
+                    _ if count == 0 => fail!("No matches kept from a \"search all\""),
+                    Some(num) if num >= 1 && num <= count => {
+                        let found = &self.last_matches[num - 1];
+                        let text = if subcmd == "from-match" {
+                            &found.text
+                        } else {
+                            &found.line
+                        };
+                        println!("Using {:?}", text);
+                        self.push_text(text.clone());
+                    }
+                    _ => fail!("text {} [N], where N is from 1 to {}", subcmd, count),
                 }
             }
-            "set" => self.texts.push(input_substring(words, 2, 1000)),
             "history" | "list" => {
                 if len == 0 {
                     println!("No saved texts");
                 } else {
                     for i in 0..len {
-                        println!("  {}: \"{}\"", i, self.texts[len - i - 1]);
+                        let text = &self.texts[len - i - 1];
+                        println!("  {}: {}\"{}\"", i, slot_label(text), text.text);
                     }
                 }
             }
             _ => {
-                if let Some(num) = int_arg(words, 1, 0) {
-                    if num >= len {
-                        println!("Number too large, no such text");
-                    } else {
-                        let text = self.texts.remove(len - 1 - num);
-                        println!("Using {:?}", text);
-                        self.texts.push(text);
-                    }
+                if let Some(num) = history_index(words, len) {
+                    let text = self.texts.remove(len - 1 - num);
+                    println!(
+                        "Using text {} from the history (use \"text set {}\" for the literal text): {:?}",
+                        num, num, text.text
+                    );
+                    self.texts.push(text);
                 } else {
-                    self.texts.push(input_substring(words, 1, 1000));
+                    let text = input_substring(words, 1, 1000);
+                    if words.len() == 2 && int_arg(words, 1, 0).is_some() {
+                        println!(
+                            "No text {} in the history, using {:?} as the text",
+                            words.word(1),
+                            text
+                        );
+                    }
+                    self.push_text(text);
+                }
+            }
+        }
+    }
+
+    /// executes a **replace** command: prints the current text with every match of the current RE replaced by the
+    /// template. If **push** is set the result also becomes the new current text. Returns the number of replacements.
+    fn do_replace(&mut self, words: &Words, push: bool) -> usize {
+        let template = input_substring(words, 1, 1000);
+        let (re, text) = match (self.re(), self.text()) {
+            (Some(re), Some(text)) => (re, text),
+            (None, Some(_)) => {
+                fail!("No current regular expression");
+                return 0;
+            }
+            (Some(_), None) => {
+                fail!("No current text");
+                return 0;
+            }
+            (None, None) => {
+                fail!("No regular expression or text, add some and try again");
+                return 0;
+            }
+        };
+        let node = match self.parse_re(re) {
+            Ok(node) => node,
+            Err(err) => {
+                fail!("Error parsing RE: {}", err.msg);
+                return 0;
+            }
+        };
+        if let Err(msg) = Input::init_text(text, false) {
+            fail!("{}", msg);
+            return 0;
+        }
+        match replace_all(&node, &template) {
+            Err(msg) => {
+                fail!("Error: {}", msg);
+                0
+            }
+            Ok((_, 0)) => {
+                println!("Warning: no matches, nothing replaced");
+                0
+            }
+            Ok((result, count)) => {
+                println!("{}", result);
+                println!("Made {} replacements", count);
+                if push {
+                    self.push_text(result);
+                }
+                count
+            }
+        }
+    }
+
+    /// Writes the RE and text stacks and the def table to **path** as JSON
+    pub(crate) fn save(&self, path: &str) -> Result<(), String> {
+        let session = Session {
+            res: self.res.clone(),
+            texts: self.texts.clone(),
+            defs: def_sources(),
+            options: self.options.clone(),
+        };
+        let json = serde_json::to_string_pretty(&session)
+            .map_err(|err| format!("Error saving session: {}", err))?;
+        std::fs::write(path, json).map_err(|err| format!("Error writing {}: {}", path, err))
+    }
+
+    /// Replaces the RE and text stacks with those saved in **path**, and adds the saved definitions to the def table.
+    /// Definitions and REs that no longer parse are dropped, and a description of each is returned.
+    pub(crate) fn load(&mut self, path: &str) -> Result<Vec<String>, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Error reading {}: {}", path, err))?;
+        let session: Session = serde_json::from_str(&json)
+            .map_err(|err| format!("Error loading session from {}: {}", path, err))?;
+        let mut dropped = Vec::<String>::new();
+        // definitions go first since the REs may use them
+        for def in session.defs.iter() {
+            if let Err(err) = load_defs(def) {
+                dropped.push(format!("definition {}: {}", def, err.msg));
+            }
+        }
+        self.res.clear();
+        for re in session.res.into_iter() {
+            match parse_tree(re.re.as_str(), re.alt_parser) {
+                Ok(_) => self.res.push(re),
+                Err(err) => dropped.push(format!("RE {:?}: {}", re, err.msg)),
+            }
+        }
+        self.texts = session.texts;
+        self.options = session.options;
+        Ok(dropped)
+    }
+
+    /// loads a session, telling the user what happened
+    fn report_load(&mut self, path: &str) {
+        match self.load(path) {
+            Ok(dropped) => {
+                println!(
+                    "Loaded {} regular expressions and {} texts from {}",
+                    self.res.len(),
+                    self.texts.len(),
+                    path
+                );
+                for msg in dropped {
+                    println!("  dropped {}", msg);
                 }
             }
+            Err(msg) => fail!("{}", msg),
         }
     }
 
     /// executes a **tree** command: parses and prints the tree for the current regular executes
-    fn do_tree(&self, words: &Vec<&Report>) {
-        let trace_level = if let Some(num) = int_arg(words, 1, 0) {
-            num
-        } else {
-            println!("'tree' takes an optional integer argument");
+    fn do_tree(&self, words: &Words) {
+        if words.len() == 2 && words.word(1) == "json" {
+            match self.re().map(|re| self.parse_re_with_warnings(re)) {
+                None => fail!("No current RE, first enter one"),
+                Some(Err(error)) => fail!("Error parsing tree: {}", error),
+                Some(Ok((node, _))) => println!("{}", node.to_ast().to_json()),
+            }
             return;
+        }
+        if words.len() >= 2 && words.word(1) == "expanded" {
+            let notes = match (words.len(), words.word(2)) {
+                (2, _) => false,
+                (3, "notes") => true,
+                _ => {
+                    fail!("'tree expanded' takes an optional argument 'notes'");
+                    return;
+                }
+            };
+            match self.re().map(|re| self.parse_re_with_warnings(re)) {
+                None => fail!("No current RE, first enter one"),
+                Some(Err(error)) => fail!("Error parsing tree: {}", error),
+                Some(Ok((node, _))) if notes => println!("{}", node.to_annotated_source()),
+                Some(Ok((node, _))) => println!("{}", node.to_alt_source()),
+            }
+            return;
+        }
+        let (trace_level, view) = match tree_args(words, self.options.trace) {
+            Ok(args) => args,
+            Err(msg) => {
+                fail!("{}", msg);
+                return;
+            }
         };
         if let Some(re) = self.re() {
             set_trace(trace_level);
-            match parse_tree(&re.re, re.alt_parser) {
-                Ok(node) => {
+            match self.parse_re_with_warnings(re) {
+                Ok((node, warnings)) => {
                     println!("--- Parse tree:");
-                    node.desc(0);
+                    view.print(&node);
+                    if self.options.warnings {
+                        warnings.iter().for_each(|warning| println!("{}", warning));
+                    }
+                }
+                Err(error) => {
+                    fail!("Error parsing tree: {}", error);
+                    if error.code == 108 {
+                        println!("Define the missing names with def() in the RE, or with 'defs load FILE'");
+                    }
                 }
-                Err(error) => println!("Error parsing tree: {}", error),
             }
             set_trace(0);
         } else {
-            println!("No current RE, first enter one");
+            fail!("No current RE, first enter one");
+        }
+    }
+
+    /// executes an **explain** command: describes the current RE, with its warnings if they are on
+    fn do_explain(&self) {
+        match self.re().map(|re| self.parse_re_with_warnings(re)) {
+            None => fail!("No current RE, first enter one"),
+            Some(Err(error)) => fail!("Error parsing tree: {}", error),
+            Some(Ok((node, warnings))) => {
+                let notes = if self.options.warnings {
+                    &warnings[..]
+                } else {
+                    &[][..]
+                };
+                println!("{}", node.explain(notes));
+            }
         }
     }
 
     /// executes a **search** command: parses and prints the results for the current regexp and text
-    fn do_search(&self, words: &Vec<&Report>) {
-        let mut trace: usize = 0;
+    fn do_search(&mut self, words: &Words) {
+        let mut trace = self.options.trace;
         let mut names = Vec::<String>::new();
         let mut all = false;
         let mut ptr = 1;
         if words.len() > 1 {
-            if let Ok(num) = Input::apply(|input| words[1].string(input).parse::<usize>()) {
+            if let Ok(num) = words.word(1).parse::<usize>() {
                 trace = num;
                 ptr += 1;
             }
-            for word in words[ptr..].iter() {
-                Input::apply_mut(|input| {
-                    if word.string(input) == "*" {
-                        all = true;
+            if words.word(ptr) == "file" {
+                let files: Vec<String> = words.tail(ptr + 1).map(|word| word.to_string()).collect();
+                if !files.is_empty() {
+                    self.files = files;
+                }
+                self.search_files(trace);
+                return;
+            }
+            if words.len() == ptr + 1 {
+                match words.word(ptr) {
+                    "all" => {
+                        self.search_all(trace, false);
+                        return;
+                    }
+                    "count" => {
+                        self.search_all(trace, true);
+                        return;
                     }
-                    names.push(word.string(input).to_string());
-                });
+                    _ => (),
+                }
+            }
+            for word in words.tail(ptr) {
+                if word == "*" {
+                    all = true;
+                }
+                names.push(word.to_string());
             }
         }
         match (self.re(), self.text()) {
-            (None, Some(_)) => println!("No current regular expression"),
-            (Some(_), None) => println!("No current text"),
-            (None, None) => println!("No regular expression or text, add some and try again"),
-            (Some(re), Some(text)) => match parse_tree(re.re.as_str(), re.alt_parser) {
-                Err(err) => println!("Error parsing RE: {}", err.msg),
+            (None, Some(_)) => fail!("No current regular expression"),
+            (Some(_), None) => fail!("No current text"),
+            (None, None) => fail!("No regular expression or text, add some and try again"),
+            (Some(re), Some(text)) => match self.parse_re(re) {
+                Err(err) => fail!("Error parsing RE: {}", err.msg),
                 Ok(node) => {
                     set_trace(trace);
                     if let Err(msg) = Input::init_text(text, false) {
-                        println!("{}", msg);
+                        fail!("{}", msg);
                         return;
                     }
-                    match walk_tree(&node, 0) {
-                        Err(msg) => println!("Error: {}", msg),
+                    match self.first_match(&node) {
+                        Err(msg) => fail!("Error: {}", msg),
                         Ok(None) => println!("No match"),
                         Ok(Some(path)) => {
                             let report = Report::new(&path);
@@ -461,12 +1788,8 @@ impl Interactive {
                                     print_named_match(name, &matches);
                                 }
                             } else {
-                                let reports = report.get_named();
-                                for name in names {
-                                    if let Some(matches) = reports.get(name.as_str()) {
-                                        print_named_match(&name, matches);
-                                    }
-                                }
+                                let keep: Vec<&str> = names.iter().map(String::as_str).collect();
+                                report.prune(&keep).display(0);
                             }
                         }
                     }
@@ -475,9 +1798,656 @@ impl Interactive {
             },
         }
     }
+
+    /// executes a **walk** command: runs the current RE against the current text like **search**, but dumps the
+    /// successful *Path* instead of the report. Returns *true* if a match was found.
+    pub(crate) fn walk(&self, trace: usize) -> bool {
+        match (self.re(), self.text()) {
+            (None, Some(_)) => fail!("No current regular expression"),
+            (Some(_), None) => fail!("No current text"),
+            (None, None) => fail!("No regular expression or text, add some and try again"),
+            (Some(re), Some(text)) => match self.parse_re(re) {
+                Err(err) => fail!("Error parsing RE: {}", err.msg),
+                Ok(node) => {
+                    if let Err(msg) = Input::init_text(text, false) {
+                        fail!("{}", msg);
+                        return false;
+                    }
+                    set_trace(trace);
+                    let result = self.first_match(&node);
+                    set_trace(0);
+                    match result {
+                        Err(msg) => fail!("Error: {}", msg),
+                        Ok(None) => {
+                            println!("No match, try 'search NUMBER' to trace the failing walk")
+                        }
+                        Ok(Some(path)) => {
+                            path.dump(0);
+                            return true;
+                        }
+                    }
+                }
+            },
+        }
+        false
+    }
+
+    /// Runs a **walk** with the trace written to **path** as JSON events, then reads the events back and prints the
+    /// tree of attempts the walk made. Returns the attempts, or None if the trace could not be written or read.
+    pub(crate) fn walk_json(&self, path: &str) -> Option<Vec<Attempt>> {
+        let file = match std::fs::File::create(path) {
+            Ok(file) => file,
+            Err(err) => {
+                fail!("Error creating {}: {}", path, err);
+                return None;
+            }
+        };
+        let previous = set_trace_writer(Some(Box::new(io::BufWriter::new(file))));
+        set_trace_format(TraceFormat::Json);
+        let _ = self.walk(self.options.trace.max(1));
+        set_trace_format(TraceFormat::Text);
+        // dropping the writer flushes the file
+        drop(set_trace_writer(previous));
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) => {
+                fail!("Error reading {}: {}", path, err);
+                return None;
+            }
+        };
+        let events = read_events(&json);
+        println!(
+            "--- {} trace events written to {}, attempts:",
+            events.len(),
+            path
+        );
+        let attempts = attempt_tree(&events);
+        attempts.iter().for_each(|attempt| attempt.print(0));
+        Some(attempts)
+    }
+
+    /// Times parsing the current RE and searching the current text with it, **count** times each after a warm up
+    /// run. Tracing is turned off so the output does not affect the times.
+    pub(crate) fn bench(&self, count: usize) -> Option<Bench> {
+        let (re, text) = match (self.re(), self.text()) {
+            (Some(re), Some(text)) => (re, text),
+            _ => {
+                fail!("bench needs both a regular expression and a text");
+                return None;
+            }
+        };
+        set_trace(0);
+        let node = match self.parse_re(re) {
+            Ok(node) => node,
+            Err(err) => {
+                fail!("Error parsing RE: {}", err.msg);
+                return None;
+            }
+        };
+        if let Err(msg) = Input::init_text(text, false) {
+            fail!("{}", msg);
+            return None;
+        }
+        // the warm up walk also gives the step counts, which are the same every time
+        reset_step_count();
+        let matched = match walk_tree(&node, 0) {
+            Ok(path) => path.is_some(),
+            Err(msg) => {
+                fail!("Error: {}", msg);
+                return None;
+            }
+        };
+        let (starts, steps, backtracks, allocations) = (
+            start_count(),
+            step_count(),
+            backtrack_count(),
+            allocation_count(),
+        );
+        let mut parse_times = Vec::<Duration>::new();
+        let mut walk_times = Vec::<Duration>::new();
+        for _i in 0..count {
+            let start = Instant::now();
+            let _ = self.parse_re_with_warnings(re);
+            parse_times.push(start.elapsed());
+            let start = Instant::now();
+            let _ = walk_tree(&node, 0);
+            walk_times.push(start.elapsed());
+        }
+        let bench = Bench {
+            parse: Timing::new(parse_times),
+            walk: Timing::new(walk_times),
+            starts,
+            steps,
+            backtracks,
+            allocations,
+            peak_buffered: Input::peak_buffered_bytes(),
+        };
+        println!(
+            "{} runs, {}",
+            count,
+            if matched { "match found" } else { "no match" }
+        );
+        println!("parse: {}", bench.parse);
+        println!("walk:  {}", bench.walk);
+        println!(
+            "starts: {}, steps: {}, backtracks: {}, allocations: {}, peak buffered: {} bytes",
+            bench.starts, bench.steps, bench.backtracks, bench.allocations, bench.peak_buffered
+        );
+        Some(bench)
+    }
+
+    /// Finds every match of the current RE in the current text. Unless **count_only** is set each match is printed,
+    /// numbered, with its position and any named units. Returns the number of matches.
+    pub(crate) fn search_all(&mut self, trace: usize, count_only: bool) -> usize {
+        let (re, text) = match (self.re(), self.text()) {
+            (Some(re), Some(text)) => (re, text),
+            (None, Some(_)) => {
+                fail!("No current regular expression");
+                return 0;
+            }
+            (Some(_), None) => {
+                fail!("No current text");
+                return 0;
+            }
+            (None, None) => {
+                fail!("No regular expression or text, add some and try again");
+                return 0;
+            }
+        };
+        let node = match self.parse_re(re) {
+            Ok(node) => node,
+            Err(err) => {
+                fail!("Error parsing RE: {}", err.msg);
+                return 0;
+            }
+        };
+        if let Err(msg) = Input::init_text(text, false) {
+            fail!("{}", msg);
+            return 0;
+        }
+        set_trace(trace);
+        let mut count = 0;
+        let mut found = Vec::new();
+        // with multiline off the matches are found a line at a time, so none runs past the end of its line
+        let reports: Box<dyn Iterator<Item = Result<Report, Error>>> = if self.options.multiline {
+            Box::new(find_iter(&node))
+        } else {
+            Box::new(search_lines(&node).flat_map(|line| match line {
+                Ok((_, _, reports)) => reports.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err)],
+            }))
+        };
+        self.with_timeout(|| {
+            for report in reports {
+                match report {
+                    Err(msg) => {
+                        fail!("Error: {}", msg);
+                        break;
+                    }
+                    Ok(report) => {
+                        count += 1;
+                        if !count_only {
+                            let (start, end) = report.byte_pos();
+                            let line_start = text[..start].rfind('\n').map_or(0, |pos| pos + 1);
+                            let line_end =
+                                text[end..].find('\n').map_or(text.len(), |pos| end + pos);
+                            found.push(FoundMatch {
+                                text: report.text(),
+                                line: text[line_start..line_end].to_string(),
+                            });
+                            print!("{}: ", count);
+                            print_one_named_match(&report, "");
+                            let named = report.get_named();
+                            for (name, matches) in named.iter().filter(|(name, _)| !name.is_empty())
+                            {
+                                print!("    ");
+                                print_named_match(name, matches);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        set_trace(0);
+        if count_only {
+            println!("{}", count);
+        } else {
+            if count == 0 {
+                println!("No match");
+            }
+            self.last_matches = found;
+        }
+        count
+    }
+
+    /// Searches the files from the last **search file** command with the current RE, printing every match along
+    /// with its file name and line number, a screenful at a time (see **show_paged()**). The files are read through
+    /// **Input**, so they are not held in memory any more than needed; the matches are kept for the **results**
+    /// command. Returns the number of matches found.
+    pub(crate) fn search_files(&mut self, trace: usize) -> usize {
+        if self.files.is_empty() {
+            fail!("No files to search: search file PATH [PATH...]");
+            return 0;
+        }
+        let re = if let Some(re) = self.re() {
+            re
+        } else {
+            fail!("No current regular expression");
+            return 0;
+        };
+        let node = match self.parse_re(re) {
+            Ok(node) => node,
+            Err(err) => {
+                fail!("Error parsing RE: {}", err.msg);
+                return 0;
+            }
+        };
+        if let Err(msg) = Input::init_files(&self.files, true) {
+            Input::skipped()
+                .iter()
+                .for_each(|(source, error)| println!("{}: {}", source, error));
+            fail!("{}", msg);
+            return 0;
+        }
+        set_trace(trace);
+        let mut errors = Vec::new();
+        let mut hits = Vec::new();
+        let stats = self.with_timeout(|| {
+            SearchDriver::new(&node)
+                .all()
+                .run(&mut |event: &MatchEvent| match event {
+                    MatchEvent::Match(found) => {
+                        let mut detail = Vec::new();
+                        let _ = found.report.display_to(&mut detail, 0);
+                        hits.push(FileHit {
+                            file: found.file.unwrap_or("").to_string(),
+                            line: found.line,
+                            text: Input::apply(|input| {
+                                Input::display_text(found.path.match_display(input)).into_owned()
+                            }),
+                            detail: String::from_utf8_lossy(&detail).into_owned(),
+                        });
+                    }
+                    MatchEvent::SearchFailed(error) => fail!("Error: {}", error),
+                    MatchEvent::FileErrored(source, error) => {
+                        errors.push(format!("{}: {}", source, error))
+                    }
+                    _ => (),
+                })
+        });
+        set_trace(0);
+        self.last_results = hits;
+        self.show_results();
+        errors.iter().for_each(|error| println!("{}", error));
+        println!("Found {} matches", stats.matches);
+        stats.matches
+    }
+
+    /// executes a **results** command: lists the matches from the last **search file** again, or with a number
+    /// shows that one in full
+    fn do_results(&self, words: &Words) {
+        if self.last_results.is_empty() {
+            fail!("No results: the last search file found nothing, or there has not been one");
+            return;
+        }
+        match words.len() {
+            1 => self.show_results(),
+            2 => match words.word(1).parse::<usize>() {
+                Ok(number) if number > 0 && number <= self.last_results.len() => {
+                    let hit = &self.last_results[number - 1];
+                    println!("{}", hit.summary(number));
+                    print!("{}", hit.detail);
+                }
+                _ => fail!(
+                    "results NUMBER: NUMBER is from 1 to {}",
+                    self.last_results.len()
+                ),
+            },
+            _ => fail!("results [NUMBER]"),
+        }
+    }
+
+    /// lists the matches from the last **search file**
+    fn show_results(&self) {
+        let lines: Vec<String> = self
+            .last_results
+            .iter()
+            .enumerate()
+            .map(|(i, hit)| hit.summary(i + 1))
+            .collect();
+        self.show_paged(&lines);
+    }
+
+    /// Prints LINES a screenful at a time, asking after each one whether to go on (see **PagerKey**). Scripts and
+    /// sessions not run from a terminal get them all at once.
+    fn show_paged(&self, lines: &[String]) {
+        if self.source_depth > 0 || !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            lines.iter().for_each(|line| println!("{}", line));
+            return;
+        }
+        // leave a line for the prompt
+        let mut pages = Pages::new(lines.len(), terminal_height().saturating_sub(1));
+        while let Some(page) = pages.next() {
+            lines[page].iter().for_each(|line| println!("{}", line));
+            if pages.done() {
+                break;
+            }
+            loop {
+                match read_line(PAGER_PROMPT).as_deref().map(PagerKey::parse) {
+                    None | Some(Some(PagerKey::Quit)) => return,
+                    Some(Some(PagerKey::Next)) => break,
+                    Some(Some(PagerKey::Jump(number))) => {
+                        pages.jump(number);
+                        break;
+                    }
+                    Some(None) => (),
+                }
+            }
+        }
+    }
+
+    /// Finds an RE from the history by number or by name
+    fn lookup_re(&self, arg: &str) -> Option<&RegExp> {
+        let num = match arg.parse::<usize>() {
+            Ok(num) => num,
+            Err(_) => find_slot(&self.res, arg)?,
+        };
+        self.res.iter().rev().nth(num)
+    }
+
+    /// Runs two REs from the history (by number or name) against the current text in find-all mode and prints the
+    /// matches found by only the first, only the second, and both. Matches common to both are also checked for
+    /// differences in their named units.
+    pub(crate) fn diff(&self, first: &str, second: &str) -> Option<Diff> {
+        let text = match self.text() {
+            Some(text) => text,
+            None => {
+                fail!("No current text");
+                return None;
+            }
+        };
+        let mut found = Vec::<Vec<DiffMatch>>::new();
+        for arg in [first, second] {
+            let re = match self.lookup_re(arg) {
+                Some(re) => re,
+                None => {
+                    fail!("No RE {} in the history", arg);
+                    return None;
+                }
+            };
+            match self
+                .parse_re(re)
+                .map_err(|err| err.msg)
+                .and_then(|node| all_matches(&node, text))
+            {
+                Ok(matches) => found.push(matches),
+                Err(msg) => {
+                    fail!("{}: {}", arg, msg);
+                    return None;
+                }
+            }
+        }
+        let (matches1, matches2) = (&found[0], &found[1]);
+        let span_in = |m: &DiffMatch, matches: &[DiffMatch]| {
+            matches.iter().find(|x| x.span == m.span).cloned()
+        };
+        let mut diff = Diff::default();
+        for m in matches1.iter() {
+            match span_in(m, matches2) {
+                Some(other) => {
+                    if other.named != m.named {
+                        diff.group_differences.push(m.span);
+                    }
+                    diff.both.push(m.span);
+                }
+                None => diff.only_first.push(m.span),
+            }
+        }
+        diff.only_second = matches2
+            .iter()
+            .filter(|m| span_in(m, matches1).is_none())
+            .map(|m| m.span)
+            .collect();
+
+        for (label, spans) in [
+            (format!("Only {}", first), &diff.only_first),
+            (format!("Only {}", second), &diff.only_second),
+        ] {
+            println!("{}: {} match(es)", label, spans.len());
+            for span in spans {
+                print_highlighted(text, *span);
+            }
+        }
+        println!("Both: {} match(es)", diff.both.len());
+        for span in diff.both.iter() {
+            println!("    {}", span_display(text, *span));
+        }
+        for span in diff.group_differences.iter() {
+            let named = |matches: &[DiffMatch]| {
+                matches
+                    .iter()
+                    .find(|m| m.span == *span)
+                    .map(|m| format!("{:?}", m.named))
+                    .unwrap_or_default()
+            };
+            println!(
+                "Named units differ for {}: {} {} vs {} {}",
+                span_display(text, *span),
+                first,
+                named(matches1),
+                second,
+                named(matches2)
+            );
+        }
+        Some(diff)
+    }
+}
+
+/// One match found by **diff**: its char span and its named units as (name, matched string) pairs
+#[derive(Clone)]
+struct DiffMatch {
+    span: (usize, usize),
+    named: Vec<(String, String)>,
+}
+
+/// Results of a **diff** command. All spans are [start, end) in chars.
+#[derive(Debug, Default)]
+pub(crate) struct Diff {
+    pub(crate) only_first: Vec<(usize, usize)>,
+    pub(crate) only_second: Vec<(usize, usize)>,
+    pub(crate) both: Vec<(usize, usize)>,
+    /// spans matched by both REs but with different named units
+    pub(crate) group_differences: Vec<(usize, usize)>,
+}
+
+/// Finds all matches of an RE in a text, recording what **diff** needs to compare them
+fn all_matches(node: &Node, text: &str) -> Result<Vec<DiffMatch>, String> {
+    Input::init_text(text, false).map_err(|err| err.msg)?;
+    let mut matches = Vec::new();
+    for report in find_iter(node) {
+        let report = report.map_err(|err| err.msg)?;
+        let mut named: Vec<(String, String)> = Input::apply(|input| {
+            report
+                .get_named()
+                .into_iter()
+                .filter(|(name, _)| !name.is_empty())
+                .flat_map(|(name, reports)| {
+                    reports
+                        .into_iter()
+                        .map(|r| (name.to_string(), r.string(input).to_string()))
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        });
+        named.sort();
+        matches.push(DiffMatch {
+            span: report.char_pos(),
+            named,
+        });
+    }
+    Ok(matches)
+}
+
+/// Formats a char span of the text for display
+fn span_display(text: &str, span: (usize, usize)) -> String {
+    let matched: String = text.chars().skip(span.0).take(span.1 - span.0).collect();
+    format!("{:?}, char position [{}, {})", matched, span.0, span.1)
+}
+
+/// Prints a span along with the line of the text containing it, with the span marked underneath
+fn print_highlighted(text: &str, span: (usize, usize)) {
+    println!("    {}", span_display(text, span));
+    let chars: Vec<char> = text.chars().collect();
+    let line_start = chars[..span.0]
+        .iter()
+        .rposition(|ch| *ch == '\n')
+        .map_or(0, |pos| pos + 1);
+    let line_end = chars[span.0..]
+        .iter()
+        .position(|ch| *ch == '\n')
+        .map_or(chars.len(), |pos| span.0 + pos);
+    // the carets are placed by the printed text, where a control character takes up more than one place
+    let printed = |from: usize, to: usize| {
+        Input::display_text(&chars[from..to].iter().collect::<String>()).into_owned()
+    };
+    let end = span.1.clamp(span.0, line_end);
+    println!("        {}", printed(line_start, line_end));
+    println!(
+        "        {}{}",
+        " ".repeat(printed(line_start, span.0).chars().count()),
+        "^".repeat(printed(span.0, end).chars().count().max(1))
+    );
+}
+
+/// Timing summary for one phase of a benchmark
+#[derive(Debug)]
+pub(crate) struct Timing {
+    pub(crate) min: Duration,
+    pub(crate) median: Duration,
+    pub(crate) mean: Duration,
+}
+
+impl Timing {
+    fn new(mut samples: Vec<Duration>) -> Timing {
+        samples.sort();
+        Timing {
+            min: samples[0],
+            median: samples[samples.len() / 2],
+            mean: samples.iter().sum::<Duration>() / samples.len() as u32,
+        }
+    }
+}
+
+impl core::fmt::Display for Timing {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "min {:?}, median {:?}, mean {:?}",
+            self.min, self.median, self.mean
+        )
+    }
+}
+
+/// Results of a **bench** command
+#[derive(Debug)]
+pub(crate) struct Bench {
+    pub(crate) parse: Timing,
+    pub(crate) walk: Timing,
+    pub(crate) starts: usize,
+    pub(crate) steps: usize,
+    pub(crate) backtracks: usize,
+    pub(crate) allocations: usize,
+    pub(crate) peak_buffered: usize,
+}
+
+/// executes a **split** command: shows how **line** is broken into words, both by **split_command()** and by
+/// searching it with **CMD_PARSE_ALT_RE**
+fn split_demo(line: &str) {
+    println!("split_command:");
+    for (word, range) in split_command(line) {
+        println!("    {:?} {:?}", word, range);
+    }
+    println!("{}:", CMD_PARSE_ALT_RE);
+    match self_hosted_split(line) {
+        Ok(words) => words
+            .iter()
+            .for_each(|(word, range)| println!("    {:?} {:?}", word, range)),
+        Err(msg) => println!("    {}", msg),
+    }
+}
+
+/// splits a line into words by searching it with **CMD_PARSE_ALT_RE**, the way commands used to be parsed
+pub(crate) fn self_hosted_split(line: &str) -> Result<Vec<(String, Range<usize>)>, String> {
+    let tree = parse_tree(CMD_PARSE_ALT_RE, true).map_err(|err| err.msg)?;
+    Input::init_text(line, false).map_err(|err| err.msg)?;
+    match walk_tree(&tree, 0).map_err(|err| err.msg)? {
+        Some(path) => {
+            let report = Report::new(&path);
+            Ok(Input::apply(|input| {
+                report
+                    .get_by_name("words")
+                    .iter()
+                    .map(|word| {
+                        let (start, end) = word.byte_pos();
+                        (word.string(input).to_string(), start..end)
+                    })
+                    .collect()
+            }))
+        }
+        None => Ok(Vec::new()),
+    }
 }
 
-fn print_named_match(name: &str, matches: &Vec<&Report>) {
+/// executes a **defs** command, which manages the table of definitions made with **def()** and **use()**
+fn do_defs(words: &Words) {
+    let subcmd = if words.len() > 1 {
+        get_command(&DEFS_SUBCOMMANDS, words.word(1))
+    } else {
+        "list"
+    };
+    let arg = rest_arg(words, 2);
+    match subcmd {
+        "list" => {
+            let names = def_names();
+            if names.is_empty() {
+                println!("No definitions");
+            }
+            for name in names {
+                let source = def_source(&name).unwrap_or_default();
+                println!(
+                    "{}: {}",
+                    name,
+                    source.split_whitespace().collect::<Vec<&str>>().join(" ")
+                );
+            }
+        }
+        "show" => match get_def(&arg) {
+            Some(node) => {
+                println!("{}:", arg);
+                node.desc(0);
+            }
+            None => fail!("No definition for {}", arg),
+        },
+        // not a guard: the delete is done for its effect, so it belongs in the body
+        #[allow(clippy::collapsible_match)]
+        "delete" => {
+            if !delete_def(&arg) {
+                fail!("No definition for {}", arg);
+            }
+        }
+        "clear" => clear_defs(),
+        "load" => {
+            if let Err(err) = load_def_file(&arg) {
+                fail!("{}", err);
+            }
+        }
+        "unrecognized" => fail!("Unrecognized defs subcommand"),
+        "ambiguous" => fail!("Ambiguous defs subcommand"),
+        _ => (),
+    }
+}
+
+fn print_named_match(name: &str, matches: &[&Report]) {
     if matches.len() == 1 {
         print!("{}: ", name);
         print_one_named_match(matches.last().unwrap(), "");
@@ -495,7 +2465,7 @@ fn print_one_named_match(report: &Report, prefix: &str) {
         println!(
             "{}\"{}\", byte position ({}, {}], char position [{}, {})",
             prefix,
-            report.string(input),
+            Input::display_text(report.string(input)),
             byte_pos.0,
             byte_pos.1,
             char_pos.0,
@@ -504,58 +2474,90 @@ fn print_one_named_match(report: &Report, prefix: &str) {
     });
 }
 
+/// asks the user to confirm leaving the session, returns *true* to exit
+pub(crate) fn confirm_exit<R: BufRead>(reader: &mut R) -> bool {
+    get_response_from(
+        |prompt| read_plain_line(reader, prompt),
+        "Really exit?",
+        vec!["yes", "no"],
+        0,
+    ) == "yes"
+}
+
 /// gets user response to a question. It takes a list of potential reply strings and returns
 /// the matching string if there is one
 /// - prompt: prompt to display to user
 /// - choices: acceptable choices
 /// - dflt: the default value to choose for empty input
 fn get_response<'a>(prompt: &'a str, choices: Vec<&'a str>, dflt: usize) -> &'a str {
-    let mut buffer = String::new();
-    let stdin = io::stdin();
+    get_response_from(read_line, prompt, choices, dflt)
+}
+
+/// prompts for one of **choices**, getting the response from **read**. An empty response (or end of input)
+/// takes the default
+fn get_response_from<'a, F: FnMut(&str) -> Option<String>>(
+    mut read: F,
+    prompt: &'a str,
+    choices: Vec<&'a str>,
+    dflt: usize,
+) -> &'a str {
     let dflt_choice = if dflt < choices.len() {
         format!(" (default {})", choices[dflt])
     } else {
         "".to_string()
     };
     loop {
-        print!("{}{}:", prompt, dflt_choice);
-        std::io::stdout().flush().unwrap();
-        if let Ok(count) = stdin.read_line(&mut buffer) {
-            if count < 2 {
-                break;
-            } // take default
-            let _ = buffer.pop();
-            let mut candidate: Option<&str> = None;
-            for c in choices.iter() {
-                if c.starts_with(buffer.as_str()) {
-                    if candidate.is_none() {
-                        candidate = Some(c);
-                    } else {
-                        println!("ambiguous response");
-                    }
-                }
+        let response = match read(&format!("{}{}:", prompt, dflt_choice)) {
+            Some(response) => response,
+            None => {
+                println!();
+                // nothing more is coming, so take the default or give up
+                return choices.get(dflt).copied().unwrap_or("");
             }
-            if let Some(cmd) = candidate {
-                return cmd;
-            } else {
-                println!("Unrecognized command");
+        };
+        if response.is_empty() {
+            if dflt < choices.len() {
+                return choices[dflt];
+            }
+            println!("no default response");
+            continue;
+        }
+        let mut candidate: Option<&str> = None;
+        for c in choices.iter() {
+            if c.starts_with(response.as_str()) {
+                if candidate.is_none() {
+                    candidate = Some(c);
+                } else {
+                    println!("ambiguous response");
+                }
             }
-            buffer = "".to_string()
+        }
+        if let Some(cmd) = candidate {
+            return cmd;
         } else {
-            println!("error reading input");
+            println!("Unrecognized command");
         }
     }
-    if dflt < choices.len() {
-        return choices[dflt];
+}
+
+/// gets the file to use for **save** and **load**, defaulting to ~/.regexp_session
+fn session_path(path: &str) -> String {
+    if !path.is_empty() {
+        return path.to_string();
+    }
+    match home::home_dir() {
+        Some(home) => home.join(SESSION_FILE).display().to_string(),
+        None => SESSION_FILE.to_string(),
     }
-    println!("no default response");
-    get_response(prompt, choices, dflt)
 }
 
 /// parses the command from the possibly abbreviated version passed in
 fn get_command(candidates: &'static [&str], cmd: &str) -> &'static str {
     if cmd.is_empty() {
         ""
+    } else if let Some(exact) = candidates.iter().find(|x| **x == cmd) {
+        // needed when one command is a prefix of another
+        exact
     } else {
         match candidates
             .iter()
@@ -569,26 +2571,150 @@ fn get_command(candidates: &'static [&str], cmd: &str) -> &'static str {
     }
 }
 
-/// gets the raw input string from the user input, retaining all whitespace characters
-fn input_substring(words: &Vec<&Report>, from: usize, to: usize) -> String {
+/// A command line broken into words by **split_command()**. The line is kept so REs and texts can be taken from it
+/// exactly as they were typed.
+pub(crate) struct Words<'a> {
+    line: &'a str,
+    words: Vec<(String, Range<usize>)>,
+}
+
+impl<'a> Words<'a> {
+    pub(crate) fn new(line: &'a str) -> Words<'a> {
+        Words {
+            line,
+            words: split_command(line),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// gets a word with its quotes and escapes removed, or "" if there are not that many words
+    pub(crate) fn word(&self, num: usize) -> &str {
+        self.words.get(num).map_or("", |(word, _)| word.as_str())
+    }
+
+    /// iterates over the words starting with word number **from**
+    fn tail(&self, from: usize) -> impl Iterator<Item = &str> {
+        self.words.iter().skip(from).map(|(word, _)| word.as_str())
+    }
+}
+
+/// Breaks a command line into words separated by whitespace, returning each word along with its byte range in the
+/// line. Inside single quotes everything is literal, inside double quotes a backslash escapes the next character,
+/// and outside of quotes a backslash escapes the next character. Quotes and escapes are removed from the words,
+/// and quoted parts join with whatever they touch. A quote with no closing quote runs to the end of the line, so
+/// an RE containing a quote can still be entered.
+pub(crate) fn split_command(line: &str) -> Vec<(String, Range<usize>)> {
+    let mut words = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        if ch.is_whitespace() {
+            continue;
+        }
+        let mut word = String::new();
+        let mut quote: Option<char> = None;
+        let mut next = Some((start, ch));
+        let mut end = line.len();
+        while let Some((pos, ch)) = next {
+            match (quote, ch) {
+                (None, ch) if ch.is_whitespace() => {
+                    end = pos;
+                    break;
+                }
+                (None, '\'') | (None, '"') => quote = Some(ch),
+                (Some(q), ch) if q == ch => quote = None,
+                (None, '\\') | (Some('"'), '\\') => match chars.next() {
+                    Some((_, escaped)) => word.push(escaped),
+                    None => word.push(ch),
+                },
+                _ => word.push(ch),
+            }
+            next = chars.next();
+        }
+        words.push((word, start..end));
+    }
+    words
+}
+
+/// gets the raw input string from the user input, retaining all whitespace characters and quotes
+fn input_substring(words: &Words, from: usize, to: usize) -> String {
     let len = words.len();
     if from >= len {
         "".to_string()
     } else {
-        let r0 = words[from];
-        let r1 = if to < len { words[to] } else { words[len - 1] };
-        Input::apply(|input| input.full_text[r0.byte_pos().0..r1.byte_pos().1].to_string())
+        let start = words.words[from].1.start;
+        let end = words.words[to.min(len - 1)].1.end;
+        words.line[start..end].to_string()
+    }
+}
+
+/// gets an argument that is the rest of the line, such as a file name: a single word is used with its quotes
+/// removed, several are taken as typed
+fn rest_arg(words: &Words, from: usize) -> String {
+    if words.len() == from + 1 {
+        words.word(from).to_string()
+    } else {
+        input_substring(words, from, usize::MAX)
+    }
+}
+
+/// formats a boolean option for display
+fn on_off_str(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// returns the history index named by the command, which must consist of the command and a single bare integer less
+/// than **len**. Anything else is content, not an index.
+fn history_index(words: &Words, len: usize) -> Option<usize> {
+    if words.len() != 2 {
+        return None;
+    }
+    words.word(1).parse::<usize>().ok().filter(|num| *num < len)
+}
+
+/// gets the arguments of a **tree** command, an optional trace level (DFLT if it is not given) followed by "depth N"
+/// and "filter NAME" in either order
+fn tree_args(words: &Words, dflt: usize) -> Result<(usize, TreeView), String> {
+    let usage = "'tree' takes an optional integer argument, then 'depth N' and 'filter NAME'";
+    let mut arg = 1;
+    let trace_level = match words.word(1).parse::<usize>() {
+        Ok(level) => {
+            arg = 2;
+            level
+        }
+        Err(_) => dflt,
+    };
+    let mut view = TreeView::new();
+    while arg < words.len() {
+        match (words.word(arg), words.word(arg + 1)) {
+            ("depth", depth) => match depth.parse::<usize>() {
+                Ok(depth) if depth > 0 => view = view.depth(depth),
+                _ => return Err("'depth' takes a number greater than 0".to_string()),
+            },
+            ("filter", name) if !name.is_empty() => view = view.filter(name),
+            _ => return Err(usage.to_string()),
+        }
+        arg += 2;
     }
+    Ok((trace_level, view))
 }
 
 /// tries to interpret the given argument as an int
-fn int_arg(words: &Vec<&Report>, arg_num: usize, dflt: usize) -> Option<usize> {
-    let arg = input_substring(words, arg_num, arg_num);
+fn int_arg(words: &Words, arg_num: usize, dflt: usize) -> Option<usize> {
+    let arg = words.word(arg_num);
     if arg.is_empty() {
         Some(dflt)
-    } else if let Ok(num) = arg.parse::<usize>() {
-        Some(num)
     } else {
-        None
+        arg.parse::<usize>().ok()
     }
 }
@@ -25,8 +25,14 @@
 //!    - **\(...\)**: takes everything inside the escaped parens as a sub-regular expression. And groups can show up in the result optionally identified by a name or not, or can be hidden from the results
 //!    - **\(?...\)**: a hidden group, it will not be recorded in the search results
 //!    - **\(?\<NAME\>...\)**: Matches will be reported in the Report structure associated with NAME, to make it easier to find
+//!    - **\(?\<NAME+\>...\)**: the same, but if the group is repeated all the repetitions are reported as a single block
+//!      instead of one block each (like **and(...)+\<NAME\>** in the alternative syntax)
 //!  - **or groups**
-//!    -**A\|B** matches either the regular expression A or the regular expression B
+//!    - **A\|B** matches either the regular expression A or the regular expression B. A and B are the single units on
+//!      either side, so for characters only the one next to the **\|** is included: **abc\|def** matches "abdef" or "abcef"
+//!    - **(?g)** at the very front of the RE (before any **^**) makes the OR take the whole string of characters on
+//!      each side instead, as other engines do: **(?g)abc\|def** matches "abc" or "def". A repetition still only applies
+//!      to the character before it.
 //!  - **repetition counts**: any expression can beexecuted multiple times by suffixing it with a repetition code
 //!    - __*__: match any number of times from 0 up
 //!    - **+**: match any number of times from 1 up
@@ -35,12 +41,26 @@
 //!    - **{N,}**: match N or more times
 //!    - **{N,M}**: match any number of repititions from M to N
 //!
+//! A '{' that does not start a well formed count is an error, and the message says which part is wrong: a count
+//! that does not start with a number (7), a first number not followed by ',' or '}' (13), a second number not
+//! followed by '}' (8), or a count that runs off the end of the RE (14). With **--literal-braces** a '{' that is
+//! not followed by a digit matches itself instead, so "a{x}" finds the text "a{x}".
+//!
+//! A **\)** with no group open ends the RE early, and the text after it is an error (6) that shows where the
+//! leftover text starts. With **--lenient** the leftover text is added to the end of the RE as literal text instead,
+//! the **\)** as a ')', with a warning, so "ab\)c" finds "ab)c". The same goes for a ')' in the alternative syntax.
+//!
 //! By default this uses a greedy search algorithm: it always matches as many times as possible and backs off if needed.
 //! Any repetition code can be directed to use a lazy algorithm by suffixing it with '?'. (ie "*?, +?, ??, etc.) Lazy
 //! evaluation first matches the smalles number allowed and adds extra instances if allowed as needed.
 //!
 //! Putting "\c" at the front of a string ignores case for that match only.
 //!
+//! **\n**, **\t**, **\r** and **\0** stand for a newline, tab, carriage return and NUL char wherever they are
+//! written: in strings, in ranges, and in the alternative syntax in the quoted strings of **any()**, **none()**,
+//! **upto()** and **until()**. Any other escaped character that is not special, like **\\** or **\***, matches
+//! itself, and can be repeated like any other character: **a\.+** matches "a...".
+//!
 //! ## Alternate RE syntax
 //!
 //! In addition to the standard(ish) regular expressions, there is an alternative style regular expression syntax supported.
@@ -50,28 +70,39 @@
 //! I ran across elisp code where the writer created  macros to make understanding long, complicated REs easier.
 //! Putting this together I designed a simpler regexp syntax, which made writing a parser to support it much simpler. The basic syntax:
 //!
-//! - There are 3 kinds of nodes: **AND** nodes, **OR** nodes, and **CHAR** nodes.
+//! - There are 4 kinds of nodes: **AND** nodes, **OR** nodes, **SET** nodes and **CHAR** nodes.
 //!   - **AND** nodes search for all subnides sequentially. They are created by using the notation **and(...)**
 //!   - **OR** nodes search for one of the subnodes to succeed. are created by using the syntax "**or(...)**"
+//!     The report for a named OR tells which branch matched, as in "**<animal> (branch 2 of 3)**", and
+//!     **Report::branch** holds it, so the branches do not each need a name.
+//!   - **SET** nodes, **any('...')** and **none('...')**, match a single character that is (or for **none** is not) one of
+//!     the characters in the quoted string. Unlike a range there is nothing special inside but the backslash, so
+//!     **any('-^]')** matches any of '-', '^' and ']'. Special characters like **\d** cannot be used in a set.
+//!   - **upto('...')** matches any single character except where the quoted string starts, so it can stop before a
+//!     sequence of characters the way **none()** stops before a single one. **until('...')** is the same as
+//!     **upto('...')\***, the longest run of characters before the string, or to the end of the text if it is not
+//!     there: **"/\*" until('\*/')<body> "\*/"** matches a C comment and reports its text as **body**.
 //!   - **CHAR** nodes match a sring of chars or special chars explicitly.
 //!     - They can be written with four different notations:
 //!       - explicitly wrapping text with the **txt** tag: **txt(...)**
 //!       - Wrapping the text in single quotation marks: **'...'**
 //!       - Wrapping the text in double quotation marks: **"..."**
 //!       - any text entered that is not included in some other tag is assumed to be text. This form, while simple,
-//!          can have unexpected behavior: first, whitespace acts to terminate a node rather than being embedded in it, so
-//!          "**AB CD**" matched "_ABCD_" while "**"AB CD"**" matches "AB CD". Also, there must be a terminating space. For
-//!          example, while "**and('abc')**" is an **and** node containing the single subnode "abc", "**and(abc)**" will fail
-//!          to parse because it will be interpreted as an **and** node containing a **text** node "abc)", and so be missing
-//!          a node terminator.
+//!         can have unexpected behavior: first, whitespace acts to terminate a node rather than being embedded in it, so
+//!         "**AB CD**" matched "_ABCD_" while "**"AB CD"**" matches "AB CD". Also, there must be a terminating space. For
+//!         example, while "**and('abc')**" is an **and** node containing the single subnode "abc", "**and(abc)**" will fail
+//!         to parse because it will be interpreted as an **and** node containing a **text** node "abc)", and so be missing
+//!         a node terminator.
 //!     - **char** nodes can contain:
 //!       - regular characters: any character that has no other meaning in its context. These match exactly.
 //!       - special characters (in context): there are some characters with special meaning inside definitions. These need to be
-//!           escaped (using backslash) to include them in the series. These include repetition characters ('?', '*', '+', '{'),
-//!           '[' used to open a range, and the terminating character, ')', '"', ''', or whitespace, depending on how the node is defined.
+//!         escaped (using backslash) to include them in the series. These include repetition characters ('?', '*', '+', '{'),
+//!         '[' used to open a range, and the terminating character, ')', '"', ''', or whitespace, depending on how the node is defined.
 //!       - repetition: repetitions can be attached to individual characters and ranges inside **char** nodes simply by including the
-//!           the repetition definition character(s). These refer to the single character or range preceding the repetition count, and
-//!           cannot be named.
+//!         the repetition definition character(s). These refer to the single character or range preceding the repetition count.
+//!         They can be named by putting the name right after the repetition (**'ab\d+<num>cd'** names all the digits) or right
+//!         before it (**'ab\d<num>+cd'** names each digit). Anywhere else **<** is an ordinary character, but a name before the first
+//!         unit in the node, with nothing for it to name, is an error.
 //!   - Special characters are the same as for traditional regular expressions:
 //!     - **^** (only at front of RE): matches the beginning of the string
 //!     - **$** (only at end of RE): matches the end of the string
@@ -84,7 +115,7 @@
 //!     - **\t**: matches tab
 //! - Repetitions are also defined the same as traditional regular expressions, but see the description of named blocks following.
 //!    - Repetitions can be attached to individual characters inside **chars** nodes and to each type of node (except **char**
-//!        defined using default syntax)
+//!      defined using default syntax)
 //!    - Repetitions are defined by:
 //!      - __*__: match any number of times from 0 up
 //!      - **+**: match any number of times from 1 up
@@ -93,28 +124,59 @@
 //!      - **{N,}**: match N or more times
 //!      - **{N,M}**: match any number of repititions from M to N
 //! - Named nodes: Like with traditional regular expressions nodes can be named, and the names used to label matched blocks
-//!     of code.
+//!   of code.
 //!   - Names can be assigned to any node, not just **AND** nodes like in traditional regular expressions
 //!   - Names are assigned by trailing the node definition with "**\<NAME\>**".
 //!   - If no name is defined for a node then matching strings are not reported individually (they are of course still reported by
-//!       containing nodes). This differs from traditional regular expressions, where the default is to report all **AND** nodes
+//!     containing nodes). This differs from traditional regular expressions, where the default is to report all **AND** nodes
 //!   - The empty name (\<\>) causes a matched block ro report itself, but without a name attached
 //!   - Name definitions interact with repetition definitions. The order they are defined in is important. If the name is defined
-//!       first the repetition refers to named blocks, no multiple named blocks can be returned. If the range comes first the name
-//!       refers to the entire matched sequence, so a single named block will be returned. Example:
+//!     first the repetition refers to named blocks, no multiple named blocks can be returned. If the range comes first the name
+//!     refers to the entire matched sequence, so a single named block will be returned. Example:
 //!     - **and("abc")+\<name\>** will match the string "abcabcabc" by returning a single named "name" containing the string "abcabcabc"
 //!     - **and("abc")\<name\>+** will match the string "abcabcabc" by returning 3 blocks named "name", each block containing the string "abc"
+//!   - Repeated units are reported the same way whatever kind of node they are, in the order they matched:
+//!     - named before the repetition, each repetition is reported on its own, holding what it contains. For an **or**
+//!       each report says which branch it matched, so **or("a"\<x\> "b"\<y\>)\<v\>+** matching "abb" reports three
+//!       "v"s, the first for branch 1 holding an "x" and the others for branch 2 each holding a "y"
+//!     - named after the repetition, a single report covers all of them and holds what each repetition contains, one
+//!       after the other, so **or("a"\<x\> "b"\<y\>)+\<v\>** reports one "v" for "abb" holding an "x" and two
+//!       "y"s. It has no branch, since each repetition can match a different one
+//!     - Other engines instead report only the last repetition of a group, so **\(x\(a\|b\)\)+** there gives
+//!       "xb" for "xaxb". **--last-only** (**Report::last_only()** in the API) does the same for the names it is given:
+//!       only the last report with each name is kept
+//!   - A name can pick out a single repetition by ending it with an index: **and("abc")\<name[2]\>+** reports only the second
+//!     "abc", named "name". The index starts from 1 and counts the matches inside the nearest enclosing repetition (or the whole
+//!     match if there is none), so **and(and("\d+")\<col[2]\> ","?)\*** reports the second column of a comma separated record.
+//!     An index past the number of matches reports nothing.
 //! - Definitions: Commonly used regular expression sequences can be defined and inserted into a regular expression multiple times
 //!   - A definition can be made inline by using the syntax "**def(NAME:...)**". This creates a regular expression from the "..." part
-//!      that can be referred to by NAME. Definitions can be followed with block name and repetition count, which will be inherited by
-//!      default by the inserted subtree
+//!     that can be referred to by NAME. Definitions can be followed with block name and repetition count, which will be inherited by
+//!     default by the inserted subtree
 //!   - Definitions are included in a regular expression by using the "**get(NAME)**" function. If the **get** function has a name
-//!      or repetition count attached to it, that will override any default values from the definition
+//!     or repetition count attached to it, that will override any default values from the definition
+//!     - Everything after the **get()** is for that use only and wins over the definition: a name, a repetition count
+//!       (lazy too, **get(NAME)+?**), and "\c" or "\C" right after the ")" to ignore case or not. So
+//!       **get(w)\<a\> get(w)\c+?\<b\>** fetches one definition two ways in the same RE
+//!     - A definition that ignores case keeps doing so with a new repetition count. Since its strings are kept in lower
+//!       case, "\C" makes it match only lower case
 //!   - Definitions can also be defined in a file and included by the "**use(FILENAME)**" statement
+//!     - FILENAME can have '/', '\', ':' and '~' in it, so Windows paths like **use(C:\defs\net.re)** work, and a
+//!       leading "~/" is the home directory. A name in quotes, **use("my defs/net.re")**, is taken just as it is, so it
+//!       can have spaces in it
+//!     - Files saved with Windows line endings (CRLF) are read the same as ones with plain newlines
+//!   - "**use(FILENAME as NS)**" puts the file's definitions in the namespace NS, so they are called as **get(NS.NAME)** and
+//!     cannot clash with the same names from another file. Inside the file they still refer to each other by their plain names.
+//!     An unqualified **get(NAME)** only looks in the flat table, and a name that is not found is reported with the
+//!     definitions it might have meant
+//!   - A library of common patterns is built in and loaded by "**use(builtin)**": **ipv4**, **ipv6**, **email**, **iso_date**,
+//!     **iso_time**, **uuid**, **quoted_string**, **integer** and **float**. They are named with a "std." prefix so they do not
+//!     replace user definitions, and **get(NAME)** falls back to "std.NAME" when NAME itself is not defined.
+//!     **--defs-list builtin** lists them
 //!   - If more than one definition with the same NAME is made the last one overrides all previous ones. Evaluation is done at the
-//!      end of the tree parse phase.
+//!     end of the tree parse phase.
 //!   - Definitions are evaluated recursively, so they can contain **def()** and **get()** statements. The parser checks to assure
-//!       there are no loops in the definitions
+//!     there are no loops in the definitions
 //!
 //! ## Usage
 //! #### Command line
@@ -129,17 +191,95 @@
 //! Options:
 //!   -t, --text \<TEXT\>      \[default: "" \]
 //!   -p, --parser \<PARSER\>  Parser to use. Will accept abbreviations. Currently supported are 'traditional' and 'alternative' [default: traditional]
+//!       --syntax \[\<PARSER\>\]  print a summary of the syntax of PARSER (default the one given by --parser), with examples
+//!       --defs-list \<SOURCE\>  list the definitions in SOURCE, a library file or 'builtin' for the ones compiled in
+//!       --self-test        run the built in self test, a table of REs searched with both syntaxes, to check the build works.
+//!                          The exit status is 1 if any case fails
+//!       --check            parse the RE and the --patterns-check and --defs-check files without searching, printing each problem. The
+//!                          exit status is 2 if there are any
+//!       --patterns-check \<FILE\>  with --check, a file of REs to check, one to a line. Blank lines and lines starting with '#' are skipped
+//!       --defs-check \<FILE\>  with --check, a library file of definitions to check
 //!   -i, --interactive      Start up an interactive session
 //!   -T, --tree             Prints the parsed regexp tree
+//!       --tree-depth \<DEPTH\>  print the parsed regexp tree only DEPTH levels deep, saying how many nodes are left out below them
+//!       --tree-filter \<NAME\>  print only the subtrees of the parsed regexp tree under the units named NAME
+//!       --tree-json        print the parsed regexp tree as JSON, for tools like editor plugins, instead of searching
+//!       --explain          print a plain English description of what the RE matches, with any warnings as notes, instead of
+//!                          searching
+//!       --expanded         print the RE in the alternative syntax with the definitions it uses written out in place, instead of
+//!                          searching
+//!       --expanded-notes   with --expanded, print a unit to a line, marking each expanded definition with a "# from def NAME" comment
 //!   -w, --walk             Dumps the current path (the successful path, if called on the result of walk())
 //!   -d, --debug \<DEBUG\>    Prints debug information. 1 - 8 give progressively more data [default: 0]
+//!       --debug-format \<DEBUG_FORMAT\>  Format for debug output: 'text', or 'json' to write the walk phase as one JSON event per line [default: text]
 //!   -n, --named            Prints result for all named units
+//!       --only-named \<NAMES\>  only show the named units in the comma separated list (and the units containing them)
+//!       --last-only \<NAMES\>  only show the last match of each of the named units in the comma separated list, the way
+//!                          other engines report a repeated group
 //!   -a, --all              find all instances instead of just first
 //!   -c, --count \<COUNT\>    number of matches to find. Overruled by --all if it appears [default: 1]
+//!       --count-only       just print the number of matches. This is faster than --all since no match is reported
+//!       --set-count \<FILE\>  count the lines each RE in FILE matches, one RE to a line, reading the input once and printing
+//!                          each count with its RE. All the other arguments are files to search
+//!       --summary-json     in place of the "Found N instances" line, print a JSON summary of the run to stderr: the
+//!                          matches, bytes and lines in each file, the files skipped as unopenable or binary and the
+//!                          time taken
+//!       --replace \<TEMPLATE\>  print the text with every match replaced by TEMPLATE, where $name or ${name} is a named unit, $0 the
+//!                          whole match, $N the Nth recorded unit and $$ is '$'
+//!       --replace-preserve-case  with --replace, make each replacement follow the case of the text it replaces: all upper case,
+//!                          capitalized or all lower case
 //!   -q, --quiet            just print out matched strings, no details or names
+//!   -l, --lines            print out line(s) containing each match
+//!       --context-chars \<N\>  print out each match with up to N characters of the text on each side of it
+//!       --no-warnings      do not print warnings about suspicious constructs in the regular expression
+//!       --strict           make escapes with no meaning, like "\q", an error instead of a warning
+//!       --literal-braces   make a '{' that is not followed by a digit match itself instead of being an error
+//!       --lenient          take text left over after the RE is complete, like a stray closing paren, as literal text
+//!                          with a warning instead of an error
+//!       --ignore-case      ignore case in all the strings of the RE, as if each started with "\c"
+//!       --whitespace \<SET\>  what the alternative syntax skips between units besides space, tab and newline: 'unicode' for
+//!                          carriage return and the Unicode spaces like NBSP, 'strict-lines' to make a definition in a def file
+//!                          that runs onto another line an error unless the line ends with '\'. Both can be given, separated by
+//!                          a comma
+//!       --no-optimize      do not simplify the parsed regexp tree, scan ahead for where matches can start, or skip OR
+//!                          branches that already failed
+//!       --max-complexity \<SCORE\>  refuse an RE whose complexity score, a rough measure of how expensive it can be to
+//!                          search for, is over SCORE
+//!       --persist          in interactive mode, load the saved session at startup and save it at exit
+//!       --script \<FILE\>    run the interactive commands in FILE, showing each one before its output, then exit. The exit
+//!                          status is 1 if a command fails
+//!       --script-interactive  with --script, start the interactive prompt after the script instead of exiting
+//!   -k, --keep-going       with --script, go on to the next command when one fails instead of stopping
+//!       --timeout \<SECONDS\>  give up a search that takes longer than this
+//!       --deepen           best effort search for heavy REs: try the RE with its unlimited repetitions capped at 64, then
+//!                          1024, then 16384, before searching with it as it is, and take the first match found. This can
+//!                          miss very long matches
+//!       --max-match-len \<BYTES\>  the longest match to accept. Longer matches are cut back, or not found
+//!       --read-block \<BYTES\>  read files and stdin in blocks of about this many bytes \[default: 65536\]
+//!       --max-buffer \<BYTES\>  give up a search when the text read in would be more than this \[default: no limit\]
+//!       --bytes            search the input as bytes, so it does not have to be UTF-8. The RE must be ASCII, positions are in bytes
+//!       --check-boundaries  abandon a search with an error if a match would end inside a character, instead of splitting it
+//!       --raw-output       print matched text as it is, instead of showing control characters like NUL and ESC as escapes ("\x1b")
 //!   -h, --help             Print help
 //!   -V, --version          Print version
 //!
+//! **--check** validates REs without searching anything, so it needs no text or input files, which makes it useful in CI.
+//! Each problem is printed on a line starting with where it came from: "RE" for the one on the command line, PATH:LINE
+//! for a line of a **--patterns-check** file, or the PATH of a **--defs-check** library. Warnings are problems too, unless
+//! **--no-warnings** is given. The exit status is 0 if everything parsed cleanly and 2 otherwise:
+//!
+//! regexp --check -p alt --patterns-check patterns.txt --defs-check lib.re
+//!
+//! Adding **--whitespace strict-lines** checks that a library keeps to one definition a line, with a '\' at the end
+//! of any line a definition is continued from.
+//!
+//! **--set-count** answers "which of these patterns occur in this log, and how often" in one pass over the input,
+//! however many patterns there are. FILE holds the REs in the same form as a **--patterns-check** file, and there is
+//! no RE argument, so every other argument is a file to search. Each line of the input is tested against all the REs,
+//! and the output is the number of lines each one matched, then a tab, then the RE:
+//!
+//! regexp --set-count patterns.txt big.log
+//!
 //! #### API
 //! A search has three phases. The first phase parses the regular expression to get a regular expression tree, which is the map needed to
 //! search the target string. The second phase uses the tree to walk through the target string to see if there is a match. Finally, the
@@ -148,22 +288,21 @@
 //! A simple example of how to use it is:
 //!
 //! ```
-//! fn search(regexp: &str, text: &str) -> Result<Option<Report>, String>{
-//!     let tree = match regexp::parse_tree(regexp) {
-//!         Ok(node) => node,
-//!         Err(error) => { return Err(error); },
-//!     };
-//!     stderr::Input::init_text(text);      // sets the string to search to TEXT
-//!     match regexp::walk_tree(&tree, 0) {
-//!         Ok(Some((path, char_start, bytes_start))) => {
-//!             return Ok(Some(regexp::Report::new(&path, char_start, bytes_start).display(0)))
-//!         },
-//!         Ok(None) => return Ok(None),
-//!         Err(error) => Err(error),
-//!     }
+//! fn search(re: &str, text: &str) -> Result<Option<regexp::Report>, regexp::Error> {
+//!     let tree = regexp::parse_tree(re, false)?;
+//!     regexp::Input::init_string(text, false);      // sets the string to search to TEXT
+//!     Ok(regexp::walk_tree(&tree, 0)?.map(|path| regexp::Report::new(&path)))
 //! }
+//! ```
+//!
+//! Each node of the tree remembers the text it was parsed from: **Node::source_span()** gives its (start, end) byte
+//! offsets in the RE. A node brought in by a **get()** has the span of the **get()**. Warnings about a whole unit,
+//! like nested repetitions, and errors like an empty loop (10) quote its text and carry the span in their **span**
+//! field.
 //!
-//!```
+//! The positions in a **Report** are absolute, counted from the start of the text, in bytes (**Report::offsets()**)
+//! and in chars, even when the walk starts part way in or is limited to a window (**WalkOptions::window()**). Code
+//! searching a window gets positions counted from its start with **Report::relative_to(window_start)**.
 //!
 //!
 //! THere are 3 functions to choose from to initialize the buffer:
@@ -172,10 +311,161 @@
 //! search a string from STDIN. The START argument to walk_tree()
 //! gives the position to start the search from. This is needed to
 //! find all instances, the regexp library only finds a single
-//! instance.
+//! instance. walk_tree_next() also returns where to start the next
+//! search, which is past the match, or a char further on after an
+//! empty match so the same empty match is not found forever.
+//!
+//! Tokenizers only need to know how far a match goes. **match_prefix_len(tree, at)** tries the one start AT and
+//! returns the length of the match there, and **shortest_match(tree, from)** finds the match that starts where
+//! walk_tree() would but ends as soon as it can, taking every repetition lazily. Neither builds a **Report**.
+//!
+//! For the common case of wanting the text of the groups there is a simpler interface, shaped like the regex
+//! crate's, where the groups are numbered in order (0 is the whole match) and can also be found by name:
+//!
+//! ```ignore
+//! use regexp::regexp::{Error, Regexp};
+//!
+//! fn main() -> Result<(), Error> {
+//!     let re = Regexp::new(r"\(?<user>\l+\)@\(?<host>[a-z.]+\)")?;
+//!     if let Some(caps) = re.captures("mail bob@example.com")? {
+//!         println!("user {}, host {}", &caps["user"], &caps["host"]);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! In bytes mode a **Regexp** searches the text as bytes, but the positions are still byte positions in the &str. A
+//! match that ends inside one of its chars cannot be returned, and is error 232.
+//!
+//! The options for parsing and searching, like the syntax, ignoring case, or a timeout, are gathered in a
+//! **SearchOptions**, which can be given to **Regexp::with_options()** or passed to **parse_tree_opts()** and
+//! **walk_tree_opts()**. The command line flags are turned into one by **Config::search_options()**.
+//!
+//! **regexp()** runs a whole search as the command line does, printing the matches, and returns a **RunStats** with
+//! the number of matches, the matches, bytes and lines in each source, the files skipped because they could not be
+//! opened (with the reason), the sources skipped as binary and the time taken. **RunStats::to_json()** is what **--summary-json** prints.
+//!
+//! **regexp()** is built on **SearchDriver**, which runs the same search through all the sources without printing
+//! anything, handing each match (with its **Report**, file and line), each source started, finished or unreadable,
+//! and the final **RunStats** to a **MatchSink**. A closure taking a **&MatchEvent** is a sink, so other output
+//! formats can be written without touching the search loop.
+//!
+//! Tools that work a line at a time, like grep, can use **search_lines()**, which returns each line containing a
+//! match along with its line number and the matches in it. Matches are kept inside their line, and '$' matches at the
+//! end of each one, unless **multiline()** is called on the iterator:
+//!
+//! ```ignore
+//! use regexp::regexp::{parse_tree, search_lines, Error, Input};
+//!
+//! fn main() -> Result<(), Error> {
+//!     let tree = parse_tree("b+", false)?;
+//!     Input::init_string("abc\nxyz\nbb b\n", false);
+//!     for line in search_lines(&tree) {
+//!         let (number, _range, reports) = line?;
+//!         println!("{}: {} matches", number, reports.len());
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The pretty-printers used by the command line, **Report::display()**, **Node::desc()** and **Path::dump()**, print to
+//! stdout. **display_to()**, **desc_to()** and **dump_to()** write the same thing to any **std::io::Write**, and the
+//! **Display** implementations give it as a string, so a report can go into a log or be compared in a test.
+//!
+//! A long search can be stopped from outside by giving the walk a cancellation flag. When the flag is set the
+//! walk returns error 220 ("Search cancelled") the next time it checks, which it does every few hundred steps:
+//!
+//! ```ignore
+//! use regexp::regexp::{set_walk_options, WalkOptions};
+//! use std::sync::{
+//!     atomic::{AtomicBool, Ordering},
+//!     Arc,
+//! };
+//!
+//! let cancel = Arc::new(AtomicBool::new(false));
+//! set_walk_options(WalkOptions::new().cancel(cancel.clone()));
+//! // ... from another thread, to stop the search:
+//! cancel.store(true, Ordering::Release);
+//! ```
+//!
+//! From the command line Ctrl-C sets the flag, so an interrupted search stops cleanly and reports what it found
+//! so far. A second Ctrl-C exits immediately.
+//!
+//! A simpler way to bound a search is a timeout, **WalkOptions::new().timeout(duration)**. A call to walk_tree()
+//! that runs longer returns error 221 ("Search timed out"). On the command line this is **--timeout SECONDS**.
+//!
+//! **WalkOptions::new().max_match_len(bytes)** (**--max-match-len BYTES**) limits how long a match can be. This
+//! is useful for REs like **".*"**, which can match megabytes if the closing quote is missing. A step that would
+//! take the match past the limit fails, so the walk backs off to a shorter match if there is one, or moves on.
+//!
+//! REs taken from users can be checked before they are run. **Node::complexity_score()** scores how expensive an RE
+//! is likely to be, counting its units and weighing unlimited repetitions inside other ones most heavily, and
+//! **SearchOptions::new().max_complexity(score)** (**--max-complexity SCORE**) makes parsing one that scores higher
+//! fail with error 18. The complexity module describes how the score is worked out.
+//!
+//! Some REs, like **"a[^;]\*b[^;]\*c[^;]\*d"** over long runs of text with no "d", take so long to fail at the early
+//! start positions that the search times out before it gets to a short match further on. **--deepen** makes the
+//! search best effort: **walk_tree_deepening()** searches with the unlimited repetitions capped at 64, then 1024, then
+//! 16384 (**deepening_trees()** makes the capped trees), and only then with the RE as it is, taking the first match
+//! found. The hopeless start positions are given up quickly, but a match needing more repetitions than the cap is
+//! missed at that cap, so a very long match can be passed over for a later one. **SearchDriver::deepening()** does
+//! the same for a library search.
+//!
+//! Files and stdin are read in as the walk needs them, in blocks of about 64KB. **Input::set_block_size(bytes)**
+//! (**WalkOptions::new().read_block(bytes)**, **--read-block BYTES**) changes this. When a read leaves the text close
+//! to running out the next block is read too; **Input::set_read_ahead(false)** turns this off.
+//!
+//! A source that fails to read is given up on, and the search goes on with the text read before the error. Files that
+//! cannot be opened or read are skipped. **Input::errors()** lists what went wrong with each source, and
+//! **Input::skipped()** the files that could not be opened, which **RunStats** lists as well. The command line prints a
+//! warning line for each skipped file and the other errors at the end, and it is an error (211) if nothing could be
+//! read at all; **Input::init_files()** fails with it straight away if none of the files can be opened. A source that is not
+//! UTF-8 text gives error 212, and is listed by **RunStats** (below) as skipped as binary. **Input::init_reader()**
+//! searches text from any **BufRead**.
+//!
+//! All the text read from a source is kept while it is searched, so a search of a long stream with no match holds
+//! all of it. **Input::buffered_bytes()** and **Input::peak_buffered_bytes()** show how much that is, and
+//! **WalkOptions::new().max_buffer(bytes)** (**--max-buffer BYTES**) sets a limit: a walk that needs to read past it
+//! gives up with error 230 instead of using up memory.
+//!
+//! Text that is mostly ASCII but not quite UTF-8, like old log files, can be searched in bytes mode,
+//! **WalkOptions::new().bytes()** (**--bytes**, or **Input::set_bytes_mode(true)**). Each byte of the input is held as
+//! a single char, so char positions are byte positions in the input (**Report::offsets()** gives them either way) and
+//! **Report::bytes()** gets the bytes matched. The specials only match ASCII, and an RE containing anything else is
+//! rejected with error 12, since it could not match the bytes it looks like. **Input::init_bytes()** searches a buffer.
+//!
+//! Matched text is printed through **Input::display_text()**, which shows control characters as escapes, so a log
+//! with NULs or ESC sequences in it prints as "\x00" and "\x1b[31m" rather than being sent to the terminal as it
+//! is. **Input::set_raw_output(true)** (**--raw-output**) turns this off.
+//!
+//! To search a slice of the input, for example to split a huge file into chunks searched in parallel, walk from the
+//! start of the slice with **WalkOptions::new().window(end, edge)**. Only matches starting before **end** are found.
+//! With **WindowEdge::Extend** a match can run on past it; with **WindowEdge::Clip** the end of the window is treated
+//! as the end of the text, so a match is cut back to fit (or not found) and '$' matches there.
+//!
+//! A match that ends inside a character means a node matched the wrong number of bytes, which would panic when the
+//! match is printed. Debug builds check every step for this and panic straight away. **WalkOptions::new()
+//! .check_boundaries()** makes any build check, failing the walk with error 231 naming the node instead, and
+//! **Matched::validate()** checks a single match against the text. On the command line the check is turned on with
+//! **--check-boundaries**.
+//!
+//! To handle named units as they are found instead of going through a **Report**, register callbacks by name on the
+//! walk options. They are called after each successful walk, for each match of the unit in the order they start:
+//!
+//! ```
+//! regexp::set_walk_options(regexp::WalkOptions::new().on_group("date", |matched, text| {
+//!     println!("date \"{}\" at {}", text, matched.start);
+//! }));
+//! ```
+//!
+//! Trace output goes to stdout, or to the writer given to **set_trace_writer()**. Built with the **log** feature it
+//! can go through the **log** crate instead, **set_trace_backend(TraceBackend::Log)**, with trace levels 1 and 2 logged
+//! at debug and the rest at trace, under targets like "regexp::walk".
 //!
 //! #### Interactive
-//! There is also an interactive mode which allows storing of multiple regular expressions and text strings. From the help:
+//! There is also an interactive mode which allows storing of multiple regular expressions and text strings. When run
+//! from a terminal it supports line editing, up-arrow history and tab completion of commands, names and file paths
+//! (the **readline** feature, on by default; build with **--no-default-features** to use plain stdin). From the help:
 //! This is an interactive interface to the regexp search engine. The program keeps stacks of  
 //! regular expressions and search texts and uses them to run searches. Besides simple searching  
 //! the program will print out the parsed search tree and also details of the walk over the target  
@@ -183,14 +473,17 @@
 //!   
 //! Commands are in general of the form CMD \[SUBCMD \[DATA\]\], though it will try to guess the   
 //! meaning of ambiguous commands. The commands and subcommands can be abbreviated with the   
-//! first couple unique letters.  
+//! first couple unique letters. Words are separated by whitespace, and names and file names  
+//! containing spaces can be put in single or double quotes. REs and texts are taken exactly as typed.  
 //!   
 //! The commands are:  
 //!   - regexp:         display the current active regular expression  
 //!   - regexp \[traditional | alternative\] RE:  sets a new regular expression to be the current one. The   
-//!                     keyword is optional, if not given the program usually will guess what the text  
-//!                     is, and ask for confirmation  
+//!     keyword is optional, if not given the program usually will guess what the text  
+//!     is, and ask for confirmation  
 //!   - regexp history: lists the most recent regular expressions  
+//!   - regexp name NAME: gives the current regular expression a name. A name can only belong to one regular expression  
+//!   - regexp use NAME: makes the regular expression with the given name the current one  
 //!   - regexp list:    same as 're history'  
 //!   - regexp NUMBER:  sets the NUMBERth item on the history list to be the current regular expression  
 //!   - regular pop \[n\]:pops off (deletes) the nth re from the list. Defaults to 0 (the current RE)  
@@ -198,34 +491,150 @@
 //!   - text TEXT:      sets new search text  
 //!   - text list:      same as 'text history'  
 //!   - text history:   lists the most recent regular expressions  
-//!   - text NUMBER:    sets the NUMBERth item on the history list to be the current text  
+//!   - text NUMBER:    sets the NUMBERth item on the history list to be the current text. If there is no  
+//!     such item, or anything follows the number, the input is taken as the new text instead  
+//!   - text name NAME: gives the current text a name. A name can only belong to one text  
+//!   - text use NAME:  makes the text with the given name the current one  
 //!   - text pop \[n\]:   pops off (deletes) the nth tex string from memory. Defauls to 0 (the current text string)  
+//!   - text file PATH: reads the contents of the file PATH in as the new search text  
+//!   - text from-match \[N\]: uses the text of the Nth match (default 1) found by the last **search all** as the new search text  
+//!   - text from-line \[N\]: the same, but uses the whole line the match is in  
 //!   - search :        performs a RE search using the current RE and the current text.   
 //!   - search NAME1 \[NAME2...\]: performs a RE search using the current RE and the current text, report only on units with the given names  
 //!   - search * :      performs a RE search using the current RE and the current text, report on all named units  
 //!   - search NUMBER:  performs a RE search using the current RE and the current text setting debug level to NUMBER to examine the path.  
-//!                     This can be combined with search for name.  
-//!   - tree \[NUMBER\]:  displays the parse tree for the current regular expression. Optional **NUMBER** sets the trace level  
-//!                     to see how the parse is performed.  
+//!     This can be combined with search for name.  
+//!   - search \[NUMBER\] all: finds every match of the current RE in the current text, numbering them and showing named units  
+//!   - search \[NUMBER\] count: prints the number of matches of the current RE in the current text  
+//!   - search file \[PATH...\]: searches the files for the current RE, printing each match numbered, with its file and  
+//!     line number. If no PATH is given the files from the last file search are used again. When there are more than fit  
+//!     on the screen they are shown a screenful at a time: Enter shows the next, q stops, and a NUMBER jumps to that match.  
+//!     The height is taken from $LINES or the terminal, or is 24.  
+//!   - results:        shows the matches from the last search file again  
+//!   - results NUMBER: shows the NUMBERth match from the last search file in full, with its named units and positions  
+//!   - walk \[NUMBER\]:  performs a search like **search**, but dumps the successful path instead of the report. Optional  
+//!     **NUMBER** sets the trace level for the walk.  
+//!   - walk json FILE: performs a walk, writing the trace to FILE as one JSON event per line, then reads it back  
+//!     and prints the tree of attempts the walk made  
+//!   - tree \[NUMBER\] \[depth N\] \[filter NAME\]: displays the parse tree for the current regular expression, followed by any  
+//!     warnings. Optional **NUMBER** sets the trace level to see how the parse is performed. **depth N** prints only the  
+//!     top N levels and **filter NAME** only the subtrees under the units named NAME, like **--tree-depth** and **--tree-filter**.  
+//!   - tree json:      prints the parse tree for the current regular expression as JSON, the same as **--tree-json**  
+//!   - tree expanded \[notes\]: prints the current regular expression in the alternative syntax with its definitions  
+//!     written out in place, the same as **--expanded**. With **notes** the expanded definitions are marked  
+//!   - explain:        describes the current regular expression in plain English, the same as **--explain**, followed by  
+//!     any warnings as notes if warnings are on  
+//!   - replace TEMPLATE: prints the current text with every match of the current RE replaced by TEMPLATE. In TEMPLATE  
+//!     $name or ${name} is the named unit, $0 the whole match, $N the Nth recorded unit, and $$ is '$'  
+//!   - replace! TEMPLATE: the same as replace, but the result becomes the new current text  
+//!   - bench \[COUNT\]:  times parsing the current RE and searching the current text COUNT times (default 100), and  
+//!     shows the number of start positions, steps, backtracks, and allocations the search takes,  
+//!     and the most text held in memory  
+//!   - diff RE1 RE2:   finds all matches of two REs from the history (by number or name) in the current text and  
+//!     shows the matches found by only one of them and by both, noting named units that differ  
+//!   - defs \[list\]:    lists the defined snippet names with their definitions  
+//!   - defs show NAME: displays the parse tree for the definition of NAME  
+//!   - defs delete NAME: removes the definition of NAME  
+//!   - defs clear:     removes all definitions  
+//!   - defs load \[FILE\]: loads definitions from FILE, the same as use(FILE) in an RE. Defaults to ~/.regexp, and "builtin" loads the builtin definitions  
+//!   - set:            lists the session options  
+//!   - set OPTION VALUE: sets a session option. The options are:  
+//!     parser guess|traditional|alternative: parser for an RE entered without a keyword (default guess)  
+//!     trace NUMBER: trace level for search, walk and tree when none is given (default 0)  
+//!     caseless on|off: ignore case in the characters of the RE (default off)  
+//!     warnings on|off: show warnings with the tree command (default on)  
+//!     optimize on|off: simplify the parse tree (default on)  
+//!     strict on|off: make unknown escapes like \\q an error instead of a warning (default off)  
+//!     lenient on|off: take text left over after the RE is complete, like a stray closing paren, as literal text  
+//!     with a warning instead of an error (default off)  
+//!     auto on|off: while a regexp command is continued over several lines with '\\', close what  
+//!     has been typed so far and preview its first match in the current text (default off)  
+//!     timeout SECONDS|off: abandon a search or walk that runs longer than this (default off)  
+//!     multiline on|off: let matches in the current text run past the end of a line. When off each  
+//!     line is searched on its own, with '^' and '$' matching at its ends (default on)  
+//!     There is no color option since nothing is printed in color, and no step budget: the walk does  
+//!     not count steps against a limit, so a timeout is the way to bound a search  
+//!   - split LINE:     shows how LINE is broken into words, both by the command splitter and by the RE the  
+//!     program once used to parse its own commands  
+//!   - syntax \[traditional|alternative\]: prints a summary of the syntax, with examples. The default is the  
+//!     syntax of the current RE  
+//!   - save \[FILE\]:    saves the REs, texts, definitions and options to FILE (default ~/.regexp_session)  
+//!   - load \[FILE\]:    replaces the REs, texts and options with those saved in FILE (default ~/.regexp_session) and adds its definitions  
+//!   - source \[-k\] FILE: runs the commands in FILE as if they were typed, showing each one before its output. Lines  
+//!     starting with '#' are skipped. It stops at the first command that fails unless -k is given  
 //!   - help:           displays this help  
 //!   - ?:              displays this help  
+//!
+//! A saved sequence of commands, for example the steps to reproduce a bug, can be replayed with **source FILE** or
+//! from the command line with **--script FILE**. Each command is printed after the prompt before its output, so the
+//! output is a transcript of the session:
+//!
+//! regexp --script steps.txt > transcript.txt
 
+#[cfg(test)]
+mod fuzz;
 mod interactive;
 pub mod regexp;
 mod tests;
 
-use crate::interactive::Interactive;
+use crate::interactive::{Interactive, ScriptEnd};
 use crate::regexp::*;
+use core::sync::atomic::{AtomicBool, Ordering};
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+
+/// Set by Ctrl-C, this is the cancellation flag for command line searches
+static INTERRUPTED: Lazy<Arc<AtomicBool>> = Lazy::new(|| Arc::new(AtomicBool::new(false)));
+
+/// The Ctrl-C handler. The first interrupt asks the search to stop, the second one exits.
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::AcqRel) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Makes Ctrl-C cancel the search instead of killing the program
+fn catch_interrupt() {
+    Lazy::force(&INTERRUPTED);
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Runs an interactive session. With **--script** the commands in the script are run first, and the session ends
+/// after them unless **--script-interactive** is given. A script that stops at a failed command exits with status 1.
+fn run_interactive(config: Config) {
+    let script = config.script.clone();
+    let (keep_going, then_prompt) = (config.keep_going, config.script_interactive);
+    let mut interactive = Interactive::new(config);
+    let end = match script {
+        None => return interactive.run(),
+        Some(path) => interactive.source(&path, keep_going),
+    };
+    if end != ScriptEnd::Quit && then_prompt {
+        return interactive.run();
+    }
+    interactive.end();
+    if end == ScriptEnd::Failed {
+        std::process::exit(1);
+    }
+}
 
 /// Main function to run regexp as a function. It is called by
 /// > cargo run [-t] [-i] [-d LEVEL] [-a LENGTH] \[REGEXP\] \[-t TARGET | FILES...\]
+///
 /// where:
 ///  - **REGEXP**: a regular expression. This is always required excep if _-i_ is given
 ///  - **TARGET**: a string to search with *REGEXP*. It is required unless _-i_ or _-t_ are given
 ///  - **FILES**: a list of files to search. If empty or the first file is "-" STDIN is used
 ///  - **-t** (**--tree**): just parse the regexp to make the regexp tree and print it out in a user friendly format
 ///  - **-i** (**--interactive**): run an interactive session. This lets the user enter regexps and targets and run them to see the
-/// results, as well as viewing the details of the tree parse or tree walk phases.
+///    results, as well as viewing the details of the tree parse or tree walk phases.
 ///  - **-d LEVEL** (**--debug LEVEL**): set the debug level to LEVEL. The default level is 0, good values to try are 1, 2, or 3.
 ///  - **-a** (**--all**): Finds all instances in the input string. By default only the first is found.
 ///  - **-c COUNT** (**--count COUNT**): finds the first COUNT occurences and exits. The default is 1, and this is overruled by the **-a** switch
@@ -238,20 +647,103 @@ pub fn main() {
         }
     };
 
-    if config.interactive {
-        return Interactive::new(config).run();
+    // before the options are applied, so the cases run the way they were written
+    if config.self_test {
+        let failed = regexp::selftest::self_test(&mut std::io::stdout()).unwrap_or(1);
+        std::process::exit(if failed == 0 { 0 } else { 1 });
+    }
+    set_literal_braces(config.literal_braces);
+    set_lenient(config.lenient);
+    Input::set_raw_output(config.raw_output);
+    if config.interactive || config.script.is_some() {
+        return run_interactive(config);
+    }
+    if let Some(alt) = config.syntax_alt() {
+        print!("{}", regexp::syntax::syntax_summary(alt));
+        return;
+    }
+    if let Some(source) = &config.defs_list {
+        if source == regexp::builtin::BUILTIN_PATH {
+            print!("{}", regexp::builtin::builtin_summary());
+        } else if let Err(err) = regexp::tree::load_def_file(source) {
+            eprintln!("{}", err);
+        } else {
+            for def in regexp::tree::def_sources() {
+                println!("{}", def);
+            }
+        }
+        return;
+    }
+    if config.check {
+        let problems = check_patterns(&config);
+        problems.iter().for_each(|problem| println!("{}", problem));
+        std::process::exit(if problems.is_empty() { 0 } else { 2 });
+    }
+    if config.tree_json {
+        match parse_tree_opts(&config.re, &config.search_options()) {
+            Err(msg) => eprintln!("{}", msg),
+            Ok((tree, warnings)) => {
+                if !config.no_warnings {
+                    warnings.iter().for_each(|warning| eprintln!("{}", warning));
+                }
+                println!("{}", tree.to_ast().to_json());
+            }
+        }
+        return;
+    }
+    if config.explain {
+        match parse_tree_opts(&config.re, &config.search_options()) {
+            Err(msg) => eprintln!("{}", msg),
+            Ok((tree, warnings)) => {
+                let notes = if config.no_warnings {
+                    &[][..]
+                } else {
+                    &warnings[..]
+                };
+                println!("{}", tree.explain(notes));
+            }
+        }
+        return;
+    }
+    if config.expanded {
+        match parse_tree_opts(&config.re, &config.search_options()) {
+            Err(msg) => eprintln!("{}", msg),
+            Ok((tree, warnings)) => {
+                if !config.no_warnings {
+                    warnings.iter().for_each(|warning| eprintln!("{}", warning));
+                }
+                if config.expanded_notes {
+                    println!("{}", tree.to_annotated_source());
+                } else {
+                    println!("{}", tree.to_alt_source());
+                }
+            }
+        }
+        return;
     }
     set_trace(config.debug as usize);
+    set_trace_format(config.trace_format());
+    catch_interrupt();
+    set_walk_options(config.walk_options().cancel(INTERRUPTED.clone()));
     // execution starts
     match regexp(&config) {
         Err(msg) => eprintln!("{}", msg),
-        Ok(count) => {
-            if !config.quiet {
+        Ok(stats) => {
+            if config.summary_json {
+                eprintln!("{}", stats.to_json());
+            } else if !config.quiet && config.set_count.is_none() {
                 let file_count = Input::file_count();
-                if file_count > 0 {
-                    eprintln!("Found {} instances in {} files", count, file_count);
+                if !stats.files_skipped.is_empty() {
+                    eprintln!(
+                        "Found {} instances in {} files, skipping {}",
+                        stats.matches,
+                        stats.files.len(),
+                        stats.files_skipped.len()
+                    );
+                } else if file_count > 0 {
+                    eprintln!("Found {} instances in {} files", stats.matches, file_count);
                 } else {
-                    eprintln!("Found {} instances", count);
+                    eprintln!("Found {} instances", stats.matches);
                 }
             }
         }
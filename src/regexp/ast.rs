@@ -0,0 +1,159 @@
+//! ## JSON AST
+//! A plain description of a parsed tree for tools outside the crate, like editor plugins that highlight or explain
+//! REs. **Node::to_ast()** builds it, and **--tree-json** and the interactive **tree json** command write it out as
+//! JSON. It is kept apart from the tree so the tree can change without changing the schema: it holds only what the
+//! RE means, not how the parser stores it, and definitions are expanded in place.
+//!
+//! Every node has a **kind** and **limits**. Leaves ("chars", "special" and "range") have a **literal**, and "and"
+//! and "or" nodes have **children**. The other fields are left out unless they apply:
+//! - **name**: the unit is recorded, under this name ("" for a unit recorded without one)
+//! - **name_all_reps**: the name applies to all the repetitions together instead of each one
+//! - **anchor**: an "and" node must start at the beginning of the text
+//! - **def**: the node is the definition of this name, inserted with **get()**. If the **get()** is repeated or named
+//!   it is an "and" node holding the definition as its only child
+//!
+//! For example, with the limits left out, '^"\d+"<num>' in the alternative syntax is
+//!
+//! {"kind":"and","name":"","anchor":true,"children":[{"kind":"special","literal":"\\d","name":"num"}]}
+//!
+//! where the outer "and" is the whole match, which is always recorded.
+
+use crate::regexp::tree::{Limits, Node, EFFECTIVELY_INFINITE};
+use serde::{Deserialize, Serialize};
+
+/// The kinds of node in the AST
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AstKind {
+    /// a string of characters, matched exactly
+    Chars,
+    /// a special character like "\d" or "."
+    Special,
+    /// a set of characters in brackets
+    Range,
+    /// all the children, one after the other
+    And,
+    /// any one of the children
+    Or,
+}
+
+/// How many times a node repeats, and how it matches
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AstLimits {
+    pub min: usize,
+    /// None if there is no limit
+    pub max: Option<usize>,
+    /// true if it takes as few repetitions as it can
+    pub lazy: bool,
+    /// true if case is ignored
+    pub no_case: bool,
+}
+
+/// A single node of the AST. See the module documentation for which fields each kind has.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AstNode {
+    pub kind: AstKind,
+    /// for leaves, the text as it would be written in the RE
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub literal: Option<String>,
+    pub limits: AstLimits,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub name_all_reps: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub anchor: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub def: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<AstNode>>,
+}
+
+impl AstNode {
+    /// makes a node with only the required fields set
+    fn make(kind: AstKind, limits: &Limits, named: &Option<String>, name_outside: bool) -> AstNode {
+        AstNode {
+            kind,
+            literal: None,
+            limits: AstLimits::from(limits),
+            name: named.clone(),
+            name_all_reps: named.is_some() && name_outside,
+            anchor: false,
+            def: None,
+            children: None,
+        }
+    }
+
+    /// Writes the AST as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+impl From<&Limits> for AstLimits {
+    fn from(limits: &Limits) -> AstLimits {
+        AstLimits {
+            min: limits.min,
+            max: if limits.max == EFFECTIVELY_INFINITE {
+                None
+            } else {
+                Some(limits.max)
+            },
+            lazy: limits.lazy(),
+            no_case: limits.no_case(),
+        }
+    }
+}
+
+impl Node {
+    /// Builds the AST for the tree, expanding definitions
+    pub fn to_ast(&self) -> AstNode {
+        match self {
+            Node::Chars(a) => AstNode {
+                literal: Some(a.string.clone()),
+                ..AstNode::make(AstKind::Chars, &a.limits, &a.named, a.name_outside)
+            },
+            Node::Special(a) => AstNode {
+                literal: Some(if let Some(upto) = a.upto_source() {
+                    upto
+                } else if ".$".contains(a.special) {
+                    a.special.to_string()
+                } else {
+                    format!("\\{}", a.special)
+                }),
+                ..AstNode::make(AstKind::Special, &a.limits, &a.named, a.name_outside)
+            },
+            Node::Range(a) => AstNode {
+                literal: Some(a.to_string()),
+                ..AstNode::make(AstKind::Range, &a.limits, &a.named, a.name_outside)
+            },
+            Node::And(a) => AstNode {
+                anchor: a.anchor,
+                children: Some(a.nodes.iter().map(Node::to_ast).collect()),
+                ..AstNode::make(AstKind::And, &a.limits, &a.named, a.name_outside)
+            },
+            Node::Or(a) => AstNode {
+                children: Some(a.nodes.iter().map(Node::to_ast).collect()),
+                ..AstNode::make(AstKind::Or, &a.limits, &a.named, a.name_outside)
+            },
+            Node::Def(a) => {
+                let mut ast = a.node.to_ast();
+                // a get() that is repeated or named wraps the definition, otherwise it is the definition
+                if a.limits == Limits::default() && a.named.is_none() {
+                    ast.def.get_or_insert(a.name.clone());
+                    ast
+                } else {
+                    AstNode {
+                        def: Some(a.name.clone()),
+                        children: Some(vec![ast]),
+                        ..AstNode::make(AstKind::And, &a.limits, &a.named, a.name_outside)
+                    }
+                }
+            }
+            Node::None => AstNode {
+                children: Some(Vec::new()),
+                ..AstNode::make(AstKind::And, &Limits::default(), &None, false)
+            },
+        }
+    }
+}
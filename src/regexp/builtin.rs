@@ -0,0 +1,152 @@
+//! ## Builtin definitions
+//! A library of snippets for patterns that come up often, like dates, addresses and numbers, written in the
+//! alternative syntax. They are compiled into the binary and loaded with **use(builtin)** (or **load_builtin_defs()**
+//! from code), after which they can be inserted with **get()** like any other definition.
+//!
+//! The names all start with "std." so loading them never replaces a user definition. **get(NAME)** looks for a user
+//! definition of NAME first and falls back to "std.NAME", so **get(ipv4)** finds the builtin one unless the RE or a
+//! library has defined its own **ipv4**. They aim to pick out likely candidates in running text rather than to be
+//! exact validators (**email** in particular only checks the general shape).
+
+/// The prefix on the names of builtin definitions
+pub const BUILTIN_PREFIX: &str = "std.";
+
+/// The path given to **use()** to load the builtin definitions instead of a file
+pub const BUILTIN_PATH: &str = "builtin";
+
+/// A single builtin definition
+#[derive(Debug, Clone, Copy)]
+pub struct BuiltinDef {
+    /// the name, including the "std." prefix
+    pub name: &'static str,
+    /// what it matches
+    pub meaning: &'static str,
+    /// the definition, in the same form as a library file
+    pub source: &'static str,
+    /// texts it should match in full. The tests check them.
+    pub matches: &'static [&'static str],
+    /// texts it should not match in full
+    pub misses: &'static [&'static str],
+}
+
+/// The definitions, in the order they are listed. A definition can only **get()** the ones before it.
+#[rustfmt::skip]
+pub const BUILTIN_DEFS: &[BuiltinDef] = &[
+    BuiltinDef {
+        name: "std.octet",
+        meaning: "a decimal number from 0 to 255, as in an IPv4 address",
+        source: r#"def(std.octet: or("25[0-5]" "2[0-4]\d" "1\d\d" "[1-9]\d" "\d"))"#,
+        matches: &["0", "7", "42", "199", "255"],
+        misses: &["256", "300", "07", ""],
+    },
+    BuiltinDef {
+        name: "std.ipv4",
+        meaning: "an IPv4 address in dotted decimal, like 192.168.0.1",
+        source: r#"def(std.ipv4: get(std.octet) and("\." get(std.octet)){3})"#,
+        matches: &["192.168.0.1", "0.0.0.0", "255.255.255.255"],
+        misses: &["256.1.1.1", "1.2.3", "1.2.3.4.5", "01.2.3.4"],
+    },
+    BuiltinDef {
+        name: "std.hex4",
+        meaning: "one to four hex digits, a group of an IPv6 address",
+        source: r#"def(std.hex4: "\x{1,4}")"#,
+        matches: &["0", "fe80", "FFFF"],
+        misses: &["12345", "g1"],
+    },
+    BuiltinDef {
+        name: "std.ipv6",
+        meaning: "an IPv6 address, either all eight groups or with one \"::\" standing for missing ones",
+        source: r#"def(std.ipv6: or(
+    and(get(std.hex4) and(":" get(std.hex4)){7})
+    and(and(and(get(std.hex4) ":"){0,6} get(std.hex4))? "::" and(get(std.hex4) and(":" get(std.hex4)){0,6})?)))"#,
+        matches: &["2001:db8:0:0:0:ff00:42:8329", "2001:db8::ff00:42:8329", "::1", "fe80::", "::"],
+        misses: &["2001:db8:0:0:0:ff00:42", "1::2::3", "12345::", "fe80:::1"],
+    },
+    BuiltinDef {
+        name: "std.email",
+        meaning: "something shaped like an email address: a user name, '@', and a dotted domain name",
+        source: r#"def(std.email: "[a-zA-Z0-9._%+-]+@" and("[a-zA-Z0-9-]+\.")+ "[a-zA-Z]{2,}")"#,
+        matches: &["someone@example.com", "first.last+tag@mail.example.co.uk"],
+        misses: &["someone@", "@example.com", "someone@example", "some one@example.com"],
+    },
+    BuiltinDef {
+        name: "std.iso_date",
+        meaning: "an ISO 8601 date, YYYY-MM-DD",
+        source: r#"def(std.iso_date: "\d{4}-" or("0[1-9]" "1[0-2]") "-" or("0[1-9]" "[12]\d" "3[01]"))"#,
+        matches: &["2024-06-01", "1999-12-31"],
+        misses: &["2024-13-01", "2024-06-32", "2024-6-1", "24-06-01"],
+    },
+    BuiltinDef {
+        name: "std.iso_time",
+        meaning: "an ISO 8601 time, HH:MM with optional seconds, fraction and time zone",
+        source: r#"def(std.iso_time: or("[01]\d" "2[0-3]") ":[0-5]\d" and(":[0-5]\d" "\.\d+"?)? or("Z" "[+-]\d\d:\d\d")?)"#,
+        matches: &["09:30", "23:59:59", "12:00:00.123Z", "08:15:00+02:00"],
+        misses: &["24:00", "12:60", "9:30", "12:30:61"],
+    },
+    BuiltinDef {
+        name: "std.uuid",
+        meaning: "a UUID, 32 hex digits in groups of 8, 4, 4, 4 and 12",
+        source: r#"def(std.uuid: "\x{8}-\x{4}-\x{4}-\x{4}-\x{12}")"#,
+        matches: &["123e4567-e89b-12d3-a456-426614174000", "00000000-0000-0000-0000-000000000000"],
+        misses: &["123e4567e89b12d3a456426614174000", "123e4567-e89b-12d3-a456-42661417400g"],
+    },
+    BuiltinDef {
+        name: "std.quoted_string",
+        meaning: "a double quoted string, in which a backslash escapes the next character",
+        source: r#"def(std.quoted_string: '"' or(none('"\\') "\\.")* '"')"#,
+        matches: &[r#""""#, r#""hello""#, r#""say \"hi\"""#, r#""back\\slash""#],
+        misses: &[r#""open"#, r#""a"b""#, "'single'"],
+    },
+    BuiltinDef {
+        name: "std.integer",
+        meaning: "a decimal integer with an optional sign",
+        source: r#"def(std.integer: "[+-]?\d+")"#,
+        matches: &["0", "42", "-17", "+3"],
+        misses: &["", "-", "4.2", "1e3"],
+    },
+    BuiltinDef {
+        name: "std.float",
+        meaning: "a decimal number with a fraction, an exponent or both, and an optional sign",
+        source: r#"def(std.float: "[+-]?" or(
+    and("\d+\.\d*" "[eE][+-]?\d+"?)
+    and("\.\d+" "[eE][+-]?\d+"?)
+    "\d+[eE][+-]?\d+"))"#,
+        matches: &["3.14", "-0.5", "1.", ".5", "6.02e23", "1E-9", "+2.5e+3"],
+        misses: &["42", ".", "1e", "e5", "1.2.3"],
+    },
+];
+
+/// Gets the builtin definitions as the text of a library file
+pub fn builtin_source() -> String {
+    BUILTIN_DEFS
+        .iter()
+        .map(|def| format!("# {}\n{}\n", def.meaning, def.source))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+/// Builds the listing printed by **--defs-list builtin**, with each definition and some texts it matches
+pub fn builtin_summary() -> String {
+    let mut summary = format!(
+        "Builtin definitions, loaded by use({}). get(NAME) finds {}NAME unless NAME has been defined.\n",
+        BUILTIN_PATH, BUILTIN_PREFIX
+    );
+    for def in BUILTIN_DEFS.iter() {
+        summary.push_str(&format!(
+            "\n{}: {}\n  {}\n  matches: {}\n",
+            def.name,
+            def.meaning,
+            def.source
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" "),
+            def.matches
+                .iter()
+                .filter(|text| !text.is_empty())
+                .copied()
+                .collect::<Vec<&str>>()
+                .join("  ")
+        ));
+    }
+    summary
+}
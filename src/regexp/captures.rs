@@ -0,0 +1,269 @@
+//! ## Captures
+//! A **Report** is a tree, which is good for showing the structure of a match but makes simple questions like "what
+//! text did group 2 match" take several calls and an **Input::apply()**. This module gives a flatter API shaped like
+//! the regex crate's, so code using that can be moved over easily:
+//!
+//! ```ignore
+//! use regexp::regexp::{Error, Regexp};
+//!
+//! fn main() -> Result<(), Error> {
+//!     let re = Regexp::new(r"\(?<user>\l+\)@\(?<host>[a-z.]+\)")?;
+//!     if let Some(caps) = re.captures("mail bob@example.com")? {
+//!         assert_eq!(&caps[0], "bob@example.com");
+//!         assert_eq!(&caps["user"], "bob");
+//!         assert_eq!(caps.name("host").unwrap().start(), 9);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The crate is only built as a program, so the example is not run as a doctest. The **captures** unit test checks
+//! the same searches.
+//!
+//! The RE is parsed once, when the **Regexp** is made, with any definitions it uses copied into the tree. Searches
+//! only walk the tree, so one **Regexp** can be used on any number of texts without parsing again, and later changes
+//! to the definitions do not affect it.
+//!
+//! The groups are the recorded nodes of the **Report** in order: 0 is the whole match, followed by the others in the
+//! order they start in the RE. A group inside a repetition is recorded once for each time it matched, so it can take
+//! up several numbers.
+
+use crate::regexp::options::{parse_tree_opts, with_walk_options, SearchOptions};
+use crate::regexp::tree::Node;
+use crate::regexp::walk::{walk_tree_opts, wildcard_reaches_end, Input};
+use crate::regexp::{Error, Report};
+use std::ops::Index;
+
+/// A compiled regular expression
+#[derive(Debug, Clone)]
+pub struct Regexp {
+    tree: Node,
+    options: SearchOptions,
+    /// the tree without a trailing ".*", and whether it was greedy, for searches that need only the whole match
+    trimmed: Option<(Node, bool)>,
+}
+
+impl Regexp {
+    /// Parses RE using the traditional syntax
+    pub fn new(re: &str) -> Result<Regexp, Error> {
+        Regexp::with_parser(re, false)
+    }
+
+    /// Parses RE using the alternative syntax
+    pub fn new_alt(re: &str) -> Result<Regexp, Error> {
+        Regexp::with_parser(re, true)
+    }
+
+    /// Parses RE, using the alternative syntax if **alt_parser** is set
+    pub fn with_parser(re: &str, alt_parser: bool) -> Result<Regexp, Error> {
+        Regexp::with_options(re, SearchOptions::new().alt_parser(alt_parser))
+    }
+
+    /// Parses RE as set out by OPTIONS, which are kept for the searches
+    pub fn with_options(re: &str, options: SearchOptions) -> Result<Regexp, Error> {
+        let tree = parse_tree_opts(re, &options)?.0;
+        Ok(Regexp {
+            trimmed: tree.without_trailing_wildcard(),
+            tree,
+            options,
+        })
+    }
+
+    /// Gets the parse tree
+    pub fn tree(&self) -> &Node {
+        &self.tree
+    }
+
+    /// Gets the options the RE was made with
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+
+    /// Searches TEXT for the first match, returning its groups. Like all searches this uses the global **Input**, so
+    /// it replaces any text set up earlier.
+    ///
+    /// The text is set up with the RE's options in force, so in bytes mode it is searched as bytes. Positions are
+    /// still byte positions in TEXT, and a match that ends inside one of its chars is error 232.
+    pub fn captures<'t>(&self, text: &'t str) -> Result<Option<Captures<'t>>, Error> {
+        with_walk_options(&self.options, || {
+            Input::init_string(text, false);
+            walk_tree_opts(&self.tree, 0, &self.options)?
+                .map(|path| Captures::new(text, &Report::new(&path)))
+                .transpose()
+        })
+    }
+
+    /// Searches TEXT for the first match, returning just the whole match
+    pub fn find<'t>(&self, text: &'t str) -> Result<Option<Match<'t>>, Error> {
+        with_walk_options(&self.options, || {
+            Input::init_string(text, false);
+            // a trailing ".*" does not change where the match starts, and a greedy one always ends at the end of the
+            // text
+            let (tree, to_end) = match &self.trimmed {
+                Some((trimmed, greedy)) if wildcard_reaches_end() => (trimmed, *greedy),
+                _ => (&self.tree, false),
+            };
+            walk_tree_opts(tree, 0, &self.options)?
+                .map(|path| {
+                    let (start, end) = if Input::bytes_mode() {
+                        Report::new(&path).offsets()
+                    } else {
+                        path.range()
+                    };
+                    let end = if to_end { text.len() } else { end };
+                    Match::new(text, (start, end), Some("".to_string()))
+                })
+                .transpose()
+        })
+    }
+
+    /// true if the RE matches somewhere in TEXT
+    pub fn is_match(&self, text: &str) -> Result<bool, Error> {
+        Ok(self.find(text)?.is_some())
+    }
+}
+
+/// A single group from a match. Positions are byte positions in the searched text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'t> {
+    start: usize,
+    end: usize,
+    text: &'t str,
+    name: Option<String>,
+}
+
+impl<'t> Match<'t> {
+    /// makes the match of bytes START to END of TEXT. In bytes mode a match can end inside a char of TEXT, which
+    /// cannot be given as a &str
+    fn new(
+        text: &'t str,
+        (start, end): (usize, usize),
+        name: Option<String>,
+    ) -> Result<Match<'t>, Error> {
+        match text.get(start..end) {
+            Some(matched) => Ok(Match {
+                start,
+                end,
+                text: matched,
+                name,
+            }),
+            None => Err(Error::make(
+                232,
+                &format!(
+                    "The match at bytes {} to {} splits a character of the text, so it cannot be returned as a str",
+                    start, end
+                ),
+            )),
+        }
+    }
+
+    /// the byte position the match starts at
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// the byte position just past the end of the match
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// the range of the match in the searched text
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+
+    /// the length of the match in bytes
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// true if the match is empty
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// the matched text
+    pub fn as_str(&self) -> &'t str {
+        self.text
+    }
+
+    /// the group's name, None if it is unnamed
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref().filter(|name| !name.is_empty())
+    }
+}
+
+/// All the groups from a single match
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures<'t> {
+    matches: Vec<Match<'t>>,
+}
+
+impl<'t> Captures<'t> {
+    /// flattens a **Report** for a search of TEXT
+    fn new(text: &'t str, report: &Report) -> Result<Captures<'t>, Error> {
+        let mut matches = Vec::new();
+        Captures::flatten(text, report, &mut matches)?;
+        Ok(Captures { matches })
+    }
+
+    /// adds REPORT and its children to MATCHES in order
+    fn flatten(text: &'t str, report: &Report, matches: &mut Vec<Match<'t>>) -> Result<(), Error> {
+        matches.push(Match::new(text, report.offsets(), report.name.clone())?);
+        for subreport in report.subreports.iter() {
+            Captures::flatten(text, subreport, matches)?;
+        }
+        Ok(())
+    }
+
+    /// Gets group I, 0 is the whole match
+    pub fn get(&self, i: usize) -> Option<&Match<'t>> {
+        self.matches.get(i)
+    }
+
+    /// Gets the group named NAME. If the group matched more than once (it is in a repetition) this is the first.
+    pub fn name(&self, name: &str) -> Option<&Match<'t>> {
+        self.matches
+            .iter()
+            .find(|m| m.name.as_deref() == Some(name) && !name.is_empty())
+    }
+
+    /// the number of groups, including the whole match
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// always false, since the whole match is always there
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// iterates over the groups in order
+    pub fn iter(&self) -> std::slice::Iter<'_, Match<'t>> {
+        self.matches.iter()
+    }
+}
+
+/// Gets the text of group I. This panics if there is no such group, use **get()** to check.
+impl Index<usize> for Captures<'_> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        match self.get(i) {
+            Some(m) => m.as_str(),
+            None => panic!("No group {} in the match", i),
+        }
+    }
+}
+
+/// Gets the text of the group named NAME. This panics if there is no such group, use **name()** to check.
+impl Index<&str> for Captures<'_> {
+    type Output = str;
+
+    fn index(&self, name: &str) -> &str {
+        match self.name(name) {
+            Some(m) => m.as_str(),
+            None => panic!("No group named \"{}\" in the match", name),
+        }
+    }
+}
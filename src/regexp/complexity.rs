@@ -0,0 +1,73 @@
+//! ## Complexity score
+//! **Node::complexity_score()** gives a rough idea of how expensive an RE can be to search for, so a program taking
+//! REs from its users can turn away the likely troublemakers before running them. **SearchOptions::max_complexity()**
+//! (**--max-complexity N** on the command line) does that as part of parsing, failing with error 18.
+//!
+//! The score is worked out from the parsed tree, after it is optimized, as follows:
+//! - each node is worth 1 point, plus 10 if it repeats without limit ('*', '+', "{N,}"), 2 if it repeats a limited
+//!   number of times more than once, and 2 for each OR branch after the first
+//! - the points of a node are multiplied by 4 for each unlimited repetition around it, since every repetition of the
+//!   outer one can try every repetition of the inner one again when the walk backs off
+//! - the number of levels in the tree is added to the total
+//!
+//! Definitions count where they are used, as the units they stand for. The score saturates rather than overflowing.
+//! Some guide marks: plain text like "abc" scores 4, "\d+-\d+" 26, a log line pattern with a few ".\*" and an OR
+//! around 70, and the builtin definitions 35 to 90. Nested unlimited repetitions go up fast: "\(a+\)+" scores 59,
+//! "\(\(a+\)+\)+" 236, and one more level over 900. A limit of 150 lets ordinary REs through while stopping
+//! the worst ones. REs that are slow without any nesting, like "a[^;]\*b[^;]\*c[^;]\*d" (40), are not
+//! caught, so a timeout is still worth having. These rules are meant to stay the same from release to release, so
+//! limits set against them keep working.
+
+use crate::regexp::tree::{Node, EFFECTIVELY_INFINITE};
+
+/// the points for an unlimited repetition
+const UNLIMITED_POINTS: usize = 10;
+/// the points for a limited repetition
+const LIMITED_POINTS: usize = 2;
+/// the points for each OR branch after the first
+const BRANCH_POINTS: usize = 2;
+/// what the points of a node are multiplied by for each unlimited repetition around it
+const NESTING_FACTOR: usize = 4;
+
+impl Node {
+    /// A heuristic for how expensive the RE can be to search for, see the module documentation
+    pub fn complexity_score(&self) -> usize {
+        let mut depth = 0;
+        let points = self.complexity_points(0, 1, &mut depth);
+        points.saturating_add(depth)
+    }
+
+    /// the points for the node and those under it, where it is inside UNLIMITED unlimited repetitions and on level
+    /// LEVEL. DEPTH is set to the deepest level found.
+    fn complexity_points(&self, unlimited: u32, level: usize, depth: &mut usize) -> usize {
+        if let Node::Def(a) = self {
+            return a.node.complexity_points(unlimited, level, depth);
+        }
+        if self.is_none() {
+            return 0;
+        }
+        *depth = (*depth).max(level);
+        let limits = self.limits();
+        let mut points = 1;
+        let mut inside = unlimited;
+        if limits.max == EFFECTIVELY_INFINITE {
+            points += UNLIMITED_POINTS;
+            inside += 1;
+        } else if limits.max > 1 {
+            points += LIMITED_POINTS;
+        }
+        let children = match self {
+            Node::And(a) => &a.nodes[..],
+            Node::Or(a) => {
+                points += BRANCH_POINTS * a.nodes.len().saturating_sub(1);
+                &a.nodes[..]
+            }
+            _ => &[],
+        };
+        let mut total = points.saturating_mul(NESTING_FACTOR.saturating_pow(unlimited));
+        for child in children {
+            total = total.saturating_add(child.complexity_points(inside, level + 1, depth));
+        }
+        total
+    }
+}
@@ -0,0 +1,228 @@
+//! ## Search driver
+//! **SearchDriver** searches everything set up in **Input**, one source after another, the way the command line
+//! does, but prints nothing. What happens is handed to a **MatchSink** as a **MatchEvent**: each source as it is
+//! started, each match, each source that could not be read, a search that failed, each source as it is finished,
+//! and the **RunStats** at the end. **regexp()** runs one with the sinks that print the command line output, and the
+//! interactive **search file** command runs one with its own.
+//!
+//! Any closure taking a **&MatchEvent** is a sink, so a library caller can follow the events without writing a type:
+//!
+//! > Input::init_files(&files, false)?;
+//! > let stats = SearchDriver::new(&tree).all().run(&mut |event: &MatchEvent| {
+//! >     if let MatchEvent::Match(found) = event {
+//! >         println!("{}:{}: {:?}", found.file.unwrap_or("-"), found.line, found.report.byte_pos());
+//! >     }
+//! > });
+//!
+//! The sink is called with **INPUT** unlocked and the source the event is about still loaded, so it can look at
+//! the text with **Input::apply()** or **Input::with_text()**.
+
+use crate::regexp::stats::{RunStats, SourceStats};
+use crate::regexp::tree::Node;
+use crate::regexp::walk::{
+    count_matches, deepening_trees, next_start, walk_tree_deepening, walk_tree_next, Input, Path,
+};
+use crate::regexp::{Error, Report};
+
+/// A match found by a **SearchDriver**
+pub struct FoundMatch<'e> {
+    /// the path walked to find it, which is what **--walk** dumps
+    pub path: &'e Path<'e>,
+    /// the report made from the path
+    pub report: &'e Report,
+    /// the file it is in, None for text or stdin
+    pub file: Option<&'e str>,
+    /// the line it starts in, counting from 1
+    pub line: usize,
+}
+
+/// Something that happened in a run of a **SearchDriver**
+pub enum MatchEvent<'e> {
+    /// a source is about to be searched, with its name: the file name, or "text" or "stdin"
+    FileStarted(&'e str),
+    /// a match was found
+    Match(FoundMatch<'e>),
+    /// a source could not be read, or stopped being readable part way through. It is skipped, or the search goes on
+    /// with what was read from it.
+    FileErrored(&'e str, &'e Error),
+    /// the search failed, for example it was cancelled or timed out, and the run stops
+    SearchFailed(&'e Error),
+    /// a source has been searched, with what was found in it
+    FileFinished(&'e SourceStats),
+    /// the run is over
+    Finished(&'e RunStats),
+}
+
+/// Receives the events of a **SearchDriver** run, in the order they happen
+pub trait MatchSink {
+    fn event(&mut self, event: &MatchEvent);
+}
+
+impl<F: FnMut(&MatchEvent)> MatchSink for F {
+    fn event(&mut self, event: &MatchEvent) {
+        self(event)
+    }
+}
+
+/// Runs a search over all the sources in **Input**, see the module documentation
+pub struct SearchDriver<'t> {
+    tree: &'t Node,
+    /// the most matches to find, None to find them all
+    limit: Option<usize>,
+    /// if set the matches are only counted (see **count_matches()**), so there are no **Match** events
+    count_only: bool,
+    /// if set, the caps for a best effort search (see **walk_tree_deepening()**)
+    deepening: Option<Vec<usize>>,
+}
+
+impl<'t> SearchDriver<'t> {
+    /// constructor, for a search for the first match of TREE
+    pub fn new(tree: &'t Node) -> SearchDriver<'t> {
+        SearchDriver {
+            tree,
+            limit: Some(1),
+            count_only: false,
+            deepening: None,
+        }
+    }
+
+    /// finds every match
+    pub fn all(mut self) -> SearchDriver<'t> {
+        self.limit = None;
+        self
+    }
+
+    /// finds at most COUNT matches, or all of them if COUNT is 0
+    pub fn limit(mut self, count: usize) -> SearchDriver<'t> {
+        self.limit = if count == 0 { None } else { Some(count) };
+        self
+    }
+
+    /// counts all the matches without reporting them, which is faster
+    pub fn count_only(mut self) -> SearchDriver<'t> {
+        self.count_only = true;
+        self
+    }
+
+    /// makes each search a best effort one, trying the tree with its unlimited repetitions capped at each of CAPS
+    /// before the full tree (see **walk_tree_deepening()**). This can miss very long matches.
+    pub fn deepening(mut self, caps: &[usize]) -> SearchDriver<'t> {
+        self.deepening = Some(caps.to_vec());
+        self
+    }
+
+    /// Searches the sources, sending what happens to SINK, and returns the statistics for the run
+    pub fn run(&self, sink: &mut dyn MatchSink) -> RunStats {
+        let mut stats = RunStats::new();
+        let trees = self
+            .deepening
+            .as_ref()
+            .map(|caps| deepening_trees(self.tree, caps));
+        // the number of entries of Input::errors() sent to the sink
+        let mut errors = send_errors(0, sink);
+        loop {
+            sink.event(&MatchEvent::FileStarted(&Input::source_name()));
+            let (found, go_on) = if self.count_only && trees.is_none() {
+                match count_matches(self.tree, 0) {
+                    Err(error) => {
+                        sink.event(&MatchEvent::SearchFailed(&error));
+                        (0, false)
+                    }
+                    Ok(found) => (found, true),
+                }
+            } else {
+                self.search_source(trees.as_deref(), stats.matches, sink)
+            };
+            errors = send_errors(errors, sink);
+            stats.add_source(found);
+            sink.event(&MatchEvent::FileFinished(stats.files.last().unwrap()));
+            if !go_on {
+                break;
+            }
+            // files that cannot be read are skipped by next_file(), so this moves on or ends
+            match Input::next_file() {
+                Err(error) => {
+                    sink.event(&MatchEvent::SearchFailed(&error));
+                    break;
+                }
+                Ok(false) => break,
+                Ok(true) => errors = send_errors(errors, sink),
+            }
+        }
+        stats.finish();
+        sink.event(&MatchEvent::Finished(&stats));
+        stats
+    }
+
+    /// Finds the matches in the current source, FOUND_BEFORE having been found in the sources before it, searching
+    /// with the deepening TREES if there are any. Returns the number found, and whether to go on to the next source.
+    fn search_source(
+        &self,
+        trees: Option<&[Node]>,
+        found_before: usize,
+        sink: &mut dyn MatchSink,
+    ) -> (usize, bool) {
+        let file = Input::apply(|input| input.current_file().map(str::to_string));
+        let mut found = 0;
+        let mut start = 0;
+        // the line LINE_POS is in, so each match only counts the newlines since the last one
+        let (mut line, mut line_pos) = (1, 0);
+        loop {
+            let walked = match trees {
+                Some(trees) => walk_tree_deepening(trees, start).map(|path| {
+                    path.map(|path| {
+                        let (start, end) = path.range();
+                        (path, next_start(start, end))
+                    })
+                }),
+                None => walk_tree_next(self.tree, start),
+            };
+            match walked {
+                Err(error) => {
+                    sink.event(&MatchEvent::SearchFailed(&error));
+                    return (found, false);
+                }
+                Ok(None) => return (found, true),
+                // counting with deepening trees, which count_matches() cannot do
+                Ok(Some((_, next))) if self.count_only => {
+                    found += 1;
+                    start = next;
+                    if self.tree.anchored() {
+                        return (found, true);
+                    }
+                }
+                Ok(Some((path, next))) => {
+                    found += 1;
+                    let match_start = path.range().0;
+                    line +=
+                        Input::with_text(|text| text[line_pos..match_start].matches('\n').count());
+                    line_pos = match_start;
+                    let report = Report::new(&path);
+                    sink.event(&MatchEvent::Match(FoundMatch {
+                        path: &path,
+                        report: &report,
+                        file: file.as_deref(),
+                        line,
+                    }));
+                    start = next;
+                    if self.limit == Some(found_before + found) {
+                        return (found, false);
+                    }
+                    // an anchored RE can only match where the text starts
+                    if self.tree.anchored() {
+                        return (found, true);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// sends the entries of **Input::errors()** from number SENT on to SINK, returning the number sent in all
+fn send_errors(sent: usize, sink: &mut dyn MatchSink) -> usize {
+    let errors = Input::errors();
+    for (name, error) in errors.iter().skip(sent) {
+        sink.event(&MatchEvent::FileErrored(name, error));
+    }
+    errors.len()
+}
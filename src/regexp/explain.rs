@@ -0,0 +1,233 @@
+//! ## Explaining REs
+//! **Node::explain()** describes a parsed tree in plain English, for reading an RE someone else wrote or learning the
+//! syntax. It follows the shape of **desc()**: a unit to a line, with the units of a group or OR indented under it
+//! by **TAB_SIZE**, but says what each one matches rather than how it is stored:
+//!
+//! > $ regexp --explain '^\(?<id>\d+\)-\(\(cat\)\|\(dog\)\)?'
+//! > at the start of the text, match 1 or more digits, captured as 'id'
+//! > then "-"
+//! > then optionally a group, captured:
+//! >     match one of these:
+//! >         either "cat", captured
+//! >         or "dog", captured
+//!
+//! Both syntaxes give the same trees, so they are explained the same way. Definitions are explained where they are
+//! used, marked with the name they came from. The warnings from the parse follow as notes. **--explain** and the
+//! interactive **explain** command print it.
+
+use crate::regexp::tree::{Limits, Node, EFFECTIVELY_INFINITE};
+use crate::regexp::{Warning, TAB_SIZE};
+
+impl Node {
+    /// Describes the tree in plain English, a unit to a line, followed by WARNINGS as notes
+    pub fn explain(&self, warnings: &[Warning]) -> String {
+        let mut lines = Vec::new();
+        match self {
+            Node::And(and_node) => {
+                let first = if and_node.anchor {
+                    "at the start of the text, match "
+                } else {
+                    "match "
+                };
+                explain_sequence(&and_node.nodes, first, 0, &mut lines);
+            }
+            node => explain_unit(node, "match ", 0, "", &mut lines),
+        }
+        if lines.is_empty() {
+            lines.push("match the empty string".to_string());
+        }
+        if !warnings.is_empty() {
+            lines.push("Notes:".to_string());
+            for warning in warnings {
+                lines.push(match warning.pos {
+                    Some(pos) => format!("  - {} (at position {})", warning.msg, pos),
+                    None => format!("  - {}", warning.msg),
+                });
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// adds the lines for NODES, which match one after the other, indented by INDENT. The first starts with FIRST and
+/// the rest with "then".
+fn explain_sequence(nodes: &[Node], first: &str, indent: usize, lines: &mut Vec<String>) {
+    for (i, node) in nodes.iter().filter(|node| !node.is_none()).enumerate() {
+        explain_unit(
+            node,
+            if i == 0 { first } else { "then " },
+            indent,
+            "",
+            lines,
+        );
+    }
+}
+
+/// adds the lines for NODE, indented by INDENT, with PREFIX before it and EXTRA after what it says about itself
+fn explain_unit(node: &Node, prefix: &str, indent: usize, extra: &str, lines: &mut Vec<String>) {
+    let (phrase, limits) = match node {
+        Node::Chars(a) => (repeated(&a.limits, &format!("{:?}", a.string)), &a.limits),
+        Node::Special(a) if a.special == '$' => ("the end of the text".to_string(), &a.limits),
+        Node::Special(a) => {
+            let phrase = match &a.upto {
+                Some(upto) => counted(
+                    &a.limits,
+                    &format!("a character that does not start {:?}", upto),
+                    &format!("characters up to {:?}", upto),
+                ),
+                None => {
+                    let (one, many) = special_nouns(a.special);
+                    counted(&a.limits, one, many)
+                }
+            };
+            (phrase, &a.limits)
+        }
+        Node::Range(a) => (
+            counted(
+                &a.limits,
+                &format!("a character matching {}", a),
+                &format!("characters matching {}", a),
+            ),
+            &a.limits,
+        ),
+        // a group around a single unit, with nothing of its own but a name, is just that unit
+        Node::And(a)
+            if a.nodes.len() == 1
+                && a.limits == Limits::default()
+                && a.nodes[0].named().is_none() =>
+        {
+            let extra = format!("{}{}", captured(&a.named, &a.limits, false), extra);
+            return explain_unit(&a.nodes[0], prefix, indent, &extra, lines);
+        }
+        Node::And(a) if a.nodes.is_empty() => (repeated(&a.limits, "the empty string"), &a.limits),
+        Node::And(a) => {
+            let header = repeated(&a.limits, "a group");
+            lines.push(header_line(prefix, indent, &header, node, extra));
+            explain_sequence(&a.nodes, "match ", indent + TAB_SIZE, lines);
+            return;
+        }
+        Node::Or(a) => {
+            let header = repeated(&a.limits, "one of these");
+            lines.push(header_line(prefix, indent, &header, node, extra));
+            for (i, child) in a.nodes.iter().filter(|child| !child.is_none()).enumerate() {
+                explain_unit(
+                    child,
+                    if i == 0 { "either " } else { "or " },
+                    indent + TAB_SIZE,
+                    "",
+                    lines,
+                );
+            }
+            return;
+        }
+        // the definition already has the name and repetition of the get()
+        Node::Def(a) if !a.node.is_none() => {
+            let extra = format!("{} (from def {})", extra, a.name);
+            return explain_unit(&a.node, prefix, indent, &extra, lines);
+        }
+        Node::Def(a) => (
+            format!("the definition {}, which is not loaded", a.name),
+            &a.limits,
+        ),
+        Node::None => return,
+    };
+    lines.push(format!(
+        "{:indent$}{}{}{}{}",
+        "",
+        prefix,
+        phrase,
+        qualifiers(node, limits),
+        extra,
+        indent = indent
+    ));
+}
+
+/// the line introducing a group or OR, whose units are listed under it
+fn header_line(prefix: &str, indent: usize, header: &str, node: &Node, extra: &str) -> String {
+    format!(
+        "{:indent$}{}{}{}{}:",
+        "",
+        prefix,
+        header,
+        qualifiers(node, node.limits()),
+        extra,
+        indent = indent
+    )
+}
+
+/// what a unit does besides match: taking as few repetitions as it can, ignoring case, and being captured
+fn qualifiers(node: &Node, limits: &Limits) -> String {
+    let mut qualifiers = String::new();
+    if limits.lazy() && limits.min != limits.max {
+        qualifiers.push_str(", as few as possible");
+    }
+    if limits.no_case() && matches!(node, Node::Chars(_) | Node::Range(_)) {
+        qualifiers.push_str(", ignoring case");
+    }
+    if !matches!(node, Node::Def(_)) {
+        qualifiers.push_str(&captured(node.named(), limits, node.name_outside()));
+    }
+    qualifiers
+}
+
+/// says how a unit with the name NAMED is recorded. A repeated unit is recorded once for each repetition unless the
+/// name is for them all (TOGETHER).
+fn captured(named: &Option<String>, limits: &Limits, together: bool) -> String {
+    let Some(name) = named else {
+        return "".to_string();
+    };
+    let each = if limits.max > 1 && !together {
+        " each one"
+    } else {
+        ""
+    };
+    if name.is_empty() {
+        format!(",{} captured", each)
+    } else {
+        format!(",{} captured as '{}'", each, name)
+    }
+}
+
+/// says how many repetitions LIMITS allow, to go in front of a plural like "digits" or "times"
+fn count(limits: &Limits) -> String {
+    match (limits.min, limits.max) {
+        (0, EFFECTIVELY_INFINITE) => "any number of".to_string(),
+        (min, EFFECTIVELY_INFINITE) => format!("{} or more", min),
+        (0, max) => format!("up to {}", max),
+        (min, max) if min == max => min.to_string(),
+        (min, max) => format!("{} to {}", min, max),
+    }
+}
+
+/// describes a unit matching single chars, ONE if it matches one and a count of MANY otherwise
+fn counted(limits: &Limits, one: &str, many: &str) -> String {
+    match (limits.min, limits.max) {
+        (1, 1) => one.to_string(),
+        (0, 1) => format!("optionally {}", one),
+        _ => format!("{} {}", count(limits), many),
+    }
+}
+
+/// describes a unit that can match more than one char, PHRASE followed by how many times it repeats
+fn repeated(limits: &Limits, phrase: &str) -> String {
+    match (limits.min, limits.max) {
+        (1, 1) => phrase.to_string(),
+        (0, 1) => format!("optionally {}", phrase),
+        _ => format!("{}, {} times", phrase, count(limits)),
+    }
+}
+
+/// what a special char matches, as it is said for one of them and for more than one
+fn special_nouns(special: char) -> (&'static str, &'static str) {
+    match special {
+        '.' => ("any character", "characters"),
+        'a' => ("an ascii printable character", "ascii printable characters"),
+        'd' => ("a digit", "digits"),
+        'l' => ("a lower case ascii letter", "lower case ascii letters"),
+        'o' => ("an octal digit", "octal digits"),
+        'u' => ("an upper case ascii letter", "upper case ascii letters"),
+        'w' => ("a space, tab or newline", "spaces, tabs or newlines"),
+        'x' => ("a hex digit", "hex digits"),
+        _ => ("an unknown special character", "unknown special characters"),
+    }
+}
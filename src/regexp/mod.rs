@@ -1,9 +1,40 @@
+pub mod ast;
+pub mod builtin;
+pub mod captures;
+pub mod complexity;
+pub mod driver;
+pub mod explain;
+pub mod options;
+pub mod scan;
+pub mod selftest;
+pub mod set;
+pub mod source;
+pub mod stats;
+pub mod syntax;
+pub mod trace;
 pub mod tree;
 pub mod walk;
 
 // Export functions
-pub use crate::regexp::tree::parse_tree;
-pub use crate::regexp::walk::{walk_tree, Input};
+pub use crate::regexp::ast::{AstKind, AstLimits, AstNode};
+pub use crate::regexp::captures::{Captures, Match, Regexp};
+pub use crate::regexp::driver::{FoundMatch, MatchEvent, MatchSink, SearchDriver};
+pub use crate::regexp::options::{parse_tree_opts, SearchOptions};
+pub use crate::regexp::set::{scan_lines, LineHits, LineScan, RegexpSet};
+pub use crate::regexp::stats::{RunStats, SkippedFile, SourceStats};
+pub use crate::regexp::trace::{
+    set_trace_backend, set_trace_format, set_trace_writer, trace_line, trace_message, TraceBackend,
+    TraceFormat,
+};
+pub use crate::regexp::tree::{
+    check_escapes, parse_count, parse_tree, parse_tree_full, parse_tree_with_warnings, set_lenient,
+    set_literal_braces, set_whitespace, TreeView, Whitespace,
+};
+pub use crate::regexp::walk::{
+    count_matches, deepening_trees, match_prefix_len, set_walk_options, shortest_match, walk_tree,
+    walk_tree_deepening, walk_tree_next, walk_tree_opts, GroupCallback, Input, WalkOptions,
+    WindowEdge, DEEPENING_CAPS,
+};
 
 use crate::regexp::walk::Matched;
 use clap::{value_parser, Parser}; // Command Line Argument Processing
@@ -11,90 +42,359 @@ use core::sync::atomic::{
     AtomicIsize, AtomicUsize,
     Ordering::{AcqRel, Acquire, Release},
 };
-use std::collections::HashMap;
+use std::io::Write;
 
 /// General function to run a search based on the parameters in the passed Config. This can be used to simulate a grep
 /// replacement. It does a search and prints out the results according to the instructions in Config. It returns the
-/// number of matches found.
-pub fn regexp(config: &Config) -> Result<usize, Error> {
-    let mut count: usize = 0;
-    let tree = parse_tree(&config.re, "alternative".starts_with(&config.parser))?;
-    if config.tree {
+/// number of matches found, with the matches in each source and how much was read (see **RunStats**). Sources that
+/// cannot be read are skipped, and listed at the end with a warning line for each file that could not be opened
+/// (they are also available from **Input::errors()** and **Input::skipped()**); if none of them can be read it
+/// returns an error. The search is run by a **SearchDriver**, with the sinks below doing the
+/// printing.
+pub fn regexp(config: &Config) -> Result<RunStats, Error> {
+    if let Some(path) = &config.set_count {
+        return set_count(config, path);
+    }
+    let (tree, warnings) = parse_tree_opts(&config.re, &config.search_options())?;
+    if !config.no_warnings {
+        warnings.iter().for_each(|warning| eprintln!("{}", warning));
+    }
+    if let Some(view) = config.tree_view() {
         println!("--- Parse tree:");
-        tree.desc(0);
+        view.print(&tree);
     }
     if !config.text.is_empty() {
         Input::init_text(&config.text, config.lines)?
     } else if !config.files.is_empty() {
-        Input::init_files(&config.files, config.lines)?
+        if let Err(error) = Input::init_files(&config.files, config.lines) {
+            // the files are still listed when none of them could be opened
+            check_input_errors()?;
+            return Err(error);
+        }
     } else {
         Input::init_stdin(config.lines)?
     }
-    
-    let mut start: usize = 0;
-    let match_number: usize = if config.all { 0 } else { config.count as usize };
-    'main: loop {
-        match walk_tree(&tree, start) {
-            Err(msg) => eprintln!("{}", msg),
-            Ok(None) => loop {
-                match Input::next_file() {
-                    Err(msg) => eprintln!("{}", msg),
-                    Ok(false) => {
-                        break 'main;
-                    }
-                    Ok(true) => {
-                        start = 0;
-                        break;
-                    }
+    let mut driver = SearchDriver::new(&tree);
+    if config.deepen {
+        driver = driver.deepening(&DEEPENING_CAPS);
+    }
+    let stats = if config.count_only {
+        let stats = driver.count_only().run(&mut PrintSink { config });
+        println!("{}", stats.matches);
+        stats
+    } else if let Some(template) = &config.replace {
+        driver.all().run(&mut ReplaceSink::new(
+            template,
+            config.replace_preserve_case,
+        ))
+    } else {
+        driver
+            .limit(if config.all { 0 } else { config.count as usize })
+            .run(&mut PrintSink { config })
+    };
+    check_input_errors()?;
+    Ok(stats)
+}
+
+/// Prints each match as the flags in **Config** say, for **regexp()**
+struct PrintSink<'c> {
+    config: &'c Config,
+}
+
+impl MatchSink for PrintSink<'_> {
+    fn event(&mut self, event: &MatchEvent) {
+        match event {
+            MatchEvent::Match(found) => self.print_match(found),
+            MatchEvent::SearchFailed(error) => eprintln!("{}", error),
+            // the sources that could not be read are listed at the end, by check_input_errors()
+            _ => (),
+        }
+    }
+}
+
+impl PrintSink<'_> {
+    /// prints a single match
+    fn print_match(&self, found: &FoundMatch) {
+        let (config, path) = (self.config, found.path);
+        if config.walk {
+            println!("--- Walk:");
+            path.dump(0);
+            println!("--- End walk");
+        }
+        if let Some(chars) = config.context_chars {
+            // make sure the context after the match has been read
+            Input::extend_quiet(path.range().1.saturating_add(4 * chars.saturating_add(1)));
+            Input::apply(|input| {
+                let (before, matched, after) = path.match_context(input, chars);
+                if let Some(filename) = input.current_file() {
+                    println!("{}: {}{}{}", filename, before, matched, after);
+                } else {
+                    println!("{}{}{}", before, matched, after);
+                }
+            });
+        } else if config.quiet | config.lines {
+            Input::apply(|input| {
+                let text = Input::display_text(path.match_display(input));
+                if let Some(filename) = input.current_file() {
+                    println!("{}: {}", filename, text);
+                } else {
+                    println!("{}", text);
+                }
+            });
+        } else {
+            let pruned;
+            let mut report = found.report;
+            if !config.last_only.is_empty() || !config.only_named.is_empty() {
+                let mut cut = report.clone();
+                if !config.last_only.is_empty() {
+                    let last: Vec<&str> = config.last_only.iter().map(String::as_str).collect();
+                    cut = cut.last_only(&last);
                 }
-            },
-            Ok(Some(path)) => {
-                if config.walk {
-                    println!("--- Walk:");
-                    path.dump(0);
-                    println!("--- End walk");
+                if !config.only_named.is_empty() {
+                    let keep: Vec<&str> = config.only_named.iter().map(String::as_str).collect();
+                    cut = cut.prune(&keep);
                 }
-                if config.quiet | config.lines {
-                    Input::apply(|input| {
-                        if let Some(filename) = input.current_file() {
-                            println!("{}: {}", filename, path.match_display(input));
+                pruned = cut;
+                report = &pruned;
+            }
+            report.display(0);
+            if config.named {
+                Input::apply(|input| {
+                    for (name, v) in report.get_named() {
+                        if v.len() == 1 {
+                            println!(
+                                "{}: \"{}\"",
+                                if name.is_empty() { "(unnamed)" } else { name },
+                                Input::display_text(v[0].string(input))
+                            );
                         } else {
-                            println!("{}", path.match_display(input));
+                            println!("{}: ", if name.is_empty() { "(unnamed)" } else { name });
+                            v.iter().for_each(|x| {
+                                println!("    \"{}\"", Input::display_text(x.string(input)))
+                            });
                         }
-                    });
-                } else {
-                    let report = Report::new(&path);
-                    report.display(0);
-                    if config.named {
-                        Input::apply(|input| {
-                            for (name, v) in report.get_named() {
-                                if v.len() == 1 {
-                                    println!(
-                                        "{}: \"{}\"",
-                                        if name.is_empty() { "(unnamed)" } else { name },
-                                        v[0].string(input)
-                                    );
-                                } else {
-                                    println!(
-                                        "{}: ",
-                                        if name.is_empty() { "(unnamed)" } else { name }
-                                    );
-                                    v.iter()
-                                        .for_each(|x| println!("    \"{}\"", x.string(input)));
-                                }
-                            }
-                        });
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Prints each source with every match replaced, for **--replace**. A source whose search fails is not printed.
+struct ReplaceSink<'c> {
+    template: &'c str,
+    keep_case: bool,
+    /// the current source with the replacements made so far
+    text: String,
+    /// the end of the last match in the source, which is how far it has been copied to TEXT
+    copied: usize,
+    /// set if the search of the current source failed
+    failed: bool,
+}
+
+impl<'c> ReplaceSink<'c> {
+    /// constructor, replacing each match with TEMPLATE, following its case if KEEP_CASE is set
+    fn new(template: &'c str, keep_case: bool) -> ReplaceSink<'c> {
+        ReplaceSink {
+            template,
+            keep_case,
+            text: String::new(),
+            copied: 0,
+            failed: false,
+        }
+    }
+}
+
+impl MatchSink for ReplaceSink<'_> {
+    fn event(&mut self, event: &MatchEvent) {
+        match event {
+            MatchEvent::FileStarted(_) => {
+                self.text.clear();
+                self.copied = 0;
+                self.failed = false;
+            }
+            MatchEvent::Match(found) => {
+                let (start, end) = found.report.byte_pos();
+                let replaced = Input::apply(|input| {
+                    let replacement = found.report.expand(self.template, input);
+                    let replacement = if self.keep_case {
+                        preserve_case(found.report.string(input), &replacement)
+                    } else {
+                        replacement
+                    };
+                    format!("{}{}", &input.full_text[self.copied..start], replacement)
+                });
+                self.text.push_str(&replaced);
+                self.copied = end;
+            }
+            MatchEvent::SearchFailed(error) => {
+                eprintln!("{}", error);
+                self.failed = true;
+            }
+            MatchEvent::FileFinished(_) if !self.failed => {
+                let rest = Input::apply(|input| input.full_text[self.copied..].to_string());
+                print!("{}{}", self.text, rest);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Parses the REs for **--check** without searching: the RE on the command line, each line of the
+/// **--patterns-check** files and the definitions in the **--defs-check** files. It returns the problems found, the
+/// errors and (unless **--no-warnings**) the warnings, each starting with where it came from.
+pub fn check_patterns(config: &Config) -> Vec<String> {
+    let mut problems = Vec::<String>::new();
+    if !config.re.is_empty() {
+        check_re("RE", &config.re, config, &mut problems);
+    }
+    for path in config.patterns_check.iter() {
+        match std::fs::read_to_string(path) {
+            Err(err) => problems.push(format!("{}: Error reading pattern file: {}", path, err)),
+            Ok(string) => {
+                for (line_no, line) in string.lines().enumerate() {
+                    if !line.trim().is_empty() && !line.starts_with('#') {
+                        check_re(
+                            &format!("{}:{}", path, line_no + 1),
+                            line,
+                            config,
+                            &mut problems,
+                        );
                     }
                 }
-                count += 1;
-                start = path.end();
-                if count == match_number {
+            }
+        }
+    }
+    let previous = set_whitespace(config.whitespace());
+    for path in config.defs_check.iter() {
+        if let Err(err) = tree::load_def_file(path) {
+            problems.push(format!("{}: {}", path, err));
+        }
+    }
+    set_whitespace(previous);
+    problems
+}
+
+/// parses a single RE for **check_patterns()**, adding any problems to PROBLEMS
+fn check_re(source: &str, re: &str, config: &Config, problems: &mut Vec<String>) {
+    match parse_tree_opts(re, &config.search_options()) {
+        Err(err) => problems.push(format!("{}: {}", source, err)),
+        Ok((_, warnings)) => {
+            if !config.no_warnings {
+                warnings
+                    .iter()
+                    .for_each(|warning| problems.push(format!("{}: {}", source, warning)));
+            }
+        }
+    }
+}
+
+/// Lists the sources that could not be read, returning an error if none of them could be
+fn check_input_errors() -> Result<(), Error> {
+    list_input_errors(&Input::errors(), Input::source_count())
+}
+
+/// Lists ERRORS, the sources of the SOURCES in all that could not be read, returning an error if that is all of them.
+/// The files that could not be opened get a warning line each, and the rest are listed together.
+fn list_input_errors(errors: &[(String, Error)], sources: usize) -> Result<(), Error> {
+    let (skipped, unread): (Vec<_>, Vec<_>) =
+        errors.iter().partition(|(_, error)| error.code == 201);
+    for (source, error) in skipped {
+        eprintln!("Warning: skipping {}: {}", source, error);
+    }
+    if !unread.is_empty() {
+        eprintln!("Could not read {} of {} sources:", unread.len(), sources);
+        unread
+            .iter()
+            .for_each(|(source, error)| eprintln!("    {}: {}", source, error));
+    }
+    if !errors.is_empty() && errors.len() >= sources {
+        return Err(Error::make(211, "No input could be read"));
+    }
+    Ok(())
+}
+
+/// a source for **set_count()** to read, or the error opening it
+type SetSource<'a> = Result<Box<dyn std::io::BufRead + 'a>, Error>;
+
+/// Runs **--set-count**: counts the lines of the input that each RE in the pattern file at PATH matches, reading
+/// the input only once (see **scan_lines()**), and prints each count with its RE in the order of the file. The file
+/// has one RE to a line, skipping blank lines and lines starting with '#' as for **--patterns-check**. The matches
+/// in the **RunStats** are the (line, RE) pairs that matched.
+fn set_count(config: &Config, path: &str) -> Result<RunStats, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| Error::make(201, &format!("Error opening file {}: {}", path, err)))?;
+    // the walk options are left out so the global ones are used, which is where main() puts the ^C check
+    let options = SearchOptions {
+        walk: None,
+        ..config.search_options()
+    };
+    let mut patterns = Vec::new();
+    let mut regexps = Vec::new();
+    for (line_no, line) in contents.lines().enumerate() {
+        if !line.trim().is_empty() && !line.starts_with('#') {
+            let regexp = Regexp::with_options(line, options.clone()).map_err(|err| {
+                Error::make(err.code, &format!("{}:{}: {}", path, line_no + 1, err.msg))
+            })?;
+            patterns.push(line);
+            regexps.push(regexp);
+        }
+    }
+    let set = RegexpSet::from_regexps(regexps);
+    let mut sources: Vec<(String, SetSource)> = Vec::new();
+    if !config.text.is_empty() {
+        sources.push(("text".to_string(), Ok(Box::new(config.text.as_bytes()))));
+    } else if config.set_count_files().is_empty() {
+        sources.push(("stdin".to_string(), Ok(Box::new(std::io::stdin().lock()))));
+    }
+    for file in config.set_count_files() {
+        let reader = std::fs::File::open(file)
+            .map(|file| Box::new(std::io::BufReader::new(file)) as Box<dyn std::io::BufRead>)
+            .map_err(|err| Error::make(201, &format!("Error opening file {}: {}", file, err)));
+        sources.push((file.to_string(), reader));
+    }
+    let mut counts = vec![0; set.len()];
+    let mut stats = RunStats::new();
+    let mut errors = Vec::new();
+    let source_count = sources.len();
+    for (name, reader) in sources {
+        let reader = match reader {
+            Err(error) => {
+                errors.push((name, error));
+                continue;
+            }
+            Ok(reader) => reader,
+        };
+        let mut scan = scan_lines(&set, reader);
+        let mut matches = 0;
+        for hits in scan.by_ref() {
+            match hits {
+                // what was read before the error still counts, as for the other searches
+                Err(error) => {
+                    errors.push((name.clone(), error));
                     break;
                 }
+                Ok(hits) => {
+                    matches += hits.patterns.len();
+                    hits.patterns
+                        .iter()
+                        .for_each(|&pattern| counts[pattern] += 1);
+                }
             }
         }
+        stats.push_source(SourceStats {
+            name,
+            matches,
+            bytes: scan.bytes_read(),
+            lines: scan.lines_read(),
+        });
+    }
+    stats.finish_with(&errors);
+    for (count, pattern) in counts.iter().zip(patterns) {
+        println!("{}\t{}", count, pattern);
     }
-    Ok(count)
+    list_input_errors(&errors, source_count)?;
+    Ok(stats)
 }
 
 /// search strings using either traditional regular expressions or in a new (better) syntax
@@ -119,30 +419,165 @@ pub struct Config {
     /// Start up an interactive session
     #[clap(short, long, default_value_t = false)]
     pub interactive: bool,
+    /// print a summary of the syntax of PARSER (default the one given by --parser), with examples
+    #[clap(long, value_name = "PARSER", num_args = 0..=1, default_missing_value = "")]
+    pub syntax: Option<String>,
+    /// list the definitions in SOURCE, a library file or 'builtin' for the ones compiled in
+    #[clap(long, value_name = "SOURCE")]
+    pub defs_list: Option<String>,
+    /// run the built in self test, a table of REs searched with both syntaxes, to check the build works. The exit
+    /// status is 1 if any case fails
+    #[clap(long, default_value_t = false)]
+    pub self_test: bool,
+    /// parse the RE and the --patterns-check and --defs-check files without searching, printing each problem. The
+    /// exit status is 2 if there are any
+    #[clap(long, default_value_t = false)]
+    pub check: bool,
+    /// with --check, a file of REs to check, one to a line. Blank lines and lines starting with '#' are skipped
+    #[clap(long, value_name = "FILE")]
+    pub patterns_check: Vec<String>,
+    /// with --check, a library file of definitions to check
+    #[clap(long, value_name = "FILE")]
+    pub defs_check: Vec<String>,
     /// Prints the parsed regexp tree
     #[clap(short('T'), long, default_value_t = false)]
     pub tree: bool,
+    /// print the parsed regexp tree only DEPTH levels deep, saying how many nodes are left out below them
+    #[clap(long, value_name = "DEPTH", value_parser=value_parser!(u32).range(1..))]
+    pub tree_depth: Option<u32>,
+    /// print only the subtrees of the parsed regexp tree under the units named NAME
+    #[clap(long, value_name = "NAME")]
+    pub tree_filter: Option<String>,
+    /// print the parsed regexp tree as JSON, for tools like editor plugins, instead of searching
+    #[clap(long, default_value_t = false)]
+    pub tree_json: bool,
+    /// print a plain English description of what the RE matches, with any warnings as notes, instead of searching
+    #[clap(long, default_value_t = false)]
+    pub explain: bool,
+    /// print the RE in the alternative syntax with the definitions it uses written out in place, instead of searching
+    #[clap(long, default_value_t = false)]
+    pub expanded: bool,
+    /// with --expanded, print a unit to a line, marking each expanded definition with a "# from def NAME" comment
+    #[clap(long, default_value_t = false)]
+    pub expanded_notes: bool,
     /// Dumps the current path (the successful path, if called on the result of walk())
     #[clap(short, long, default_value_t = false)]
     pub walk: bool,
     /// Prints debug information. 1 - 8 give progressively more data
     #[clap(short, long, default_value_t = 0, value_parser=value_parser!(u32).range(0..40))]
     pub debug: u32,
+    /// Format for debug output: 'text', or 'json' to write the walk phase as one JSON event per line
+    #[clap(long, default_value_t = String::from("text"))]
+    pub debug_format: String,
     /// Prints result for all named units
     #[clap(short, long, default_value_t = false)]
     pub named: bool,
+    /// only show the named units in the comma separated list (and the units containing them)
+    #[clap(long, value_name = "NAMES", value_delimiter = ',')]
+    pub only_named: Vec<String>,
+    /// only show the last match of each of the named units in the comma separated list, the way other engines report
+    /// a repeated group
+    #[clap(long, value_name = "NAMES", value_delimiter = ',')]
+    pub last_only: Vec<String>,
     /// find all instances instead of just first
     #[clap(short, long, default_value_t = false)]
     pub all: bool,
     /// number of matches to find. Overruled by --all if it appears
     #[clap(short, long, default_value_t = 1)]
     pub count: u32,
+    /// just print the number of matches. This is faster than --all since no match is reported
+    #[clap(long, default_value_t = false)]
+    pub count_only: bool,
+    /// count the lines each RE in FILE matches, one RE to a line, reading the input once and printing each count with
+    /// its RE. All the other arguments are files to search
+    #[clap(long, value_name = "FILE")]
+    pub set_count: Option<String>,
+    /// in place of the "Found N instances" line, print a JSON summary of the run to stderr: the matches, bytes and
+    /// lines in each file, the files skipped as unopenable or binary and the time taken
+    #[clap(long, default_value_t = false)]
+    pub summary_json: bool,
+    /// print the text with every match replaced by TEMPLATE, where $name or ${name} is a named unit, $0 the whole
+    /// match, $N the Nth recorded unit and $$ is '$'
+    #[clap(long, value_name = "TEMPLATE")]
+    pub replace: Option<String>,
+    /// with --replace, make each replacement follow the case of the text it replaces: all upper case, capitalized or
+    /// all lower case
+    #[clap(long, default_value_t = false)]
+    pub replace_preserve_case: bool,
     /// just print out matched strings, no details or names
     #[clap(short, long, default_value_t = false)]
     pub quiet: bool,
     /// print out line(s) containing each match
     #[clap(short, long, default_value_t = false)]
     pub lines: bool,
+    /// print out each match with up to N characters of the text on each side of it
+    #[clap(long, value_name = "N")]
+    pub context_chars: Option<usize>,
+    /// do not print warnings about suspicious constructs in the regular expression
+    #[clap(long, default_value_t = false)]
+    pub no_warnings: bool,
+    /// make escapes with no meaning, like "\q", an error instead of a warning
+    #[clap(long, default_value_t = false)]
+    pub strict: bool,
+    /// make a '{' that is not followed by a digit match itself instead of being an error
+    #[clap(long, default_value_t = false)]
+    pub literal_braces: bool,
+    /// take text left over after the RE is complete, like a stray closing paren, as literal text with a warning
+    /// instead of an error
+    #[clap(long, default_value_t = false)]
+    pub lenient: bool,
+    /// ignore case in all the strings of the RE, as if each started with "\c"
+    #[clap(long, default_value_t = false)]
+    pub ignore_case: bool,
+    /// what the alternative syntax skips between units besides space, tab and newline: 'unicode' for carriage return
+    /// and the Unicode spaces like NBSP, 'strict-lines' to make a definition in a def file that runs onto another line
+    /// an error unless the line ends with '\'. Both can be given, separated by a comma
+    #[clap(long, value_name = "SET", value_delimiter = ',')]
+    pub whitespace: Vec<String>,
+    /// do not simplify the parsed regexp tree, scan ahead for where matches can start, or skip OR branches that already failed
+    #[clap(long, default_value_t = false)]
+    pub no_optimize: bool,
+    /// refuse an RE whose complexity score, a rough measure of how expensive it can be to search for, is over SCORE
+    #[clap(long, value_name = "SCORE")]
+    pub max_complexity: Option<usize>,
+    /// in interactive mode, load the saved session at startup and save it at exit
+    #[clap(long, default_value_t = false)]
+    pub persist: bool,
+    /// run the interactive commands in FILE, showing each one before its output, then exit. The exit status is 1 if
+    /// a command fails
+    #[clap(long, value_name = "FILE")]
+    pub script: Option<String>,
+    /// with --script, start the interactive prompt after the script instead of exiting
+    #[clap(long, default_value_t = false)]
+    pub script_interactive: bool,
+    /// with --script, go on to the next command when one fails instead of stopping
+    #[clap(short, long, default_value_t = false)]
+    pub keep_going: bool,
+    /// give up a search that takes longer than this
+    #[clap(long, value_name = "SECONDS")]
+    pub timeout: Option<f64>,
+    /// best effort search for heavy REs: try the RE with its unlimited repetitions capped at 64, then 1024, then
+    /// 16384, before searching with it as it is, and take the first match found. This can miss very long matches
+    #[clap(long, default_value_t = false)]
+    pub deepen: bool,
+    /// the longest match to accept. Longer matches are cut back, or not found
+    #[clap(long, value_name = "BYTES")]
+    pub max_match_len: Option<usize>,
+    /// read files and stdin in blocks of about this many bytes [default: 65536]
+    #[clap(long, value_name = "BYTES")]
+    pub read_block: Option<usize>,
+    /// give up a search when the text read in would be more than this [default: no limit]
+    #[clap(long, value_name = "BYTES")]
+    pub max_buffer: Option<usize>,
+    /// search the input as bytes, so it does not have to be UTF-8. The RE must be ASCII, positions are in bytes
+    #[clap(long, default_value_t = false)]
+    pub bytes: bool,
+    /// abandon a search with an error if a match would end inside a character, instead of splitting it
+    #[clap(long, default_value_t = false)]
+    pub check_boundaries: bool,
+    /// print matched text as it is, instead of showing control characters like NUL and ESC as escapes ("\x1b")
+    #[clap(long, default_value_t = false)]
+    pub raw_output: bool,
 }
 
 impl Config {
@@ -153,11 +588,75 @@ impl Config {
         if !"alternative".starts_with(&config.parser) && !"traditional".starts_with(&config.parser)
         {
             Err("Choices for parser are 'traditional' or 'alternative'")
-        } else if config.interactive {
+        } else if config.debug_format != "text" && config.debug_format != "json" {
+            Err("Choices for debug-format are 'text' or 'json'")
+        } else if config
+            .timeout
+            .is_some_and(|secs| !secs.is_finite() || secs <= 0.0)
+        {
+            Err("TIMEOUT must be a positive number of seconds")
+        } else if config.read_block == Some(0) {
+            Err("READ_BLOCK must be at least 1 byte")
+        } else if config
+            .whitespace
+            .iter()
+            .any(|set| set != "unicode" && set != "strict-lines")
+        {
+            Err("Choices for whitespace are 'unicode' and 'strict-lines'")
+        } else if config.syntax.as_ref().is_some_and(|syntax| {
+            !"alternative".starts_with(syntax.as_str())
+                && !"traditional".starts_with(syntax.as_str())
+        }) {
+            Err("Choices for syntax are 'traditional' or 'alternative'")
+        } else if config.syntax.is_some() || config.defs_list.is_some() || config.self_test {
+            Ok(config)
+        } else if config.check {
+            if config.re.is_empty()
+                && config.patterns_check.is_empty()
+                && config.defs_check.is_empty()
+            {
+                Err("--check needs an RE, --patterns-check or --defs-check")
+            } else {
+                Ok(config)
+            }
+        } else if !config.patterns_check.is_empty() || !config.defs_check.is_empty() {
+            Err("PATTERNS_CHECK and DEFS_CHECK can only be given with --check")
+        } else if config.expanded_notes && !config.expanded {
+            Err("EXPANDED_NOTES can only be given with --expanded")
+        } else if config.tree_json || config.expanded || config.explain {
+            if config.re.is_empty() {
+                Err(if config.tree_json {
+                    "RE is required for --tree-json"
+                } else if config.expanded {
+                    "RE is required for --expanded"
+                } else {
+                    "RE is required for --explain"
+                })
+            } else {
+                Ok(config)
+            }
+        } else if (config.script_interactive || config.keep_going) && config.script.is_none() {
+            Err("SCRIPT_INTERACTIVE and KEEP_GOING can only be given with --script")
+        } else if config.interactive || config.script.is_some() {
             if !config.files.is_empty() {
                 Err("FILE cannot be specified for interactive run")
-            } else if config.tree {
+            } else if config.tree_view().is_some() {
                 Err("TREE cannot be specified for interactive run")
+            } else if config.bytes {
+                Err("BYTES cannot be specified for interactive run")
+            } else {
+                Ok(config)
+            }
+        } else if config.set_count.is_some() {
+            if !config.text.is_empty() && !config.set_count_files().is_empty() {
+                Err("FILE cannot be given if search text is passed in")
+            } else if config.replace.is_some()
+                || config.count_only
+                || config.lines
+                || config.context_chars.is_some()
+                || config.deepen
+            {
+                Err("SET_COUNT cannot be given with --replace, --count-only, --lines, --context-chars or --deepen")
             } else {
                 Ok(config)
             }
@@ -165,13 +664,117 @@ impl Config {
             Err("RE is required unless --interactive given")
         } else if !config.text.is_empty() && !config.files.is_empty() {
             Err("FILE cannot be given if search text is passed in")
+        } else if config.lines && config.context_chars.is_some() {
+            Err("LINES and CONTEXT_CHARS cannot both be given")
+        } else if config.replace_preserve_case && config.replace.is_none() {
+            Err("REPLACE_PRESERVE_CASE can only be given with --replace")
+        } else if config.replace.is_some() && config.count_only {
+            Err("REPLACE and COUNT_ONLY cannot both be given")
         } else {
             Ok(config)
         }
     }
+    /// returns the files to search for **--set-count**, which takes the place of the RE, so the first of them is
+    /// where the RE would be
+    pub fn set_count_files(&self) -> Vec<&str> {
+        std::iter::once(self.re.as_str())
+            .filter(|re| !re.is_empty())
+            .chain(self.files.iter().map(String::as_str))
+            .collect()
+    }
+    /// returns how to print the tree, or None if it is not to be printed. **--tree-depth** and **--tree-filter** print
+    /// it without **--tree**.
+    pub fn tree_view(&self) -> Option<TreeView> {
+        if !self.tree && self.tree_depth.is_none() && self.tree_filter.is_none() {
+            return None;
+        }
+        let mut view = TreeView::new();
+        if let Some(depth) = self.tree_depth {
+            view = view.depth(depth as usize);
+        }
+        if let Some(name) = &self.tree_filter {
+            view = view.filter(name);
+        }
+        Some(view)
+    }
     /// returns TRUE if the argument directs using the alternative parser, FALSE to use the traditional one
     pub fn alt_parser(&self) -> bool {
-        "traditional".starts_with(&self.parser)
+        "alternative".starts_with(&self.parser)
+    }
+    /// returns None unless **--syntax** was given, otherwise TRUE if it asks for the alternative syntax. With no value
+    /// it follows **--parser**.
+    pub fn syntax_alt(&self) -> Option<bool> {
+        self.syntax.as_ref().map(|syntax| {
+            if syntax.is_empty() {
+                self.alt_parser()
+            } else {
+                "alternative".starts_with(syntax.as_str())
+            }
+        })
+    }
+    /// returns the format for debug output given by **--debug-format**
+    pub fn trace_format(&self) -> TraceFormat {
+        if self.debug_format == "json" {
+            TraceFormat::Json
+        } else {
+            TraceFormat::Text
+        }
+    }
+    /// returns the options for parsing and searching given on the command line. This is the one place the flags are
+    /// turned into options.
+    pub fn search_options(&self) -> SearchOptions {
+        let options = SearchOptions::new()
+            .alt_parser(self.alt_parser())
+            .optimize(!self.no_optimize)
+            .literal_braces(self.literal_braces)
+            .lenient(self.lenient)
+            .strict(self.strict)
+            .caseless(self.ignore_case)
+            .whitespace(self.whitespace())
+            .walk(self.walk_options());
+        match self.max_complexity {
+            Some(max) => options.max_complexity(max),
+            None => options,
+        }
+    }
+
+    /// returns what the alternative syntax skips between units, as given by **--whitespace**
+    pub fn whitespace(&self) -> Whitespace {
+        let mut whitespace = Whitespace::new();
+        if self.whitespace.iter().any(|set| set == "unicode") {
+            whitespace = whitespace.unicode();
+        }
+        if self.whitespace.iter().any(|set| set == "strict-lines") {
+            whitespace = whitespace.strict_lines();
+        }
+        whitespace
+    }
+
+    /// returns the options for the walk phase given on the command line
+    pub fn walk_options(&self) -> WalkOptions {
+        let mut options = WalkOptions::new();
+        if let Some(secs) = self.timeout {
+            options = options.timeout(std::time::Duration::from_secs_f64(secs));
+        }
+        if let Some(len) = self.max_match_len {
+            options = options.max_match_len(len);
+        }
+        if self.no_optimize {
+            options = options.no_scan().no_or_memo();
+        }
+        if let Some(bytes) = self.read_block {
+            options = options.read_block(bytes);
+        }
+        if let Some(bytes) = self.max_buffer {
+            options = options.max_buffer(bytes);
+        }
+        if self.bytes {
+            options = options.bytes();
+        }
+        if self.check_boundaries {
+            options = options.check_boundaries();
+        }
+        options
     }
 }
 
@@ -181,6 +784,10 @@ impl Config {
 //
 /// Used to deliver the search results to the caller. Results form a tree, AndNode and OrNode are branches, the other
 /// Nodes are leaves. **Report** is built up from the successful **Path** that walked the entire tree.
+///
+/// All the positions in a report, in bytes and in chars, are absolute: they count from the start of the text of the
+/// source being searched, whatever position the walk was started from and whatever window it was limited to. Code
+/// searching a window of the text can get positions counted from the start of the window with **relative_to()**.
 //
 //////////////////////////////////////////////////////////////////
 
@@ -193,26 +800,105 @@ pub struct Report {
     pub name: Option<String>,
     /// Array of child Report structs, only non-empty for And and Or nodes. OrNodes will have only a single child node, AndNodes can have many.
     pub subreports: Vec<Report>,
+    /// For an Or node, the branch that matched (from 0) and the number of branches. This is None if the name covers
+    /// all the repetitions of the node, since each can match a different branch.
+    pub branch: Option<(usize, usize)>,
 }
 
 impl<'a> Report {
     /// Constructor: creates a new report from a successful Path
     pub fn new(root: &'a crate::walk::Path) -> Report {
-        let mut reports = root.gather_reports();
+        // indexed names not inside any repetition are picked from the whole match
+        let mut reports = Report::select_indexed(root.gather_reports());
         let mut ret = reports.splice(0.., None);
         ret.next().unwrap()
     }
 
+    /// Splits a name like "val[3]" into the name and the index, which starts from 1
+    fn indexed_name(name: &str) -> Option<(&str, usize)> {
+        let (name, index) = name.strip_suffix(']')?.rsplit_once('[')?;
+        Some((name, index.parse().ok()?))
+    }
+
+    /// Goes through REPORTS and everything they contain in order, and for each name like "val[3]" keeps only the
+    /// third report with that name, renamed to "val". The others are dropped along with their contents, as is any
+    /// whose index is out of range.
+    pub(crate) fn select_indexed(reports: Vec<Report>) -> Vec<Report> {
+        Report::select_indexed_counting(reports, &mut Vec::new())
+    }
+
+    /// Picks the indexed names from the REPORTS of the steps of a node repeated at most MAX times. If the node is
+    /// named outside its repetition its own name belongs to the level above, so only what the steps contain is
+    /// picked from.
+    pub(crate) fn select_repeated(
+        reports: Vec<Report>,
+        max: usize,
+        name_outside: bool,
+    ) -> Vec<Report> {
+        if max <= 1 {
+            reports
+        } else if name_outside {
+            let mut counts = Vec::new();
+            reports
+                .into_iter()
+                .map(|mut report| {
+                    let subreports = std::mem::take(&mut report.subreports);
+                    report.subreports = Report::select_indexed_counting(subreports, &mut counts);
+                    report
+                })
+                .collect()
+        } else {
+            Report::select_indexed(reports)
+        }
+    }
+
+    /// does the work for **select_indexed()**, keeping COUNTS of the indexed names seen so far
+    fn select_indexed_counting(
+        reports: Vec<Report>,
+        counts: &mut Vec<(String, usize)>,
+    ) -> Vec<Report> {
+        let mut kept = Vec::with_capacity(reports.len());
+        for mut report in reports {
+            if let Some((name, index)) = report.name.as_deref().and_then(Report::indexed_name) {
+                let full_name = report.name.as_deref().unwrap_or_default();
+                let count = match counts.iter_mut().find(|(seen, _)| seen == full_name) {
+                    Some((_, count)) => {
+                        *count += 1;
+                        *count
+                    }
+                    None => {
+                        counts.push((full_name.to_string(), 1));
+                        1
+                    }
+                };
+                if count != index {
+                    continue;
+                }
+                report.name = Some(name.to_string());
+            }
+            report.subreports =
+                Report::select_indexed_counting(std::mem::take(&mut report.subreports), counts);
+            kept.push(report);
+        }
+        kept
+    }
+
     // API accessor functions
     /// Gets the string matched by this unit
-    /// This is intended to be used inside an Input::apply() block, which is how to get the Input object
+    /// This is intended to be used inside an Input::apply() block, which is how to get the Input object. Outside of one,
+    /// **text()** does the same without taking the lock.
     pub fn string<'b>(&'b self, input: &'b Input) -> &'b str {
         &input.full_text[self.matched.start..self.matched.end]
     }
 
+    /// Gets the string matched by this unit from the current snapshot of the input (see **Input::with_text()**)
+    pub fn text(&self) -> String {
+        Input::with_text(|text| text[self.matched.start..self.matched.end].to_string())
+    }
+
     /// Gets **Report** nodes representing matches for named Nodes. The return is a *Vec* because named matches can occur multiple
     /// times - for example, _\?\<name\>abc\)*_
-    pub fn get_by_name<'b>(&'b self, name: &'b str) -> Vec<&Report> {
+    pub fn get_by_name<'b>(&'b self, name: &'b str) -> Vec<&'b Report> {
         let mut v = Vec::<&Report>::new();
         if let Some(n) = &self.name {
             if n == name {
@@ -226,28 +912,115 @@ impl<'a> Report {
         v
     }
 
-    /// Gets a hash of  **Report** nodes grouped by name. This just sets things up and calls **get_named_internal()** to do the work
-    pub fn get_named(&self) -> HashMap<&str, Vec<&Report>> {
-        let hash = HashMap::new();
-        self.get_named_internal(hash)
+    /// Gets the **Report** nodes grouped by name, in the order the names first appear in the match. This just sets
+    /// things up and calls **get_named_internal()** to do the work
+    pub fn get_named(&self) -> NamedReports<'_> {
+        let named = NamedReports::default();
+        self.get_named_internal(named)
     }
 
     /// internal function that does the work for **get_named()**
-    fn get_named_internal<'b: 'a>(
-        &'b self,
-        mut hash: HashMap<&'b str, Vec<&'b Report>>,
-    ) -> HashMap<&'b str, Vec<&Report>> {
+    fn get_named_internal<'b: 'a>(&'b self, mut named: NamedReports<'b>) -> NamedReports<'b> {
         if let Some(name) = &self.name {
-            if let Some(mut_v) = hash.get_mut(&name.as_str()) {
+            if let Some(mut_v) = named.get_mut(name.as_str()) {
                 mut_v.push(self);
             } else {
-                hash.insert(name.as_str(), vec![self]);
+                named.entries.push((name.as_str(), vec![self]));
             }
             for r in self.subreports.iter() {
-                hash = r.get_named_internal(hash);
+                named = r.get_named_internal(named);
             }
         }
-        hash
+        named
+    }
+
+    /// Makes a copy of the report holding only the root, the units named in KEEP, and the units that contain them, so
+    /// big reports can be cut down to the parts of interest before being displayed
+    pub fn prune(&self, keep: &[&str]) -> Report {
+        Report {
+            matched: self.matched,
+            name: self.name.clone(),
+            subreports: Report::prune_all(&self.subreports, keep),
+            branch: self.branch,
+        }
+    }
+
+    /// does the work for **prune()**, copying the REPORTS that are named in KEEP or contain one that is
+    fn prune_all(reports: &[Report], keep: &[&str]) -> Vec<Report> {
+        reports
+            .iter()
+            .filter_map(|report| {
+                let subreports = Report::prune_all(&report.subreports, keep);
+                if subreports.is_empty()
+                    && !report
+                        .name
+                        .as_deref()
+                        .is_some_and(|name| keep.contains(&name))
+                {
+                    None
+                } else {
+                    Some(Report {
+                        matched: report.matched,
+                        name: report.name.clone(),
+                        subreports,
+                        branch: report.branch,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Makes a copy of the report where each name in NAMES is only reported for its last match, the way other
+    /// engines report a group that is repeated: the earlier reports with the name are dropped along with what they
+    /// contain. Reports are in the order they matched, so the one kept is the one furthest into the text. The root is
+    /// always kept.
+    pub fn last_only(&self, names: &[&str]) -> Report {
+        // the depth-first number of the last report with each name
+        let mut last = vec![0; names.len()];
+        for (number, report) in self.iter().enumerate() {
+            if let Some(i) = names
+                .iter()
+                .position(|name| report.name.as_deref() == Some(name))
+            {
+                last[i] = number;
+            }
+        }
+        Report {
+            matched: self.matched,
+            name: self.name.clone(),
+            subreports: Report::last_only_all(&self.subreports, names, &last, &mut 1),
+            branch: self.branch,
+        }
+    }
+
+    /// does the work for **last_only()**, copying the REPORTS that are not named in NAMES or are the LAST with their
+    /// name. SEEN is the depth-first number of the next report, which counts the dropped ones too.
+    fn last_only_all(
+        reports: &[Report],
+        names: &[&str],
+        last: &[usize],
+        seen: &mut usize,
+    ) -> Vec<Report> {
+        reports
+            .iter()
+            .filter_map(|report| {
+                let number = *seen;
+                *seen += 1;
+                let subreports = Report::last_only_all(&report.subreports, names, last, seen);
+                match names
+                    .iter()
+                    .position(|name| report.name.as_deref() == Some(name))
+                {
+                    Some(i) if last[i] != number => None,
+                    _ => Some(Report {
+                        matched: report.matched,
+                        name: report.name.clone(),
+                        subreports,
+                        branch: report.branch,
+                    }),
+                }
+            })
+            .collect()
     }
 
     /// Gets the start and end position of the match in bytes
@@ -261,6 +1034,46 @@ impl<'a> Report {
             self.matched.char_start + self.matched.len_chars(),
         )
     }
+    /// Gets the start and end position of the match in the input, in bytes. Unlike **byte_pos()** this is the
+    /// position in the bytes searched in bytes mode (see **Input::set_bytes_mode()**), not in the text holding them.
+    pub fn offsets(&self) -> (usize, usize) {
+        if Input::bytes_mode() {
+            self.char_pos()
+        } else {
+            self.byte_pos()
+        }
+    }
+    /// Gets the position of the match counted from byte WINDOW_START of the text instead of from its start, for code
+    /// searching a window of the text: the start and end in bytes, and the start and end in chars. Like **text()**
+    /// this looks at the text in **Input**, so it must be called before that changes. Returns None if the match
+    /// starts before WINDOW_START, or WINDOW_START is inside a char.
+    pub fn relative_to(&self, window_start: usize) -> Option<RelativePos> {
+        if window_start > self.matched.start {
+            return None;
+        }
+        let skipped = Input::with_text(|text| {
+            text.is_char_boundary(window_start)
+                .then(|| text[..window_start].chars().count())
+        })?;
+        let (char_start, char_end) = self.char_pos();
+        Some(RelativePos {
+            bytes: (
+                self.matched.start - window_start,
+                self.matched.end - window_start,
+            ),
+            chars: (char_start - skipped, char_end - skipped),
+        })
+    }
+    /// Gets the bytes matched by this unit. In bytes mode these are the bytes searched, otherwise the UTF-8 of
+    /// **text()**
+    pub fn bytes(&self) -> Vec<u8> {
+        let text = self.text();
+        if Input::bytes_mode() {
+            text.chars().map(|ch| ch as u8).collect()
+        } else {
+            text.into_bytes()
+        }
+    }
     /// Gets the length of the match in bytes
     pub(crate) fn len_bytes(&self) -> usize {
         self.matched.len_bytes()
@@ -271,15 +1084,34 @@ impl<'a> Report {
     }
     //    pub fn full_string(&self) -> &str { self.matched.full_string }
     /// Pretty-prints a report with indentation to help make it easier to read
-    pub(crate) fn display(&self, indent: usize) {
-        let name_str = {
+    pub fn display(&self, indent: usize) {
+        let _ = self.display_to(&mut std::io::stdout(), indent);
+    }
+
+    /// Like **display()**, but writes to W instead of stdout, so a report can be rendered into a String or a log
+    pub fn display_to<W: Write + ?Sized>(&self, w: &mut W, indent: usize) -> std::io::Result<()> {
+        let mut result = Ok(());
+        self.visit(|report, depth| {
+            if result.is_ok() {
+                result = report.display_one(w, indent + depth * TAB_SIZE);
+            }
+        });
+        result
+    }
+
+    /// writes a single line for **display_to()**
+    fn display_one<W: Write + ?Sized>(&self, w: &mut W, indent: usize) -> std::io::Result<()> {
+        let mut name_str = {
             if let Some(name) = &self.name {
                 format!("<{}> ", name)
             } else {
                 "".to_string()
             }
         };
-        print!("{0:1$}", "", indent);
+        if let Some((branch, branches)) = self.branch {
+            name_str.push_str(&format!("(branch {} of {}) ", branch + 1, branches));
+        }
+        write!(w, "{0:1$}", "", indent)?;
         let len_chars = self.matched.len_chars();
         let file_str = Input::apply(|input| {
             if let Some(filename) = input.current_file() {
@@ -288,21 +1120,464 @@ impl<'a> Report {
                 "".to_string()
             }
         });
-        Input::apply(|input| {
-            println!(
+        Input::with_text(|text| {
+            // in bytes mode chars are bytes, so only the positions in the input are given
+            if Input::bytes_mode() {
+                return writeln!(
+                    w,
+                    "{}\"{}\" {}bytes start {}, length {}",
+                    file_str,
+                    Input::display_text(&text[self.matched.start..self.matched.end]),
+                    name_str,
+                    self.matched.char_start,
+                    len_chars,
+                );
+            }
+            writeln!(
+                w,
                 "{}\"{}\" {}chars start {}, length {}; bytes start {}, length {}",
                 file_str,
-                &input.full_text[self.matched.start..self.matched.end],
+                Input::display_text(&text[self.matched.start..self.matched.end]),
                 name_str,
                 self.matched.char_start,
                 len_chars,
                 self.matched.start,
                 self.matched.end - self.matched.start
             )
-        });
-        self.subreports
+        })
+    }
+
+    /// Iterates over this report and all those below it, depth first
+    pub fn iter(&self) -> ReportIter<'_> {
+        ReportIter {
+            stack: vec![(self, 0)],
+        }
+    }
+
+    /// Like **iter()**, but only the reports for named units
+    pub fn iter_named(&self) -> impl Iterator<Item = &Report> {
+        self.iter()
+            .filter(|report| report.name.as_ref().is_some_and(|name| !name.is_empty()))
+    }
+
+    /// Calls F on this report and all those below it, depth first, along with how deep each is (this one is 0)
+    pub fn visit(&self, mut f: impl FnMut(&Report, usize)) {
+        let mut iter = self.iter();
+        while let Some((report, depth)) = iter.next_with_depth() {
+            f(report, depth);
+        }
+    }
+
+    /// Gets the recorded units below this one in depth-first order. These are the units referred to as $1, $2... in
+    /// **expand()**.
+    pub fn groups(&self) -> Vec<&Report> {
+        self.iter().skip(1).collect()
+    }
+
+    /// Builds a string from **template** with references to this match filled in:
+    /// - **$0** is the whole match, **$N** the Nth recorded unit (see **groups()**)
+    /// - **$name** or **${name}** is the unit with the given name. If it matched more than once the last one is used
+    /// - **$$** is a single '$'
+    ///
+    /// References to units that did not match are replaced by an empty string. This is intended to be used inside an
+    /// Input::apply() block, which is how to get the Input object.
+    pub fn expand(&self, template: &str, input: &Input) -> String {
+        let mut result = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if ch != '$' {
+                result.push(ch);
+                continue;
+            }
+            let braced = chars.peek() == Some(&'{');
+            if braced {
+                chars.next();
+            }
+            let mut name = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_alphanumeric() || ch == '_' {
+                    name.push(ch);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            if braced {
+                if chars.peek() == Some(&'}') {
+                    chars.next();
+                } else {
+                    // not a well formed reference, leave it alone
+                    result.push_str("${");
+                    result.push_str(&name);
+                    continue;
+                }
+            }
+            if name.is_empty() {
+                if !braced && chars.peek() == Some(&'$') {
+                    chars.next();
+                }
+                result.push('$');
+            } else if let Ok(num) = name.parse::<usize>() {
+                if num == 0 {
+                    result.push_str(self.string(input));
+                } else if let Some(group) = self.groups().get(num - 1) {
+                    result.push_str(group.string(input));
+                }
+            } else if let Some(named) = self.get_by_name(&name).last() {
+                result.push_str(named.string(input));
+            }
+        }
+        result
+    }
+}
+
+/// Shows the report the way **display()** prints it
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = Vec::new();
+        self.display_to(&mut out, 0).map_err(|_| std::fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
+
+/// The position of a match counted from the start of a window of the text, returned by **Report::relative_to()**
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelativePos {
+    /// the start and end in bytes
+    pub bytes: (usize, usize),
+    /// the start and end in chars
+    pub chars: (usize, usize),
+}
+
+/// Depth first iterator over a **Report** and those below it, returned by **Report::iter()**. It keeps its own stack
+/// rather than recursing, so it is safe for any depth of tree.
+pub struct ReportIter<'b> {
+    /// the reports still to visit with their depths, the next one last
+    stack: Vec<(&'b Report, usize)>,
+}
+
+impl<'b> ReportIter<'b> {
+    /// gets the next report along with its depth below the one the iteration started from
+    fn next_with_depth(&mut self) -> Option<(&'b Report, usize)> {
+        let (report, depth) = self.stack.pop()?;
+        self.stack.extend(
+            report
+                .subreports
+                .iter()
+                .rev()
+                .map(|subreport| (subreport, depth + 1)),
+        );
+        Some((report, depth))
+    }
+}
+
+impl<'b> Iterator for ReportIter<'b> {
+    type Item = &'b Report;
+    fn next(&mut self) -> Option<&'b Report> {
+        self.next_with_depth().map(|(report, _)| report)
+    }
+}
+
+/// The named **Report**s from a match, returned by **Report::get_named()**. It works like a map from names to the
+/// reports with that name, but keeps the names in the order they first appear so output is the same from run to run.
+#[derive(Debug, Default)]
+pub struct NamedReports<'b> {
+    entries: Vec<(&'b str, Vec<&'b Report>)>,
+}
+
+impl<'b> NamedReports<'b> {
+    /// Gets the reports named NAME
+    pub fn get(&self, name: &str) -> Option<&Vec<&'b Report>> {
+        self.entries
             .iter()
-            .for_each(move |r| r.display(indent + TAB_SIZE));
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v)
+    }
+
+    /// like **get()**, but mutable
+    fn get_mut(&mut self, name: &str) -> Option<&mut Vec<&'b Report>> {
+        self.entries
+            .iter_mut()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v)
+    }
+
+    /// true if any report is named NAME
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// the names, in order
+    pub fn keys(&self) -> impl Iterator<Item = &'b str> + '_ {
+        self.entries.iter().map(|(name, _)| *name)
+    }
+
+    /// iterates over the names and their reports, in order
+    pub fn iter(&self) -> impl Iterator<Item = (&'b str, &Vec<&'b Report>)> {
+        self.entries.iter().map(|(name, v)| (*name, v))
+    }
+
+    /// the number of different names
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// true if nothing was named
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'b> IntoIterator for NamedReports<'b> {
+    type Item = (&'b str, Vec<&'b Report>);
+    type IntoIter = std::vec::IntoIter<(&'b str, Vec<&'b Report>)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+/// Iterator over all the matches of a tree in the current **Input** text, returned by **find_iter()**. Matches do not
+/// overlap. After an empty match the search resumes one char further on so the iteration always ends (see
+/// **walk::next_start()**).
+pub struct FindIter<'a> {
+    tree: &'a tree::Node,
+    /// where to start the next walk, None when finished
+    start: Option<usize>,
+}
+
+impl<'a> Iterator for FindIter<'a> {
+    type Item = Result<Report, Error>;
+    fn next(&mut self) -> Option<Result<Report, Error>> {
+        let start = self.start?;
+        match walk_tree_next(self.tree, start) {
+            Err(err) => {
+                self.start = None;
+                Some(Err(err))
+            }
+            Ok(None) => {
+                self.start = None;
+                None
+            }
+            Ok(Some((path, next))) => {
+                // an anchored RE can only match where the text starts
+                self.start = if self.tree.anchored() {
+                    None
+                } else {
+                    Some(next)
+                };
+                Some(Ok(Report::new(&path)))
+            }
+        }
+    }
+}
+
+/// Finds all the matches for **tree** in the text already loaded into **Input**
+pub fn find_iter(tree: &tree::Node) -> FindIter<'_> {
+    FindIter {
+        tree,
+        start: Some(0),
+    }
+}
+
+/// The matches in a single line, returned by **LineMatches**: the line number (starting from 1), the byte range of
+/// the line not counting its newline, and the matches that start in it
+pub type LineMatch = (usize, std::ops::Range<usize>, Vec<Report>);
+
+/// Iterator over the lines of the current **Input** text that contain matches, returned by **search_lines()**. By
+/// default matches are kept inside a line, with '$' matching at its end; **multiline()** lets them run on past it.
+pub struct LineMatches<'a> {
+    tree: &'a tree::Node,
+    /// the start of the next line to search, None when finished
+    line_start: Option<usize>,
+    /// the number of the next line
+    line_number: usize,
+    /// if set, matches can cross newlines
+    multiline: bool,
+    /// in multiline mode, where to start the next walk, which may be in a later line if the last match ran past the
+    /// end of its own
+    resume: usize,
+    /// in multiline mode, a match found starting in a later line than the one being searched
+    pending: Option<Report>,
+}
+
+impl<'a> LineMatches<'a> {
+    /// lets matches cross newlines. A match is reported with the line it starts in.
+    pub fn multiline(mut self) -> LineMatches<'a> {
+        self.multiline = true;
+        self
+    }
+
+    /// finds the next match starting in the line from START to END, beginning the walk at FROM
+    fn next_match(&mut self, from: usize, end: usize) -> Result<Option<Report>, Error> {
+        if !self.multiline {
+            return Ok(walk::walk_tree_until(self.tree, from, end)?.map(|path| Report::new(&path)));
+        }
+        if self.pending.is_none() {
+            self.pending = walk_tree(self.tree, from)?.map(|path| Report::new(&path));
+        }
+        Ok(self.pending.take_if(|report| report.byte_pos().0 <= end))
+    }
+
+    /// collects the matches in the line from START to END. An anchored RE is only tried at the start of the line, so
+    /// in multiline mode a line that a match ran on into has nothing more to find.
+    fn line_reports(&mut self, start: usize, end: usize) -> Result<Vec<Report>, Error> {
+        let mut reports = Vec::new();
+        let mut from = start.max(self.resume);
+        let anchored = self.tree.anchored();
+        if anchored && from > start {
+            return Ok(reports);
+        }
+        while from <= end {
+            let Some(report) = self.next_match(from, end)? else {
+                break;
+            };
+            let (match_start, match_end) = report.byte_pos();
+            // after an empty match the search moves on a char, the same as **find_iter()**
+            from = walk::next_start(match_start, match_end);
+            reports.push(report);
+            if anchored {
+                break;
+            }
+        }
+        self.resume = from;
+        Ok(reports)
+    }
+}
+
+impl Iterator for LineMatches<'_> {
+    type Item = Result<LineMatch, Error>;
+    fn next(&mut self) -> Option<Result<LineMatch, Error>> {
+        loop {
+            let start = self.line_start?;
+            let end = Input::line_end(start);
+            let line_number = self.line_number;
+            self.line_number += 1;
+            // the text ends with this line unless there is a newline after it
+            self.line_start = if end < Input::len() {
+                Some(end + 1)
+            } else {
+                None
+            };
+            if start == end && self.line_start.is_none() {
+                // the empty string after a final newline (or an empty text) is not a line
+                return None;
+            }
+            match self.line_reports(start, end) {
+                Err(err) => {
+                    self.line_start = None;
+                    return Some(Err(err));
+                }
+                Ok(reports) if !reports.is_empty() => {
+                    return Some(Ok((line_number, start..end, reports)))
+                }
+                Ok(_) => (),
+            }
+        }
+    }
+}
+
+/// Searches the text already loaded into **Input** a line at a time, for consumers that want per-line results like
+/// grep. Only lines with at least one match are returned.
+pub fn search_lines(tree: &tree::Node) -> LineMatches<'_> {
+    LineMatches {
+        tree,
+        line_start: Some(0),
+        line_number: 1,
+        multiline: false,
+        resume: 0,
+        pending: None,
+    }
+}
+
+/// Replaces every match of **tree** in the text already loaded into **Input** with **template**, as expanded by
+/// **Report::expand()**. Returns the new string and the number of replacements made.
+pub fn replace_all(tree: &tree::Node, template: &str) -> Result<(String, usize), Error> {
+    replace_all_with(tree, |report, input| report.expand(template, input))
+}
+
+/// Like **replace_all()**, but each match is replaced by what **replacer** returns for it. To make the replacements
+/// follow the case of the text they replace, pass it through **preserve_case()**:
+///
+/// replace_all_with(&tree, |report, input| preserve_case(report.string(input), &report.expand("color", input)))
+pub fn replace_all_with(
+    tree: &tree::Node,
+    mut replacer: impl FnMut(&Report, &Input) -> String,
+) -> Result<(String, usize), Error> {
+    let mut result = String::new();
+    let mut count = 0;
+    let mut copied = 0;
+    for report in find_iter(tree) {
+        let report = report?;
+        let (start, end) = report.byte_pos();
+        let replaced = Input::apply_mut(|input| {
+            format!(
+                "{}{}",
+                &input.full_text[copied..start],
+                replacer(&report, input)
+            )
+        });
+        result.push_str(&replaced);
+        copied = end;
+        count += 1;
+    }
+    result.push_str(&Input::apply(|input| input.full_text[copied..].to_string()));
+    Ok((result, count))
+}
+
+/// Changes the case of **replacement** to follow that of **matched**, the text it replaces, so replacing "colour"
+/// with "color" gives "COLOR" for "COLOUR" and "Color" for "Colour". Only the letters that have case count:
+/// - if there are two or more and they are all upper case, the replacement is made upper case
+/// - if the first is upper (or title) case and the rest are lower case, the replacement is capitalized, with the
+///   first letter put in title case (so "ǆ" becomes "ǅ", not "Ǆ") and the rest lower case
+/// - if they are all lower case, the replacement is made lower case
+///
+/// Anything else, like "cOLOUR" or a match with no letters, leaves the replacement alone.
+pub fn preserve_case(matched: &str, replacement: &str) -> String {
+    let cased: Vec<char> = matched
+        .chars()
+        .filter(|ch| ch.is_lowercase() || is_capital(*ch))
+        .collect();
+    match cased.split_first() {
+        None => replacement.to_string(),
+        Some((_, rest)) if !rest.is_empty() && cased.iter().all(|ch| ch.is_uppercase()) => {
+            replacement.to_uppercase()
+        }
+        Some((first, rest)) if is_capital(*first) && rest.iter().all(|ch| ch.is_lowercase()) => {
+            let mut chars = replacement.chars();
+            match chars.next() {
+                Some(first) => format!("{}{}", titlecase(first), chars.as_str().to_lowercase()),
+                None => String::new(),
+            }
+        }
+        _ if cased.iter().all(|ch| ch.is_lowercase()) => replacement.to_lowercase(),
+        _ => replacement.to_string(),
+    }
+}
+
+/// true if **ch** is upper case or title case (like "ǅ", which is neither upper nor lower case)
+fn is_capital(ch: char) -> bool {
+    ch.is_uppercase() || (!ch.is_lowercase() && !ch.to_lowercase().eq(std::iter::once(ch)))
+}
+
+/// Gets the title case form of **ch**, for the start of a capitalized word. It is the upper case form except for the
+/// few characters where they differ: the digraphs like "ǆ" and the ones whose upper case is two letters, like "ß".
+fn titlecase(ch: char) -> String {
+    match ch {
+        'Ǆ' | 'ǅ' | 'ǆ' => "ǅ".to_string(),
+        'Ǉ' | 'ǈ' | 'ǉ' => "ǈ".to_string(),
+        'Ǌ' | 'ǋ' | 'ǌ' => "ǋ".to_string(),
+        'Ǳ' | 'ǲ' | 'ǳ' => "ǲ".to_string(),
+        ch => {
+            let upper: String = ch.to_uppercase().collect();
+            let mut chars = upper.chars();
+            match chars.next() {
+                // "ß" is "SS" in upper case but "Ss" in title case
+                Some(first) if upper.chars().count() > 1 => {
+                    format!("{}{}", first, chars.as_str().to_lowercase())
+                }
+                _ => upper,
+            }
+        }
     }
 }
 
@@ -341,10 +1616,12 @@ pub(crate) fn trace_indent() {
 
 /// simple struct used to provide control on how errors are displayed
 /// Binding messages with numbers makes testing easier
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Error {
     pub msg: String,
     pub code: usize,
+    /// for an error about a unit of the RE, where it is in the RE (see **Node::source_span()**)
+    pub span: Option<(usize, usize)>,
 }
 
 impl Error {
@@ -353,8 +1630,15 @@ impl Error {
         Error {
             code,
             msg: msg.to_string(),
+            span: None,
         }
     }
+
+    /// adds the SPAN in the RE of the unit the error is about
+    pub fn with_span(mut self, span: Option<(usize, usize)>) -> Error {
+        self.span = span;
+        self
+    }
 }
 
 impl core::fmt::Display for Error {
@@ -363,12 +1647,45 @@ impl core::fmt::Display for Error {
     }
 }
 
+/// Used to report constructs in a regular expression that are legal but are probably mistakes. Like **Error** it
+/// has a number to make testing easier, and also the position (in chars) in the RE where it was found, if known.
+/// Warnings about a whole unit, found after the parse, have its span instead (see **Node::source_span()**).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub msg: String,
+    pub code: usize,
+    pub pos: Option<usize>,
+    pub span: Option<(usize, usize)>,
+}
+
+impl Warning {
+    /// constructor
+    pub fn make(code: usize, msg: &str, pos: Option<usize>) -> Warning {
+        Warning {
+            code,
+            msg: msg.to_string(),
+            pos,
+            span: None,
+        }
+    }
+}
+
+impl core::fmt::Display for Warning {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        if let Some(pos) = self.pos {
+            write!(f, "Warning:{}: {} (at {})", self.code, self.msg, pos)
+        } else {
+            write!(f, "Warning:{}: {}", self.code, self.msg)
+        }
+    }
+}
+
 /// If LEVEL >= current debug level then print out the remaining arguments in println!()-like fashion
 #[macro_export]
 macro_rules! trace {
     ( $level:expr, $($arg:tt)*) => {
         #[allow(unused_comparisons)]   // pass 0 as level to print a message, his suppresses the warning
-        if $level <= $crate::TRACE_LEVEL.load(core::sync::atomic::Ordering::Acquire) { trace_indent(); println!($($arg)*); }
+        if $level <= $crate::TRACE_LEVEL.load(core::sync::atomic::Ordering::Acquire) { $crate::trace_message($level, module_path!(), &format!($($arg)*)); }
     }
 }
 
@@ -0,0 +1,170 @@
+//! ## Search options
+//! **SearchOptions** gathers everything that changes how an RE is parsed and searched for in one struct, so a new
+//! option is a field and a setter instead of another argument threaded through every call. It is built up with the
+//! builder methods and passed by reference to **parse_tree_opts()** and **walk_tree_opts()**, or given to a
+//! **Regexp** with **Regexp::with_options()**:
+//!
+//! > let options = SearchOptions::new().alt_parser(true).caseless(true);
+//! > let re = Regexp::with_options("'hello'", options)?;
+//!
+//! The default options change nothing, which is what the plain **parse_tree()** and **walk_tree()** use. Options
+//! that have a global setting, like the **WalkOptions** set by **set_walk_options()**, only replace it when they are
+//! given, and only for the call they are given to.
+
+use crate::regexp::tree::{
+    check_escapes, make_caseless, parse_tree_full, set_lenient, set_literal_braces, set_whitespace,
+    Node, Whitespace,
+};
+use crate::regexp::walk::{set_walk_options, WalkOptions};
+use crate::regexp::{Error, Warning};
+
+/// Options for parsing an RE and searching for it
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    /// parse the RE with the alternative syntax instead of the traditional one
+    pub alt_parser: bool,
+    /// simplify the parsed tree (see **optimize()**). On by default.
+    pub optimize: bool,
+    /// if set, replaces the setting from **set_literal_braces()** while parsing
+    pub literal_braces: Option<bool>,
+    /// if set, replaces the setting from **set_lenient()** while parsing
+    pub lenient: Option<bool>,
+    /// make escapes with no meaning, like "\q", an error (11) instead of a warning
+    pub strict: bool,
+    /// ignore case in every string of the RE, as if each started with "\c"
+    pub caseless: bool,
+    /// if set, replaces the setting from **set_whitespace()** while parsing
+    pub whitespace: Option<Whitespace>,
+    /// if set, replaces the options from **set_walk_options()** while searching
+    pub walk: Option<WalkOptions>,
+    /// if set, an RE whose **complexity_score()** is higher is an error (18)
+    pub max_complexity: Option<usize>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            alt_parser: false,
+            optimize: true,
+            literal_braces: None,
+            lenient: None,
+            strict: false,
+            caseless: false,
+            whitespace: None,
+            walk: None,
+            max_complexity: None,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// constructor, with the defaults
+    pub fn new() -> SearchOptions {
+        SearchOptions::default()
+    }
+
+    /// sets whether the RE uses the alternative syntax
+    pub fn alt_parser(mut self, alt_parser: bool) -> SearchOptions {
+        self.alt_parser = alt_parser;
+        self
+    }
+
+    /// sets whether the tree is optimized
+    pub fn optimize(mut self, optimize: bool) -> SearchOptions {
+        self.optimize = optimize;
+        self
+    }
+
+    /// sets whether a '{' that does not start a count matches itself
+    pub fn literal_braces(mut self, literal: bool) -> SearchOptions {
+        self.literal_braces = Some(literal);
+        self
+    }
+
+    /// sets whether text left over after the RE is complete is taken as literal text instead of being an error
+    pub fn lenient(mut self, lenient: bool) -> SearchOptions {
+        self.lenient = Some(lenient);
+        self
+    }
+
+    /// sets whether unknown escapes are errors
+    pub fn strict(mut self, strict: bool) -> SearchOptions {
+        self.strict = strict;
+        self
+    }
+
+    /// sets whether case is ignored in the strings of the RE
+    pub fn caseless(mut self, caseless: bool) -> SearchOptions {
+        self.caseless = caseless;
+        self
+    }
+
+    /// sets what the alternative syntax skips between units
+    pub fn whitespace(mut self, whitespace: Whitespace) -> SearchOptions {
+        self.whitespace = Some(whitespace);
+        self
+    }
+
+    /// sets the options for the walk
+    pub fn walk(mut self, walk: WalkOptions) -> SearchOptions {
+        self.walk = Some(walk);
+        self
+    }
+
+    /// turns away REs likely to be expensive to search for: parsing one with a complexity score (see
+    /// **Node::complexity_score()**) higher than MAX fails
+    pub fn max_complexity(mut self, max: usize) -> SearchOptions {
+        self.max_complexity = Some(max);
+        self
+    }
+}
+
+/// Parses INPUT as set out by OPTIONS, returning the tree and the warnings
+pub fn parse_tree_opts(
+    input: &str,
+    options: &SearchOptions,
+) -> Result<(Node, Vec<Warning>), Error> {
+    let previous = options.literal_braces.map(set_literal_braces);
+    let previous_lenient = options.lenient.map(set_lenient);
+    let previous_whitespace = options.whitespace.clone().map(set_whitespace);
+    let parsed = parse_tree_full(input, options.alt_parser, options.optimize);
+    if let Some(literal) = previous {
+        set_literal_braces(literal);
+    }
+    if let Some(lenient) = previous_lenient {
+        set_lenient(lenient);
+    }
+    if let Some(whitespace) = previous_whitespace {
+        set_whitespace(whitespace);
+    }
+    let (mut tree, warnings) = parsed?;
+    if options.strict {
+        check_escapes(&warnings)?;
+    }
+    if options.caseless {
+        make_caseless(&mut tree);
+    }
+    if let Some(max) = options.max_complexity {
+        let score = tree.complexity_score();
+        if score > max {
+            return Err(Error::make(
+                18,
+                &format!("RE complexity score {} is over the limit of {}", score, max),
+            ));
+        }
+    }
+    Ok((tree, warnings))
+}
+
+/// Runs F with the walk options in OPTIONS in place, if it has any
+pub(crate) fn with_walk_options<T>(options: &SearchOptions, f: impl FnOnce() -> T) -> T {
+    match &options.walk {
+        None => f(),
+        Some(walk) => {
+            let previous = set_walk_options(walk.clone());
+            let result = f();
+            set_walk_options(previous);
+            result
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! ## Literal scanning
+//! A RE like "or('apple' 'banana' 'cherry' ...)" can only match where one of its strings starts, but the walk tries
+//! each branch in turn at every position in the text, which gets slow when there are hundreds of branches. Instead,
+//! before the walk starts the strings are compiled into an Aho-Corasick automaton, which finds the next place any of
+//! them starts in a single pass over the text. **walk_tree()** jumps straight there, and the usual walk then checks
+//! the match, so names, repetitions, and whatever follows the strings in the RE are handled as always.
+//!
+//! The scan is used when every match of the RE has to start with one of a set of case-sensitive strings: the RE
+//! starts with a string, an OR of strings, or an OR of units that start with strings.
+//!
+//! REs that are just a short fixed sequence of chars, specials, and ranges, like "\\d\\d\\d\\d-\\d\\d-\\d\\d", get a
+//! **ShiftOr** matcher instead. It runs the whole RE over the text a char at a time using bit operations, so the
+//! walk is only started where there is a match, and only has to build its **Report**.
+//!
+//! Inside the walk, **can_start()** looks at the next char to see whether a node could start there at all. When a
+//! greedy repetition like ".*" backs off it uses this to skip straight to the places the node after it can start.
+
+use crate::regexp::tree::{Node, RangeNode, SpecialNode};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// An Aho-Corasick automaton for a set of strings. It works on bytes, which is safe for UTF-8: a string can only
+/// match starting at the beginning of a char, since its first byte is never a continuation byte.
+#[derive(Debug)]
+pub struct LiteralScanner {
+    /// the strings being searched for
+    strings: Vec<String>,
+    /// the transitions out of each state, state 0 is the start
+    goto: Vec<HashMap<u8, usize>>,
+    /// the state to fall back to when there is no transition
+    fail: Vec<usize>,
+    /// the length of the longest string ending at each state, 0 if none does
+    found: Vec<usize>,
+    /// the length of the longest string
+    longest: usize,
+}
+
+impl LiteralScanner {
+    /// Builds the automaton for STRINGS, which should all be non-empty
+    pub fn new(strings: Vec<String>) -> LiteralScanner {
+        let mut scanner = LiteralScanner {
+            goto: vec![HashMap::new()],
+            fail: vec![0],
+            found: vec![0],
+            longest: strings.iter().map(|s| s.len()).max().unwrap_or(0),
+            strings,
+        };
+        // the trie of the strings
+        for string in scanner.strings.iter() {
+            let mut state = 0;
+            for byte in string.bytes() {
+                state = match scanner.goto[state].get(&byte) {
+                    Some(next) => *next,
+                    None => {
+                        scanner.goto.push(HashMap::new());
+                        scanner.fail.push(0);
+                        scanner.found.push(0);
+                        let next = scanner.goto.len() - 1;
+                        scanner.goto[state].insert(byte, next);
+                        next
+                    }
+                }
+            }
+            scanner.found[state] = string.len();
+        }
+        // the fail links, breadth first so the state a link goes to is always done first
+        let mut queue: VecDeque<usize> = scanner.goto[0].values().copied().collect();
+        while let Some(state) = queue.pop_front() {
+            let moves: Vec<(u8, usize)> = scanner.goto[state]
+                .iter()
+                .map(|(byte, next)| (*byte, *next))
+                .collect();
+            for (byte, next) in moves {
+                let mut fallback = scanner.fail[state];
+                while fallback != 0 && !scanner.goto[fallback].contains_key(&byte) {
+                    fallback = scanner.fail[fallback];
+                }
+                let fail = match scanner.goto[fallback].get(&byte) {
+                    Some(target) if *target != next => *target,
+                    _ => 0,
+                };
+                scanner.fail[next] = fail;
+                if scanner.found[next] == 0 {
+                    scanner.found[next] = scanner.found[fail];
+                }
+                queue.push_back(next);
+            }
+        }
+        scanner
+    }
+
+    /// the strings being searched for
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
+
+    /// the length of the longest string
+    pub fn longest(&self) -> usize {
+        self.longest
+    }
+
+    /// Finds the first position at or after FROM where one of the strings starts in TEXT. Since the scan finds
+    /// strings by where they end, after the first one it keeps going far enough to be sure no longer string starts
+    /// before it. If TEXT may be continued the caller should check that **longest()** bytes past the result are
+    /// loaded, or a longer string starting earlier could be missed.
+    pub fn find(&self, text: &str, from: usize) -> Option<usize> {
+        let mut state = 0;
+        let mut first: Option<usize> = None;
+        for (pos, byte) in text.as_bytes().iter().enumerate().skip(from) {
+            if first.is_some_and(|start| pos + 1 > start + self.longest) {
+                break;
+            }
+            while state != 0 && !self.goto[state].contains_key(byte) {
+                state = self.fail[state];
+            }
+            state = self.goto[state].get(byte).copied().unwrap_or(0);
+            if self.found[state] > 0 {
+                let start = pos + 1 - self.found[state];
+                first = Some(first.map_or(start, |first| first.min(start)));
+            }
+        }
+        first
+    }
+}
+
+/// One position in a sequence matched by **ShiftOr**
+#[derive(Debug, Clone, PartialEq)]
+pub enum CharClass {
+    Char(char),
+    Special(SpecialNode),
+    Range(RangeNode),
+}
+
+impl CharClass {
+    /// true if CH is in the class
+    pub fn matches(&self, ch: char) -> bool {
+        let mut buf = [0u8; 4];
+        match self {
+            CharClass::Char(class_ch) => *class_ch == ch,
+            CharClass::Special(special_node) => {
+                special_node.matches(ch.encode_utf8(&mut buf)).is_some()
+            }
+            CharClass::Range(range_node) => range_node.matches(ch.encode_utf8(&mut buf)).is_some(),
+        }
+    }
+}
+
+/// A Shift-Or (bitap) matcher for a sequence of up to 64 **CharClass**es. Bit I of the state is clear when the
+/// last I+1 chars matched the first I+1 classes, so a clear top bit is a match.
+#[derive(Debug)]
+pub struct ShiftOr {
+    /// the sequence to match
+    classes: Vec<CharClass>,
+    /// for each ASCII char, the classes it is not in as set bits
+    ascii_masks: Box<[u64; 128]>,
+    /// the bit for the last class
+    top: u64,
+}
+
+impl ShiftOr {
+    /// the longest sequence that fits in the state
+    pub const MAX_LEN: usize = 64;
+
+    /// Builds the matcher for CLASSES, which should have between 1 and **MAX_LEN** entries
+    pub fn new(classes: Vec<CharClass>) -> ShiftOr {
+        let mut ascii_masks = Box::new([0u64; 128]);
+        for (ch, mask) in ascii_masks.iter_mut().enumerate() {
+            *mask = ShiftOr::make_mask(&classes, ch as u8 as char);
+        }
+        ShiftOr {
+            top: 1 << (classes.len() - 1),
+            classes,
+            ascii_masks,
+        }
+    }
+
+    /// the classes CH is not in, as set bits
+    fn make_mask(classes: &[CharClass], ch: char) -> u64 {
+        classes
+            .iter()
+            .enumerate()
+            .filter(|(_, class)| !class.matches(ch))
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    /// the sequence matched
+    pub fn classes(&self) -> &[CharClass] {
+        &self.classes
+    }
+
+    /// Finds the first position at or after FROM where the sequence matches in TEXT
+    pub fn find(&self, text: &str, from: usize) -> Option<usize> {
+        let len = self.classes.len();
+        // the byte positions of the last 64 chars, so the start of a match can be found from its end
+        let mut starts = [0usize; ShiftOr::MAX_LEN];
+        let mut state = !0u64;
+        for (i, (pos, ch)) in text[from..].char_indices().enumerate() {
+            starts[i % ShiftOr::MAX_LEN] = from + pos;
+            let mask = if ch.is_ascii() {
+                self.ascii_masks[ch as usize]
+            } else {
+                ShiftOr::make_mask(&self.classes, ch)
+            };
+            state = (state << 1) | mask;
+            if state & self.top == 0 {
+                return Some(starts[(i + 1 - len) % ShiftOr::MAX_LEN]);
+            }
+        }
+        None
+    }
+
+    /// Gets the sequence of classes that TREE matches, or None if it is not a sequence **ShiftOr** can handle: it
+    /// has to be an unanchored list of case-sensitive chars, specials, ranges, and groups of them, all with fixed
+    /// repetition counts and no more than **MAX_LEN** chars in all. Names do not matter, since the walk still
+    /// builds the **Report**.
+    pub fn sequence(tree: &Node) -> Option<Vec<CharClass>> {
+        match tree {
+            Node::And(and_node) if !and_node.anchor => (),
+            _ => return None,
+        }
+        let mut classes = Vec::new();
+        ShiftOr::push_sequence(tree, &mut classes)?;
+        if classes.is_empty() {
+            None
+        } else {
+            Some(classes)
+        }
+    }
+
+    /// adds the classes for NODE to CLASSES, returning None if it cannot be done
+    fn push_sequence(node: &Node, classes: &mut Vec<CharClass>) -> Option<()> {
+        if matches!(node, Node::None | Node::Or(_) | Node::Def(_)) {
+            return None;
+        }
+        let limits = node.limits();
+        if limits.min != limits.max || limits.min > ShiftOr::MAX_LEN {
+            return None;
+        }
+        let mut unit = Vec::new();
+        match node {
+            Node::Chars(chars_node) if !limits.no_case() => {
+                unit.extend(chars_node.string.chars().map(CharClass::Char))
+            }
+            Node::Special(special_node) if special_node.special != '$' => {
+                unit.push(CharClass::Special(special_node.clone()))
+            }
+            Node::Range(range_node) => unit.push(CharClass::Range(range_node.clone())),
+            Node::And(and_node) if !limits.no_case() => {
+                for child in and_node.nodes.iter() {
+                    ShiftOr::push_sequence(child, &mut unit)?;
+                }
+            }
+            _ => return None,
+        }
+        for _ in 0..limits.min {
+            classes.extend(unit.iter().cloned());
+            if classes.len() > ShiftOr::MAX_LEN {
+                return None;
+            }
+        }
+        Some(())
+    }
+}
+
+/// The ways of scanning ahead for where a match can start
+#[derive(Debug)]
+pub enum Scanner {
+    Literals(LiteralScanner),
+    Sequence(ShiftOr),
+}
+
+/// What a **Scanner** looks for
+#[derive(PartialEq)]
+enum ScanFor {
+    Strings(Vec<String>),
+    Sequence(Vec<CharClass>),
+}
+
+impl ScanFor {
+    /// Gets what to scan for to find TREE. A sequence is better, since it checks the whole RE.
+    fn new(tree: &Node) -> Option<ScanFor> {
+        match ShiftOr::sequence(tree) {
+            Some(classes) => Some(ScanFor::Sequence(classes)),
+            None => leading_strings(tree).map(ScanFor::Strings),
+        }
+    }
+}
+
+impl Scanner {
+    /// builds the scanner
+    fn new(scan_for: ScanFor) -> Scanner {
+        match scan_for {
+            ScanFor::Strings(strings) => Scanner::Literals(LiteralScanner::new(strings)),
+            ScanFor::Sequence(classes) => Scanner::Sequence(ShiftOr::new(classes)),
+        }
+    }
+
+    /// Finds the first position at or after FROM in TEXT where a match can start
+    pub fn find(&self, text: &str, from: usize) -> Option<usize> {
+        match self {
+            Scanner::Literals(scanner) => scanner.find(text, from),
+            Scanner::Sequence(scanner) => scanner.find(text, from),
+        }
+    }
+
+    /// the text that has to be loaded past a position found by **find()** to be sure nothing earlier was missed
+    pub fn needed(&self, found: usize) -> usize {
+        match self {
+            Scanner::Literals(scanner) => found + scanner.longest(),
+            Scanner::Sequence(_) => found,
+        }
+    }
+
+    /// Gets where to scan from after more text is added to TEXT, if nothing was found in it
+    pub fn restart(&self, text: &str) -> usize {
+        match self {
+            Scanner::Literals(scanner) => {
+                let mut restart = text.len().saturating_sub(scanner.longest());
+                while !text.is_char_boundary(restart) {
+                    restart -= 1;
+                }
+                restart
+            }
+            // a match not found yet can only start in the last chars, one less than the length of the sequence
+            Scanner::Sequence(scanner) => match scanner.classes.len() {
+                1 => text.len(),
+                len => text
+                    .char_indices()
+                    .rev()
+                    .nth(len - 2)
+                    .map_or(0, |(pos, _)| pos),
+            },
+        }
+    }
+
+    /// true if the scanner looks for SCAN_FOR
+    fn scans_for(&self, scan_for: &ScanFor) -> bool {
+        match (self, scan_for) {
+            (Scanner::Literals(scanner), ScanFor::Strings(strings)) => scanner.strings == *strings,
+            (Scanner::Sequence(scanner), ScanFor::Sequence(classes)) => scanner.classes == *classes,
+            _ => false,
+        }
+    }
+}
+
+/// Gets the strings one of which every match of NODE has to start with, or None if there is no such set
+pub fn leading_strings(node: &Node) -> Option<Vec<String>> {
+    if matches!(node, Node::None) || node.limits().min == 0 || node.no_case() {
+        return None;
+    }
+    match node {
+        Node::Chars(chars_node) if !chars_node.string.is_empty() => {
+            Some(vec![chars_node.string.clone()])
+        }
+        Node::And(and_node) => leading_strings(and_node.nodes.first()?),
+        Node::Or(or_node) if !or_node.nodes.is_empty() => {
+            let mut strings = Vec::new();
+            for branch in or_node.nodes.iter() {
+                strings.append(&mut leading_strings(branch)?);
+            }
+            Some(strings)
+        }
+        _ => None,
+    }
+}
+
+/// Checks whether a match of NODE could start at the front of TEXT, looking only at its first char. It only returns
+/// false when it is sure, so a node that can match nothing, ignores case, or is at the end of the text loaded so far
+/// always gets true. The walk uses it to skip the places a greedy repetition backs off to where the node after it
+/// cannot start.
+pub fn can_start(node: &Node, text: &str) -> bool {
+    if matches!(node, Node::None) || node.limits().min == 0 || node.no_case() || text.is_empty() {
+        return true;
+    }
+    match node {
+        Node::Chars(chars_node) => match chars_node.string.chars().next() {
+            Some(ch) => text.starts_with(ch),
+            None => true,
+        },
+        Node::Special(special_node) => {
+            special_node.special == '$'
+                || special_node.upto.is_some()
+                || special_node.matches(text).is_some()
+        }
+        Node::Range(range_node) => range_node.matches(text).is_some(),
+        Node::And(and_node) => {
+            and_node.anchor
+                || and_node
+                    .nodes
+                    .first()
+                    .is_none_or(|first| can_start(first, text))
+        }
+        Node::Or(or_node) => {
+            or_node.nodes.is_empty() || or_node.nodes.iter().any(|branch| can_start(branch, text))
+        }
+        Node::Def(def_node) => can_start(&def_node.node, text),
+        Node::None => true,
+    }
+}
+
+/// Gets a scanner for TREE that is not shared with the walk, or None if it does not have any. A caller testing many
+/// REs against the same text, like **RegexpSet**, keeps one for each so they are not rebuilt every time.
+pub fn prefilter(tree: &Node) -> Option<Scanner> {
+    ScanFor::new(tree).map(Scanner::new)
+}
+
+/// the scanners used by the last few walks, the most recent first. They are kept since **walk_tree()** is called
+/// again for each match in a search for all, and a **RegexpSet** walks each of its REs in turn on every line.
+static SCANNERS: Lazy<Mutex<VecDeque<Arc<Scanner>>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// the most scanners kept in **SCANNERS**
+const SCANNERS_KEPT: usize = 128;
+
+/// Gets a scanner for TREE, or None if it does not have any
+pub fn scanner(tree: &Node) -> Option<Arc<Scanner>> {
+    let scan_for = ScanFor::new(tree)?;
+    let mut scanners = SCANNERS.lock().unwrap();
+    let scanner = match scanners
+        .iter()
+        .position(|scanner| scanner.scans_for(&scan_for))
+    {
+        Some(0) => return Some(scanners[0].clone()),
+        Some(index) => scanners.remove(index).unwrap(),
+        None => Arc::new(Scanner::new(scan_for)),
+    };
+    scanners.push_front(scanner.clone());
+    scanners.truncate(SCANNERS_KEPT);
+    Some(scanner)
+}
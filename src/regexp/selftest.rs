@@ -0,0 +1,153 @@
+//! ## Self test
+//! A quick check that a build works, for use where there is no cargo to run the tests. **--self-test** runs each case
+//! in **SELF_TEST_CASES** through the public API, parsing the RE with **Regexp::with_parser()** and searching the
+//! text with **Regexp::find()**, prints a line for each saying whether it passed, and exits with status 1 if any
+//! failed. The cases are a small selection from the unit tests covering both syntaxes, and the unit tests run the
+//! same table, so a case that stops passing fails the test suite as well as the self test.
+
+use crate::regexp::captures::Regexp;
+use std::io::Write;
+use Expected::{Error, NoMatch, Span};
+
+/// What a case expects to happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// the first match is the byte range START..END of the text
+    Span(usize, usize),
+    /// there is no match
+    NoMatch,
+    /// parsing the RE fails with this error code
+    Error(usize),
+}
+
+/// A single case of the self test
+#[derive(Debug, Clone, Copy)]
+pub struct SelfTestCase {
+    /// true to parse the RE with the alternative syntax
+    pub alt: bool,
+    /// the RE
+    pub re: &'static str,
+    /// the text searched
+    pub text: &'static str,
+    /// what should happen
+    pub expected: Expected,
+}
+
+/// shortens the table
+const fn case(alt: bool, re: &'static str, text: &'static str, expected: Expected) -> SelfTestCase {
+    SelfTestCase {
+        alt,
+        re,
+        text,
+        expected,
+    }
+}
+
+/// the syntaxes, to make the table easier to read
+const TRAD: bool = false;
+const ALT: bool = true;
+
+/// The cases run by **--self-test**. The definitions made here are all named "selftest_..." so they do not get in
+/// the way of others.
+#[rustfmt::skip]
+pub const SELF_TEST_CASES: &[SelfTestCase] = &[
+    // traditional syntax
+    case(TRAD, "abc", "xxabcxx", Span(2, 5)),
+    case(TRAD, "abc", "xxabxcx", NoMatch),
+    case(TRAD, "^abc", "abcabc", Span(0, 3)),
+    case(TRAD, "^abc", "xabc", NoMatch),
+    case(TRAD, "abc$", "abcabc", Span(3, 6)),
+    case(TRAD, "ab*c", "xac abbbc", Span(1, 3)),
+    case(TRAD, "ab+c", "xac abbbc", Span(4, 9)),
+    case(TRAD, "ab?c", "abbc abc", Span(5, 8)),
+    case(TRAD, "ab{2,3}c", "abc abbbbc abbc", Span(11, 15)),
+    case(TRAD, "a.*c", "xabcbcx", Span(1, 6)),
+    case(TRAD, "a.*?c", "xabcbcx", Span(1, 4)),
+    case(TRAD, r"\d+", "abc 1234 56", Span(4, 8)),
+    case(TRAD, "[a-c]+", "xyzbcaz", Span(3, 6)),
+    case(TRAD, "[^a-c]+", "abcxyzc", Span(3, 6)),
+    case(TRAD, r"\(?<animal>\(cat\)\|\(dog\)\)s", "hot dogs", Span(4, 8)),
+    case(TRAD, r"x\(ab\)+y", "xaby xababy", Span(0, 4)),
+    case(TRAD, r"\cabc", "xxABCxx", Span(2, 5)),
+    case(TRAD, "h\u{e9}llo", "oh h\u{e9}llo", Span(3, 9)),
+    case(TRAD, r"abc\(de", "", Error(1)),
+    case(TRAD, r"asd\)as", "", Error(6)),
+    case(TRAD, "abc[de", "", Error(9)),
+    case(TRAD, r"\(x*\)*", "", Error(10)),
+    // alternative syntax
+    case(ALT, "\"abc\"", "xxabcxx", Span(2, 5)),
+    case(ALT, "'a' 'b'+ 'c'", "ac abbc", Span(3, 7)),
+    case(ALT, "or('cat' 'dog')<pet>", "hotdog", Span(3, 6)),
+    case(ALT, "and('ab')+ 'c'", "ababc", Span(0, 5)),
+    case(ALT, "and('\\d{4}'<year> '-' '\\d\\d'<month>)", "on 2024-06-01", Span(3, 10)),
+    case(ALT, "'/\\*' until('*/') '*/'", "x = 1; /* set x */ y", Span(7, 18)),
+    case(ALT, "any('+-')? '\\d+'", "x-42", Span(1, 4)),
+    case(ALT, "none('abc')+", "abcxyzabc", Span(3, 6)),
+    case(ALT, "'\\cdog'", "hot DOG", Span(4, 7)),
+    case(ALT, "def(selftest_num: '\\d+') get(selftest_num) '.' get(selftest_num)", "v 1.25", Span(2, 6)),
+    case(ALT, "def(selftest_word: 'ab') get(selftest_word)\\c+", "xABab", Span(1, 5)),
+    case(ALT, "use(builtin) get(ipv4)", "host 10.0.0.1 up", Span(5, 13)),
+    case(ALT, "'abc'", "xyz", NoMatch),
+    case(ALT, "\"asd", "", Error(102)),
+    case(ALT, "or(abc def)", "", Error(105)),
+    case(ALT, "get(selftest_no_such_def) ", "", Error(108)),
+    case(ALT, "def(selftest_empty:)", "", Error(112)),
+];
+
+impl SelfTestCase {
+    /// Runs the case, returning a description of what went wrong if it fails
+    pub fn run(&self) -> Result<(), String> {
+        let found = Regexp::with_parser(self.re, self.alt).and_then(|re| re.find(self.text));
+        let got = match &found {
+            Err(error) => Error(error.code),
+            Ok(None) => NoMatch,
+            Ok(Some(found)) => Span(found.start(), found.end()),
+        };
+        if got == self.expected {
+            return Ok(());
+        }
+        Err(match found {
+            Err(error) => format!("expected {:?}, got error {}", self.expected, error),
+            _ => format!("expected {:?}, got {:?}", self.expected, got),
+        })
+    }
+
+    /// the syntax the case uses, for the report
+    fn syntax(&self) -> &'static str {
+        if self.alt {
+            "alternative"
+        } else {
+            "traditional"
+        }
+    }
+}
+
+/// Runs every case in **SELF_TEST_CASES**, writing a line for each to W followed by a count of those that passed,
+/// and returns the number that failed
+pub fn self_test<W: Write + ?Sized>(w: &mut W) -> std::io::Result<usize> {
+    let mut failed = 0;
+    for (i, case) in SELF_TEST_CASES.iter().enumerate() {
+        match case.run() {
+            Ok(()) => writeln!(w, "ok   {:3} {} {:?}", i + 1, case.syntax(), case.re)?,
+            Err(msg) => {
+                failed += 1;
+                writeln!(
+                    w,
+                    "FAIL {:3} {} {:?} on {:?}: {}",
+                    i + 1,
+                    case.syntax(),
+                    case.re,
+                    case.text,
+                    msg
+                )?;
+            }
+        }
+    }
+    writeln!(
+        w,
+        "{} of {} passed",
+        SELF_TEST_CASES.len() - failed,
+        SELF_TEST_CASES.len()
+    )?;
+    Ok(failed)
+}
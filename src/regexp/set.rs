@@ -0,0 +1,219 @@
+//! ## Pattern sets
+//! A **RegexpSet** is a list of REs tested together, for questions like "which of these 80 patterns occur in this
+//! log, and how often". Running the search once for each RE reads the text once for each, and keeps all of it in
+//! **Input**. Instead **scan_lines()** reads it once, a line at a time, and tests every RE on each line as it goes, so
+//! only the current line is held however big the text is.
+//!
+//! Each RE with a **Scanner** (see the scan module) keeps its own as a prefilter: a line the scanner finds nothing in
+//! cannot match, so the walk is only run for the REs that might, and a line no RE can match is never loaded into
+//! **Input** at all. REs with no scanner are walked on every line.
+//!
+//! > let set = RegexpSet::new(&["ERROR", "timeout after \\d+ms"], SearchOptions::new())?;
+//! > let mut counts = vec![0; set.len()];
+//! > for hits in scan_lines(&set, BufReader::new(File::open("big.log")?)) {
+//! >     hits?.patterns.iter().for_each(|&pattern| counts[pattern] += 1);
+//! > }
+//!
+//! Lines are searched on their own, as by **search_lines()**: a match cannot run past the end of its line, and '$'
+//! matches there.
+
+use crate::regexp::captures::Regexp;
+use crate::regexp::options::SearchOptions;
+use crate::regexp::scan::{prefilter, Scanner};
+use crate::regexp::walk::{walk_tree_opts, Input};
+use crate::regexp::Error;
+use std::io::BufRead;
+
+/// A list of REs searched for together, see the module documentation
+pub struct RegexpSet {
+    regexps: Vec<Regexp>,
+    /// the prefilter for each RE, if it has one
+    scanners: Vec<Option<Scanner>>,
+}
+
+impl RegexpSet {
+    /// Parses each of RES as set out by OPTIONS
+    pub fn new(res: &[&str], options: SearchOptions) -> Result<RegexpSet, Error> {
+        let regexps = res
+            .iter()
+            .map(|re| Regexp::with_options(re, options.clone()))
+            .collect::<Result<Vec<Regexp>, Error>>()?;
+        Ok(RegexpSet::from_regexps(regexps))
+    }
+
+    /// Makes a set of REGEXPS, which keep the options they were made with. Those parsed without optimizing get no
+    /// prefilter.
+    pub fn from_regexps(regexps: Vec<Regexp>) -> RegexpSet {
+        let scanners = regexps
+            .iter()
+            .map(|regexp| {
+                if regexp.options().optimize {
+                    prefilter(regexp.tree())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        RegexpSet { regexps, scanners }
+    }
+
+    /// the REs in the set, in the order the pattern numbers in **LineHits** refer to
+    pub fn regexps(&self) -> &[Regexp] {
+        &self.regexps
+    }
+
+    /// the number of REs in the set
+    pub fn len(&self) -> usize {
+        self.regexps.len()
+    }
+
+    /// true if the set has no REs
+    pub fn is_empty(&self) -> bool {
+        self.regexps.is_empty()
+    }
+
+    /// Gets the numbers of the REs that match somewhere in TEXT. Like all searches this uses the global **Input**, so
+    /// it replaces any text set up earlier, but only if a prefilter lets some RE through.
+    pub fn matches(&self, text: &str) -> Result<Vec<usize>, Error> {
+        if Input::bytes_mode() {
+            self.hits(text.as_bytes(), &Input::bytes_to_text(text.as_bytes()))
+        } else {
+            self.hits(text.as_bytes(), text)
+        }
+    }
+
+    /// does the work for **matches()**, where TEXT is BYTES as **Input** holds them. The prefilters have to look at
+    /// the same text as the walk, or in bytes mode a sequence could be missed.
+    fn hits(&self, bytes: &[u8], text: &str) -> Result<Vec<usize>, Error> {
+        let mut loaded = false;
+        let mut found = Vec::new();
+        for (pattern, (regexp, scanner)) in
+            self.regexps.iter().zip(self.scanners.iter()).enumerate()
+        {
+            // no match starts before where the prefilter found one could, but an anchored RE is still walked from the
+            // start so '^' matches there
+            let from = match scanner.as_ref().map(|scanner| scanner.find(text, 0)) {
+                Some(None) => continue,
+                Some(Some(pos)) if !regexp.tree().anchored() => pos,
+                _ => 0,
+            };
+            if !loaded {
+                Input::init_bytes(bytes, false);
+                loaded = true;
+            }
+            if walk_tree_opts(regexp.tree(), from, regexp.options())?.is_some() {
+                found.push(pattern);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// A line that at least one RE of a **RegexpSet** matches, returned by **scan_lines()**
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineHits {
+    /// the line number, counting from 1
+    pub number: usize,
+    /// the line, without its newline
+    pub text: String,
+    /// the numbers of the REs that match in it, in order
+    pub patterns: Vec<usize>,
+}
+
+/// Iterator over the lines of a reader that match a **RegexpSet**, returned by **scan_lines()**
+pub struct LineScan<'s, R: BufRead> {
+    set: &'s RegexpSet,
+    reader: R,
+    /// the line being read, kept to reuse its buffer
+    line: Vec<u8>,
+    /// the number of lines read
+    lines: usize,
+    /// the number of bytes read
+    bytes: usize,
+    /// set after the end of the text or an error
+    done: bool,
+}
+
+impl<R: BufRead> LineScan<'_, R> {
+    /// the number of lines read so far, counting a last line with no newline
+    pub fn lines_read(&self) -> usize {
+        self.lines
+    }
+
+    /// the number of bytes read so far
+    pub fn bytes_read(&self) -> usize {
+        self.bytes
+    }
+
+    /// Reads the next line, returning None at the end of the text. Outside of bytes mode (see
+    /// **Input::set_bytes_mode()**) a line that is not UTF-8 is an error (212).
+    fn read_line(&mut self) -> Result<Option<String>, Error> {
+        self.line.clear();
+        let read = self
+            .reader
+            .read_until(b'\n', &mut self.line)
+            .map_err(|error| Error::make(210, &error.to_string()))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.lines += 1;
+        self.bytes += read;
+        if self.line.last() == Some(&b'\n') {
+            self.line.pop();
+        }
+        if Input::bytes_mode() {
+            return Ok(Some(Input::bytes_to_text(&self.line)));
+        }
+        match std::str::from_utf8(&self.line) {
+            Ok(text) => Ok(Some(text.to_string())),
+            Err(error) => Err(Error::make(
+                212,
+                &format!(
+                    "line {}: {} (--bytes searches it as bytes)",
+                    self.lines, error
+                ),
+            )),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for LineScan<'_, R> {
+    type Item = Result<LineHits, Error>;
+    fn next(&mut self) -> Option<Result<LineHits, Error>> {
+        while !self.done {
+            let found = self.read_line().and_then(|line| match line {
+                None => Ok(None),
+                Some(text) => Ok(Some((self.set.hits(&self.line, &text)?, text))),
+            });
+            match found {
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+                Ok(None) => self.done = true,
+                Ok(Some((patterns, text))) if !patterns.is_empty() => {
+                    return Some(Ok(LineHits {
+                        number: self.lines,
+                        text,
+                        patterns,
+                    }))
+                }
+                Ok(Some(_)) => (),
+            }
+        }
+        None
+    }
+}
+
+/// Reads READER once, a line at a time, testing every RE in SET on each line. Only the lines at least one of them
+/// matches are returned. A read error, or a failed search, is returned in place of a line and ends the scan.
+pub fn scan_lines<R: BufRead>(set: &RegexpSet, reader: R) -> LineScan<'_, R> {
+    LineScan {
+        set,
+        reader,
+        line: Vec::new(),
+        lines: 0,
+        bytes: 0,
+        done: false,
+    }
+}
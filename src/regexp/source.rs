@@ -0,0 +1,171 @@
+//! ## Writing REs out
+//! **Node::to_alt_source()** writes a parsed tree back out as an alternative syntax RE, whichever syntax it was
+//! parsed from. The definitions it uses are written out in place of their **get()**s, so the result stands on its
+//! own: parsing it gives the same tree without needing the definitions. **--expanded** and the interactive **tree
+//! expanded** command print it, to show what an RE assembled from **get()**s and **use()** libraries really is.
+//!
+//! **Node::to_annotated_source()** writes the same RE a unit to a line, indented by depth, with a "# from def NAME"
+//! comment on the line starting each unit a definition was put in for. The alternative syntax has no comments, so
+//! this is for reading rather than parsing.
+
+use crate::regexp::tree::{CharsNode, Limits, Node};
+
+impl Node {
+    /// Writes the tree as an alternative syntax RE, with the definitions it uses expanded
+    pub fn to_alt_source(&self) -> String {
+        match self {
+            Node::And(and_node) => format!(
+                "{}{}",
+                if and_node.anchor { "^" } else { "" },
+                and_node
+                    .nodes
+                    .iter()
+                    .map(alt_unit)
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            node => alt_unit(node),
+        }
+    }
+
+    /// Writes the tree the same way as **to_alt_source()**, but a unit to a line with the expanded definitions marked
+    pub fn to_annotated_source(&self) -> String {
+        let mut lines = Vec::new();
+        match self {
+            Node::And(and_node) => {
+                for node in and_node.nodes.iter() {
+                    annotated_unit(node, 0, Vec::new(), &mut lines);
+                }
+                if let (true, Some(first)) = (and_node.anchor, lines.first_mut()) {
+                    first.insert(0, '^');
+                }
+            }
+            node => annotated_unit(node, 0, Vec::new(), &mut lines),
+        }
+        lines.join("\n")
+    }
+
+    /// true if a definition was expanded anywhere in the tree
+    fn has_defs(&self) -> bool {
+        match self {
+            Node::Def(_) => true,
+            Node::And(and_node) => and_node.nodes.iter().any(Node::has_defs),
+            Node::Or(or_node) => or_node.nodes.iter().any(Node::has_defs),
+            _ => false,
+        }
+    }
+}
+
+/// writes out a single node with its name and repetition
+fn alt_unit(node: &Node) -> String {
+    let unit = match node {
+        Node::Chars(a) => alt_quoted(&escape_chars(&a.string), &a.limits),
+        Node::Special(a) => a.upto_source().unwrap_or_else(|| {
+            if ".$".contains(a.special) {
+                alt_quoted(&a.special.to_string(), &a.limits)
+            } else {
+                alt_quoted(&format!("\\{}", a.special), &a.limits)
+            }
+        }),
+        Node::Range(a) => alt_quoted(&a.to_string(), &a.limits),
+        Node::And(a) => format!(
+            "and({})",
+            a.nodes
+                .iter()
+                .map(alt_unit)
+                .collect::<Vec<String>>()
+                .join(" ")
+        ),
+        Node::Or(a) => format!(
+            "or({})",
+            a.nodes
+                .iter()
+                .map(alt_unit)
+                .collect::<Vec<String>>()
+                .join(" ")
+        ),
+        // the definition already has the name and repetition of the get()
+        Node::Def(a) if !a.node.is_none() => return alt_unit(&a.node),
+        Node::Def(a) => match a.no_case {
+            Some(true) => format!("get({})\\c", a.name),
+            Some(false) => format!("get({})\\C", a.name),
+            None => format!("get({})", a.name),
+        },
+        Node::None => return "".to_string(),
+    };
+    format!("{}{}", unit, suffix(node))
+}
+
+/// adds the lines for NODE to LINES, indented by INDENT. DEFS are the definitions NODE was put in for, outermost
+/// first.
+fn annotated_unit<'a>(
+    node: &'a Node,
+    indent: usize,
+    mut defs: Vec<&'a str>,
+    lines: &mut Vec<String>,
+) {
+    let note = if defs.is_empty() {
+        "".to_string()
+    } else {
+        format!("  # from def {}", defs.join(" > "))
+    };
+    let (kind, nodes) = match node {
+        Node::Def(a) if !a.node.is_none() => {
+            defs.push(&a.name);
+            return annotated_unit(&a.node, indent, defs, lines);
+        }
+        Node::And(a) if node.has_defs() => ("and", &a.nodes),
+        Node::Or(a) if node.has_defs() => ("or", &a.nodes),
+        _ => {
+            lines.push(format!(
+                "{:indent$}{}{}",
+                "",
+                alt_unit(node),
+                note,
+                indent = indent
+            ));
+            return;
+        }
+    };
+    lines.push(format!("{:indent$}{}({}", "", kind, note, indent = indent));
+    for child in nodes.iter() {
+        annotated_unit(child, indent + 2, Vec::new(), lines);
+    }
+    lines.push(format!("{:indent$}){}", "", suffix(node), indent = indent));
+}
+
+/// the repetition and name that follow a unit
+fn suffix(node: &Node) -> String {
+    let limits = node.limits();
+    let name = node
+        .named()
+        .as_ref()
+        .map_or("".to_string(), |name| format!("<{}>", name));
+    if node.name_outside() {
+        format!("{}{}", limits, name)
+    } else {
+        format!("{}{}", name, limits)
+    }
+}
+
+/// wraps the text of a leaf node in double quotes, marking it caseless if needed
+fn alt_quoted(text: &str, limits: &Limits) -> String {
+    format!("\"{}{}\"", if limits.no_case() { "\\c" } else { "" }, text)
+}
+
+/// escapes the characters that are not literal inside double quotes, and writes control chars as escapes
+fn escape_chars(string: &str) -> String {
+    let mut escaped = String::new();
+    for ch in string.chars() {
+        if let Some(code) = CharsNode::escape_code(ch) {
+            escaped.push('\\');
+            escaped.push(code);
+            continue;
+        }
+        if "\\\"[.$*+?{<".contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
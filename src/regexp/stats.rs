@@ -0,0 +1,113 @@
+//! ## Run statistics
+//! **regexp()** returns a **RunStats** along with doing the search, saying how many matches it found in each source,
+//! how much text it read, which files it skipped because they could not be opened, which sources it gave up on
+//! because they are not UTF-8 text, and how long it took.
+//! **--summary-json** prints it as JSON in place of the "Found N instances" line, so scripts do not have to parse
+//! the text meant for people.
+
+use crate::regexp::walk::Input;
+use crate::regexp::Error;
+use serde::Serialize;
+use std::time::Instant;
+
+/// What one source contributed to a run
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SourceStats {
+    /// the file name, or "text" or "stdin"
+    pub name: String,
+    /// the number of matches found in it
+    pub matches: usize,
+    /// the bytes read from it. A search that stops early does not read all of it.
+    pub bytes: usize,
+    /// the lines read from it, counting a last line with no newline
+    pub lines: usize,
+}
+
+/// A file that was skipped because it could not be opened
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SkippedFile {
+    /// the file name
+    pub name: String,
+    /// why it could not be opened
+    pub reason: String,
+}
+
+/// What a call to **regexp()** did
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunStats {
+    /// the number of matches found, or replaced with **--replace**
+    pub matches: usize,
+    /// the sources searched, in order
+    pub files: Vec<SourceStats>,
+    /// the bytes read from all the sources
+    pub bytes_scanned: usize,
+    /// the lines read from all the sources
+    pub lines_scanned: usize,
+    /// the files skipped because they could not be opened (error 201)
+    pub files_skipped: Vec<SkippedFile>,
+    /// the sources given up on because they are not UTF-8 text (error 212)
+    pub files_skipped_binary: Vec<String>,
+    /// the time the run took, in seconds
+    pub elapsed_secs: f64,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+impl RunStats {
+    /// constructor, starting the clock
+    pub fn new() -> RunStats {
+        RunStats {
+            started: Some(Instant::now()),
+            ..RunStats::default()
+        }
+    }
+
+    /// adds the source **Input** is reading from, where MATCHES were found
+    pub fn add_source(&mut self, matches: usize) {
+        let (name, bytes, lines) = Input::source_stats();
+        self.push_source(SourceStats {
+            name,
+            matches,
+            bytes,
+            lines,
+        });
+    }
+
+    /// adds a source that was not read through **Input**
+    pub fn push_source(&mut self, source: SourceStats) {
+        self.matches += source.matches;
+        self.bytes_scanned += source.bytes;
+        self.lines_scanned += source.lines;
+        self.files.push(source);
+    }
+
+    /// fills in what is only known at the end of the run: the skipped and binary sources, and the time taken
+    pub fn finish(&mut self) {
+        self.finish_with(&Input::errors());
+    }
+
+    /// Like **finish()**, for a run whose sources were not read through **Input**, with the ERRORS met reading them
+    pub fn finish_with(&mut self, errors: &[(String, Error)]) {
+        self.files_skipped = errors
+            .iter()
+            .filter(|(_, error)| error.code == 201)
+            .map(|(name, error)| SkippedFile {
+                name: name.clone(),
+                reason: error.msg.clone(),
+            })
+            .collect();
+        self.files_skipped_binary = errors
+            .iter()
+            .filter(|(_, error)| error.code == 212)
+            .map(|(name, _)| name.clone())
+            .collect();
+        if let Some(started) = self.started {
+            self.elapsed_secs = started.elapsed().as_secs_f64();
+        }
+    }
+
+    /// Writes the statistics as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
@@ -0,0 +1,212 @@
+//! ## Syntax summary
+//! A short description of each piece of both RE syntaxes, with a few examples, printed by **--syntax** and the
+//! interactive **syntax** command. It is kept as a table rather than text so other code can look through it or add
+//! to it, and the tests check it against the parser so it cannot fall out of date.
+
+/// The syntaxes an entry belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Traditional,
+    Alternative,
+    Both,
+}
+
+impl Syntax {
+    /// true if the entry is part of the traditional syntax (ALT false) or the alternative one (ALT true)
+    pub fn includes(&self, alt: bool) -> bool {
+        match self {
+            Syntax::Both => true,
+            Syntax::Traditional => !alt,
+            Syntax::Alternative => alt,
+        }
+    }
+}
+
+/// A single line of the summary
+#[derive(Debug, Clone, Copy)]
+pub struct SyntaxEntry {
+    /// the heading it is listed under
+    pub section: &'static str,
+    /// the syntaxes that use it
+    pub syntax: Syntax,
+    /// how it is written
+    pub form: &'static str,
+    /// what it does
+    pub meaning: &'static str,
+}
+
+/// shortens the table
+const fn entry(
+    section: &'static str,
+    syntax: Syntax,
+    form: &'static str,
+    meaning: &'static str,
+) -> SyntaxEntry {
+    SyntaxEntry {
+        section,
+        syntax,
+        form,
+        meaning,
+    }
+}
+
+/// The summary, in the order it is printed. Sections are printed in the order they first appear.
+#[rustfmt::skip]
+pub const SYNTAX_TABLE: &[SyntaxEntry] = &[
+    entry("Special characters", Syntax::Both, "^", "the beginning of the text (only at the front of the RE)"),
+    entry("Special characters", Syntax::Both, "$", "the end of the text"),
+    entry("Special characters", Syntax::Both, ".", "any character"),
+    entry("Special characters", Syntax::Both, "\\a", "an ascii printable character"),
+    entry("Special characters", Syntax::Both, "\\d", "a digit"),
+    entry("Special characters", Syntax::Both, "\\l", "a lower case ascii letter"),
+    entry("Special characters", Syntax::Both, "\\u", "an upper case ascii letter"),
+    entry("Special characters", Syntax::Both, "\\o", "an octal digit"),
+    entry("Special characters", Syntax::Both, "\\x", "a hex digit"),
+    entry("Special characters", Syntax::Both, "\\w", "whitespace: space, tab or newline"),
+    entry("Special characters", Syntax::Both, "\\c", "at the front of a string, ignore case in it"),
+    entry("Escapes", Syntax::Both, "\\n", "a newline, in strings, ranges and quoted strings alike"),
+    entry("Escapes", Syntax::Both, "\\t", "a tab"),
+    entry("Escapes", Syntax::Both, "\\r", "a carriage return"),
+    entry("Escapes", Syntax::Both, "\\0", "a NUL char"),
+    entry("Escapes", Syntax::Both, "\\\\", "a backslash. Any other escaped char is itself, as in \\* or \\["),
+    entry("Ranges", Syntax::Both, "[abx-z]", "any one of a, b, x, y or z"),
+    entry("Ranges", Syntax::Both, "[^abx-z]", "any character but a, b, x, y or z"),
+    entry("Ranges", Syntax::Alternative, "any('...')", "any one of the characters in the string, with no range syntax"),
+    entry("Ranges", Syntax::Alternative, "none('...')", "any character not in the string"),
+    entry("Ranges", Syntax::Alternative, "upto('...')", "any character, except where the string starts"),
+    entry("Ranges", Syntax::Alternative, "until('...')", "the longest run of characters before the string (or to the end)"),
+    entry("Units", Syntax::Traditional, "\\(...\\)", "a group, recorded in the report"),
+    entry("Units", Syntax::Traditional, "\\(?...\\)", "a group that is not recorded"),
+    entry("Units", Syntax::Traditional, "\\(?<NAME>...\\)", "a group recorded as NAME, once for each repetition"),
+    entry("Units", Syntax::Traditional, "\\(?<NAME+>...\\)", "a group recorded as NAME, all its repetitions together"),
+    entry("Units", Syntax::Traditional, "A\\|B", "A or B, where A and B are the single units next to the \\|"),
+    entry("Units", Syntax::Traditional, "(?g)", "at the front, makes \\| take the whole run of characters each side"),
+    entry("Units", Syntax::Alternative, "\"...\" '...'", "a string of characters, which can contain specials and ranges"),
+    entry("Units", Syntax::Alternative, "txt(...)", "the same, ended by ')'"),
+    entry("Units", Syntax::Alternative, "and(U U ...)", "all the units, one after the other"),
+    entry("Units", Syntax::Alternative, "or(U U ...)", "any one of the units"),
+    entry("Units", Syntax::Alternative, "U<NAME>", "records each match of U as NAME (<> records it unnamed)"),
+    entry("Units", Syntax::Alternative, "U*<NAME>", "records all the repetitions of U together as NAME"),
+    entry("Units", Syntax::Alternative, "U<NAME[N]>*", "records only the Nth repetition of U"),
+    entry("Definitions", Syntax::Alternative, "def(NAME: U ...)", "defines NAME as the units"),
+    entry("Definitions", Syntax::Alternative, "get(NAME)", "inserts the definition of NAME"),
+    entry("Definitions", Syntax::Alternative, "use(FILE)", "reads definitions from FILE"),
+    entry("Definitions", Syntax::Alternative, "use(FILE as NS)", "reads them as NS.NAME, so files cannot clash"),
+    entry("Definitions", Syntax::Alternative, "use(builtin)", "loads common patterns, listed by --defs-list builtin"),
+    entry("Repetitions", Syntax::Both, "*", "0 or more times"),
+    entry("Repetitions", Syntax::Both, "+", "1 or more times"),
+    entry("Repetitions", Syntax::Both, "?", "0 or 1 times"),
+    entry("Repetitions", Syntax::Both, "{N}", "exactly N times"),
+    entry("Repetitions", Syntax::Both, "{N,}", "N or more times"),
+    entry("Repetitions", Syntax::Both, "{N,M}", "from N to M times"),
+    entry("Repetitions", Syntax::Both, "*? +? ...", "a trailing '?' makes the repetition lazy, taking as few as it can"),
+];
+
+/// An example RE, with a text it matches and what it does
+#[derive(Debug, Clone, Copy)]
+pub struct SyntaxExample {
+    /// true for the alternative syntax
+    pub alt: bool,
+    pub re: &'static str,
+    pub text: &'static str,
+    pub meaning: &'static str,
+}
+
+/// The examples. They are run by the tests, so each RE must match its text.
+pub const SYNTAX_EXAMPLES: &[SyntaxExample] = &[
+    SyntaxExample {
+        alt: false,
+        re: r"ab*c",
+        text: "xabbbc",
+        meaning: "a, any number of b's, then c",
+    },
+    SyntaxExample {
+        alt: false,
+        re: r"\(?<year>\d{4}\)-\(?<month>\d\d\)",
+        text: "on 2024-06-01",
+        meaning: "a date, with the year and month reported by name",
+    },
+    SyntaxExample {
+        alt: false,
+        re: r"(?g)cat\|dog",
+        text: "hotdog",
+        meaning: "either word",
+    },
+    SyntaxExample {
+        alt: true,
+        re: r#""ab*c""#,
+        text: "xabbbc",
+        meaning: "a, any number of b's, then c",
+    },
+    SyntaxExample {
+        alt: true,
+        re: r#"and("\d{4}"<year> "-" "\d\d"<month>)"#,
+        text: "on 2024-06-01",
+        meaning: "a date, with the year and month reported by name",
+    },
+    SyntaxExample {
+        alt: true,
+        re: r#"or("cat" "dog")"#,
+        text: "hotdog",
+        meaning: "either word",
+    },
+    SyntaxExample {
+        alt: true,
+        re: r#""/\*" until("*/")<body> "*/""#,
+        text: "x = 1; /* set x */",
+        meaning: "a C comment, with its text reported as body",
+    },
+    SyntaxExample {
+        alt: true,
+        re: r#"any("+-")? "\d+"<num>"#,
+        text: "x-42",
+        meaning: "a number with an optional sign",
+    },
+];
+
+/// Builds the summary for the traditional syntax, or the alternative one if ALT is set
+pub fn syntax_summary(alt: bool) -> String {
+    let entries: Vec<&SyntaxEntry> = SYNTAX_TABLE
+        .iter()
+        .filter(|entry| entry.syntax.includes(alt))
+        .collect();
+    let width = entries
+        .iter()
+        .map(|entry| entry.form.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let mut summary = if alt {
+        "Alternative syntax (--parser alternative)\n".to_string()
+    } else {
+        "Traditional syntax (--parser traditional)\n".to_string()
+    };
+    let mut sections = Vec::<&str>::new();
+    for entry in entries.iter() {
+        if !sections.contains(&entry.section) {
+            sections.push(entry.section);
+        }
+    }
+    for section in sections {
+        summary.push_str(&format!("\n{}:\n", section));
+        for entry in entries.iter().filter(|entry| entry.section == section) {
+            summary.push_str(&format!(
+                "  {:width$}{}\n",
+                entry.form,
+                entry.meaning,
+                width = width
+            ));
+        }
+    }
+    summary.push_str("\nExamples:\n");
+    for example in SYNTAX_EXAMPLES.iter().filter(|example| example.alt == alt) {
+        summary.push_str(&format!(
+            "  regexp {}-n '{}' -t '{}'\n      {}\n",
+            if alt { "-p alt " } else { "" },
+            example.re,
+            example.text,
+            example.meaning
+        ));
+    }
+    summary
+}
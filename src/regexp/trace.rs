@@ -0,0 +1,343 @@
+//! ## Trace output
+//! Trace output normally goes to stdout as indented text, which is good for reading but not for processing. This
+//! module lets the output be sent somewhere else, and adds a JSON format for the walk phase where each event (the
+//! start and end of walking a node, pushing a step, backing off, switching an OR branch) is written as a line of
+//! JSON. **attempt_tree()** reads the events back into the tree of attempts the walk made.
+//!
+//! With the **log** feature the trace can go through the **log** crate instead, so a program embedding the matcher
+//! gets it with the rest of its logging: **set_trace_backend(TraceBackend::Log)**. Trace levels 1 and 2 (the outline
+//! of the parse and the walk) are logged at debug and the higher levels at trace, with a target naming the module
+//! the line comes from, like "regexp::walk". The trace level still selects what is traced; the logger then filters
+//! it as it does anything else. JSON events are logged at trace level with the target "regexp::walk".
+
+use crate::regexp::trace_get_indent;
+use core::sync::atomic::{
+    AtomicBool, AtomicUsize,
+    Ordering::{AcqRel, Acquire, Release},
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::Mutex;
+
+/// The formats trace output can be written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    /// indented text lines, as selected by the trace level
+    Text,
+    /// one JSON **TraceEvent** per line for the walk phase, the text lines are not written
+    Json,
+}
+
+/// Where trace output goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceBackend {
+    /// the writer set by **set_trace_writer()**, or stdout. This is the default.
+    Writer,
+    /// the **log** crate
+    #[cfg(feature = "log")]
+    Log,
+}
+
+/// set when the trace goes to the log crate
+#[cfg(feature = "log")]
+static TRACE_LOG: AtomicBool = AtomicBool::new(false);
+
+/// set when the trace is in JSON format
+static TRACE_JSON: AtomicBool = AtomicBool::new(false);
+
+/// the nesting depth of the walk, used for JSON events
+static TRACE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Where the trace goes. None means stdout.
+static TRACE_WRITER: Lazy<Mutex<Option<Box<dyn Write + Send>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Selects the format for trace output
+pub fn set_trace_format(format: TraceFormat) {
+    TRACE_JSON.store(format == TraceFormat::Json, Release);
+}
+
+/// Gets the current trace format
+pub fn trace_format() -> TraceFormat {
+    if TRACE_JSON.load(Acquire) {
+        TraceFormat::Json
+    } else {
+        TraceFormat::Text
+    }
+}
+
+/// Sends trace output to **writer**, or back to stdout if it is None. The previous writer is returned.
+pub fn set_trace_writer(writer: Option<Box<dyn Write + Send>>) -> Option<Box<dyn Write + Send>> {
+    std::mem::replace(&mut *TRACE_WRITER.lock().unwrap(), writer)
+}
+
+/// Selects where trace output goes, returning where it went before
+pub fn set_trace_backend(backend: TraceBackend) -> TraceBackend {
+    #[cfg(feature = "log")]
+    if TRACE_LOG.swap(backend == TraceBackend::Log, AcqRel) {
+        return TraceBackend::Log;
+    }
+    #[cfg(not(feature = "log"))]
+    let _ = backend;
+    TraceBackend::Writer
+}
+
+/// Gets where trace output currently goes
+pub fn trace_backend() -> TraceBackend {
+    #[cfg(feature = "log")]
+    if TRACE_LOG.load(Acquire) {
+        return TraceBackend::Log;
+    }
+    TraceBackend::Writer
+}
+
+/// the log target for a line from the module MODULE (from **module_path!()**): "regexp::" and the last part of it
+#[cfg(feature = "log")]
+fn log_target(module: &str) -> String {
+    format!("regexp::{}", module.rsplit("::").next().unwrap_or_default())
+}
+
+/// writes a line of trace output
+fn write_line(line: &str) {
+    #[cfg(feature = "log")]
+    if TRACE_LOG.load(Acquire) {
+        log::trace!(target: "regexp::walk", "{}", line);
+        return;
+    }
+    match &mut *TRACE_WRITER.lock().unwrap() {
+        Some(writer) => {
+            let _ = writeln!(writer, "{}", line);
+        }
+        None => println!("{}", line),
+    }
+}
+
+/// Writes a text trace line at the current indent, as trace level 1. This does nothing when the trace is in JSON
+/// format.
+pub fn trace_line(line: &str) {
+    trace_message(1, module_path!(), line);
+}
+
+/// Writes a text trace line at the current indent for trace level LEVEL, from the module MODULE. This is used by the
+/// **trace!()** macro, and does nothing when the trace is in JSON format.
+pub fn trace_message(level: usize, module: &str, line: &str) {
+    if TRACE_JSON.load(Acquire) {
+        return;
+    }
+    let line = format!("{0:1$}{2}", "", trace_get_indent(), line);
+    #[cfg(feature = "log")]
+    if TRACE_LOG.load(Acquire) {
+        let log_level = if level <= 2 {
+            log::Level::Debug
+        } else {
+            log::Level::Trace
+        };
+        log::log!(target: &log_target(module), log_level, "{}", line);
+        return;
+    }
+    let _ = (level, module);
+    write_line(&line);
+}
+
+/// The kinds of event in a JSON trace
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceKind {
+    /// a node starts walking at **start**
+    WalkStart,
+    /// a node finished walking, with **reps** repetitions over the byte range
+    WalkEnd,
+    /// a new repetition (step) was added to a path
+    Push,
+    /// a path is being backed off after a failure further on
+    BackOff,
+    /// an OR node moved on to the next branch
+    OrBranch,
+}
+
+/// A single event in a JSON trace. Positions are byte positions in the text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub event: TraceKind,
+    /// description of the node being walked
+    pub node: String,
+    pub start: usize,
+    pub end: usize,
+    /// nesting depth of the walk, 0 for the root node
+    pub depth: usize,
+    /// for **WalkEnd** and **Push**, the number of repetitions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reps: Option<usize>,
+    /// for **WalkEnd**, whether the node matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matched: Option<bool>,
+    /// for **OrBranch**, the branch now being tried (from 0). For the **WalkEnd** of an OR that matched, the branch
+    /// its last repetition matched with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<usize>,
+}
+
+impl TraceEvent {
+    /// constructor for an event with none of the optional fields
+    pub fn make(event: TraceKind, node: String, range: (usize, usize)) -> TraceEvent {
+        TraceEvent {
+            event,
+            node: node.trim_end().to_string(),
+            start: range.0,
+            end: range.1,
+            depth: 0,
+            reps: None,
+            matched: None,
+            branch: None,
+        }
+    }
+}
+
+/// true if JSON events should be written: the format is JSON and tracing is turned on
+pub(crate) fn json_tracing() -> bool {
+    TRACE_JSON.load(Acquire) && crate::regexp::trace_level(1)
+}
+
+/// Writes a JSON event, filling in the depth. **WalkStart** increases the depth for the events that follow and
+/// **WalkEnd** decreases it, so both are reported at the depth of the node itself. Backing off is done by the
+/// parent to a child path whose walk has already ended, so it gets the child's depth.
+pub(crate) fn trace_event(mut event: TraceEvent) {
+    event.depth = match event.event {
+        TraceKind::WalkStart => TRACE_DEPTH.fetch_add(1, AcqRel),
+        TraceKind::WalkEnd => TRACE_DEPTH.fetch_sub(1, AcqRel).saturating_sub(1),
+        TraceKind::BackOff => TRACE_DEPTH.load(Acquire),
+        _ => TRACE_DEPTH.load(Acquire).saturating_sub(1),
+    };
+    if let Ok(json) = serde_json::to_string(&event) {
+        write_line(&json);
+    }
+}
+
+/// resets the walk depth, called at the start of each walk from the root
+pub(crate) fn trace_reset_depth() {
+    TRACE_DEPTH.store(0, Release);
+}
+
+/// One attempt to match a node, rebuilt from the trace events
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    pub node: String,
+    pub start: usize,
+    pub end: usize,
+    /// None if the events ended before the walk of this node did
+    pub matched: Option<bool>,
+    pub reps: usize,
+    pub back_offs: usize,
+    pub branches: Vec<usize>,
+    /// attempts made for the node's children, in order
+    pub children: Vec<Attempt>,
+}
+
+/// Reads JSON trace events, one per line, ignoring lines that are not events
+pub fn read_events(json: &str) -> Vec<TraceEvent> {
+    json.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Rebuilds the tree of attempts from a list of events. Each **WalkStart** begins an attempt, which the matching
+/// **WalkEnd** finishes; the events in between belong to it or to its children. Several walks from the root give
+/// several trees, so a list is returned.
+pub fn attempt_tree(events: &[TraceEvent]) -> Vec<Attempt> {
+    let mut roots = Vec::<Attempt>::new();
+    let mut open = Vec::<Attempt>::new();
+    for event in events {
+        match event.event {
+            TraceKind::WalkStart => open.push(Attempt {
+                node: event.node.clone(),
+                start: event.start,
+                end: event.end,
+                matched: None,
+                reps: 0,
+                back_offs: 0,
+                branches: Vec::new(),
+                children: Vec::new(),
+            }),
+            TraceKind::WalkEnd => {
+                if let Some(mut attempt) = open.pop() {
+                    attempt.end = event.end;
+                    attempt.matched = event.matched;
+                    attempt.reps = event.reps.unwrap_or(attempt.reps);
+                    match open.last_mut() {
+                        Some(parent) => parent.children.push(attempt),
+                        None => roots.push(attempt),
+                    }
+                }
+            }
+            TraceKind::Push => {
+                if let Some(attempt) = open.last_mut() {
+                    attempt.reps = event.reps.unwrap_or(attempt.reps + 1);
+                }
+            }
+            // this backs off a path that has already ended, so it goes to the most recent attempt for the node
+            TraceKind::BackOff => {
+                let siblings = match open.last_mut() {
+                    Some(parent) => &mut parent.children,
+                    None => &mut roots,
+                };
+                if let Some(attempt) = siblings
+                    .iter_mut()
+                    .rev()
+                    .find(|attempt| attempt.node == event.node)
+                {
+                    attempt.back_offs += 1;
+                }
+            }
+            TraceKind::OrBranch => {
+                if let Some(attempt) = open.last_mut() {
+                    attempt.branches.extend(event.branch);
+                }
+            }
+        }
+    }
+    // walks that never finished, if the events were cut off
+    while let Some(attempt) = open.pop() {
+        match open.last_mut() {
+            Some(parent) => parent.children.push(attempt),
+            None => roots.push(attempt),
+        }
+    }
+    roots
+}
+
+impl Attempt {
+    /// prints the attempt and its children as an indented tree
+    pub fn print(&self, indent: usize) {
+        println!(
+            "{0:1$}{2} [{3}, {4}) {5}{6}{7}{8}",
+            "",
+            indent * crate::regexp::TAB_SIZE,
+            self.node,
+            self.start,
+            self.end,
+            match self.matched {
+                Some(true) => "matched",
+                Some(false) => "failed",
+                None => "unfinished",
+            },
+            if self.reps > 0 {
+                format!(", {} reps", self.reps)
+            } else {
+                "".to_string()
+            },
+            if self.back_offs > 0 {
+                format!(", {} back offs", self.back_offs)
+            } else {
+                "".to_string()
+            },
+            if self.branches.is_empty() {
+                "".to_string()
+            } else {
+                format!(", branches tried {:?}", self.branches)
+            },
+        );
+        self.children
+            .iter()
+            .for_each(|child| child.print(indent + 1));
+    }
+}
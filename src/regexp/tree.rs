@@ -1,12 +1,19 @@
 //! ## Regular expression search: RE parser
 //! This module offers all functionality for RE searches. It contains the code to parse the RE into a tree, and also exports
 //! the functionality to walk the tree and display the results. The walking is handled in the walk subpackage.
-use crate::regexp::{trace_indent, trace_level, trace_set_indent, Error, TAB_SIZE};
+use crate::regexp::builtin::{builtin_source, BUILTIN_PATH, BUILTIN_PREFIX};
+use crate::regexp::options::{parse_tree_opts, SearchOptions};
+use crate::regexp::{trace_indent, trace_level, trace_set_indent, Error, Warning, TAB_SIZE};
 use crate::walk::*;
 use crate::{trace, trace_change_indent};
 use core::fmt::Debug;
+use core::sync::atomic::{
+    AtomicBool, AtomicUsize,
+    Ordering::{AcqRel, Acquire},
+};
 use home;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
 ///
 /// Besides traditional (elisp/perl) style regular expressions there is also a parser for a new style of regular expression,
 /// While writing the RE parser it became clear that by compiling the REs into a tree structure that would then be passed to
@@ -39,6 +46,21 @@ use std::sync::Mutex;
 /// big number to server as a cap for repetition count
 pub const EFFECTIVELY_INFINITE: usize = 99999999;
 
+/// The escapes that stand for a single char, as (code, char). Escapes mean the same thing everywhere they can be
+/// written, in both syntaxes:
+///
+/// | escape             | strings          | ranges [...]     | any(), none(), upto(), until() |
+/// |--------------------|------------------|------------------|--------------------------------|
+/// | \n \t \r \0        | the char here    | the char here    | the char here                  |
+/// | \d \l \u \a ...    | a special        | a special        | error                          |
+/// | \\ and the rest    | the char itself  | the char itself  | the char itself                |
+///
+/// Strings are traditional REs and the "...", '...', txt() and bare text units of the alternative syntax. An escaped
+/// letter or digit that is not in either list is taken as itself with a warning, since it is probably a typo. Only
+/// a real whitespace char ends a bare text unit, so "\t" can be used in one.
+pub(crate) const CHAR_ESCAPES: &[(char, char)] =
+    &[('n', '\n'), ('t', '\t'), ('r', '\r'), ('0', '\0')];
+
 //////////////////////////////////////////////////////////////////
 //
 // Node
@@ -64,7 +86,11 @@ pub const EFFECTIVELY_INFINITE: usize = 99999999;
 /// types: CharsNode, SpecialCharNode, SetNode, AndNode, and OrNode.
 /// Besides serving like a Box to hold the different XXXNode structs it
 /// also functions to distribute messages to the proper XXXNode
-#[derive(PartialEq)]
+///
+/// Equality and hashing are structural, so after **canonicalize()** a tree can be used as a HashMap key to find
+/// REs that are written differently but search the same way. For **DefNode**s both the name and the substituted
+/// contents take part, so redefining a snippet changes the hash of any tree using it.
+#[derive(PartialEq, Eq, Hash)]
 pub enum Node {
     Chars(CharsNode),
     And(AndNode),
@@ -89,6 +115,92 @@ impl core::fmt::Debug for Node {
     }
 }
 
+/// Shows the node and those under it the way **desc()** prints them, one to a line
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = Vec::new();
+        self.desc_to(&mut out, 0).map_err(|_| std::fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
+
+/// How much of a tree **desc()** prints. A tree built from a big definition library can run to hundreds of lines, so
+/// a **TreeView** can cut it off below a depth, with a line saying how many nodes were left out, or print only the
+/// subtrees under the units with a given name:
+///
+/// > TreeView::new().depth(2).filter("date").print(&tree);
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeView {
+    /// the number of levels printed, None for all of them
+    depth: Option<usize>,
+    /// if set only the subtrees under units with this name are printed
+    filter: Option<String>,
+}
+
+impl TreeView {
+    /// constructor, for the whole tree
+    pub fn new() -> TreeView {
+        TreeView::default()
+    }
+
+    /// prints only the top DEPTH levels of the tree, the root being the first
+    pub fn depth(mut self, depth: usize) -> TreeView {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// prints only the subtrees under the units named NAME, each from the left margin. A unit named NAME inside one
+    /// already printed is not printed again.
+    pub fn filter(mut self, name: &str) -> TreeView {
+        self.filter = Some(name.to_string());
+        self
+    }
+
+    /// Prints the part of TREE asked for to stdout
+    pub fn print(&self, tree: &Node) {
+        let _ = self.write_to(tree, &mut std::io::stdout());
+    }
+
+    /// Like **print()**, but writes to W instead of stdout
+    pub fn write_to<W: Write + ?Sized>(&self, tree: &Node, w: &mut W) -> std::io::Result<()> {
+        let Some(name) = &self.filter else {
+            return tree.desc_view(w, 0, self, 1);
+        };
+        let mut found = Vec::new();
+        tree.find_named(name, &mut found);
+        if found.is_empty() {
+            writeln!(w, "(no unit named {})", name)?;
+        }
+        for node in found {
+            node.desc_view(w, 0, self, 1)?;
+        }
+        Ok(())
+    }
+
+    /// prints the CHILDREN of a node on level LEVEL, at INDENT, or if they are below the depth printed a line
+    /// saying how many nodes are left out
+    fn children<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        children: &[Node],
+        indent: usize,
+        level: usize,
+    ) -> std::io::Result<()> {
+        if self.depth.is_some_and(|depth| level >= depth) {
+            let count: usize = children.iter().map(Node::node_count).sum();
+            return match count {
+                0 => Ok(()),
+                1 => writeln!(w, "{0:1$}\u{2026} 1 more node", "", indent),
+                count => writeln!(w, "{0:1$}\u{2026} {2} more nodes", "", indent, count),
+            };
+        }
+        for child in children {
+            child.desc_view(w, indent, self, level + 1)?;
+        }
+        Ok(())
+    }
+}
+
 impl Clone for Node {
     fn clone(&self) -> Node {
         match self {
@@ -124,29 +236,115 @@ impl Node {
         }
     }
 
+    /// The fewest bytes a match of the node can take, used to skip walks that cannot fit in the text that is left.
+    /// A caseless string can match text with different chars, so it only counts one byte for each char.
+    pub fn min_len(&self) -> usize {
+        let (one, limits) = match self {
+            Node::Chars(chars_node) => (
+                if chars_node.limits.no_case() {
+                    chars_node.string.chars().count()
+                } else {
+                    chars_node.string.len()
+                },
+                &chars_node.limits,
+            ),
+            Node::Special(special_node) => (
+                if special_node.special == '$' { 0 } else { 1 },
+                &special_node.limits,
+            ),
+            Node::Range(range_node) => (1, &range_node.limits),
+            Node::And(and_node) => (
+                and_node
+                    .nodes
+                    .iter()
+                    .fold(0usize, |sum, node| sum.saturating_add(node.min_len())),
+                &and_node.limits,
+            ),
+            Node::Or(or_node) => (
+                or_node
+                    .nodes
+                    .iter()
+                    .map(|node| node.min_len())
+                    .min()
+                    .unwrap_or(0),
+                &or_node.limits,
+            ),
+            Node::Def(def_node) => return def_node.node.min_len(),
+            Node::None => return 0,
+        };
+        one.saturating_mul(limits.min)
+    }
+
     /// Distributes a walk request to the proper XXXNode struct
-    pub fn walk(&self, matched: Matched) -> Result<Path, Error> {
+    pub fn walk<'a>(
+        &'a self,
+        matched: Matched,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<Path<'a>, Error> {
         match self {
-            Node::Chars(chars_node) => CharsStep::walk(chars_node, matched),
-            Node::Special(special_node) => SpecialStep::walk(special_node, matched),
-            Node::Range(range_node) => RangeStep::walk(range_node, matched),
-            Node::And(and_node) => AndStep::walk(and_node, matched),
-            Node::Or(or_node) => OrStep::walk(or_node, matched),
-            Node::Def(def_node) => def_node.node.walk(matched),
+            Node::Chars(chars_node) => CharsStep::walk(chars_node, matched, arena),
+            Node::Special(special_node) => SpecialStep::walk(special_node, matched, arena),
+            Node::Range(range_node) => RangeStep::walk(range_node, matched, arena),
+            Node::And(and_node) => AndStep::walk(and_node, matched, arena),
+            Node::Or(or_node) => OrStep::walk(or_node, matched, arena),
+            Node::Def(def_node) => def_node.node.walk(matched, arena),
             Node::None => panic!("NONE node should not be in final tree"),
         }
     }
 
     /// **desc()** similar to Debug or Display, but for AND and OR nodes also prints descendents with indenting by generation
     pub fn desc(&self, indent: usize) {
+        let _ = self.desc_to(&mut std::io::stdout(), indent);
+    }
+
+    /// Like **desc()**, but writes to W instead of stdout
+    pub fn desc_to<W: Write + ?Sized>(&self, w: &mut W, indent: usize) -> std::io::Result<()> {
+        self.desc_view(w, indent, &TreeView::new(), 1)
+    }
+
+    /// does the work for **desc_to()** and **TreeView**, printing the node, which is on level LEVEL, at INDENT and
+    /// as much under it as VIEW asks for
+    fn desc_view<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        indent: usize,
+        view: &TreeView,
+        level: usize,
+    ) -> std::io::Result<()> {
+        match self {
+            Node::Chars(a) => a.desc_to(w, indent),
+            Node::Special(a) => a.desc_to(w, indent),
+            Node::Range(a) => a.desc_to(w, indent),
+            Node::And(a) => a.desc_to(w, indent, view, level),
+            Node::Or(a) => a.desc_to(w, indent, view, level),
+            Node::Def(a) => a.desc_to(w, indent, view, level),
+            Node::None => write!(w, "{0:1$}", "None", indent),
+        }
+    }
+
+    /// the number of nodes in the tree under and including this one, as **desc()** prints them
+    fn node_count(&self) -> usize {
+        match self {
+            Node::And(a) => 1 + a.nodes.iter().map(Node::node_count).sum::<usize>(),
+            Node::Or(a) => 1 + a.nodes.iter().map(Node::node_count).sum::<usize>(),
+            Node::Def(a) if !a.node.is_none() => 1 + a.node.node_count(),
+            Node::Def(a) => 1 + Defs::get(a.name.as_str()).map_or(0, |node| node.node_count()),
+            Node::None => 0,
+            _ => 1,
+        }
+    }
+
+    /// adds the outermost units named NAME in the tree to FOUND
+    fn find_named<'a>(&'a self, name: &str, found: &mut Vec<&'a Node>) {
+        if !self.is_none() && self.named().as_deref() == Some(name) {
+            found.push(self);
+            return;
+        }
         match self {
-            Node::Chars(a) => a.desc(indent),
-            Node::Special(a) => a.desc(indent),
-            Node::Range(a) => a.desc(indent),
-            Node::And(a) => a.desc(indent),
-            Node::Or(a) => a.desc(indent),
-            Node::Def(a) => a.desc(indent),
-            Node::None => print!("{0:1$}", "None", indent),
+            Node::And(a) => a.nodes.iter().for_each(|node| node.find_named(name, found)),
+            Node::Or(a) => a.nodes.iter().for_each(|node| node.find_named(name, found)),
+            Node::Def(a) => a.node.find_named(name, found),
+            _ => (),
         }
     }
 
@@ -183,7 +381,7 @@ impl Node {
     }
 
     /// Gets the **self.named** value from the wrapped XXXNode
-    fn named(&self) -> &Option<String> {
+    pub(crate) fn named(&self) -> &Option<String> {
         match self {
             Node::Chars(a) => &a.named,
             Node::Special(a) => &a.named,
@@ -195,6 +393,19 @@ impl Node {
         }
     }
 
+    /// Gets the **self.name_outside** value from the wrapped XXXNode
+    pub(crate) fn name_outside(&self) -> bool {
+        match self {
+            Node::Chars(a) => a.name_outside,
+            Node::Special(a) => a.name_outside,
+            Node::Range(a) => a.name_outside,
+            Node::And(a) => a.name_outside,
+            Node::Or(a) => a.name_outside,
+            Node::Def(a) => a.name_outside,
+            Node::None => false,
+        }
+    }
+
     /// Sets the **self.limits** value for the wrapped XXXNode
     fn set_limits(&mut self, limits: Limits) {
         match self {
@@ -208,34 +419,108 @@ impl Node {
         };
     }
 
-    /// Fills in the definitions from the Defs hash table
-    fn substitute_defs<'a>(&'a mut self, nested: &mut Vec<&'a str>) -> Result<(), Error> {
+    /// The byte offsets in the RE of the text the node was parsed from, as (start, end). A node from a definition has
+    /// the span of the **get()** that brought it in, and one made from part of a unit, like a string broken up around
+    /// a special char, has the span of the whole unit. None for a node that was not parsed from an RE.
+    pub fn source_span(&self) -> Option<(usize, usize)> {
+        self.span().get()
+    }
+
+    /// the **SourceSpan** of the wrapped XXXNode
+    fn span(&self) -> SourceSpan {
+        match self {
+            Node::Chars(a) => a.span,
+            Node::Special(a) => a.span,
+            Node::Range(a) => a.span,
+            Node::And(a) => a.span,
+            Node::Or(a) => a.span,
+            Node::Def(a) => a.span,
+            Node::None => SourceSpan::default(),
+        }
+    }
+
+    /// Sets the span of the wrapped XXXNode
+    fn set_span(&mut self, span: SourceSpan) {
+        match self {
+            Node::Chars(a) => a.span = span,
+            Node::Special(a) => a.span = span,
+            Node::Range(a) => a.span = span,
+            Node::And(a) => a.span = span,
+            Node::Or(a) => a.span = span,
+            Node::Def(a) => a.span = span,
+            Node::None => (),
+        }
+    }
+
+    /// Gives the nodes under this one that have no span of their own the span of the closest node above them that
+    /// does, SPAN being the one for this node if it has none
+    fn fill_spans(&mut self, span: SourceSpan) {
+        if self.span().get().is_none() {
+            self.set_span(span);
+        }
+        let span = self.span();
+        match self {
+            Node::And(a) => a.nodes.iter_mut().for_each(|x| x.fill_spans(span)),
+            Node::Or(a) => a.nodes.iter_mut().for_each(|x| x.fill_spans(span)),
+            Node::Def(a) => a.node.fill_spans(span),
+            _ => (),
+        }
+    }
+
+    /// Gives the node and all those under it SPAN, for a definition brought in by a **get()**
+    fn set_spans(&mut self, span: SourceSpan) {
+        self.set_span(span);
+        match self {
+            Node::And(a) => a.nodes.iter_mut().for_each(|x| x.set_spans(span)),
+            Node::Or(a) => a.nodes.iter_mut().for_each(|x| x.set_spans(span)),
+            Node::Def(a) => a.node.set_spans(span),
+            _ => (),
+        }
+    }
+
+    /// Fills in the definitions from the Defs hash table. Names with no definition are collected in **missing** so
+    /// they can all be reported at once.
+    fn substitute_defs<'a>(
+        &'a mut self,
+        nested: &mut Vec<&'a str>,
+        missing: &mut Vec<String>,
+    ) -> Result<(), Error> {
         match self {
             Node::And(a) => {
                 for x in &mut a.nodes[..] {
-                    x.substitute_defs(nested)?;
+                    x.substitute_defs(nested, missing)?;
                 }
             }
             Node::Or(a) => {
                 for x in &mut a.nodes[..] {
-                    x.substitute_defs(nested)?;
+                    x.substitute_defs(nested, missing)?;
                 }
             }
             Node::Def(def_node) => {
                 if def_node.node.is_none() {
                     if let Some(mut node) = Defs::get(def_node.name.as_str()) {
+                        // what is given with the get() wins over what the definition has
                         if def_node.limits != Limits::default() {
-                            node.set_limits(def_node.limits);
+                            let mut limits = def_node.limits;
+                            limits.options |= node.limits().options & Limits::NO_CASE;
+                            node.set_limits(limits);
                         }
                         if def_node.named.is_some() {
                             node.set_named(def_node.named.clone(), def_node.name_outside);
                         }
-                        def_node.node = Box::new(node);
+                        match def_node.no_case {
+                            Some(true) => make_caseless(&mut node),
+                            Some(false) => make_case_sensitive(&mut node),
+                            None => (),
+                        }
+                        // the definition was parsed from other text, so it is shown as the get() that used it
+                        node.set_spans(def_node.span);
+                        *def_node.node = node;
                     } else {
-                        return Err(Error::make(
-                            108,
-                            format!("No definition for DefNode {}", def_node.name).as_str(),
-                        ));
+                        if !missing.contains(&def_node.name) {
+                            missing.push(def_node.name.clone());
+                        }
+                        return Ok(());
                     }
                 }
                 if nested.contains(&def_node.name.as_str()) {
@@ -245,7 +530,7 @@ impl Node {
                     ));
                 }
                 nested.push(def_node.name.as_str());
-                def_node.node.substitute_defs(nested)?;
+                def_node.node.substitute_defs(nested, missing)?;
                 nested.pop();
             }
             _ => (),
@@ -253,11 +538,153 @@ impl Node {
         Ok(())
     }
 
+    /// Puts NAMESPACE on the front of the **get()**s of NAMES, the definitions loaded into the namespace along with
+    /// this one
+    fn qualify_defs(&mut self, namespace: &str, names: &[String]) {
+        match self {
+            Node::And(a) => a
+                .nodes
+                .iter_mut()
+                .for_each(|x| x.qualify_defs(namespace, names)),
+            Node::Or(a) => a
+                .nodes
+                .iter_mut()
+                .for_each(|x| x.qualify_defs(namespace, names)),
+            Node::Def(def_node) => {
+                if names.contains(&def_node.name) {
+                    def_node.name = format!("{}.{}", namespace, def_node.name);
+                }
+                def_node.node.qualify_defs(namespace, names);
+            }
+            _ => (),
+        }
+    }
+
+    /// Checks whether the node, including its repetition count, can match an empty string
+    fn can_match_empty(&self) -> bool {
+        match self {
+            Node::Def(def_node) => def_node.node.can_match_empty(),
+            Node::None => false,
+            _ => self.limits().min == 0 || self.rep_can_match_empty(),
+        }
+    }
+
+    /// Checks whether a single repetition of the node can match an empty string, ignoring its repetition count
+    fn rep_can_match_empty(&self) -> bool {
+        match self {
+            Node::Chars(a) => a.string.is_empty(),
+            Node::Special(a) => a.special == '$',
+            Node::Range(_) => false,
+            Node::And(a) => a.nodes.iter().all(|x| x.can_match_empty()),
+            Node::Or(a) => a.nodes.iter().any(|x| x.can_match_empty()),
+            Node::Def(a) => a.node.rep_can_match_empty(),
+            Node::None => false,
+        }
+    }
+
+    /// Looks for unlimited repetitions of a node that can match an empty string. These would loop forever
+    /// in the walk phase, so it is better to reject them at parse time. The runtime **loop_check()** stays
+    /// as a backstop for anything this misses.
+    fn check_empty_loops(&self) -> Result<(), Error> {
+        match self {
+            Node::Def(def_node) => return def_node.node.check_empty_loops(),
+            Node::None => return Ok(()),
+            _ => (),
+        }
+        if self.limits().max == EFFECTIVELY_INFINITE && self.rep_can_match_empty() {
+            return Err(Error::make(
+                10,
+                format!(
+                    "Unlimited repetition of {} can match an empty string and would loop forever",
+                    Lint::quote(self)
+                )
+                .as_str(),
+            )
+            .with_span(self.source_span()));
+        }
+        match self {
+            Node::And(a) => a.nodes.iter().try_for_each(|x| x.check_empty_loops()),
+            Node::Or(a) => a.nodes.iter().try_for_each(|x| x.check_empty_loops()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Looks for characters that are not ASCII. In bytes mode (see **Input::set_bytes_mode()**) each byte of the text
+    /// is a single char, so a character that takes more than one byte in UTF-8 could never match what it looks like.
+    fn check_bytes(&self) -> Result<(), Error> {
+        let found = match self {
+            Node::Chars(a) => a.string.chars().find(|ch| !ch.is_ascii()),
+            Node::Range(a) => a
+                .chars
+                .chars()
+                .chain(a.ranges.iter().flat_map(|range| [range.from, range.to]))
+                .find(|ch| !ch.is_ascii()),
+            Node::And(a) => return a.nodes.iter().try_for_each(|x| x.check_bytes()),
+            Node::Or(a) => return a.nodes.iter().try_for_each(|x| x.check_bytes()),
+            Node::Def(a) => return a.node.check_bytes(),
+            Node::Special(_) | Node::None => None,
+        };
+        match found {
+            Some(ch) => Err(Error::make(
+                12,
+                format!(
+                    "'{}' is not ASCII, and cannot be searched for in bytes mode",
+                    ch
+                )
+                .as_str(),
+            )
+            .with_span(self.source_span())),
+            None => Ok(()),
+        }
+    }
+
     /// checks whether the node is the special Node::None type, used to initialize structures and in case of errors.
-    fn is_none(&self) -> bool {
+    pub(crate) fn is_none(&self) -> bool {
         *self == Node::None
     }
 
+    /// checks whether the node is an unnamed ".*" or ".*?": any character any number of times, which can stretch
+    /// from wherever it starts over any text
+    pub(crate) fn is_wildcard(&self) -> bool {
+        matches!(self, Node::Special(a) if a.special == '.'
+                 && a.upto.is_none()
+                 && a.named.is_none()
+                 && a.limits.min == 0
+                 && a.limits.max == EFFECTIVELY_INFINITE)
+    }
+
+    /// checks whether the node is the root of an RE starting with '^'. A walk of it only tries the position it starts
+    /// from, so a search for more matches is over after the first walk: from anywhere else it could only find
+    /// matches that do not start where the text (or in **search_lines()**, the line) does.
+    pub fn anchored(&self) -> bool {
+        matches!(self, Node::And(a) if a.anchor)
+    }
+
+    /// checks whether every match of the node starts with an unnamed ".*" or ".*?". A walk of such a node that fails
+    /// at one start position fails at all the later ones too, since the wildcard could have stretched to them.
+    pub(crate) fn leading_wildcard(&self) -> bool {
+        match self {
+            Node::Special(_) => self.is_wildcard(),
+            Node::And(a) => a.limits.min > 0 && a.nodes.first().is_some_and(Node::leading_wildcard),
+            Node::Def(a) => a.limits.min > 0 && a.node.leading_wildcard(),
+            _ => false,
+        }
+    }
+
+    /// For the root of a tree that ends with an unnamed ".*" or ".*?", gets the tree without it, and whether it was
+    /// greedy. The rest of the tree is walked the same way either way, after which a lazy wildcard takes nothing and a
+    /// greedy one takes the rest of the text, so a search that needs only the whole match can walk the smaller tree.
+    pub(crate) fn without_trailing_wildcard(&self) -> Option<(Node, bool)> {
+        match self {
+            Node::And(a) if a.nodes.len() > 1 && a.nodes.last().is_some_and(Node::is_wildcard) => {
+                let mut trimmed = a.clone();
+                let wildcard = trimmed.nodes.pop()?;
+                Some((Node::And(trimmed), !wildcard.limits().lazy()))
+            }
+            _ => None,
+        }
+    }
+
     /// Used for tracing, expected to be called on Node creation so if the trace level is 2 or higher it is displayed.
     fn trace(self) -> Self {
         if trace_level(2) {
@@ -275,9 +702,49 @@ impl Node {
 // Node struct subtypes: these are wrapped in the Node enum to make them easy to pass around
 //
 
+/// Where a node came from in the RE, as a range of byte offsets, or nothing for a node that was not parsed from it.
+/// It is left out of comparisons and hashing, so trees that search the same way are equal however they were written.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SourceSpan(Option<(usize, usize)>);
+
+impl PartialEq for SourceSpan {
+    fn eq(&self, _other: &SourceSpan) -> bool {
+        true
+    }
+}
+
+impl Eq for SourceSpan {}
+
+impl std::hash::Hash for SourceSpan {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
+
+impl SourceSpan {
+    /// constructor, for the bytes START..END of the RE
+    pub(crate) fn new(start: usize, end: usize) -> SourceSpan {
+        SourceSpan(Some((start, end)))
+    }
+
+    /// the start and end byte offsets, if known
+    pub fn get(&self) -> Option<(usize, usize)> {
+        self.0
+    }
+
+    /// the span covering both this one and OTHER
+    fn join(self, other: SourceSpan) -> SourceSpan {
+        match (self.0, other.0) {
+            (Some((start0, end0)), Some((start1, end1))) => {
+                SourceSpan::new(start0.min(start1), end0.max(end1))
+            }
+            (None, _) => other,
+            (_, None) => self,
+        }
+    }
+}
+
 /// represents strings of regular characters that match themselves in the target string. This is a leaf node in the parse tree.
 /// It holds a character string that must be matched exactly to match.
-#[derive(Default, PartialEq, Clone)]
+#[derive(Default, PartialEq, Eq, Hash, Clone)]
 pub struct CharsNode {
     /// the repetition counts that are accepted in a match. In
     /// traditional REs this is only relevant if **string** is of length 1,
@@ -295,10 +762,12 @@ pub struct CharsNode {
     /// whether it is whether each repetition is named, or the name
     /// refers to all the repetitions
     pub(crate) name_outside: bool,
+    /// where the unit is in the RE
+    pub(crate) span: SourceSpan,
 }
 
 /// Represents special character codes, such as \d for digits, . for anything, etc.
-#[derive(Default, PartialEq, Clone)]
+#[derive(Default, PartialEq, Eq, Hash, Clone)]
 pub struct SpecialNode {
     /// the repetition counts that are accepted in a match.
     pub(crate) limits: Limits,
@@ -308,14 +777,19 @@ pub struct SpecialNode {
     /// The character that is special. In case of an escape sequence
     /// (ie \a) it holds only the
     pub(crate) special: char,
+    /// For **upto()** and **until()** units, whose special is '.': a character only matches if this text does not
+    /// start at it
+    pub(crate) upto: Option<String>,
     /// Not used in traditional parser, in alternative one tells
     /// whether it is whether each repetition is named, or the name
     /// refers to all the repetitions
     pub(crate) name_outside: bool,
+    /// where the unit is in the RE
+    pub(crate) span: SourceSpan,
 }
 
 /// Represents a character that is a member of, or is not a member of, a particular set.
-#[derive(Default, PartialEq, Clone)]
+#[derive(Default, PartialEq, Eq, Hash, Clone)]
 pub struct RangeNode {
     /// the repetition counts that are accepted in a match.
     pub(crate) limits: Limits,
@@ -334,10 +808,12 @@ pub struct RangeNode {
     /// whether it is whether each repetition is named, or the name
     /// refers to all the repetitions
     pub(crate) name_outside: bool,
+    /// where the unit is in the RE
+    pub(crate) span: SourceSpan,
 }
 
 /// handles AND (sequential) matches: this node represents a branch in the parse tree
-#[derive(Default, PartialEq)]
+#[derive(Default, PartialEq, Eq, Hash)]
 pub struct AndNode {
     /// the repetition counts that are accepted in a match.
     pub(crate) limits: Limits,
@@ -353,10 +829,12 @@ pub struct AndNode {
     /// whether it is whether each repetition is named, or the name
     /// refers to all the repetitions
     pub(crate) name_outside: bool,
+    /// where the unit is in the RE
+    pub(crate) span: SourceSpan,
 }
 
 /// handles OR nodes (A\|B style matches). This node represents a branch in the parse tree
-#[derive(Default, PartialEq)]
+#[derive(Default, PartialEq, Eq, Hash)]
 pub struct OrNode {
     /// An array of child nodes one of which must be satisfied for the walk to succeed
     pub(crate) nodes: Vec<Node>,
@@ -373,24 +851,31 @@ pub struct OrNode {
     /// whether it is whether each repetition is named, or the name
     /// refers to all the repetitions
     pub(crate) name_outside: bool,
+    /// where the unit is in the RE
+    pub(crate) span: SourceSpan,
 }
 
 // TODO: This should contain a ref to the node in the defs table, but this requires major lifeline changes which I'm
 // not ready to do now, so in the short term it should have its own copy
 // TODO: lazy evaluation, so a DefNode can be in the tree before its definition has been loaded
 /// Provided solely for the alternative parser, this is a
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, Hash)]
 pub struct DefNode {
     /// Name of the snippet
-    name: String,
+    pub(crate) name: String,
     //    /// Subtree giving the snippet
-    node: Box<Node>,
+    pub(crate) node: Box<Node>,
     pub(crate) limits: Limits,
     pub(crate) named: Option<String>,
     /// Not used in traditional parser, in alternative one tells
     /// whether it is whether each repetition is named, or the name
     /// refers to all the repetitions
     pub(crate) name_outside: bool,
+    /// set by a "\\c" (Some(true)) or "\\C" (Some(false)) after the **get()**, to ignore case or match it in this use
+    /// of the definition whatever the definition says
+    pub(crate) no_case: Option<bool>,
+    /// where the **get()** is in the RE. The substituted definition is given the same span.
+    pub(crate) span: SourceSpan,
 }
 
 impl Default for DefNode {
@@ -401,6 +886,8 @@ impl Default for DefNode {
             named: None,
             limits: Limits::default(),
             name_outside: false,
+            no_case: None,
+            span: SourceSpan::default(),
         }
     }
 }
@@ -413,6 +900,7 @@ impl Clone for AndNode {
             anchor: self.anchor,
             nodes: self.nodes.to_vec(),
             name_outside: false,
+            span: self.span,
         }
     }
 }
@@ -424,6 +912,7 @@ impl Clone for OrNode {
             named: self.named.clone(),
             nodes: self.nodes.to_vec(),
             name_outside: false,
+            span: self.span,
         }
     }
 }
@@ -435,6 +924,8 @@ impl Clone for DefNode {
             named: self.named.clone(),
             limits: self.limits,
             name_outside: false,
+            no_case: self.no_case,
+            span: self.span,
         }
     }
 }
@@ -484,8 +975,13 @@ impl CharsNode {
         trace!(2, "CHARS starting from \"{}\"", chars.preview(6));
         let mut node = CharsNode::default();
         let mut count = 0;
+        // the code of the last char if it was escaped, so it can be put back the way it was written
+        let mut last_escape: Option<char> = None;
+        // where a leading "\\c" or "\\C" is, if there is one
+        let mut case_pos = None;
         if let (Some('\\'), Some(c)) = chars.peek_2() {
             if "cC".contains(c) {
+                case_pos = Some(chars.pos());
                 chars.consume(2);
                 if c == 'c' {
                     node.limits.options |= Limits::NO_CASE;
@@ -493,35 +989,39 @@ impl CharsNode {
             }
         }
         loop {
-            match chars.peek_n(3)[..] {
-                [Some('\\'), Some(ch1), o_ch2] => {
-                    if "()|".contains(ch1) || "*?+|".contains(o_ch2.unwrap_or('x')) {
+            match chars.peek_3() {
+                (Some('\\'), Some(ch1), o_ch2) => {
+                    // a repeated char gets its own node, unless it is the first
+                    if "()|".contains(ch1) || (count > 0 && "*?+|".contains(o_ch2.unwrap_or('x'))) {
                         break;
                     }
                     if SpecialNode::ESCAPE_CODES.contains(ch1) {
                         break;
                     }
+                    Lint::check_escape(ch1, chars.pos());
                     node.string.push(CharsNode::escaped_chars(ch1));
+                    last_escape = Some(ch1);
                     count += 1;
                     chars.consume(2);
                 }
-                [Some(ch0), _, _] => {
-                    if "[$.*+?{".contains(ch0) {
+                (Some(ch0), _, _) => {
+                    if "[$.*+?".contains(ch0) || Limits::starts_count(chars) {
                         break;
                     }
                     count += 1;
                     node.string.push(chars.next().unwrap());
+                    last_escape = None;
                 }
                 _ => {
                     break;
                 }
             }
-            if after_or && count > 0 {
+            if after_or && count > 0 && !chars.group_alternation {
                 break;
             }
         }
         match count {
-            0 => return Ok(Node::None),
+            0 => return case_pos.map_or(Ok(Node::None), |pos| Err(dangling_case(pos))),
             1 => {
                 let no_case = node.limits.options;
                 node.limits = Limits::parse(chars)?;
@@ -529,8 +1029,18 @@ impl CharsNode {
             }
             _ => {
                 if let (Some(ch0), Some(ch1)) = chars.peek_2() {
-                    if "*?+{".contains(ch0) || (ch0 == '\\' && ch1 == '|') {
-                        chars.put_back(node.string.pop().unwrap());
+                    if "*?+".contains(ch0)
+                        || Limits::starts_count(chars)
+                        || (ch0 == '\\' && ch1 == '|' && !chars.group_alternation)
+                    {
+                        let last = node.string.pop().unwrap();
+                        match last_escape {
+                            Some(code) => {
+                                chars.put_back(code);
+                                chars.put_back('\\');
+                            }
+                            None => chars.put_back(last),
+                        }
                         if node.limits.no_case() {
                             chars.put_back('c');
                             chars.put_back('\\');
@@ -546,27 +1056,44 @@ impl CharsNode {
     }
     /// Checks a string to see if its head matches the contents of this node
     pub fn matches(&self, string: &str) -> Option<usize> {
-        if string.starts_with(self.string.as_str())
-            || (self.limits.no_case() && compare_caseless(&self.string, string))
-        {
+        if string.starts_with(self.string.as_str()) {
             Some(self.string.len())
+        } else if self.limits.no_case() {
+            compare_caseless(&self.string, string)
         } else {
             None
         }
     }
 
     /// Used to prety-print, including proper indentation
-    fn desc(&self, indent: usize) {
-        println!("{0:1$}{2:?}", "", indent, self);
+    fn desc_to<W: Write + ?Sized>(&self, w: &mut W, indent: usize) -> std::io::Result<()> {
+        writeln!(w, "{0:1$}{2:?}", "", indent, self)
     }
 
-    /// maps escape characters to the actual code they represent
+    /// maps escape characters to the actual code they represent, see **CHAR_ESCAPES**
     fn escaped_chars(ch: char) -> char {
-        match ch {
-            'n' => '\n',
-            't' => '\t',
-            c => c,
-        }
+        CHAR_ESCAPES
+            .iter()
+            .find(|(code, _)| *code == ch)
+            .map_or(ch, |(_, escaped)| *escaped)
+    }
+
+    /// the escape code for a char in **CHAR_ESCAPES**, used to write it back out
+    pub(crate) fn escape_code(ch: char) -> Option<char> {
+        CHAR_ESCAPES
+            .iter()
+            .find(|(_, escaped)| *escaped == ch)
+            .map(|(code, _)| *code)
+    }
+
+    /// checks whether NEXT can be appended to this node without changing what it matches
+    fn can_join(&self, next: &CharsNode) -> bool {
+        self.named.is_none()
+            && next.named.is_none()
+            && self.limits == next.limits
+            && self.limits.min == 1
+            && self.limits.max == 1
+            && !self.limits.lazy()
     }
 
     /// recovers a CharsNode from the Node::Chars enum
@@ -579,25 +1106,20 @@ impl CharsNode {
     }
 }
 
-/// Checks if a string matches ignoring case. This is a little tricky because strings cannot be
-/// split in mid-UTF8
-fn compare_caseless(goal: &String, text: &str) -> bool {
-    if goal.len() > text.len() {
-        return false;
-    }
-    let mut iter1 = text.chars();
+/// Checks if the start of TEXT matches GOAL ignoring case, returning the number of bytes of TEXT matched. This is a
+/// little tricky because strings cannot be split in mid-UTF8, and the text matched can be a different length from
+/// GOAL: the Kelvin sign, 3 bytes, matches "k"
+fn compare_caseless(goal: &str, text: &str) -> Option<usize> {
+    let mut iter1 = text.char_indices();
     for ch in goal.chars() {
-        if let Some(ch1) = iter1.next() {
-            if let Some(lc1) = ch1.to_lowercase().next() {
-                if lc1 != ch {
-                    return false;
-                }
+        let (_, ch1) = iter1.next()?;
+        if let Some(lc1) = ch1.to_lowercase().next() {
+            if lc1 != ch {
+                return None;
             }
-        } else {
-            return false;
         }
     }
-    true
+    Some(iter1.next().map_or(text.len(), |(pos, _)| pos))
 }
 impl Debug for SpecialNode {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
@@ -611,22 +1133,24 @@ impl Debug for SpecialNode {
         } else {
             (&name, &limits_str)
         };
-        let slash = if ".$".contains(self.special) {
-            ""
+        let special = if let Some(upto) = self.upto_source() {
+            upto
+        } else if ".$".contains(self.special) {
+            self.special.to_string()
         } else {
-            "\\"
+            format!("\\{}", self.special)
         };
         write!(
             f,
-            "SpecialNode{}: \"{}{}\"{}{}",
-            name, slash, self.special, name_limits.0, name_limits.1
+            "SpecialNode{}: \"{}\"{}{}",
+            name, special, name_limits.0, name_limits.1
         )
     }
 }
 
 impl SpecialNode {
     /// These are the defined escaped characters that are recognized as special codes
-    const ESCAPE_CODES: &str = "adluowx";
+    pub(crate) const ESCAPE_CODES: &str = "adluowx";
 
     /// Traditional parser for Special Character units, escape
     /// sequences with special meaning ('\x' for hex) or characters
@@ -646,10 +1170,31 @@ impl SpecialNode {
         Ok(Node::Special(node))
     }
 
+    /// Gets an **upto()** node as it is written in the RE, or None for other specials
+    pub(crate) fn upto_source(&self) -> Option<String> {
+        self.upto.as_ref().map(|upto| {
+            let escaped: String = upto
+                .chars()
+                .map(|ch| match (ch, CharsNode::escape_code(ch)) {
+                    (_, Some(code)) => format!("\\{}", code),
+                    ('\'' | '\\', _) => format!("\\{}", ch),
+                    (ch, _) => ch.to_string(),
+                })
+                .collect();
+            format!("upto('{}')", escaped)
+        })
+    }
+
     /// Checks whether the given character at the front of the string
     /// matches this node
     pub fn matches(&self, string: &str) -> Option<usize> {
-        if SpecialNode::char_match(self.special, string) {
+        if self
+            .upto
+            .as_ref()
+            .is_some_and(|upto| string.starts_with(upto.as_str()))
+        {
+            None
+        } else if SpecialNode::char_match(self.special, string) {
             Some(if self.special == '$' {
                 0
             } else {
@@ -666,18 +1211,12 @@ impl SpecialNode {
             match sp_ch {
                 '.' => true,
                 'a' => (' '..='~').contains(&ch), // ascii printable
-                'd' => ('0'..='9').contains(&ch), // numeric
-                'l' => ('a'..='z').contains(&ch), // lc ascii
-                'n' => ch == '\n',
-                't' => ch == '\t',
+                'd' => ch.is_ascii_digit(),       // numeric
+                'l' => ch.is_ascii_lowercase(),   // lc ascii
                 'o' => ('0'..='7').contains(&ch), // octal digit
-                'u' => ('A'..='Z').contains(&ch), // uc ascii
+                'u' => ch.is_ascii_uppercase(),   // uc ascii
                 'w' => " \t\n".contains(ch),      // whitespace
-                'x' => {
-                    ('A'..='F').contains(&ch)    // hex digit
-                    || ('a'..='f').contains(&ch)
-                    || ('0'..='9').contains(&ch)
-                }
+                'x' => ch.is_ascii_hexdigit(),    // hex digit
                 _ => false,
             }
         } else {
@@ -686,15 +1225,15 @@ impl SpecialNode {
     }
 
     /// Used to prety-print, including proper indentation
-    fn desc(&self, indent: usize) {
-        println!("{0:1$}{2:?}", "", indent, self);
+    fn desc_to<W: Write + ?Sized>(&self, w: &mut W, indent: usize) -> std::io::Result<()> {
+        writeln!(w, "{0:1$}{2:?}", "", indent, self)
     }
 }
 
 /// **Range** is used to represent a single range in a RangeNode
 /// set. The sets are of the form [abj-mxyz], where "j-m" represents
 /// any character between 'j' and 'm' inclusive.
-#[derive(Default, PartialEq, Clone)]
+#[derive(Default, PartialEq, Eq, Hash, Clone)]
 pub struct Range {
     pub from: char,
     pub to: char,
@@ -739,46 +1278,83 @@ impl RangeNode {
     /// individual characters and character ranges.
     /// This is also used in the alternative parser since the
     /// definitions and handling are identical.
+    ///
+    /// As in POSIX a ']' immediately after the opening '[' (or "[^") is taken as a literal member of the set
+    /// rather than closing it, so "[]]" and "[^]]" work. A '-' at the start or end of the set is also literal.
     fn parse_node(chars: &mut Peekable) -> Result<Node, Error> {
         let mut node = RangeNode::default();
+        let pos = chars.pos();
         trace!(2, "RANGE starting from \"{}\"", chars.preview(6));
         if let Some('^') = chars.peek() {
             chars.consume(1);
             node.not = true;
         }
+        if let (Some(']'), Some(_)) = chars.peek_2() {
+            node.chars.push(']');
+            chars.consume(1);
+        }
         loop {
-            match chars.peek_n(3)[..] {
-                [Some(']'), _, _] => {
+            match chars.peek_3() {
+                (Some(']'), _, _) => {
                     chars.consume(1);
                     break;
                 }
-                [Some('\\'), Some(ch1), _] => {
-                    if SpecialNode::ESCAPE_CODES.contains(ch1) || "nt".contains(ch1) {
+                (Some('\\'), Some(ch1), _) => {
+                    if SpecialNode::ESCAPE_CODES.contains(ch1) {
                         node.specials.push(ch1);
                     } else {
-                        node.chars.push(ch1);
+                        Lint::check_escape(ch1, chars.pos());
+                        node.chars.push(CharsNode::escaped_chars(ch1));
                     }
                     chars.consume(2);
                 }
-                [Some(ch0), Some('-'), Some(']')] => {
+                (Some(ch0), Some('-'), Some(']')) => {
                     node.chars.push(ch0);
                     node.chars.push('-');
                     chars.consume(2);
                 }
-                [Some(ch0), Some('-'), Some(ch2)] => {
+                (Some(ch0), Some('-'), Some(ch2)) => {
                     node.ranges.push(Range { from: ch0, to: ch2 });
                     chars.consume(3);
                 }
-                [Some(_), _, _] => node.chars.push(chars.next().unwrap()),
+                (Some(_), _, _) => node.chars.push(chars.next().unwrap()),
                 _ => {
-                    return Err(Error::make(9, "Unterminated range"));
+                    return Err(unterminated(9, "range", pos - 1));
                 }
             }
         }
+        node.lint_duplicates(pos);
         node.limits = Limits::parse(chars)?;
         Ok(Node::Range(node))
     }
 
+    /// Warns if any character in the set is given more than once, either explicitly or by being in a range
+    fn lint_duplicates(&self, pos: usize) {
+        let mut dups = String::new();
+        for (i, ch) in self.chars.chars().enumerate() {
+            if (self.chars.chars().skip(i + 1).any(|x| x == ch)
+                || self.ranges.iter().any(|x| x.contains(ch)))
+                && !dups.contains(ch)
+            {
+                dups.push(ch);
+            }
+        }
+        for (i, range) in self.ranges.iter().enumerate() {
+            for other in self.ranges.iter().skip(i + 1) {
+                if range.from <= other.to && other.from <= range.to {
+                    dups.push_str(format!(" {}/{}", range, other).as_str());
+                }
+            }
+        }
+        if !dups.is_empty() {
+            Lint::warn(
+                4,
+                format!("Characters repeated in range [{}]: {}", self, dups.trim()).as_str(),
+                Some(pos),
+            );
+        }
+    }
+
     /// Checks whehter the given character at the front of the string
     /// matches this node
     pub fn matches(&self, string: &str) -> Option<usize> {
@@ -801,8 +1377,8 @@ impl RangeNode {
     }
 
     /// Used to prety-print, including proper indentation
-    fn desc(&self, indent: usize) {
-        println!("{0:1$}{2:?}", "", indent, self);
+    fn desc_to<W: Write + ?Sized>(&self, w: &mut W, indent: usize) -> std::io::Result<()> {
+        writeln!(w, "{0:1$}{2:?}", "", indent, self)
     }
 }
 
@@ -812,7 +1388,15 @@ impl std::fmt::Display for RangeNode {
         if self.not {
             string.push('^')
         };
-        string.push_str(self.chars.as_str());
+        for ch in self.chars.chars() {
+            match CharsNode::escape_code(ch) {
+                Some(code) => {
+                    string.push('\\');
+                    string.push(code);
+                }
+                None => string.push(ch),
+            }
+        }
         for ch in self.specials.iter() {
             string.push('\\');
             string.push(*ch);
@@ -862,13 +1446,20 @@ impl AndNode {
     fn parse_node(chars: &mut Peekable) -> Result<Node, Error> {
         trace!(2, "AND starting from \"{}\"", chars.preview(6));
         trace_change_indent!(2, 1);
-        let named = AndNode::parse_named(chars)?;
+        // a group starts after its "\\(", while the wrapping node starts at the start of the RE
+        let group = !chars.at_start();
+        let start = chars.pos().saturating_sub(2);
+        let (named, name_outside) = AndNode::parse_named(chars)?;
         let mut nodes = Vec::<Node>::new();
         loop {
             match chars.peek_2() {
                 (None, _) => {
                     return Err(Error::make(1, "Unterminated AND node"));
                 }
+                // the "\\)" added to close the RE
+                (Some('\\'), Some(')')) if group && chars.at_end() => {
+                    return Err(unterminated(1, "group", start));
+                }
                 (Some('\\'), Some(')')) => {
                     break;
                 }
@@ -882,21 +1473,32 @@ impl AndNode {
         Ok(if nodes.is_empty() {
             Node::None
         } else {
+            // the wrapping node can only be closed early by a stray "\\)", which is not a repetition of the RE
+            let limits = if group {
+                Limits::parse(chars)?
+            } else {
+                Limits::default()
+            };
             Node::And(AndNode {
                 nodes,
-                limits: Limits::parse(chars)?,
+                limits,
                 named,
                 anchor: false,
-                name_outside: false,
+                // it makes no difference without a repetition
+                name_outside: name_outside && limits != Limits::default(),
+                span: SourceSpan::default(),
             })
         })
     }
 
-    /// Parses out the name from a named And
-    fn parse_named(chars: &mut Peekable) -> Result<Option<String>, Error> {
+    /// Parses out the name from a named And. A name ending in '+' (**?<NAME+>**) is put outside the repetition, so
+    /// all the repetitions are reported together in one block instead of one block each; the bool returned is true
+    /// for that.
+    fn parse_named(chars: &mut Peekable) -> Result<(Option<String>, bool), Error> {
         match chars.peek_2() {
             // named match
             (Some('?'), Some('<')) => {
+                let start = chars.pos() + 1;
                 let (_, _) = (chars.next(), chars.next());
                 let mut chs = Vec::<char>::new();
                 loop {
@@ -905,10 +1507,7 @@ impl AndNode {
                             break;
                         }
                         (Some('\\'), Some(')')) => {
-                            return Err(Error::make(
-                                2,
-                                "Unterminated collect name in AND definition",
-                            ));
+                            return Err(unterminated(2, "group name", start));
                         }
                         (Some(ch), _) => chs.push(ch),
                         _ => {
@@ -916,16 +1515,72 @@ impl AndNode {
                         }
                     }
                 }
-                Ok(Some(chs.into_iter().collect()))
+                let name_outside = chs.last() == Some(&'+');
+                if name_outside {
+                    chs.pop();
+                }
+                Ok((Some(chs.into_iter().collect()), name_outside))
             }
             // silent match: make no record of it
             (Some('?'), _) => {
                 chars.consume(1);
-                Ok(None)
+                Ok((None, false))
             }
             // nameless match
-            _ => Ok(Some("".to_string())),
+            _ => Ok((Some("".to_string()), false)),
+        }
+    }
+
+    /// Moves the children of unnamed, unrepeated **AndNode** children up into this node (part of **optimize()**)
+    fn flatten(&mut self) {
+        if !self.nodes.iter().any(AndNode::can_flatten) {
+            return;
+        }
+        let mut nodes = Vec::<Node>::new();
+        for node in self.nodes.drain(..) {
+            match node {
+                Node::And(mut child) if AndNode::can_flatten_node(&child) => {
+                    trace!(2, "Optimize: flattening {:?}", child);
+                    nodes.append(&mut child.nodes);
+                }
+                node => nodes.push(node),
+            }
         }
+        self.nodes = nodes;
+    }
+
+    /// checks whether NODE is an **AndNode** that can be merged into its parent
+    fn can_flatten(node: &Node) -> bool {
+        if let Node::And(and_node) = node {
+            AndNode::can_flatten_node(and_node)
+        } else {
+            false
+        }
+    }
+
+    /// checks whether an **AndNode** can be merged into its parent
+    fn can_flatten_node(and_node: &AndNode) -> bool {
+        and_node.named.is_none()
+            && !and_node.anchor
+            && and_node.limits == Limits::default()
+            && (!and_node.name_outside || and_node.nodes.iter().all(|x| x.named().is_none()))
+    }
+
+    /// Joins adjacent unnamed **CharsNode**s that match exactly once (part of **optimize()**)
+    fn join_chars(&mut self) {
+        let mut nodes = Vec::<Node>::new();
+        for node in self.nodes.drain(..) {
+            if let (Some(Node::Chars(prev)), Node::Chars(next)) = (nodes.last_mut(), &node) {
+                if prev.can_join(next) {
+                    trace!(2, "Optimize: joining {:?} and {:?}", prev, next);
+                    prev.string.push_str(&next.string);
+                    prev.span = prev.span.join(next.span);
+                    continue;
+                }
+            }
+            nodes.push(node);
+        }
+        self.nodes = nodes;
     }
 
     /// recovers an AndNode from the Node::And enum
@@ -938,11 +1593,15 @@ impl AndNode {
     }
 
     /// Used to prety-print, including proper indentation
-    fn desc(&self, indent: usize) {
-        println!("{0:1$}{2:?}", "", indent, self);
-        for i in 0..self.nodes.len() {
-            self.nodes[i].desc(indent + TAB_SIZE);
-        }
+    fn desc_to<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        indent: usize,
+        view: &TreeView,
+        level: usize,
+    ) -> std::io::Result<()> {
+        writeln!(w, "{0:1$}{2:?}", "", indent, self)?;
+        view.children(w, &self.nodes, indent + TAB_SIZE, level)
     }
 }
 
@@ -976,6 +1635,16 @@ impl OrNode {
     fn parse_node(chars: &mut Peekable, preceding_node: Node) -> Result<Node, Error> {
         trace!(2, "OR starting from \"{}\"", chars.preview(6));
         trace_change_indent!(2, 1);
+        if let (None, _) | (Some('\\'), Some(')')) = chars.peek_2() {
+            return Err(Error::make(
+                17,
+                format!(
+                    "\"\\|\" at position {} has no unit after it",
+                    chars.pos() - 2
+                )
+                .as_str(),
+            ));
+        }
         let mut nodes = vec![preceding_node];
         match parse(chars, true)? {
             Node::Or(mut or_node) => nodes.append(&mut or_node.nodes),
@@ -986,9 +1655,99 @@ impl OrNode {
             limits: Limits::default(),
             named: None,
             name_outside: false,
+            span: SourceSpan::default(),
+        }))
+    }
+
+    /// Part of **optimize()**: if all the branches start with the same string it is moved in front of the OR, so
+    /// "abc\|abd" is walked like "ab\(c\|d\)" and the prefix does not get matched again for every branch. Each
+    /// branch keeps at least one char of its string. Returns the new node, or None if it cannot be done.
+    fn factor_prefix(&mut self) -> Option<Node> {
+        let mut leads = self.nodes.iter().map(OrNode::leading_chars);
+        let first = leads.next()??;
+        let mut prefix_len = first.string.chars().count();
+        for lead in leads {
+            let lead = lead?;
+            if lead.limits != first.limits {
+                return None;
+            }
+            prefix_len = prefix_len.min(
+                first
+                    .string
+                    .chars()
+                    .zip(lead.string.chars())
+                    .take_while(|(a, b)| a == b)
+                    .count(),
+            );
+        }
+        if self
+            .nodes
+            .iter()
+            .filter_map(OrNode::leading_chars)
+            .any(|c| c.string.chars().count() <= prefix_len)
+        {
+            prefix_len = prefix_len.saturating_sub(1);
+        }
+        if prefix_len == 0 {
+            return None;
+        }
+        let prefix_bytes = char_bytes(first.string.as_str(), prefix_len);
+        let prefix = Node::Chars(CharsNode {
+            string: first.string[..prefix_bytes].to_string(),
+            limits: first.limits,
+            named: None,
+            name_outside: false,
+            span: first.span,
+        });
+        trace!(2, "Optimize: factoring {:?} out of {:?}", prefix, self);
+        let mut nodes = std::mem::take(&mut self.nodes);
+        for node in nodes.iter_mut() {
+            let chars_node = match node {
+                Node::And(a) => CharsNode::mut_from_node(&mut a.nodes[0]),
+                _ => CharsNode::mut_from_node(node),
+            };
+            chars_node.string = chars_node.string[prefix_bytes..].to_string();
+        }
+        Some(Node::And(AndNode {
+            nodes: vec![
+                prefix,
+                Node::Or(OrNode {
+                    nodes,
+                    limits: Limits::default(),
+                    named: None,
+                    name_outside: false,
+                    span: self.span,
+                }),
+            ],
+            limits: self.limits,
+            named: self.named.take(),
+            anchor: false,
+            name_outside: self.name_outside,
+            span: self.span,
         }))
     }
 
+    /// Gets the string an OR branch starts with, if it is a plain unnamed one that can be factored out
+    fn leading_chars(node: &Node) -> Option<&CharsNode> {
+        let node = match node {
+            Node::And(a) if a.named.is_none() && !a.anchor && a.limits == Limits::default() => {
+                a.nodes.first()?
+            }
+            node => node,
+        };
+        match node {
+            Node::Chars(c)
+                if c.named.is_none()
+                    && c.limits.min == 1
+                    && c.limits.max == 1
+                    && !c.limits.lazy() =>
+            {
+                Some(c)
+            }
+            _ => None,
+        }
+    }
+
     /// recovers an OrNode from the Node::Ar enum
     fn mut_from_node(node: &mut Node) -> &mut OrNode {
         if let Node::Or(or_node) = node {
@@ -999,11 +1758,15 @@ impl OrNode {
     }
 
     /// Used to prety-print, including proper indentation
-    fn desc(&self, indent: usize) {
-        println!("{0:1$}{2:?}", "", indent, self);
-        for i in 0..self.nodes.len() {
-            self.nodes[i].desc(indent + TAB_SIZE);
-        }
+    fn desc_to<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        indent: usize,
+        view: &TreeView,
+        level: usize,
+    ) -> std::io::Result<()> {
+        writeln!(w, "{0:1$}{2:?}", "", indent, self)?;
+        view.children(w, &self.nodes, indent + TAB_SIZE, level)
     }
 }
 
@@ -1024,39 +1787,519 @@ impl OrNode {
 /// The second argument, **alt_parser**, tells the engine whether to
 /// use the traditional parser or the alternative one.
 pub fn parse_tree(input: &str, alt_parser: bool) -> Result<Node, Error> {
-    trace_set_indent(0);
-    // wrap the string in "\(...\)" to make it an implicit AND node
-    let anchor_front = input.starts_with('^');
-    let mut chars = Peekable::new(&input[(if anchor_front { 1 } else { 0 })..]);
-    let mut outer_and = if alt_parser {
-        chars.push_str(" )");
-        AndNode::alt_parse_node(&mut chars)?
-    } else {
-        chars.push_str(r"\)");
-        AndNode::parse_node(&mut chars)?
-    };
-    if anchor_front {
-        let and_node = AndNode::mut_from_node(&mut outer_and);
-        and_node.anchor = true;
-    }
-    if !outer_and.is_none() {
-        outer_and.set_named(Some("".to_string()), false);
-        if chars.next().is_some() {
-            return Err(Error::make(
-                6,
-                "Extra characters after parse completed (should not happen)",
-            ));
-        }
+    Ok(parse_tree_with_warnings(input, alt_parser)?.0)
+}
+
+/// Like **parse_tree()**, but also returns a list of **Warning**s for constructs that are legal but are probably
+/// mistakes. Positions in the warnings are char offsets into **input**.
+pub fn parse_tree_with_warnings(
+    input: &str,
+    alt_parser: bool,
+) -> Result<(Node, Vec<Warning>), Error> {
+    parse_tree_opts(input, &SearchOptions::new().alt_parser(alt_parser))
+}
+
+/// Makes the warnings for unknown escapes (see **Lint::check_escape()**) into an error, for callers that want them to
+/// be fatal. Returns the first one as error 11.
+pub fn check_escapes(warnings: &[Warning]) -> Result<(), Error> {
+    match warnings.iter().find(|warning| warning.code == 6) {
+        Some(warning) => Err(Error::make(
+            11,
+            format!("{} (at {})", warning.msg, warning.pos.unwrap_or(0)).as_str(),
+        )),
+        None => Ok(()),
     }
-    let mut nested: Vec<&str> = Vec::new();
-    outer_and.substitute_defs(&mut nested)?;
-    Ok(outer_and)
 }
 
-/// main controller for the tree parse processing, it looks at the next few characters in the pipeline, decides what they are, and
+/// Finds what has to be added to the end of PARTIAL, the start of an RE still being typed, to close everything left
+/// open in it, so it can be parsed to preview the finished RE. In the alternative syntax that is quoted strings,
+/// the parens of and(), or(), def() and the other functions, and names; in the traditional syntax it is groups. In
+/// both it is ranges and repetition counts. The closers are returned innermost first: for 'and("a" or("b' it is
+/// "\"))". Nothing is checked, so the result can still fail to parse.
+pub fn auto_close(partial: &str, alt_parser: bool) -> String {
+    let mut chars = Peekable::new(partial);
+    // the closers for the open constructs, innermost last. "txt" is the ')' of txt(), which holds quoted text.
+    let mut open = Vec::<&str>::new();
+    // the word before a '(', to tell txt() from the functions holding units
+    let mut word = String::new();
+    while let Some(ch) = chars.next() {
+        match (open.last().copied(), ch) {
+            (Some("]"), ']') | (Some("}"), '}') | (Some(">"), '>') => {
+                open.pop();
+            }
+            // nothing else means anything inside a count or name
+            (Some("}" | ">"), _) => (),
+            (inner, '\\') => match chars.next() {
+                Some('(') if !alt_parser && inner != Some("]") => {
+                    open.push("\\)");
+                    if chars.peek_2() == (Some('?'), Some('<')) {
+                        chars.consume(2);
+                        open.push(">");
+                    }
+                }
+                Some(')') if inner == Some("\\)") => {
+                    open.pop();
+                }
+                _ => (),
+            },
+            (Some("]"), _) => (),
+            (_, '[') => open.push("]"),
+            // a '{' not followed by a digit is not a count
+            (_, '{') if chars.peek().is_none_or(|next| next.is_ascii_digit()) => open.push("}"),
+            (Some(quote @ ("\"" | "'")), ch) if quote.starts_with(ch) => {
+                open.pop();
+            }
+            (Some("txt"), ')') => {
+                open.pop();
+            }
+            (Some("\"" | "'" | "txt"), _) => (),
+            _ if !alt_parser => (),
+            (_, '"') => open.push("\""),
+            (_, '\'') => open.push("'"),
+            (_, '<') => open.push(">"),
+            (_, '(') => open.push(if word == "txt" { "txt" } else { ")" }),
+            (Some(")"), ')') => {
+                open.pop();
+            }
+            _ => (),
+        }
+        if ch.is_alphanumeric() {
+            word.push(ch);
+        } else {
+            word.clear();
+        }
+    }
+    open.iter()
+        .rev()
+        .map(|close| if *close == "txt" { ")" } else { close })
+        .collect()
+}
+
+/// Checks the end of an RE for an escape with nothing after it: a lone '\\' (error 15), or a "\\c" or "\\C" (error
+/// 16). Backslashes pair up as escapes in both syntaxes, so a '\\' at the end is only lone if an odd number of them end
+/// the RE. Left to the parsers, these would take in the chars added to close the RE and fail somewhere else.
+fn check_dangling_end(input: &str) -> Result<(), Error> {
+    let len = input.chars().count();
+    let backslashes = |string: &str| string.chars().rev().take_while(|ch| *ch == '\\').count();
+    if backslashes(input) % 2 == 1 {
+        return Err(Error::make(
+            15,
+            format!(
+                "The RE ends with a '\\' at position {} that has nothing to escape",
+                len - 1
+            )
+            .as_str(),
+        ));
+    }
+    match input
+        .strip_suffix("\\c")
+        .or_else(|| input.strip_suffix("\\C"))
+    {
+        Some(rest) if backslashes(rest) % 2 == 0 => Err(dangling_case(len - 2)),
+        _ => Ok(()),
+    }
+}
+
+/// Handles the text of INPUT from char POS on, left over when a CLOSER with nothing open ended the parse early. This
+/// is usually a ')' meant to be matched. It is an error (6) unless leniency is on (see **set_lenient()**), when the
+/// text is added to the end of OUTER_AND, the tree parsed so far, as literal text: the closer as a ')' and the rest
+/// just as it is written.
+fn extra_chars(input: &str, pos: usize, closer: &str, outer_and: Node) -> Result<Node, Error> {
+    const PREVIEW_LEN: usize = 20;
+    let extra: String = input.chars().skip(pos).collect();
+    let preview = if extra.chars().count() > PREVIEW_LEN {
+        format!("{}...", extra.chars().take(PREVIEW_LEN).collect::<String>())
+    } else {
+        extra.clone()
+    };
+    if !LENIENT.load(Acquire) {
+        return Err(Error::make(
+            6,
+            format!(
+                "Extra characters after parse completed, from position {}: \"{}\" (the \"{}\" there has nothing open to close)",
+                pos, preview, closer
+            )
+            .as_str(),
+        ));
+    }
+    Lint::warn(
+        8,
+        format!(
+            "Extra characters \"{}\" after the RE are taken as literal text",
+            preview
+        )
+        .as_str(),
+        Some(pos),
+    );
+    let literal = Node::Chars(CharsNode {
+        string: format!("){}", &extra[closer.len()..]),
+        ..CharsNode::default()
+    });
+    Ok(match outer_and {
+        Node::And(mut and_node) => {
+            and_node.nodes.push(literal);
+            Node::And(and_node)
+        }
+        _ => Node::And(AndNode {
+            nodes: vec![literal],
+            ..AndNode::default()
+        }),
+    })
+}
+
+/// The number of REs parsed since the program started
+static PARSE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Gets the number of REs parsed so far, so callers can check that a tree is being reused rather than parsed again
+pub fn parse_count() -> usize {
+    PARSE_COUNT.load(Acquire)
+}
+
+/// If set, a '{' that is not followed by a digit is an ordinary character instead of a bad repetition count
+static LITERAL_BRACES: AtomicBool = AtomicBool::new(false);
+
+/// Turns literal braces on or off for the REs parsed after it, returning the previous setting. It is off by
+/// default, so a mistyped count like "a{x}" is an error (7) instead of silently searching for the text. When it is
+/// on, as in many other engines, a '{' starts a repetition count only if a digit follows it, and otherwise matches
+/// itself: "a{x}" matches "a{x}", while "a{2x}" is still an error.
+pub fn set_literal_braces(literal: bool) -> bool {
+    LITERAL_BRACES.swap(literal, AcqRel)
+}
+
+/// If set, text left over after the RE is complete is literal text instead of an error
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+/// Turns leniency on or off for the REs parsed after it, returning the previous setting. It is off by default, so
+/// text left over when a ')' with nothing open ends the RE early, as in "ab\\)c", is an error (6). When it is on
+/// the leftover text is added to the end of the RE as literal text with a warning, which is handy while trying REs
+/// out interactively.
+pub fn set_lenient(lenient: bool) -> bool {
+    LENIENT.swap(lenient, AcqRel)
+}
+
+/// What the alternative parser skips between units. By default that is space, tab and newline; **unicode()** adds
+/// carriage return and the other Unicode spaces, like the non-breaking space (U+00A0) that comes along when an RE is
+/// pasted from a web page or a word processor. **strict_lines()** is for checking def files written one definition
+/// to a line: a definition that runs onto another line is an error (123) unless the line ends with '\\'.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Whitespace {
+    /// the chars skipped
+    chars: String,
+    /// if set, every char Rust counts as whitespace is skipped as well as CHARS
+    unicode: bool,
+    /// if set, a newline inside a definition in a def file has to follow a '\\'
+    strict_lines: bool,
+}
+
+impl Default for Whitespace {
+    fn default() -> Whitespace {
+        Whitespace {
+            chars: " \n\t".to_string(),
+            unicode: false,
+            strict_lines: false,
+        }
+    }
+}
+
+impl Whitespace {
+    /// constructor, for the default set
+    pub fn new() -> Whitespace {
+        Whitespace::default()
+    }
+
+    /// skips CHARS instead of the default set
+    pub fn chars(mut self, chars: &str) -> Whitespace {
+        self.chars = chars.to_string();
+        self
+    }
+
+    /// skips every Unicode space and line break too, such as carriage return and non-breaking space
+    pub fn unicode(mut self) -> Whitespace {
+        self.unicode = true;
+        self
+    }
+
+    /// makes a definition in a def file that runs onto another line without a '\\' at the end of the line an error
+    pub fn strict_lines(mut self) -> Whitespace {
+        self.strict_lines = true;
+        self
+    }
+
+    /// true if CH is skipped
+    pub fn contains(&self, ch: char) -> bool {
+        self.chars.contains(ch) || (self.unicode && ch.is_whitespace())
+    }
+}
+
+/// The whitespace the REs parsed after **set_whitespace()** skip
+static WHITESPACE: Lazy<Mutex<Whitespace>> = Lazy::new(|| Mutex::new(Whitespace::default()));
+
+/// Sets what the alternative parser skips between units for the REs and def files parsed after it, returning the
+/// previous setting. See **Whitespace**.
+pub fn set_whitespace(whitespace: Whitespace) -> Whitespace {
+    std::mem::replace(&mut *WHITESPACE.lock().unwrap(), whitespace)
+}
+
+/// A traditional RE starting with this makes "\\|" take the whole string of chars on each side, so "(?g)abc\\|def"
+/// matches "abc" or "def" instead of "ab", "c" or "d", then "ef"
+pub const GROUP_ALTERNATION: &str = "(?g)";
+
+/// The full version of the tree parser: besides the warnings, it also lets the caller decide whether to run
+/// **optimize()** on the finished tree. Turning it off is useful to see the tree exactly as the RE was written.
+pub fn parse_tree_full(
+    input: &str,
+    alt_parser: bool,
+    optimize_tree: bool,
+) -> Result<(Node, Vec<Warning>), Error> {
+    PARSE_COUNT.fetch_add(1, AcqRel);
+    trace_set_indent(0);
+    Lint::start(input);
+    check_dangling_end(input)?;
+    let group_alternation = !alt_parser && input.starts_with(GROUP_ALTERNATION);
+    let mut offset = if group_alternation {
+        GROUP_ALTERNATION.len()
+    } else {
+        0
+    };
+    // wrap the string in "\(...\)" to make it an implicit AND node
+    let anchor_front = input[offset..].starts_with('^');
+    if anchor_front {
+        offset += 1;
+    }
+    let mut chars = Peekable::new_at(&input[offset..], offset);
+    chars.group_alternation = group_alternation;
+    let (mut outer_and, closer) = if alt_parser {
+        chars.push_str(" )");
+        (AndNode::alt_parse_node(&mut chars)?, ")")
+    } else {
+        chars.push_str(r"\)");
+        (AndNode::parse_node(&mut chars)?, r"\)")
+    };
+    // the RE is closed by the closer added to it, unless it has one of its own with nothing open
+    if !chars.from_trailer() {
+        outer_and = extra_chars(
+            input,
+            chars.pos() - closer.chars().count(),
+            closer,
+            outer_and,
+        )?;
+    }
+    // "^" on its own leaves nothing to anchor
+    if anchor_front && !outer_and.is_none() {
+        let and_node = AndNode::mut_from_node(&mut outer_and);
+        and_node.anchor = true;
+    }
+    if !outer_and.is_none() {
+        outer_and.set_named(Some("".to_string()), false);
+        outer_and.set_span(SourceSpan::new(0, input.len()));
+    }
+    let mut nested: Vec<&str> = Vec::new();
+    let mut missing: Vec<String> = Vec::new();
+    outer_and.substitute_defs(&mut nested, &mut missing)?;
+    if !missing.is_empty() {
+        let missing: Vec<String> = missing
+            .iter()
+            .map(|name| Defs::with_near_misses(name))
+            .collect();
+        return Err(Error::make(
+            108,
+            format!("No definition for DefNode {}", missing.join(", ")).as_str(),
+        ));
+    }
+    outer_and.fill_spans(SourceSpan::default());
+    outer_and.check_empty_loops()?;
+    if Input::bytes_mode() {
+        outer_and.check_bytes()?;
+    }
+    outer_and.lint();
+    if optimize_tree {
+        optimize(&mut outer_and);
+    }
+    Ok((outer_and, Lint::finish()))
+}
+
+impl Node {
+    /// Normalizes settings that do not change how the node is walked, so trees that search the same way compare
+    /// (and hash) equal:
+    /// - lazy evaluation is dropped when the repetition count is fixed
+    /// - **name_outside** is cleared for unnamed nodes, and for named leaf nodes that always match exactly once
+    pub fn canonicalize(&mut self) {
+        if self.is_none() {
+            return;
+        }
+        let limits = *self.limits();
+        let leaf = matches!(self, Node::Chars(_) | Node::Special(_) | Node::Range(_));
+        let clear_outside = self.named().is_none() || (leaf && limits.min == 1 && limits.max == 1);
+        let (limits, name_outside) = match self {
+            Node::Chars(a) => (&mut a.limits, &mut a.name_outside),
+            Node::Special(a) => (&mut a.limits, &mut a.name_outside),
+            Node::Range(a) => (&mut a.limits, &mut a.name_outside),
+            Node::And(a) => {
+                a.nodes.iter_mut().for_each(|x| x.canonicalize());
+                (&mut a.limits, &mut a.name_outside)
+            }
+            Node::Or(a) => {
+                a.nodes.iter_mut().for_each(|x| x.canonicalize());
+                (&mut a.limits, &mut a.name_outside)
+            }
+            Node::Def(a) => {
+                a.node.canonicalize();
+                (&mut a.limits, &mut a.name_outside)
+            }
+            Node::None => return,
+        };
+        if limits.min == limits.max {
+            limits.options &= !Limits::LAZY;
+        }
+        if clear_outside {
+            *name_outside = false;
+        }
+    }
+
+    /// Makes a copy of the tree where no repetition is unlimited: each one that is can repeat at most CAP times, or
+    /// its minimum if that is more. This is what **walk_tree_deepening()** searches with.
+    pub fn capped(&self, cap: usize) -> Node {
+        let mut node = self.clone();
+        node.cap_reps(cap);
+        node
+    }
+
+    /// caps the unlimited repetitions of the node and those under it in place, for **capped()**
+    fn cap_reps(&mut self, cap: usize) {
+        if self.is_none() {
+            return;
+        }
+        match self {
+            Node::And(a) => a.nodes.iter_mut().for_each(|x| x.cap_reps(cap)),
+            Node::Or(a) => a.nodes.iter_mut().for_each(|x| x.cap_reps(cap)),
+            Node::Def(a) => a.node.cap_reps(cap),
+            _ => (),
+        }
+        let mut limits = *self.limits();
+        if limits.max == EFFECTIVELY_INFINITE {
+            limits.max = cap.max(limits.min);
+            self.set_limits(limits);
+        }
+    }
+
+    /// Makes a copy of the tree where every repetition is lazy, taking as few as it can. This is what
+    /// **shortest_match()** searches with.
+    pub fn all_lazy(&self) -> Node {
+        let mut node = self.clone();
+        node.make_lazy();
+        node
+    }
+
+    /// makes the repetitions of the node and those under it lazy in place, for **all_lazy()**
+    fn make_lazy(&mut self) {
+        if self.is_none() {
+            return;
+        }
+        match self {
+            Node::And(a) => a.nodes.iter_mut().for_each(Node::make_lazy),
+            Node::Or(a) => a.nodes.iter_mut().for_each(Node::make_lazy),
+            Node::Def(a) => a.node.make_lazy(),
+            _ => (),
+        }
+        let mut limits = *self.limits();
+        if limits.min != limits.max {
+            limits.options |= Limits::LAZY;
+            self.set_limits(limits);
+        }
+    }
+}
+
+/// Simplifies the tree by removing nodes that are only there because of how it was parsed:
+/// - adjacent **CharsNode**s that match exactly once are joined into a single node
+/// - unnamed **AndNode**s inside another **AndNode** are flattened into their parent
+/// - unnamed **AndNode**s and **OrNode**s with a single child are replaced by the child
+/// - a string shared by the front of all the branches of an **OrNode** is moved in front of it
+///
+/// Named nodes (including those named "") are never changed, since they need to show up in the **Report**.
+pub fn optimize(node: &mut Node) {
+    match node {
+        Node::And(and_node) => {
+            and_node.nodes.iter_mut().for_each(optimize);
+            and_node.flatten();
+            and_node.join_chars();
+        }
+        Node::Or(or_node) => {
+            or_node.nodes.iter_mut().for_each(optimize);
+            if let Some(factored) = or_node.factor_prefix() {
+                *node = factored;
+                optimize(node);
+                return;
+            }
+        }
+        Node::Def(def_node) => optimize(&mut def_node.node),
+        _ => (),
+    }
+    let child = match node {
+        Node::And(a) if a.nodes.len() == 1 && !a.anchor && a.named.is_none() => a.nodes.pop(),
+        Node::Or(a) if a.nodes.len() == 1 && a.named.is_none() => a.nodes.pop(),
+        _ => None,
+    };
+    if let Some(mut child) = child {
+        let limits = *node.limits();
+        if child.limits() == &Limits::default() && !limits.no_case() && !child.name_outside() {
+            trace!(
+                2,
+                "Optimize: replacing {:?} with its child {:?}",
+                node,
+                child
+            );
+            child.set_limits(limits);
+            *node = child;
+        } else if *node.limits() == Limits::default() {
+            trace!(
+                2,
+                "Optimize: replacing {:?} with its child {:?}",
+                node,
+                child
+            );
+            *node = child;
+        } else {
+            match node {
+                Node::And(a) => a.nodes.push(child),
+                Node::Or(a) => a.nodes.push(child),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Makes every **CharsNode** in the tree ignore case, as if each had been written with a leading "\\c". Ranges and
+/// special characters are not changed.
+pub fn make_caseless(node: &mut Node) {
+    match node {
+        Node::Chars(chars_node) => {
+            chars_node.limits.options |= Limits::NO_CASE;
+            chars_node.string = chars_node.string.to_lowercase();
+        }
+        Node::And(and_node) => and_node.nodes.iter_mut().for_each(make_caseless),
+        Node::Or(or_node) => or_node.nodes.iter_mut().for_each(make_caseless),
+        Node::Def(def_node) => make_caseless(&mut def_node.node),
+        _ => (),
+    }
+}
+
+/// Makes the strings in NODE match case exactly, undoing **make_caseless()**. The strings of a caseless unit are kept
+/// in lower case, so they then only match lower case.
+fn make_case_sensitive(node: &mut Node) {
+    match node {
+        Node::Chars(chars_node) => chars_node.limits.options &= !Limits::NO_CASE,
+        Node::And(and_node) => and_node.nodes.iter_mut().for_each(make_case_sensitive),
+        Node::Or(or_node) => or_node.nodes.iter_mut().for_each(make_case_sensitive),
+        Node::Def(def_node) => make_case_sensitive(&mut def_node.node),
+        _ => (),
+    }
+}
+
+/// main controller for the tree parse processing, it looks at the next few characters in the pipeline, decides what they are, and
 /// distributes them to the proper XXXNode constructor function
 fn parse(chars: &mut Peekable, after_or: bool) -> Result<Node, Error> {
-    let node = match chars.peek_2() {
+    let start = chars.byte_pos();
+    let mut node = match chars.peek_2() {
         (None, _) => Node::None,
         (Some('\\'), Some('(')) => AndNode::parse_node(chars.consume(2))?,
         (Some('\\'), Some(ch1)) => {
@@ -1067,7 +2310,19 @@ fn parse(chars: &mut Peekable, after_or: bool) -> Result<Node, Error> {
             }
         }
         (Some('.'), _) => SpecialNode::parse_node(chars)?,
-        (Some('$'), _) => SpecialNode::parse_node(chars)?,
+        (Some('$'), _) => {
+            let pos = chars.pos();
+            let node = SpecialNode::parse_node(chars)?;
+            match chars.peek_2() {
+                (Some('\\'), Some(')')) | (Some('\\'), Some('|')) => (),
+                _ => Lint::warn(
+                    2,
+                    "'$' is followed by more of the RE, so it only matches if that part can match nothing",
+                    Some(pos),
+                ),
+            }
+            node
+        }
         (Some('['), _) => RangeNode::parse_node(chars.consume(1))?,
         (_, _) => CharsNode::parse_node(chars, after_or)?,
     };
@@ -1077,8 +2332,11 @@ fn parse(chars: &mut Peekable, after_or: bool) -> Result<Node, Error> {
             format!("Parse error at \"{}\"", chars.preview(6)).as_str(),
         ));
     }
+    node.set_span(chars.span_from(start));
     if let (Some('\\'), Some('|')) = chars.peek_2() {
-        Ok(OrNode::parse_node(chars.consume(2), node)?)
+        let mut node = OrNode::parse_node(chars.consume(2), node)?;
+        node.set_span(chars.span_from(start));
+        Ok(node)
     } else {
         Ok(node.trace())
     }
@@ -1104,9 +2362,11 @@ fn parse(chars: &mut Peekable, after_or: bool) -> Result<Node, Error> {
 ///       etc. In addition, matching anything is "\." (not '.'), and a quote is "\""
 ///     - Ranges (ie *[a-z0-9.]* and *[^a-z0-9.]*)
 ///   - **AND** unit: and(U!U@U#...\) (starting with "and(" and ending with "\)") like retraditional
-///       REs, contains a list of 0 or more units that must all match sequentially
+///     REs, contains a list of 0 or more units that must all match sequentially
 ///   - **OR** unit: or(U!U@U#...\): (starting with "or(" and ending with "\)") Like Retraditional
-///       REs, contains a list of 0 or more units where exactly one will match
+///     REs, contains a list of 0 or more units where exactly one will match
+///   - **SET** unit: any('...') or none('...'): matches a single character that is (or is not) in the
+///     quoted string. There is no range syntax inside, so '-', '^' and ']' need no escaping
 /// - To save a unit in the results it can be either named or unnamed. Names are assigned
 ///   by following the unit definition with "&lt;NAME&gt;". If NAME is left blank ("<>") it is
 ///   unnamed but recorded. Anything without a name, aside from the entire match, will not
@@ -1122,43 +2382,61 @@ fn parse(chars: &mut Peekable, after_or: bool) -> Result<Node, Error> {
 ///   - **def(NAME: RE0 RE1...)**: defines a subtree named NAME that can be substituted into the parse tree
 ///   - **get(NAME)**: fetches a predefined subtree and inserts it into the tree at the current point
 ///   - **use(FILE)**: reads definitions in from file
+///
 /// main controller for the tree parse processing, it looks at the next few characters in the pipeline, decides what they are, and
 /// distributes them to the proper XNode constructor function
 fn alt_parse(chars: &mut Peekable) -> Result<Node, Error> {
-    let mut node = match chars.skip_whitespace().peek_n(4)[..] {
+    chars.skip_whitespace().check_line_break()?;
+    let start = chars.byte_pos();
+    let mut node = match chars.peek_array::<5>() {
         // define, insert, save, load definitions
-        [Some('d'), Some('e'), Some('f'), Some('(')] => Defs::parse(chars.consume(4))?,
-        [Some('g'), Some('e'), Some('t'), Some('(')] => DefNode::alt_parse_node(chars.consume(4))?,
-        [Some('u'), Some('s'), Some('e'), Some('(')] => Defs::load(chars.consume(4))?,
+        [Some('d'), Some('e'), Some('f'), Some('('), _] => Defs::parse(chars.consume(4))?,
+        [Some('g'), Some('e'), Some('t'), Some('('), _] => {
+            DefNode::alt_parse_node(chars.consume(4))?
+        }
+        [Some('u'), Some('s'), Some('e'), Some('('), _] => Defs::load(chars.consume(4))?,
         // and, or, various text
-        [Some('a'), Some('n'), Some('d'), Some('(')] => AndNode::alt_parse_node(chars.consume(4))?,
-        [Some('o'), Some('r'), Some('('), _] => OrNode::alt_parse_node(chars.consume(3))?,
-        [Some('"'), _, _, _] => CharsNode::alt_parse_node(chars.consume(1), '"')?,
-        [Some('\''), _, _, _] => CharsNode::alt_parse_node(chars.consume(1), '\'')?,
-        [Some('t'), Some('x'), Some('t'), Some('(')] => {
+        [Some('a'), Some('n'), Some('d'), Some('('), _] => {
+            AndNode::alt_parse_node(chars.consume(4))?
+        }
+        [Some('o'), Some('r'), Some('('), _, _] => OrNode::alt_parse_node(chars.consume(3))?,
+        [Some('"'), _, _, _, _] => CharsNode::alt_parse_node(chars.consume(1), '"')?,
+        [Some('\''), _, _, _, _] => CharsNode::alt_parse_node(chars.consume(1), '\'')?,
+        [Some('t'), Some('x'), Some('t'), Some('('), _] => {
             CharsNode::alt_parse_node(chars.consume(4), ')')?
         }
-        [_, _, _, _] => CharsNode::alt_parse_node(chars, ' ')?,
-        _ => {
-            return Err(Error::make(
-                101,
-                format!(
-                    "Unexpected chars in regular expression: \"{}\" (should not happen)",
-                    chars.preview(6)
-                )
-                .as_str(),
-            ))
+        // sets of characters
+        [Some('a'), Some('n'), Some('y'), Some('('), _] => {
+            RangeNode::alt_parse_set(chars.consume(4), false)?
+        }
+        [Some('n'), Some('o'), Some('n'), Some('e'), Some('(')] => {
+            RangeNode::alt_parse_set(chars.consume(5), true)?
+        }
+        // any character but where a string starts
+        [Some('u'), Some('p'), Some('t'), Some('o'), Some('(')] => {
+            SpecialNode::alt_parse_upto(chars.consume(5), false)?
+        }
+        [Some('u'), Some('n'), Some('t'), Some('i'), Some('l')]
+            if chars.peek_array::<6>()[5] == Some('(') =>
+        {
+            SpecialNode::alt_parse_upto(chars.consume(6), true)?
         }
+        _ => CharsNode::alt_parse_node(chars, ' ')?,
     };
     if !node.is_none() {
-        node.set_named(alt_parse_named(chars)?, false);
-        let limits = Limits::parse(chars)?;
+        // until() repeats itself, so a name right after it is for the whole run
+        let until = matches!(&node, Node::Special(a) if a.upto.is_some() && a.limits.max > 1);
+        node.set_named(alt_parse_named(chars)?, until);
+        let mut limits = Limits::parse(chars)?;
         if limits.min * limits.max != 1 {
+            // a "\c" inside the unit still applies
+            limits.options |= node.limits().options & Limits::NO_CASE;
             node.set_limits(limits);
         }
         if node.named().is_none() {
             node.set_named(alt_parse_named(chars)?, true);
         }
+        node.set_span(chars.span_from(start));
     }
     Ok(node.trace())
 }
@@ -1171,8 +2449,16 @@ impl CharsNode {
         let mut new_node: Node;
         let mut nodes = Vec::<Node>::new();
         let mut no_case = 0usize;
+        // where the opening quote or "txt(" is, for errors
+        let start = match terminate {
+            ' ' => chars.pos(),
+            ')' => chars.pos() - 4,
+            _ => chars.pos() - 1,
+        };
+        let mut case_pos = None;
         if let (Some('\\'), Some(ch)) = chars.peek_2() {
             if "cC".contains(ch) {
+                case_pos = Some(chars.pos());
                 chars.consume(2);
                 if ch == 'c' {
                     no_case = Limits::NO_CASE;
@@ -1183,25 +2469,57 @@ impl CharsNode {
         loop {
             new_node = Node::None;
             match chars.peek_2() {
-                (Some(ch), _) if ch == terminate || (terminate == ' ' && ch <= ' ') => {
+                (Some(ch), _)
+                    if ch == terminate
+                        || (terminate == ' ' && (ch <= ' ' || chars.is_space(ch))) =>
+                {
                     chars.consume(1);
+                    if terminate == ')' && chars.from_trailer() {
+                        return Err(unterminated(102, "txt()", start));
+                    }
                     break;
                 }
+                (Some('<'), _)
+                    if nodes.is_empty()
+                        && chars_node.string.is_empty()
+                        && CharsNode::in_node_name_len(chars, true).is_some() =>
+                {
+                    return Err(Error::make(
+                        114,
+                        "Name in character block with no unit before it",
+                    ));
+                }
                 (Some('\\'), Some(ch1)) if SpecialNode::ESCAPE_CODES.contains(ch1) => {
                     new_node = SpecialNode::alt_parse_node(chars)?
                 }
                 (Some('\\'), Some(ch1)) => {
+                    Lint::check_escape(ch1, chars.pos());
                     chars_node.string.push(CharsNode::escaped_chars(ch1));
                     chars.consume(2);
                 }
                 (Some('.'), _) | (Some('$'), _) => new_node = SpecialNode::alt_parse_node(chars)?,
                 (Some('['), _) => new_node = RangeNode::alt_parse_node(chars.consume(1))?,
                 (Some(_), _) => chars_node.string.push(chars.next().unwrap()),
-                (None, _) => return Err(Error::make(102, "Unterminated character block")),
+                (None, _) => return Err(unterminated(102, "string", start)),
             }
+            // specials and ranges read their own repetitions
+            let parsed_limits =
+                !new_node.is_none() && new_node.limits().min * new_node.limits().max != 1;
+            let mut named = if parsed_limits {
+                None
+            } else {
+                CharsNode::alt_parse_in_node_name(chars, true)
+            };
+            let has_limits = parsed_limits
+                || matches!(chars.peek(), Some('*' | '+' | '?'))
+                || Limits::starts_count(chars);
             let mut limits = Limits::parse(chars)?;
             limits.options |= no_case;
-            if limits.min * limits.max != 1 {
+            let name_outside = has_limits && named.is_none();
+            if name_outside {
+                named = CharsNode::alt_parse_in_node_name(chars, false);
+            }
+            if limits.min * limits.max != 1 || named.is_some() {
                 if new_node.is_none() {
                     if let Some(ch) = chars_node.string.pop() {
                         if !chars_node.string.is_empty() {
@@ -1217,7 +2535,9 @@ impl CharsNode {
                             named: None,
                             limits,
                             name_outside: false,
+                            span: SourceSpan::default(),
                         });
+                        new_node.set_named(named, name_outside);
                     } else {
                         return Err(Error::make(
                             103,
@@ -1225,7 +2545,10 @@ impl CharsNode {
                         ));
                     }
                 } else {
-                    new_node.set_limits(limits);
+                    if limits.min * limits.max != 1 {
+                        new_node.set_limits(limits);
+                    }
+                    new_node.set_named(named, name_outside);
                 }
             }
             if !new_node.is_none() {
@@ -1247,7 +2570,7 @@ impl CharsNode {
             nodes.push(Node::Chars(chars_node));
         }
         Ok(match nodes.len() {
-            0 => Node::None,
+            0 => return case_pos.map_or(Ok(Node::None), |pos| Err(dangling_case(pos))),
             1 => nodes.pop().unwrap(),
             _ => Node::And(AndNode {
                 limits: Limits::default(),
@@ -1255,9 +2578,38 @@ impl CharsNode {
                 nodes,
                 anchor: false,
                 name_outside: true,
+                span: SourceSpan::default(),
             }),
         })
     }
+
+    /// Since '<' is an ordinary char inside a chars node, "<NAME>" is only taken as a name when it is next to a
+    /// repetition: either just before one (BEFORE_REPS, the name goes inside the repetition) or just after one.
+    /// Returns the name, consuming it, if there is one.
+    fn alt_parse_in_node_name(chars: &mut Peekable, before_reps: bool) -> Option<String> {
+        let len = CharsNode::in_node_name_len(chars, before_reps)?;
+        let name: String = chars.peek_n(len)[1..len - 1].iter().flatten().collect();
+        chars.consume(len);
+        Some(name)
+    }
+
+    /// the length in chars of the "<NAME>" at the front of CHARS, if there is one where **alt_parse_in_node_name()**
+    /// would accept it
+    fn in_node_name_len(chars: &mut Peekable, before_reps: bool) -> Option<usize> {
+        let len = chars.name_len()?;
+        if before_reps {
+            let after = chars.peek_n(len + 2);
+            let reps = match after[len] {
+                Some('*' | '+' | '?') => true,
+                Some('{') => Limits::brace_starts_count(after[len + 1]),
+                _ => false,
+            };
+            if !reps {
+                return None;
+            }
+        }
+        Some(len)
+    }
 }
 
 // these defs aren't really needed since they just call the regular parser, but are here as a reminder
@@ -1269,6 +2621,32 @@ impl SpecialNode {
     fn alt_parse_node(chars: &mut Peekable) -> Result<Node, Error> {
         SpecialNode::parse_node(chars)
     }
+
+    /// Parses the inside of an **upto('...')** unit, or an **until('...')** one if UNTIL is set. Both are a '.' that
+    /// does not match where the quoted string starts, so they can stop before a closing sequence like "*/" that a
+    /// range cannot exclude. **upto()** matches a single character, and **until()** is the same as **upto()\***: the
+    /// longest run of characters before the string, or to the end of the text if it never appears.
+    fn alt_parse_upto(chars: &mut Peekable, until: bool) -> Result<Node, Error> {
+        trace!(2, "UPTO starting from \"{}\"", chars.preview(6));
+        let start = chars.pos() - if until { 6 } else { 5 };
+        let upto = alt_parse_quoted(chars, start, ("upto()", "until()"), [118, 119, 120])?;
+        if upto.is_empty() {
+            return Err(Error::make(
+                118,
+                "The string in upto() or until() cannot be empty",
+            ));
+        }
+        let mut node = SpecialNode {
+            special: '.',
+            upto: Some(upto),
+            ..SpecialNode::default()
+        };
+        if until {
+            node.limits.min = 0;
+            node.limits.max = EFFECTIVELY_INFINITE;
+        }
+        Ok(Node::Special(node))
+    }
 }
 impl RangeNode {
     /// Entry point to parse a single range set using the alternative parser
@@ -1277,6 +2655,87 @@ impl RangeNode {
     fn alt_parse_node(chars: &mut Peekable) -> Result<Node, Error> {
         RangeNode::parse_node(chars)
     }
+
+    /// Parses the inside of an **any('...')** or **none('...')** unit, a quoted string whose characters make up the
+    /// set (NOT is set for **none**). There is no range syntax, so '-', '^' and ']' are ordinary characters. A
+    /// backslash escapes the quote or itself, and the escapes in **CHAR_ESCAPES** are their chars, but special
+    /// escapes like "\d" are not allowed: write those in a range instead.
+    fn alt_parse_set(chars: &mut Peekable, not: bool) -> Result<Node, Error> {
+        trace!(2, "SET starting from \"{}\"", chars.preview(6));
+        let pos = chars.pos();
+        let node = RangeNode {
+            not,
+            chars: alt_parse_quoted(
+                chars,
+                pos - if not { 5 } else { 4 },
+                ("any()", "none()"),
+                [115, 116, 117],
+            )?,
+            ..RangeNode::default()
+        };
+        node.lint_duplicates(pos);
+        Ok(Node::Range(node))
+    }
+}
+
+/// Reads the quoted string of a unit like **any('...')**, along with the ')' after it. A backslash escapes the quote
+/// or itself, and the escapes in **CHAR_ESCAPES** are their chars, but special escapes like "\d" are not allowed.
+/// START is where the unit starts, and UNITS are the two units that take a string, for the error messages. CODES are
+/// the errors for a unit that does not hold a single quoted string, one that is not terminated, and a special escape.
+fn alt_parse_quoted(
+    chars: &mut Peekable,
+    start: usize,
+    units: (&str, &str),
+    codes: [usize; 3],
+) -> Result<String, Error> {
+    let malformed = || {
+        Error::make(
+            codes[0],
+            format!(
+                "{} and {} must contain a single quoted string",
+                units.0, units.1
+            )
+            .as_str(),
+        )
+    };
+    let unterminated =
+        || unterminated(codes[1], &format!("{} or {} unit", units.0, units.1), start);
+    let quote = match chars.next() {
+        Some(ch) if ch == '\'' || ch == '"' => ch,
+        _ if chars.from_trailer() => return Err(unterminated()),
+        _ => return Err(malformed()),
+    };
+    let mut string = String::new();
+    loop {
+        let at = chars.pos();
+        match chars.next() {
+            Some(ch) if ch == quote => break,
+            Some('\\') => match chars.next() {
+                Some(ch) if SpecialNode::ESCAPE_CODES.contains(ch) => {
+                    return Err(Error::make(
+                        codes[2],
+                        format!(
+                            "Special character \"\\{}\" cannot be used in {} or {}",
+                            ch, units.0, units.1
+                        )
+                        .as_str(),
+                    ))
+                }
+                Some(ch) => {
+                    Lint::check_escape(ch, at);
+                    string.push(CharsNode::escaped_chars(ch));
+                }
+                None => return Err(unterminated()),
+            },
+            Some(ch) => string.push(ch),
+            None => return Err(unterminated()),
+        }
+    }
+    match chars.next() {
+        Some(')') if !chars.from_trailer() => Ok(string),
+        _ if chars.from_trailer() => Err(unterminated()),
+        _ => Err(malformed()),
+    }
 }
 
 impl AndNode {
@@ -1284,16 +2743,22 @@ impl AndNode {
     fn alt_parse_node(chars: &mut Peekable) -> Result<Node, Error> {
         trace!(2, "AND starting from \"{}\"", chars.preview(6));
         trace_change_indent!(2, 1);
+        // an and() starts before its "and(", while the wrapping node starts at the start of the RE
+        let and = !chars.at_start();
+        let start = chars.pos().saturating_sub(4);
         let mut nodes = Vec::<Node>::new();
         loop {
-            match chars.next() {
+            match chars.skip_whitespace().next() {
+                None if and => return Err(unterminated(104, "and()", start)),
                 None => {
                     return Err(Error::make(104, "Unterminated AND node"));
                 }
+                Some(')') if and && chars.from_trailer() => {
+                    return Err(unterminated(104, "and()", start))
+                }
                 Some(')') => {
                     break;
                 }
-                Some(' ') | Some('\n') | Some('\t') => (),
                 Some(ch) => {
                     chars.put_back(ch);
                     let node = alt_parse(chars)?;
@@ -1312,6 +2777,7 @@ impl AndNode {
                 named: None,
                 anchor: false,
                 name_outside: false,
+                span: SourceSpan::default(),
             }))
         }
     }
@@ -1322,16 +2788,15 @@ impl OrNode {
     fn alt_parse_node(chars: &mut Peekable) -> Result<Node, Error> {
         trace!(2, "OR starting from \"{}\"", chars.preview(6));
         trace_change_indent!(2, 1);
+        let start = chars.pos() - 3;
         let mut nodes = Vec::<Node>::new();
         loop {
-            match chars.next() {
-                None => {
-                    return Err(Error::make(105, "Unterminated OR node"));
-                }
+            match chars.skip_whitespace().next() {
+                None => return Err(unterminated(105, "or()", start)),
+                Some(')') if chars.from_trailer() => return Err(unterminated(105, "or()", start)),
                 Some(')') => {
                     break;
                 }
-                Some(' ') | Some('\n') | Some('\t') => (),
                 Some(ch) => {
                     chars.put_back(ch);
                     let node = alt_parse(chars)?;
@@ -1349,6 +2814,7 @@ impl OrNode {
                 limits: Limits::default(),
                 named: None,
                 name_outside: false,
+                span: SourceSpan::default(),
             }))
         }
     }
@@ -1359,6 +2825,7 @@ fn alt_parse_named(chars: &mut Peekable) -> Result<Option<String>, Error> {
     if chars.peek() != Some('<') {
         return Ok(None);
     }
+    let start = chars.pos();
     chars.consume(1);
     let mut chs = Vec::<char>::new();
     loop {
@@ -1367,9 +2834,7 @@ fn alt_parse_named(chars: &mut Peekable) -> Result<Option<String>, Error> {
                 break;
             }
             Some(ch) => chs.push(ch),
-            _ => {
-                return Err(Error::make(110, "error getting name in AND definition"));
-            }
+            _ => return Err(unterminated(110, "name", start)),
         }
     }
     Ok(Some(chs.into_iter().collect()))
@@ -1390,10 +2855,15 @@ impl Debug for DefNode {
         } else {
             (&name, &limits_str)
         };
+        let case = match self.no_case {
+            Some(true) => "\\c",
+            Some(false) => "\\C",
+            None => "",
+        };
         write!(
             f,
-            "DefNode '{}'{}{} ",
-            self.name, name_limits.0, name_limits.1
+            "DefNode '{}'{}{}{} ",
+            self.name, case, name_limits.0, name_limits.1
         )
     }
 }
@@ -1403,30 +2873,57 @@ impl DefNode {
     fn alt_parse_node(chars: &mut Peekable) -> Result<Node, Error> {
         trace!(2, "DEF starting from \"{}\"", chars.preview(6));
         trace_change_indent!(2, 1);
+        let start = chars.pos() - 4;
         let name = Defs::name_from_stream(chars, false);
+        let close = chars.skip_whitespace().next();
+        if close.is_none() || chars.from_trailer() {
+            return Err(unterminated(121, "get()", start));
+        }
         if name.is_empty() {
             return Err(Error::make(106, "Missing required name for RE load"));
         }
         trace!(4, "defining def {}", name);
-        if let Some(')') = chars.skip_whitespace().next() {
-        } else {
+        if close != Some(')') {
             return Err(Error::make(107, "Bad char in definition name"));
         }
+        // a "\\c" or "\\C" right after it is for the get(), unless it starts a string of its own
+        let no_case = match chars.peek_3() {
+            (Some('\\'), Some(ch), next)
+                if "cC".contains(ch)
+                    && next.is_none_or(|next| next.is_whitespace() || "<)*+?{".contains(next)) =>
+            {
+                chars.consume(2);
+                Some(ch == 'c')
+            }
+            _ => None,
+        };
         Ok(Node::Def(DefNode {
             name,
-            node: Box::new(Node::None),
-            limits: Limits::default(),
-            named: None,
-            name_outside: false,
+            no_case,
+            ..DefNode::default()
         }))
     }
     /// Used to prety-print, including proper indentation
-    fn desc(&self, indent: usize) {
-        println!("{0:1$}{2:?}", "", indent, self);
-        if let Some(node) = &Defs::get(self.name.as_str()) {
-            node.desc(indent + TAB_SIZE);
+    fn desc_to<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        indent: usize,
+        view: &TreeView,
+        level: usize,
+    ) -> std::io::Result<()> {
+        writeln!(w, "{0:1$}{2:?}", "", indent, self)?;
+        // once substituted it shows what the get() made of the definition
+        if !self.node.is_none() {
+            view.children(
+                w,
+                std::slice::from_ref(&*self.node),
+                indent + TAB_SIZE,
+                level,
+            )
+        } else if let Some(node) = Defs::get(self.name.as_str()) {
+            view.children(w, std::slice::from_ref(&node), indent + TAB_SIZE, level)
         } else {
-            println!("{0:1$}(no definition yet)", "", indent + 4);
+            writeln!(w, "{0:1$}(no definition yet)", "", indent + 4)
         }
     }
 }
@@ -1436,6 +2933,14 @@ impl DefNode {
 #[derive(Default, Debug)]
 struct Defs {
     defs: HashMap<String, Node>,
+    /// the text of each definition, so it can be saved and reparsed later
+    sources: HashMap<String, String>,
+    /// counts the changes to the table, so a tree saved by a caller can tell if a definition it used may have changed
+    generation: usize,
+    /// the namespace definitions are going into, set while loading a file with **use(FILE as NAMESPACE)**
+    namespace: Option<String>,
+    /// the names defined so far in the namespace, without the namespace
+    loaded: Vec<String>,
 }
 
 static DEFS: Lazy<Mutex<Defs>> = Lazy::new(|| Mutex::new(Defs::default()));
@@ -1443,22 +2948,32 @@ static DEFS: Lazy<Mutex<Defs>> = Lazy::new(|| Mutex::new(Defs::default()));
 impl Defs {
     /// Parses a name and one or more Nodes from the input stream and stores it in the defs table
     fn parse(chars: &mut Peekable) -> Result<Node, Error> {
-        let name = Defs::name_from_stream(chars, false);
-        if let Some(':') = chars.next() {
-        } else {
-            return Err(Error::make(111, "Missing required name for RE definition"));
+        let pos = chars.pos();
+        let byte_pos = chars.byte_pos();
+        let name = Defs::qualify(Defs::name_from_stream(chars, false));
+        match chars.next() {
+            Some(':') => (),
+            None => return Err(unterminated(122, "def()", pos - 4)),
+            Some(_) if chars.from_trailer() => return Err(unterminated(122, "def()", pos - 4)),
+            Some(_) => return Err(Error::make(111, "Missing required name for RE definition")),
         }
-        if DEFS.lock().unwrap().defs.get(&name).is_some() {
+        if DEFS.lock().unwrap().defs.contains_key(&name) {
             trace!(1, "Overriding definition of {}", name);
         }
         trace!(2, "reading definition of {}", name);
         trace_change_indent!(2, 1);
         let mut nodes = Vec::<Node>::new();
         loop {
-            chars.skip_whitespace();
-            if let Some(')') = chars.peek() {
-                chars.consume(1);
-                break;
+            match chars.skip_whitespace().peek() {
+                None => return Err(unterminated(122, "def()", pos - 4)),
+                Some(')') => {
+                    chars.consume(1);
+                    if chars.from_trailer() {
+                        return Err(unterminated(122, "def()", pos - 4));
+                    }
+                    break;
+                }
+                _ => (),
             }
             let node = alt_parse(chars)?;
             if !node.is_none() {
@@ -1477,9 +2992,14 @@ impl Defs {
                 anchor: false,
                 nodes,
                 name_outside: false,
+                span: SourceSpan::default(),
             })
         };
-        root.set_named(alt_parse_named(chars)?, false);
+        // a name after the def() replaces one inside it, but no name leaves that one alone
+        let named = alt_parse_named(chars)?;
+        if named.is_some() {
+            root.set_named(named, false);
+        }
         let limits = Limits::parse(chars)?;
         if limits.min * limits.max != 1 {
             root.set_limits(limits);
@@ -1488,70 +3008,196 @@ impl Defs {
             root.set_named(alt_parse_named(chars)?, true);
         }
 
-        DEFS.lock().unwrap().defs.insert(name, root);
+        LINT.lock().unwrap().defined.push((name.clone(), pos));
+        let source = format!("def({}", chars.slice(byte_pos, chars.byte_pos()));
+        let mut defs = DEFS.lock().unwrap();
+        defs.sources.insert(name.clone(), source);
+        defs.defs.insert(name, root);
+        defs.generation += 1;
         trace_change_indent!(2, -1);
         trace!(2, "finished definition");
         Ok(Node::None)
     }
 
-    /// Fetches an already-defined function to be insered into the parse tree
+    /// Fetches an already-defined function to be insered into the parse tree. A user definition of NAME comes first,
+    /// then the builtin one.
     fn get(name: &str) -> Option<Node> {
-        DEFS.lock().unwrap().defs.get(name).cloned()
+        let defs = DEFS.lock().unwrap();
+        defs.defs
+            .get(name)
+            .or_else(|| defs.defs.get(&format!("{}{}", BUILTIN_PREFIX, name)))
+            .cloned()
+    }
+
+    /// Loads the builtin definitions into the table
+    fn load_builtin() -> Result<(), Error> {
+        trace!(1, "loading builtin definitions");
+        let inline_defs = LINT.lock().unwrap().defined.len();
+        Defs::load_string(&builtin_source(), BUILTIN_PATH)?;
+        LINT.lock().unwrap().defined.truncate(inline_defs);
+        Ok(())
     }
 
     /// Reads RE snippet definitions from a file and loads them into the table
     // TODO: check for infinite loops in load
     fn load(chars: &mut Peekable) -> Result<Node, Error> {
-        let path = Defs::path_from_stream(chars);
-        if let Some(')') = chars.skip_whitespace().next() {
+        let start = chars.pos().saturating_sub(4);
+        let path = Defs::path_from_stream(chars)?;
+        let namespace = if let (Some('a'), Some('s'), Some(ch)) = chars.peek_3() {
+            if !ch.is_whitespace() {
+                return Err(Error::make(113, "Malformed \"use\" statement"));
+            }
+            chars.consume(2);
+            let namespace = Defs::name_from_stream(chars, false);
+            if namespace.is_empty() {
+                return Err(Error::make(113, "Malformed \"use\" statement"));
+            }
+            Some(namespace)
         } else {
-            return Err(Error::make(113, "Malformed \"use\" statement"));
+            None
+        };
+        match chars.skip_whitespace().next() {
+            Some(')') if chars.from_trailer() => return Err(unterminated(113, "use()", start)),
+            Some(')') => (),
+            None => return Err(unterminated(113, "use()", start)),
+            Some(_) => return Err(Error::make(113, "Malformed \"use\" statement")),
         }
         trace!(1, "loading definitions from file '{:#?}'", path);
         trace_change_indent!(1, 1);
+        let outer = namespace.map(|namespace| Defs::enter_namespace(&namespace));
+        let result = Defs::load_path(&path);
+        if let Some(outer) = outer {
+            Defs::leave_namespace(outer);
+        }
+        trace_change_indent!(1, -1);
+        result.map(|_| Node::None)
+    }
+
+    /// Reads the definitions from a file, or the builtin ones
+    fn load_path(path: &str) -> Result<(), Error> {
+        if path == BUILTIN_PATH {
+            return Defs::load_builtin();
+        }
+        // definitions from libraries are not expected to all be used
+        let inline_defs = LINT.lock().unwrap().defined.len();
 
-        match std::fs::read_to_string(&path) {
+        match std::fs::read_to_string(path) {
             Err(err) => {
                 return Err(Error::make(
                     114,
                     format!("Error reading def file {}: {}", path, err).as_str(),
                 ))
             }
-            Ok(string) => {
-                let mut def_chars = Peekable::new(&string);
-                while def_chars.skip_whitespace().peek().is_some() {
-                    if def_chars.peek() != Some('#') {
-                        if let Node::Def(def_node) = alt_parse(&mut def_chars)? {
-                            trace!(2, "Read definition of {} from {}", def_node.name, path);
-                        }
-                    } else {
-                        loop {
-                            match def_chars.next() {
-                                Some('\n') | None => {
-                                    break;
-                                }
-                                _ => (),
-                            }
+            // a file saved with Windows line endings would leave a '\r' at the end of every line
+            Ok(string) => Defs::load_string(&string.replace("\r\n", "\n"), path)?,
+        }
+        LINT.lock().unwrap().defined.truncate(inline_defs);
+        trace!(2, "finished load of '{:#?}'", path);
+        Ok(())
+    }
+
+    /// Starts putting definitions in NAMESPACE, inside the current one if there is one. It returns the current
+    /// namespace and its names, to be passed to **leave_namespace()**.
+    fn enter_namespace(namespace: &str) -> (Option<String>, Vec<String>) {
+        let mut defs = DEFS.lock().unwrap();
+        let namespace = match &defs.namespace {
+            Some(outer) => format!("{}.{}", outer, namespace),
+            None => namespace.to_string(),
+        };
+        trace!(2, "loading into namespace {}", namespace);
+        let outer_namespace = defs.namespace.replace(namespace);
+        (outer_namespace, std::mem::take(&mut defs.loaded))
+    }
+
+    /// Finishes a namespace. References between its definitions were written without the namespace, so they are
+    /// qualified now that all its names are known. Then the namespace it was in becomes current again.
+    fn leave_namespace(outer: (Option<String>, Vec<String>)) {
+        let mut defs = DEFS.lock().unwrap();
+        let names = std::mem::replace(&mut defs.loaded, outer.1);
+        let namespace = std::mem::replace(&mut defs.namespace, outer.0).unwrap();
+        for name in names.iter() {
+            let full_name = format!("{}.{}", namespace, name);
+            if let Some(node) = defs.defs.get_mut(&full_name) {
+                node.qualify_defs(&namespace, &names);
+            }
+            if let Some(source) = defs.sources.get_mut(&full_name) {
+                *source = qualify_source(source, &namespace, &names);
+            }
+        }
+    }
+
+    /// Puts the current namespace, if any, on the front of the name of a definition
+    fn qualify(name: String) -> String {
+        let mut defs = DEFS.lock().unwrap();
+        match defs.namespace.clone() {
+            Some(namespace) => {
+                if !defs.loaded.contains(&name) {
+                    defs.loaded.push(name.clone());
+                }
+                format!("{}.{}", namespace, name)
+            }
+            None => name,
+        }
+    }
+
+    /// Adds the defined names that are close to NAME, for an error message: the same name in another namespace, or
+    /// one that is a typo or two away
+    fn with_near_misses(name: &str) -> String {
+        let last = |name: &str| name.rsplit('.').next().unwrap_or_default().to_string();
+        let close = |a: &str, b: &str| a == b || (b.len() > 3 && edit_distance(a, b) <= 2);
+        let defs = DEFS.lock().unwrap();
+        let mut near: Vec<&String> = defs
+            .defs
+            .keys()
+            .filter(|def| close(def, name) || close(&last(def), &last(name)))
+            .collect();
+        near.sort();
+        near.truncate(5);
+        match near.len() {
+            0 => name.to_string(),
+            _ => format!(
+                "{} (did you mean {}?)",
+                name,
+                near.iter()
+                    .map(|def| def.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(" or ")
+            ),
+        }
+    }
+
+    /// Parses a string of definitions, as found in a library file, into the table. Lines starting with '#' are comments.
+    fn load_string(string: &str, path: &str) -> Result<(), Error> {
+        let mut def_chars = Peekable::new(string);
+        def_chars.def_file = true;
+        while def_chars.skip_lines().peek().is_some() {
+            if def_chars.peek() != Some('#') {
+                if let Node::Def(def_node) = alt_parse(&mut def_chars)? {
+                    trace!(2, "Read definition of {} from {}", def_node.name, path);
+                }
+            } else {
+                loop {
+                    match def_chars.next() {
+                        Some('\n') | None => {
+                            break;
                         }
+                        _ => (),
                     }
                 }
             }
         }
-        trace!(2, "finished load of '{:#?}'", path);
-        trace_change_indent!(1, -1);
-        Ok(Node::None)
+        Ok(())
     }
+
     /// gets a name from the input stream
     fn name_from_stream(chars: &mut Peekable, file: bool) -> String {
         let mut name_v = Vec::<char>::new();
         chars.skip_whitespace();
         loop {
             if let Some(ch) = chars.next() {
-                if ('a'..='z').contains(&ch)
-                    || ('A'..='Z').contains(&ch)
-                    || ('0'..='9').contains(&ch)
+                if ch.is_ascii_alphanumeric()
                     || "_-$#.".contains(ch)
-                    || (file && "/~~".contains(ch))
+                    || (file && "/~\\:".contains(ch))
                 {
                     name_v.push(ch);
                 } else {
@@ -1564,21 +3210,349 @@ impl Defs {
         name_v.iter().collect::<String>()
     }
 
-    /// gets a file name from the input stream
-    fn path_from_stream(chars: &mut Peekable) -> String {
+    /// gets a file name from the input stream. Besides the name chars it can have '/', '\\', ':' and '~' in it, so
+    /// Windows paths like "C:\\defs\\net.re" work, and a leading "~/" is the home directory. A name in quotes is
+    /// taken just as it is, so it can have spaces or parens in it.
+    fn path_from_stream(chars: &mut Peekable) -> Result<String, Error> {
+        chars.skip_whitespace();
+        if let Some(quote) = chars.peek().filter(|ch| *ch == '"' || *ch == '\'') {
+            let start = chars.pos();
+            chars.consume(1);
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some(_) if chars.from_trailer() => {
+                        return Err(unterminated(113, "quoted path", start))
+                    }
+                    Some(ch) if ch == quote => break,
+                    Some(ch) => name.push(ch),
+                    None => return Err(unterminated(113, "quoted path", start)),
+                }
+            }
+            chars.skip_whitespace();
+            return Ok(name);
+        }
         let name = Defs::name_from_stream(chars, true);
         let name = if name.is_empty() {
             "~/.regexp".to_string()
         } else {
             name
         };
-        if name[0..2].eq("~/") {
-            let home = home::home_dir();
-            let mut home_str = home.unwrap().display().to_string();
-            home_str.push_str(&name[1..]);
-            home_str
-        } else {
-            name
+        // without a home directory the name is left alone, and fails to open
+        match (
+            name.strip_prefix("~/").or_else(|| name.strip_prefix("~\\")),
+            home::home_dir(),
+        ) {
+            (Some(rest), Some(home)) => Ok(home.join(rest).display().to_string()),
+            _ => Ok(name),
+        }
+    }
+}
+
+/// Puts NAMESPACE on the front of the names of NAMES in the text of a definition, where they follow "def(" or "get("
+/// outside a quoted string, so the text gives the same definition when it is loaded back in.
+fn qualify_source(source: &str, namespace: &str, names: &[String]) -> String {
+    let mut qualified = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = source.chars().peekable();
+    while let Some(ch) = chars.next() {
+        qualified.push(ch);
+        match quote {
+            Some(_) if ch == '\\' => qualified.extend(chars.next()),
+            Some(q) if ch == q => quote = None,
+            Some(_) => (),
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '(' && (qualified.ends_with("def(") || qualified.ends_with("get(")) => {
+                while let Some(space) = chars.next_if(|ch| ch.is_whitespace()) {
+                    qualified.push(space);
+                }
+                let mut name = String::new();
+                while let Some(ch) =
+                    chars.next_if(|ch| ch.is_ascii_alphanumeric() || "_-$#.".contains(*ch))
+                {
+                    name.push(ch);
+                }
+                if names.contains(&name) {
+                    qualified.push_str(&format!("{}.", namespace));
+                }
+                qualified.push_str(&name);
+            }
+            None => (),
+        }
+    }
+    qualified
+}
+
+/// The number of characters that have to be added, removed or changed to turn one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ch_a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, ch_b) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + usize::from(ch_a != *ch_b))
+                .min(row[j] + 1)
+                .min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Adds the definitions in **string** to the def table. It takes the same format as a library file loaded with
+/// **use()**, so the output of **def_sources()** can be loaded back in.
+pub fn load_defs(string: &str) -> Result<(), Error> {
+    Defs::load_string(string, "string")
+}
+
+/// Gets the text of every definition in the def table, sorted by name
+pub fn def_sources() -> Vec<String> {
+    let defs = DEFS.lock().unwrap();
+    let mut names: Vec<&String> = defs.sources.keys().collect();
+    names.sort();
+    names
+        .iter()
+        .map(|name| defs.sources[*name].clone())
+        .collect()
+}
+
+/// Gets the names of all definitions in the def table, sorted
+pub fn def_names() -> Vec<String> {
+    let mut names: Vec<String> = DEFS.lock().unwrap().defs.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+/// Gets the tree for a definition
+pub fn get_def(name: &str) -> Option<Node> {
+    Defs::get(name)
+}
+
+/// Gets the text a definition was parsed from
+pub fn def_source(name: &str) -> Option<String> {
+    DEFS.lock().unwrap().sources.get(name).cloned()
+}
+
+/// Removes a definition from the def table, returning *false* if it was not there
+pub fn delete_def(name: &str) -> bool {
+    let mut defs = DEFS.lock().unwrap();
+    defs.generation += 1;
+    defs.sources.remove(name);
+    defs.defs.remove(name).is_some()
+}
+
+/// Empties the def table
+pub fn clear_defs() {
+    let mut defs = DEFS.lock().unwrap();
+    defs.generation += 1;
+    defs.defs.clear();
+    defs.sources.clear();
+}
+
+/// Gets a number that changes whenever a definition is added, changed or removed
+pub fn defs_generation() -> usize {
+    DEFS.lock().unwrap().generation
+}
+
+/// Loads the definitions in a library file, the same as **use(FILE)** in an RE. An empty path loads ~/.regexp, and
+/// "builtin" loads the builtin definitions.
+pub fn load_def_file(path: &str) -> Result<(), Error> {
+    // the ')' is part of the string, since one read from the trailer means the RE left the use() open. A path with
+    // spaces or parens in it is quoted.
+    let source = if path.contains(|ch: char| ch.is_whitespace() || ch == '(' || ch == ')')
+        && !path.contains('"')
+    {
+        format!("\"{}\")", path)
+    } else {
+        format!("{})", path)
+    };
+    Defs::load(&mut Peekable::new(&source)).map(|_| ())
+}
+
+/// Loads the builtin definitions, the same as **use(builtin)** in an RE
+pub fn load_builtin_defs() -> Result<(), Error> {
+    Defs::load_builtin()
+}
+
+//////////////////////////////////////////////////////////////////
+//
+// Lint
+//
+/// Collects warnings about constructs that are legal but probably not what the user intended. Like **Defs** it is
+/// held in a static so the parse functions can add to it without having to pass it around. Some warnings are made
+/// as the RE is parsed, others are found by examining the finished tree in **Node::lint()**.
+//
+//////////////////////////////////////////////////////////////////
+#[derive(Default, Debug)]
+struct Lint {
+    /// warnings found so far
+    warnings: Vec<Warning>,
+    /// names and positions of the definitions made in the RE being parsed, used to find unused ones
+    defined: Vec<(String, usize)>,
+    /// the RE being parsed, to quote the units the warnings are about
+    source: String,
+}
+
+static LINT: Lazy<Mutex<Lint>> = Lazy::new(|| Mutex::new(Lint::default()));
+
+impl Lint {
+    /// clears out any old data before starting a new parse of SOURCE
+    fn start(source: &str) {
+        let mut lint = LINT.lock().unwrap();
+        lint.warnings.clear();
+        lint.defined.clear();
+        lint.source = source.to_string();
+    }
+
+    /// adds a warning to the list
+    fn warn(code: usize, msg: &str, pos: Option<usize>) {
+        trace!(1, "Warning {}: {}", code, msg);
+        LINT.lock()
+            .unwrap()
+            .warnings
+            .push(Warning::make(code, msg, pos));
+    }
+
+    /// adds a warning about NODE to the list, with its span
+    fn warn_node(code: usize, msg: &str, node: &Node) {
+        trace!(1, "Warning {}: {}", code, msg);
+        let mut warning = Warning::make(code, msg, None);
+        warning.span = node.source_span();
+        LINT.lock().unwrap().warnings.push(warning);
+    }
+
+    /// the text NODE was parsed from in quotes, or its debug form if it has no span
+    fn quote(node: &Node) -> String {
+        let lint = LINT.lock().unwrap();
+        match node
+            .source_span()
+            .and_then(|(start, end)| lint.source.get(start..end))
+        {
+            Some(text) => format!("\"{}\"", text),
+            None => format!("{:?}", node),
+        }
+    }
+
+    /// warns about an escaped letter or digit that has no meaning, like "\\q". It is taken as the plain char, but is
+    /// probably a typo, and would change meaning if that escape were ever added. POS is the position of the '\\'.
+    fn check_escape(ch: char, pos: usize) {
+        if ch.is_ascii_alphanumeric()
+            && !SpecialNode::ESCAPE_CODES.contains(ch)
+            && !CHAR_ESCAPES.iter().any(|(code, _)| *code == ch)
+        {
+            Lint::warn(
+                6,
+                format!("Unknown escape \"\\{}\" is taken as '{}'", ch, ch).as_str(),
+                Some(pos),
+            );
+        }
+    }
+
+    /// returns the warnings found, sorted by position. OFFSET is added to the positions to account for anything
+    /// stripped off the front of the RE before parsing
+    fn finish() -> Vec<Warning> {
+        let mut lint = LINT.lock().unwrap();
+        lint.defined.clear();
+        let mut warnings: Vec<Warning> = lint.warnings.drain(..).collect();
+        warnings.sort_by_key(|w| w.pos);
+        warnings
+    }
+}
+
+impl Node {
+    /// Looks over the finished tree for suspicious constructs and records them as warnings
+    fn lint(&self) {
+        self.lint_nested_reps();
+        if let Node::And(a) = self {
+            if !a.anchor && self.leading_wildcard() && self.min_len() > 0 {
+                Lint::warn_node(
+                    7,
+                    "Leading \".*\" makes the match start wherever the search does, and is slow to fail; without it the search finds the same text after it",
+                    &a.nodes[0],
+                );
+            }
+        }
+        let defined = LINT.lock().unwrap().defined.clone();
+        if !defined.is_empty() {
+            let mut used = Vec::<&str>::new();
+            self.def_names(&mut used);
+            for (name, pos) in defined {
+                if !used.contains(&name.as_str()) {
+                    Lint::warn(
+                        3,
+                        format!("Definition {} is never used", name).as_str(),
+                        Some(pos),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Warns about unlimited repetitions directly containing another unlimited repetition, like "\(a+\)*". These
+    /// can match the same text many different ways and so can take a very long time to fail.
+    fn lint_nested_reps(&self) {
+        match self {
+            Node::And(a) => {
+                if a.limits.max == EFFECTIVELY_INFINITE {
+                    for (i, node) in a.nodes.iter().enumerate() {
+                        if !node.is_none()
+                            && node.limits().max == EFFECTIVELY_INFINITE
+                            && a.nodes
+                                .iter()
+                                .enumerate()
+                                .all(|(j, x)| i == j || x.can_match_empty())
+                        {
+                            Lint::warn_node(
+                                1,
+                                format!(
+                                    "Nested unlimited repetitions: {} inside {}",
+                                    Lint::quote(node),
+                                    Lint::quote(self)
+                                )
+                                .as_str(),
+                                node,
+                            );
+                        }
+                    }
+                }
+                a.nodes.iter().for_each(|x| x.lint_nested_reps());
+            }
+            Node::Or(a) => {
+                if a.limits.max == EFFECTIVELY_INFINITE {
+                    for node in a.nodes.iter() {
+                        if !node.is_none() && node.limits().max == EFFECTIVELY_INFINITE {
+                            Lint::warn_node(
+                                1,
+                                format!(
+                                    "Nested unlimited repetitions: {} inside {}",
+                                    Lint::quote(node),
+                                    Lint::quote(self)
+                                )
+                                .as_str(),
+                                node,
+                            );
+                        }
+                    }
+                }
+                a.nodes.iter().for_each(|x| x.lint_nested_reps());
+            }
+            Node::Def(a) => a.node.lint_nested_reps(),
+            _ => (),
+        }
+    }
+
+    /// collects the names of all definitions used in the tree
+    fn def_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Node::And(a) => a.nodes.iter().for_each(|x| x.def_names(names)),
+            Node::Or(a) => a.nodes.iter().for_each(|x| x.def_names(names)),
+            Node::Def(a) => {
+                names.push(a.name.as_str());
+                a.node.def_names(names);
+            }
+            _ => (),
         }
     }
 }
@@ -1589,6 +3563,27 @@ impl Defs {
 //
 //////////////////////////////////////////////////////////////////
 
+/// makes the error for a construct the RE ends inside of: error CODE, naming WHAT is not closed and the position it
+/// starts at
+fn unterminated(code: usize, what: &str, start: usize) -> Error {
+    Error::make(
+        code,
+        format!("Unterminated {} starting at position {}", what, start).as_str(),
+    )
+}
+
+/// makes the error for a "\\c" or "\\C" at position POS with no string after it to apply to
+fn dangling_case(pos: usize) -> Error {
+    Error::make(
+        16,
+        format!(
+            "\"\\c\" or \"\\C\" at position {} has no string after it",
+            pos
+        )
+        .as_str(),
+    )
+}
+
 /// gets the number of bytes in a sring of unicode characters
 fn char_bytes(string: &str, char_count: usize) -> usize {
     let s: String = string.chars().take(char_count).collect();
@@ -1605,7 +3600,7 @@ fn read_int(chars: &mut Peekable) -> Option<usize> {
             break;
         }
         let digit = digit.unwrap();
-        if !('0'..='9').contains(&digit) {
+        if !digit.is_ascii_digit() {
             chars.put_back(digit);
             break;
         }
@@ -1628,7 +3623,7 @@ fn read_int(chars: &mut Peekable) -> Option<usize> {
 /// far the initial walk should go
 //
 //////////////////////////////////////////////////////////////////
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 /// Holds and handles the limit information for a Node: the min and max repetitions allowed, and whether
 /// it is lazy or not.
 /// **IMPORTANT**: MIN and MAX are the actual sizes allowed (that is, ? is min 0, max 1). But the check()
@@ -1704,6 +3699,9 @@ impl Limits {
     /// returns a Limit struct parsed out from point. If none is there returns the default
     /// Like parse_if() but always returns a struct, using the default if there is none in the string
     pub(crate) fn parse(chars: &mut Peekable) -> Result<Limits, Error> {
+        if chars.peek() == Some('{') && !Limits::starts_count(chars) {
+            return Ok(Limits::default());
+        }
         let next = chars.next();
         if next.is_none() {
             return Ok(Limits::default());
@@ -1730,41 +3728,137 @@ impl Limits {
         Ok(Limits { min, max, options })
     }
 
+    /// true if the next char is a '{' that starts a repetition count. Unless literal braces are on (see
+    /// **set_literal_braces()**) any '{' does.
+    pub(crate) fn starts_count(chars: &mut Peekable) -> bool {
+        match chars.peek_2() {
+            (Some('{'), next) => Limits::brace_starts_count(next),
+            _ => false,
+        }
+    }
+
+    /// true if a '{' followed by NEXT starts a repetition count, see **starts_count()**
+    fn brace_starts_count(next: Option<char>) -> bool {
+        !LITERAL_BRACES.load(Acquire) || next.is_some_and(|ch| ch.is_ascii_digit())
+    }
+
     /// helper function to parse an int at the current position of the RE being parsed
     fn parse_ints(chars: &mut Peekable) -> Result<(usize, usize), Error> {
-        let num = read_int(chars);
-        let peek = chars.next();
-        if num.is_none() || peek.is_none() {
-            return Err(Error::make(7, "malformed repetition block"));
-        }
-        let num = num.unwrap();
-        match peek.unwrap() {
-            '}' => Ok((num, num)),
-            ',' => {
+        // the position of the '{', which has been read
+        let start = chars.pos() - 1;
+        let num = match read_int(chars) {
+            Some(num) => num,
+            None => {
+                return Err(Limits::count_error(
+                    chars,
+                    start,
+                    7,
+                    "Repetition count must start with a number",
+                ))
+            }
+        };
+        match chars.peek() {
+            Some('}') => {
+                chars.next();
+                if num == 0 {
+                    Lint::warn(
+                        5,
+                        "Repetition {0} only matches an empty string",
+                        Some(chars.pos()),
+                    );
+                }
+                Ok((num, num))
+            }
+            Some(',') => {
+                chars.next();
                 let n2 = if let Some(n) = read_int(chars) {
                     n
                 } else {
                     EFFECTIVELY_INFINITE
                 };
-                let terminate = chars.next();
-                if terminate.unwrap_or('x') != '}' {
-                    Err(Error::make(8, "bad character in repeat count"))
-                } else {
-                    Ok((num, n2))
+                if chars.peek() != Some('}') {
+                    return Err(Limits::count_error(
+                        chars,
+                        start,
+                        8,
+                        "Repetition count must end with '}' after the second number",
+                    ));
                 }
+                chars.next();
+                if n2 < num {
+                    Lint::warn(
+                        5,
+                        format!(
+                            "Repetition {{{},{}}} has max less than min and can never match",
+                            num, n2
+                        )
+                        .as_str(),
+                        Some(chars.pos()),
+                    );
+                } else if n2 == 0 {
+                    Lint::warn(
+                        5,
+                        "Repetition {0,0} only matches an empty string",
+                        Some(chars.pos()),
+                    );
+                }
+                Ok((num, n2))
             }
-            _ => Err(Error::make(7, "Malformed repetition block")),
+            _ => Err(Limits::count_error(
+                chars,
+                start,
+                13,
+                "Repetition count must have ',' or '}' after the first number",
+            )),
+        }
+    }
+
+    /// makes the error for a bad char in the repetition count starting at position START: error CODE with MSG saying
+    /// what was expected, or error 14 if the RE ends first
+    fn count_error(chars: &mut Peekable, start: usize, code: usize, msg: &str) -> Error {
+        if chars.at_end() {
+            Error::make(
+                14,
+                format!(
+                    "Unterminated repetition count starting at position {}",
+                    start
+                )
+                .as_str(),
+            )
+        } else {
+            let pos = chars.pos();
+            Error::make(
+                code,
+                format!(
+                    "{}, found '{}' at position {}",
+                    msg,
+                    chars.peek().unwrap_or_default(),
+                    pos
+                )
+                .as_str(),
+            )
         }
     }
 
+    /// true if REPS repetitions are allowed: min <= REPS <= max
+    pub fn reps_ok(&self, reps: usize) -> bool {
+        self.min <= reps && reps <= self.max
+    }
+
+    /// true if one more repetition than REPS is allowed. This is what a lazy walk checks before taking another
+    /// repetition, when it has at least **min** already.
+    pub fn can_add_rep(&self, reps: usize) -> bool {
+        self.reps_ok(reps + 1)
+    }
+
     /// Checks if the size falls in the range.
     /// Returns: <0 if NUM is < min; 0 if NUM is in the range min <= NUM <= ,ax (but SEE WARNING BELOW: NUM needs
     /// to be adjusted to account for the 0-match possibility.
     ///
     /// **Beware**: the input is usize and is in general the length of steps vector.
     /// This has a 0-match in its first position, so the value entered is actually one higher than the allowed value.
-    /// I considered subtracting 1 from the number to make it match, but because the arg is usize and is sometimes 0
-    /// it is better to leave it like this.
+    /// Use **reps_ok()** or **can_add_rep()**, which take the number of repetitions, instead.
+    #[deprecated(note = "use reps_ok() or can_add_rep(), which take the number of repetitions")]
     pub fn check(&self, num: usize) -> isize {
         if num <= self.min {
             -1
@@ -1801,80 +3895,163 @@ impl Limits {
 pub struct Peekable<'a> {
     /// The char iterator sourcing the chars
     chars: Chars<'a>,
-    /// a vector holding characters taken off of *chars* but not consumed. Requests to **next()** grab input from here before looking in **chars**.
-    peeked: Vec<char>,
+    /// a queue holding characters taken off of *chars* but not consumed. Requests to **next()** grab input from here before looking in **chars**.
+    peeked: VecDeque<char>,
     /// A vector holding chars appended to the end of the input string. This is only accessed after the **chars** iterator has been exhausted.
     trailer: Vec<char>,
     /// To minimize the chance of infinite loops this is inc'ed whenever a char is read. This way if no progress is made in processing the RE
     /// string a warning can be sent. I worry there could be some bad syntax that causes an infinite loop, this should cach such a happening.
     progress_check: isize,
+    /// The number of chars consumed from the stream, used to give positions for warnings
+    consumed: usize,
+    /// the position of the start of the string in the RE, see **new_at()**
+    start: usize,
+    /// the original string, so pieces of it can be recovered
+    source: &'a str,
+    /// set by a leading "(?g)" in a traditional RE: the chars on each side of "\|" go into the OR as a group instead
+    /// of just the one next to it
+    group_alternation: bool,
+    /// what **skip_whitespace()** skips, from **set_whitespace()** when the stream is made
+    whitespace: Whitespace,
+    /// set for the text of a def file, where newlines can be significant (see **Whitespace::strict_lines()**)
+    def_file: bool,
 }
 
 impl<'a> Iterator for Peekable<'a> {
     type Item = char;
     /// gets the next char from the **Peekable** stream - first checks **peeked**, then **chars**, finally **trailer**
     fn next(&mut self) -> Option<char> {
-        if !self.peeked.is_empty() {
-            Some(self.peeked.remove(0))
+        let ch = if !self.peeked.is_empty() {
+            self.peeked.pop_front()
         } else {
             self.next_i()
+        };
+        if ch.is_some() {
+            self.consumed += 1;
         }
+        ch
     }
 }
 impl<'a> Peekable<'a> {
     /// sanity check: if peeked stack exceeds this size it is probably a problem
     const PEEKED_SANITY_SIZE: usize = 20;
     /// create a new **Peekable** to source a string
-    pub(crate) fn new(string: &str) -> Peekable {
+    pub(crate) fn new(string: &str) -> Peekable<'_> {
+        Peekable::new_at(string, 0)
+    }
+
+    /// create a new **Peekable** to source a string that starts POS chars into the RE, so positions are in the RE.
+    /// Only ASCII is skipped to get there, so POS is in bytes as well, for the spans of the nodes.
+    pub(crate) fn new_at(string: &str, pos: usize) -> Peekable<'_> {
         Peekable {
             chars: string.chars(),
-            peeked: Vec::<char>::new(),
+            peeked: VecDeque::<char>::new(),
             trailer: Vec::<char>::new(),
             progress_check: 1,
+            consumed: pos,
+            start: pos,
+            source: string,
+            group_alternation: false,
+            whitespace: WHITESPACE.lock().unwrap().clone(),
+            def_file: false,
         }
     }
 
+    /// true if the whole string has been read, so anything left comes from the trailer (see **push_str()**)
+    pub fn at_end(&self) -> bool {
+        self.consumed >= self.start + self.source.chars().count()
+    }
+
+    /// true if the last char read came from the trailer rather than the string. The parsers add a closing trailer to
+    /// the RE, so a construct that reads it as its own closing char was not closed in the RE.
+    pub fn from_trailer(&self) -> bool {
+        self.consumed > self.start + self.source.chars().count()
+    }
+
+    /// true if the stream is at the start of the string, which is where the wrapping AND node of the RE starts
+    fn at_start(&self) -> bool {
+        self.consumed == self.start
+    }
+
+    /// gets the text of the original string between two byte positions (as returned by **byte_pos()**)
+    pub fn slice(&self, from: usize, to: usize) -> String {
+        self.source.get(from..to).unwrap_or_default().to_string()
+    }
+
+    /// the position in the original string (in bytes) of the next char to be read, or the end of the string once
+    /// it has all been read. This assumes anything put back was read from the string.
+    pub fn byte_pos(&self) -> usize {
+        // once the string runs out, what is peeked past its end comes from the trailer
+        let from_string = if self.chars.as_str().is_empty() {
+            (self.start + self.source.chars().count()).saturating_sub(self.consumed)
+        } else {
+            self.peeked.len()
+        };
+        let peeked: usize = self
+            .peeked
+            .iter()
+            .take(from_string)
+            .map(|ch| ch.len_utf8())
+            .sum();
+        (self.source.len() - self.chars.as_str().len()).saturating_sub(peeked)
+    }
+
+    /// the span in the RE from byte START of the string (as returned by **byte_pos()**) to the next char to be read
+    fn span_from(&self, start: usize) -> SourceSpan {
+        SourceSpan::new(self.start + start, self.start + self.byte_pos())
+    }
+
+    /// the position in the stream (in chars) of the next char to be read
+    pub fn pos(&self) -> usize {
+        self.consumed
+    }
+
     /// peek() looks at the next character in the pipeline. If called multiple times it returns the same value
     pub fn peek(&mut self) -> Option<char> {
         if self.peeked.is_empty() {
             let ch = self.next_i()?;
-            self.peeked.push(ch);
+            self.peeked.push_back(ch);
         }
         Some(self.peeked[0])
     }
 
-    /// peek at the next n chars
+    /// peek at the next n chars. This allocates, so the parsers use the fixed size versions below.
     pub fn peek_n(&mut self, n: usize) -> Vec<Option<char>> {
-        let mut ret: Vec<Option<char>> = Vec::new();
-        for ch in self.peeked.iter() {
-            if ret.len() == n {
-                return ret;
-            }
-            ret.push(Some(*ch));
-        }
-        while ret.len() < n {
-            ret.push(self.peek_next());
-        }
-        ret
+        self.fill_peeked(n);
+        (0..n).map(|i| self.peeked.get(i).copied()).collect()
+    }
+
+    /// peek at the next N chars without allocating, for matching against array patterns
+    pub fn peek_array<const N: usize>(&mut self) -> [Option<char>; N] {
+        self.fill_peeked(N);
+        std::array::from_fn(|i| self.peeked.get(i).copied())
     }
 
     /// convenient because 2 chars is all the lookahead I usually need
     pub fn peek_2(&mut self) -> (Option<char>, Option<char>) {
-        let x = self.peek_n(2);
-        (x[0], x[1])
+        let [ch0, ch1] = self.peek_array();
+        (ch0, ch1)
+    }
+
+    /// for the few places that need one more
+    pub fn peek_3(&mut self) -> (Option<char>, Option<char>, Option<char>) {
+        let [ch0, ch1, ch2] = self.peek_array();
+        (ch0, ch1, ch2)
     }
 
     /// This simply adds the char back in the queue. It is assumed the caller returns the chars in the reverse order they are popped off
     pub fn put_back(&mut self, ch: char) {
         self.progress_check -= 1;
-        self.peeked.insert(0, ch);
+        self.consumed = self.consumed.saturating_sub(1);
+        self.peeked.push_front(ch);
     }
 
     /// Returns a string to the queue, shortcut for doing it char-by-char
     pub fn put_back_str(&mut self, string: &str) {
         self.progress_check -= 1;
         for ch in string.chars().rev() {
-            self.peeked.insert(0, ch);
+            self.consumed = self.consumed.saturating_sub(1);
+            self.peeked.push_front(ch);
         }
     }
 
@@ -1888,17 +4065,54 @@ impl<'a> Peekable<'a> {
         string.chars().for_each(move |ch| self.trailer.push(ch));
     }
 
-    /// (as the name says) skips over whitespace at the front of the stream
+    /// (as the name says) skips over whitespace at the front of the stream. What counts as whitespace is set by
+    /// **set_whitespace()**. Where newlines are significant a '\\' at the end of a line is skipped along with the
+    /// newline.
     pub fn skip_whitespace(&mut self) -> &mut Self {
-        while let Some(ch) = self.next() {
-            if !" \n\t".contains(ch) {
-                self.put_back(ch);
-                break;
-            }
+        loop {
+            match self.peek_2() {
+                (Some(ch), _) if self.is_space(ch) => self.consume(1),
+                (Some('\\'), Some('\n')) if self.strict_lines() => self.consume(2),
+                _ => break,
+            };
+        }
+        self
+    }
+
+    /// skips whitespace and newlines, even where newlines are significant, for the space between definitions
+    pub fn skip_lines(&mut self) -> &mut Self {
+        while self.skip_whitespace().peek() == Some('\n') {
+            self.consume(1);
         }
         self
     }
 
+    /// true if CH is skipped as whitespace between units
+    pub fn is_space(&self, ch: char) -> bool {
+        self.whitespace.contains(ch) && !(ch == '\n' && self.strict_lines())
+    }
+
+    /// true if a definition cannot run onto another line without a '\\'
+    fn strict_lines(&self) -> bool {
+        self.def_file && self.whitespace.strict_lines
+    }
+
+    /// returns an error if the stream is at a newline that is not allowed between units
+    fn check_line_break(&mut self) -> Result<(), Error> {
+        if self.strict_lines() && self.peek() == Some('\n') {
+            let line = self.source[..self.byte_pos()].matches('\n').count() + 1;
+            return Err(Error::make(
+                123,
+                format!(
+                    "Definition runs past the end of line {} (end the line with '\\' to continue it)",
+                    line
+                )
+                .as_str(),
+            ));
+        }
+        Ok(())
+    }
+
     /// disposes of the front **num** characters from the stream
     pub fn consume(&mut self, num: usize) -> &mut Self {
         for _i in 0..num {
@@ -1932,13 +4146,31 @@ impl<'a> Peekable<'a> {
         ret
     }
 
-    /// peek_next() gets the next unread character, adds it to the peeked list, and returns it
-    fn peek_next(&mut self) -> Option<char> {
-        let ch = self.next_i();
-        if let Some(c) = ch {
-            self.peeked.push(c);
+    /// reads chars into the peeked list until it holds **n**, or the input runs out
+    fn fill_peeked(&mut self, n: usize) {
+        while self.peeked.len() < n {
+            match self.next_i() {
+                Some(ch) => self.peeked.push_back(ch),
+                None => break,
+            }
+        }
+    }
+
+    /// if the stream starts with "<NAME>", where NAME is made of alphanumerics and '_', returns the number of chars
+    /// it takes up, without consuming anything
+    fn name_len(&mut self) -> Option<usize> {
+        if self.peek() != Some('<') {
+            return None;
+        }
+        let mut len = 1;
+        loop {
+            self.fill_peeked(len + 1);
+            match self.peeked.get(len) {
+                Some('>') => return Some(len + 1),
+                Some(ch) if ch.is_alphanumeric() || *ch == '_' => len += 1,
+                _ => return None,
+            }
         }
-        ch
     }
 
     /// get a string of the first **len** chars from the stream
@@ -3,17 +3,26 @@
 //! RE tree) is represented by a **Step** object. The **Step**s are grouped in vectors to form **Path**s, each of which represents
 //! a walk through the tree. When a **Path** reaches the end of the tree successfully it means the search has succeeded and that
 //! **Path* is returned, representing a matched string, so it can generate a **Report** giving its route.
-use crate::regexp::{trace_indent, trace_level, trace_set_indent, Error, Report};
+use crate::regexp::options::{with_walk_options, SearchOptions};
+use crate::regexp::scan::{can_start, scanner, Scanner};
+use crate::regexp::trace::{json_tracing, trace_event, trace_reset_depth, TraceEvent, TraceKind};
+use crate::regexp::{trace_get_indent, trace_level, trace_set_indent, Error, Report};
 use crate::tree::*;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::Write;
 //use lazy_static::lazy_static;
 use crate::{trace, trace_change_indent};
 use core::fmt::Debug;
+use core::sync::atomic::{
+    AtomicBool, AtomicUsize,
+    Ordering::{AcqRel, Acquire, Release},
+};
 use once_cell::sync::Lazy;
-use std::sync::Mutex;
-
-/// simplifies code a little by removing the awkward accessing of the last element in a vector
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 //////////////////////////////////////////////////////////////////
 //
@@ -67,6 +76,103 @@ pub struct OrStep<'a> {
     which: usize,
 }
 
+/// Pools of the vectors and boxes that make up **Path**s. Most walks from a start position fail after a step or two,
+/// so instead of freeing what a failed walk allocated and allocating it all again at the next position, the failed
+/// **Path** is taken apart and its parts are kept here, emptied, to be used again. An arena only lives as long as
+/// a call to **walk_tree()**, since the **Path**s borrow the tree.
+#[derive(Default)]
+pub struct WalkArena<'a> {
+    chars: Vec<Vec<CharsStep<'a>>>,
+    special: Vec<Vec<SpecialStep<'a>>>,
+    range: Vec<Vec<RangeStep<'a>>>,
+    and: Vec<Vec<AndStep<'a>>>,
+    or: Vec<Vec<OrStep<'a>>>,
+    child_paths: Vec<Vec<Path<'a>>>,
+    // the boxes are what gets reused, for **OrStep::child_path**
+    #[allow(clippy::vec_box)]
+    boxes: Vec<Box<Path<'a>>>,
+    /// the OR branches known to fail, as (node address, branch, byte position). Walking a branch from where it
+    /// already failed fails again, so when the parent of an OR backs off and steps it again, or the walk from the
+    /// next start position gets there, the branch is skipped. See **forget_or_failures()** for when they are dropped.
+    or_failures: HashSet<(usize, usize, usize)>,
+    /// true if **or_failures** is used. It is off with **WalkOptions::no_or_memo()**.
+    remember_or_failures: bool,
+}
+
+impl<'a> WalkArena<'a> {
+    /// gets an empty vector from POOL, or a new one if it is empty
+    fn take<T>(pool: &mut Vec<Vec<T>>) -> Vec<T> {
+        pool.pop().unwrap_or_else(|| {
+            count_allocation();
+            Vec::new()
+        })
+    }
+
+    /// gets a box holding **Path::None**
+    fn take_box(&mut self) -> Box<Path<'a>> {
+        self.boxes.pop().unwrap_or_else(|| {
+            count_allocation();
+            Box::new(Path::None)
+        })
+    }
+
+    /// takes PATH apart, keeping its vectors and boxes to use again
+    fn recycle(&mut self, path: Path<'a>) {
+        match path {
+            Path::Chars(mut steps) => {
+                steps.clear();
+                self.chars.push(steps);
+            }
+            Path::Special(mut steps) => {
+                steps.clear();
+                self.special.push(steps);
+            }
+            Path::Range(mut steps) => {
+                steps.clear();
+                self.range.push(steps);
+            }
+            Path::And(mut steps) => {
+                for step in steps.drain(..) {
+                    self.recycle_and_step(step);
+                }
+                self.and.push(steps);
+            }
+            Path::Or(mut steps) => {
+                for step in steps.drain(..) {
+                    self.recycle_box(step.child_path);
+                }
+                self.or.push(steps);
+            }
+            Path::None => (),
+        }
+    }
+
+    /// drops the OR branch failures that no longer apply when a walk from the root starts at START: the ones before
+    /// it, which no walk from here on can reach, or all of them if the match length is limited (**LIMITED**), since
+    /// then how far a branch can go depends on where the walk started
+    fn forget_or_failures(&mut self, start: usize, limited: bool) {
+        if limited {
+            self.or_failures.clear();
+        } else {
+            self.or_failures.retain(|&(_, _, pos)| pos >= start);
+        }
+    }
+
+    /// keeps the child paths of an **AndStep** to use again
+    fn recycle_and_step(&mut self, mut step: AndStep<'a>) {
+        for path in step.child_paths.drain(..) {
+            self.recycle(path);
+        }
+        self.child_paths.push(step.child_paths);
+    }
+
+    /// keeps the box of an **OrStep** and whatever path it holds to use again
+    fn recycle_box(&mut self, mut path: Box<Path<'a>>) {
+        self.recycle(std::mem::replace(&mut *path, Path::None));
+        self.boxes.push(path);
+    }
+}
+
 //////////////////////////////////////////////////////////////////
 //
 // Path struct
@@ -97,6 +203,15 @@ impl<'a> Path<'a> {
         self.len() == 0
     }
 
+    /// true if the number of repetitions in the path is allowed by its node's limits. The first step is the one for 0
+    /// repetitions, so an empty path has no count at all and is never OK.
+    pub fn reps_ok(&self) -> bool {
+        match self.len() {
+            0 => false,
+            len => self.limits().reps_ok(len - 1),
+        }
+    }
+
     /// length of the **Path** (the number of **Step**s it has)
     pub fn len(&self) -> usize {
         match self {
@@ -109,12 +224,45 @@ impl<'a> Path<'a> {
         }
     }
 
+    /// describes the node the path walks, for tracing
+    fn node_desc(&self) -> String {
+        match self {
+            Path::Chars(steps) => steps[0].node_desc(),
+            Path::Special(steps) => steps[0].node_desc(),
+            Path::Range(steps) => steps[0].node_desc(),
+            Path::And(steps) => steps[0].node_desc(),
+            Path::Or(steps) => steps[0].node_desc(),
+            Path::None => "None".to_string(),
+        }
+    }
+
     /// gets the range of the path, using bytes
     pub fn range(&self) -> (usize, usize) {
         let (first, last) = self.first_last();
         (first.start, last.end)
     }
 
+    /// gathers the **Matched** of every step in the path and in its child paths, to check the walk's bookkeeping
+    pub fn all_matched(&self) -> Vec<Matched> {
+        match self {
+            Path::Chars(steps) => steps.iter().map(|s| s.matched).collect(),
+            Path::Special(steps) => steps.iter().map(|s| s.matched).collect(),
+            Path::Range(steps) => steps.iter().map(|s| s.matched).collect(),
+            Path::And(steps) => steps
+                .iter()
+                .flat_map(|s| {
+                    std::iter::once(s.matched)
+                        .chain(s.child_paths.iter().flat_map(|p| p.all_matched()))
+                })
+                .collect(),
+            Path::Or(steps) => steps
+                .iter()
+                .flat_map(|s| std::iter::once(s.matched).chain(s.child_path.all_matched()))
+                .collect(),
+            Path::None => Vec::new(),
+        }
+    }
+
     /// gets the byte count of the end of the path
     pub fn end(&self) -> usize {
         let (_, last) = self.first_last();
@@ -131,7 +279,7 @@ impl<'a> Path<'a> {
     /// For debug use: allocates String
     pub fn matched_string(&'a self) -> String {
         let (first, last) = self.first_last();
-        Input::apply(|input| input.full_text[first.start..last.end].to_string())
+        Input::with_text(|text| text[first.start..last.end].to_string())
     }
 
     /// returns ths **Limit** object for the Path
@@ -166,15 +314,29 @@ impl<'a> Path<'a> {
     ///   subpath off and check. For **AND** this means backing off on the previous subpath, for **OR** it means
     ///   going to the next option
     /// - back off the last step, check if that still meets the requirements. For greedy evaluation this means popping
-    ///   off a step from the Path, for lazy eval it means adding a new step
-    fn back_off(&mut self) -> Result<bool, Error> {
+    ///   off a step from the Path, for lazy eval it means adding a new step. The leaf steps read in more input
+    ///   before deciding they are at the end of the text, so a lazy match can run on past the blocks read so far.
+    fn back_off(&mut self, arena: &mut WalkArena<'a>) -> Result<bool, Error> {
+        // the child of the zero-repetition step of an OR, there is nothing to back off
+        if self.is_none() {
+            return Ok(false);
+        }
+        count_backtrack();
+        walk_check()?;
+        if json_tracing() {
+            trace_event(TraceEvent::make(
+                TraceKind::BackOff,
+                self.node_desc(),
+                self.range(),
+            ));
+        }
         trace_change_indent!(6, 1);
         let limits = self.limits();
         let mut ret = false;
         if limits.lazy() {
             match self {
                 Path::Chars(steps) => {
-                    if limits.check(steps.len() + 1) == 0 {
+                    if limits.can_add_rep(steps.len() - 1) {
                         if let Some(next_step) = steps.last().unwrap().step() {
                             steps.push(next_step);
                             ret = true;
@@ -189,7 +351,7 @@ impl<'a> Path<'a> {
                     );
                 }
                 Path::Special(steps) => {
-                    if limits.check(steps.len() + 1) == 0 {
+                    if limits.can_add_rep(steps.len() - 1) {
                         if let Some(next_step) = steps.last().unwrap().step() {
                             steps.push(next_step);
                             ret = true;
@@ -204,7 +366,7 @@ impl<'a> Path<'a> {
                     );
                 }
                 Path::Range(steps) => {
-                    if limits.check(steps.len() + 1) == 0 {
+                    if limits.can_add_rep(steps.len() - 1) {
                         if let Some(next_step) = steps.last().unwrap().step() {
                             steps.push(next_step);
                             ret = true;
@@ -220,14 +382,7 @@ impl<'a> Path<'a> {
                 }
                 Path::And(steps) => {
                     let len0 = steps.len();
-                    if steps[len0 - 1].back_off()? {
-                        ret = true;
-                    } else if limits.check(steps.len() + 1) == 0 {
-                        if let Some(next_step) = steps[len0 - 1].step()? {
-                            steps.push(next_step);
-                            ret = true;
-                        }
-                    }
+                    ret = back_off_lazy(steps, &limits, arena)?;
                     trace!(
                         6,
                         "back off Path lazy: {:?}, steps was {}, now {}: {}",
@@ -239,14 +394,7 @@ impl<'a> Path<'a> {
                 }
                 Path::Or(steps) => {
                     let len0 = steps.len();
-                    if steps[len0 - 1].back_off()? {
-                        ret = true;
-                    } else if limits.check(steps.len() + 1) == 0 {
-                        if let Some(next_step) = steps[len0 - 1].step()? {
-                            steps.push(next_step);
-                            ret = true;
-                        }
-                    }
+                    ret = back_off_lazy(steps, &limits, arena)?;
                     trace!(
                         6,
                         "back off Path lazy: {:?}, steps was {}, now{}: {}",
@@ -262,7 +410,7 @@ impl<'a> Path<'a> {
             match self {
                 Path::Chars(steps) => {
                     let _last_step = steps.pop().unwrap();
-                    ret = limits.check(steps.len()) == 0;
+                    ret = !steps.is_empty() && limits.reps_ok(steps.len() - 1);
                     trace!(
                         6,
                         "back off Path: {:?}, new step count {}: {}",
@@ -273,7 +421,7 @@ impl<'a> Path<'a> {
                 }
                 Path::Special(steps) => {
                     let _last_step = steps.pop().unwrap();
-                    ret = limits.check(steps.len()) == 0;
+                    ret = !steps.is_empty() && limits.reps_ok(steps.len() - 1);
                     trace!(
                         6,
                         "back off Path: {:?}, new step count {}: {}",
@@ -284,7 +432,7 @@ impl<'a> Path<'a> {
                 }
                 Path::Range(steps) => {
                     let _last_step = steps.pop().unwrap();
-                    ret = limits.check(steps.len()) == 0;
+                    ret = !steps.is_empty() && limits.reps_ok(steps.len() - 1);
                     trace!(
                         6,
                         "back off Path: {:?}, new step count {}: {}",
@@ -296,11 +444,12 @@ impl<'a> Path<'a> {
                 Path::And(steps) => {
                     let mut last_step = steps.pop().unwrap();
                     let len0 = steps.len();
-                    if last_step.back_off()? {
+                    if last_step.back_off(arena)? {
                         ret = true;
                         steps.push(last_step);
                     } else {
-                        ret = limits.check(steps.len()) == 0;
+                        arena.recycle_and_step(last_step);
+                        ret = !steps.is_empty() && limits.reps_ok(steps.len() - 1);
                     }
                     trace!(
                         6,
@@ -314,11 +463,12 @@ impl<'a> Path<'a> {
                 Path::Or(steps) => {
                     let len0 = steps.len();
                     let mut last_step = steps.pop().unwrap();
-                    if last_step.back_off()? {
+                    if last_step.back_off(arena)? {
                         ret = true;
                         steps.push(last_step);
                     } else {
-                        ret = limits.check(steps.len()) == 0;
+                        arena.recycle_box(last_step.child_path);
+                        ret = !steps.is_empty() && limits.reps_ok(steps.len() - 1);
                     }
                     trace!(
                         6,
@@ -338,151 +488,85 @@ impl<'a> Path<'a> {
     /// recursively creates **Report** objects for a path. For the branches (And and Or) this means recording itself
     /// and then collecting from the children recursively. leaves just need to record themselves
     pub fn gather_reports(&'a self) -> Vec<Report> {
-        let mut reports = Vec::<Report>::new();
         match self {
-            Path::And(steps) => {
-                // The first step represents 0 matches, it should be skipped if there are any others
-                for step in steps.iter().skip(if steps.len() > 1 { 1 } else { 0 }) {
-                    let mut subreport = step.make_report();
-                    if subreport.name.is_none() {
-                        reports.append(&mut subreport.subreports);
-                    } else {
-                        reports.push(subreport);
-                    }
-                }
-                if steps[0].node.name_outside {
-                    let mut matched = steps[0].matched;
-                    matched.end = steps.last().unwrap().matched.end;
-                    let mut subreports = Vec::new();
-                    reports
-                        .into_iter()
-                        .rev()
-                        .for_each(|mut subs| subreports.append(&mut subs.subreports));
-                    reports = vec![Report {
-                        matched,
-                        name: steps[0].node.named.clone(),
-                        subreports,
-                    }];
-                };
-            }
-            Path::Or(steps) => {
-                for step in steps.iter().skip(if steps.len() > 1 { 1 } else { 0 }) {
-                    let mut subreport = step.make_report();
-                    if subreport.name.is_none() {
-                        reports.append(&mut subreport.subreports);
-                    } else {
-                        reports.push(subreport);
-                    }
-                }
-                if steps[0].node.name_outside {
-                    let mut matched = steps[0].matched;
-                    matched.end = steps.last().unwrap().matched.end;
-                    let mut subreports = Vec::new();
-                    reports
-                        .into_iter()
-                        .rev()
-                        .for_each(|mut subs| subreports.append(&mut subs.subreports));
-                    reports = vec![Report {
-                        matched,
-                        name: steps[0].node.named.clone(),
-                        subreports,
-                    }];
-                };
-            }
-            Path::Chars(steps) => {
-                for step in steps.iter().skip(if steps.len() > 1 { 1 } else { 0 }) {
-                    let subreport = step.make_report();
-                    if subreport.name.is_some() {
-                        reports.push(subreport);
-                    }
-                }
-                if steps[0].node.name_outside {
-                    let mut matched = steps[0].matched;
-                    matched.end = steps.last().unwrap().matched.end;
-                    let mut subreports = Vec::new();
-                    reports
-                        .into_iter()
-                        .rev()
-                        .for_each(|mut subs| subreports.append(&mut subs.subreports));
-                    reports = vec![Report {
-                        matched,
-                        name: steps[0].node.named.clone(),
-                        subreports,
-                    }];
-                }
-            }
-            Path::Special(steps) => {
-                for step in steps.iter().skip(if steps.len() > 1 { 1 } else { 0 }) {
-                    let subreport = step.make_report();
-                    if subreport.name.is_some() {
-                        reports.push(subreport);
-                    }
-                }
-                if steps[0].node.name_outside {
-                    let mut matched = steps[0].matched;
-                    matched.end = steps.last().unwrap().matched.end;
-                    let mut subreports = Vec::new();
-                    reports
-                        .into_iter()
-                        .rev()
-                        .for_each(|mut subs| subreports.append(&mut subs.subreports));
-                    reports = vec![Report {
-                        matched,
-                        name: steps[0].node.named.clone(),
-                        subreports,
-                    }];
-                };
-            }
-            Path::Range(steps) => {
-                for step in steps.iter().skip(if steps.len() > 1 { 1 } else { 0 }) {
-                    let subreport = step.make_report();
-                    if subreport.name.is_some() {
-                        reports.push(subreport);
-                    }
-                }
-                if steps[0].node.name_outside {
-                    let mut matched = steps[0].matched;
-                    matched.end = steps.last().unwrap().matched.end;
-                    let mut subreports = Vec::new();
-                    reports
-                        .into_iter()
-                        .rev()
-                        .for_each(|mut subs| subreports.append(&mut subs.subreports));
-                    reports = vec![Report {
-                        matched,
-                        name: steps[0].node.named.clone(),
-                        subreports,
-                    }];
-                };
-            }
-            Path::None => panic!("Should not be any None path when compiling report"),
+            Path::And(steps) => gather_steps(steps, steps[0].node.limits.max),
+            Path::Or(steps) => gather_steps(steps, steps[0].node.limits.max),
+            Path::Chars(steps) => gather_steps(steps, steps[0].node.limits.max),
+            Path::Special(steps) => gather_steps(steps, steps[0].node.limits.max),
+            Path::Range(steps) => gather_steps(steps, steps[0].node.limits.max),
+            // the child of the zero-repetition step of an OR, which has nothing to report
+            Path::None => Vec::new(),
         }
-        reports
     }
 
     /// pretty prints a report using indentation to show inclusion
-    pub fn dump(&self, mut indent: usize) {
+    pub fn dump(&self, indent: usize) {
+        let _ = self.dump_to(&mut std::io::stdout(), indent);
+    }
+
+    /// Like **dump()**, but writes to W instead of stdout
+    pub fn dump_to<W: Write + ?Sized>(&self, w: &mut W, indent: usize) -> std::io::Result<()> {
+        Input::apply_mut(|input| self.dump_with(w, indent, input))
+    }
+
+    /// does the work for **dump()**, taking the text from INPUT so the whole dump only looks at **Input** once
+    fn dump_with<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        mut indent: usize,
+        input: &Input,
+    ) -> std::io::Result<()> {
         if indent == 0 {
-            trace_indent();
-            println!("PATH {} ------------", self.node_type());
+            writeln!(
+                w,
+                "{0:1$}PATH {2} ------------",
+                "",
+                trace_get_indent(),
+                self.node_type()
+            )?;
             indent = 1;
         }
         match self {
             //Path::Chars(steps) => { for i in 0..steps.len() {steps[i].dump(i, indent)}},
-            Path::Chars(steps) => steps.iter().enumerate().for_each(|x| x.1.dump(x.0, indent)),
-            Path::Special(steps) => steps.iter().enumerate().for_each(|x| x.1.dump(x.0, indent)),
-            Path::Range(steps) => steps.iter().enumerate().for_each(|x| x.1.dump(x.0, indent)),
-            Path::And(steps) => steps.iter().enumerate().for_each(|x| x.1.dump(x.0, indent)),
-            Path::Or(steps) => steps.iter().enumerate().for_each(|x| x.1.dump(x.0, indent)),
-            Path::None => {
-                trace_indent();
-                println!("|{0:1$}0: NONE \"\"", "", 4 * indent,)
-            }
+            Path::Chars(steps) => steps
+                .iter()
+                .enumerate()
+                .try_for_each(|x| x.1.dump(w, x.0, indent, input))?,
+            Path::Special(steps) => steps
+                .iter()
+                .enumerate()
+                .try_for_each(|x| x.1.dump(w, x.0, indent, input))?,
+            Path::Range(steps) => steps
+                .iter()
+                .enumerate()
+                .try_for_each(|x| x.1.dump(w, x.0, indent, input))?,
+            Path::And(steps) => steps
+                .iter()
+                .enumerate()
+                .try_for_each(|x| x.1.dump(w, x.0, indent, input))?,
+            Path::Or(steps) => steps
+                .iter()
+                .enumerate()
+                .try_for_each(|x| x.1.dump(w, x.0, indent, input))?,
+            Path::None => writeln!(
+                w,
+                "{0:1$}|{2:3$}0: NONE \"\"",
+                "",
+                trace_get_indent(),
+                "",
+                4 * indent
+            )?,
         }
         if indent == 1 {
-            trace_indent();
-            println!("PATH {} ------------", self.node_type());
+            writeln!(
+                w,
+                "{0:1$}PATH {2} ------------",
+                "",
+                trace_get_indent(),
+                self.node_type()
+            )?;
         }
+        Ok(())
     }
     pub fn node_type(&self) -> &str {
         match self {
@@ -520,7 +604,55 @@ impl<'a> Path<'a> {
             &input.full_text[from..to]
         }
     }
-    
+
+    /// The ellipsis put where **match_context()** cuts the text off
+    pub const ELLIPSIS: &'static str = "\u{2026}";
+
+    /// Returns the match along with up to CHARS characters of the text on each side of it, for when the lines
+    /// containing it are too long to show. The text before and after is returned separately so the match can be
+    /// highlighted, and has **ELLIPSIS** where it is cut off. In bytes mode the text is made printable (see
+    /// **Input::display_text()**). Like **match_display()** it must be called from within an Input::apply() closure,
+    /// so the text after the match is only what has already been read.
+    pub fn match_context(&self, input: &Input, chars: usize) -> (String, String, String) {
+        let (from, to) = self.range();
+        let text = &input.full_text;
+        let start = if chars == 0 {
+            from
+        } else {
+            text[..from]
+                .char_indices()
+                .rev()
+                .nth(chars - 1)
+                .map_or(0, |(pos, _)| pos)
+        };
+        let end = text[to..]
+            .char_indices()
+            .nth(chars)
+            .map_or(text.len(), |(pos, _)| to + pos);
+        let more = end < text.len() || input.more_input;
+        (
+            format!(
+                "{}{}",
+                if start > 0 { Path::ELLIPSIS } else { "" },
+                Input::display_text(&text[start..from])
+            ),
+            Input::display_text(&text[from..to]).into_owned(),
+            format!(
+                "{}{}",
+                Input::display_text(&text[to..end]),
+                if more { Path::ELLIPSIS } else { "" }
+            ),
+        )
+    }
+}
+
+/// Shows the path the way **dump()** prints it
+impl std::fmt::Display for Path<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = Vec::new();
+        self.dump_to(&mut out, 0).map_err(|_| std::fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
 }
 
 // I think new steps cannot go backwards, so if any step but step 0 has a series of matches of length 0 (maybe even
@@ -536,12 +668,504 @@ fn loop_check(&matched: &Matched, limits: &Limits) -> Result<(), Error> {
     }
 }
 
+/// Options that control how a walk runs. Like **Input** they are global, and apply to every walk until they are
+/// changed with **set_walk_options()**. They are built up with the builder methods:
+///
+/// > set_walk_options(WalkOptions::new().cancel(flag.clone()));
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    /// if set, the walk is abandoned with error 220 when the flag becomes true
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// if set, each call to **walk_tree()** is abandoned with error 221 when it runs longer than this
+    pub timeout: Option<Duration>,
+    /// if set, no match can be longer than this many bytes. Steps that would go past it fail, so the walk backs off
+    /// or moves on looking for a shorter match
+    pub max_match_len: Option<usize>,
+    /// if set, the walk tries every start position instead of scanning ahead for the places a match can start
+    /// (see **scan.rs**), or stopping after the first failed one when the RE starts with ".*"
+    pub no_scan: bool,
+    /// if set, OR branches are walked again from where they already failed instead of being skipped
+    pub no_or_memo: bool,
+    /// if set, input is read in blocks of about this many bytes (see **Input::set_block_size()**)
+    pub read_block: Option<usize>,
+    /// if set, the walk is abandoned with error 230 when reading more input would make the text held in memory
+    /// longer than this many bytes
+    pub max_buffer: Option<usize>,
+    /// functions called with each match of a named unit after a successful walk
+    pub on_group: GroupCallbacks,
+    /// if set, the input is searched as bytes instead of UTF-8 text (see **Input::set_bytes_mode()**)
+    pub bytes: bool,
+    /// if set, matches must start before this byte position, and the edge policy says whether they can go past it
+    pub window: Option<(usize, WindowEdge)>,
+    /// if set, each step checks that its match ends on a char boundary, and the walk fails with error 231 if one
+    /// does not
+    pub check_boundaries: bool,
+}
+
+/// What happens to a match that starts inside the window set by **WalkOptions::window()** but would run past its end
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowEdge {
+    /// the match goes on past the end of the window as if it were not there, and '$' only matches at the end of the
+    /// text. Each match is found whole, but windows searched separately can find overlapping matches.
+    Extend,
+    /// the end of the window is treated as the end of the text: a match is cut back to fit inside it, or rejected if
+    /// it cannot be, and '$' matches there
+    Clip,
+}
+
+/// A function called with the position and text of a match of a named unit (see **WalkOptions::on_group()**)
+pub type GroupCallback = Arc<dyn Fn(&Matched, &str) + Send + Sync>;
+
+/// The callbacks registered with **WalkOptions::on_group()**, with the names they are for
+#[derive(Clone, Default)]
+pub struct GroupCallbacks(Vec<(String, GroupCallback)>);
+
+impl Debug for GroupCallbacks {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl WalkOptions {
+    /// constructor, with nothing set
+    pub fn new() -> WalkOptions {
+        WalkOptions::default()
+    }
+
+    /// sets a flag that another thread (or a signal handler) can set to stop the walk
+    pub fn cancel(mut self, flag: Arc<AtomicBool>) -> WalkOptions {
+        self.cancel = Some(flag);
+        self
+    }
+
+    /// sets the longest time a call to **walk_tree()** can take
+    pub fn timeout(mut self, timeout: Duration) -> WalkOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// sets the longest match, in bytes, that a walk will return
+    pub fn max_match_len(mut self, len: usize) -> WalkOptions {
+        self.max_match_len = Some(len);
+        self
+    }
+
+    /// turns off scanning ahead for the places a match can start
+    pub fn no_scan(mut self) -> WalkOptions {
+        self.no_scan = true;
+        self
+    }
+
+    /// turns off remembering the OR branches that failed
+    pub fn no_or_memo(mut self) -> WalkOptions {
+        self.no_or_memo = true;
+        self
+    }
+
+    /// sets the size of the blocks input is read in. Unlike the other options this is applied to **Input** when the
+    /// options are set, and stays after they are replaced.
+    pub fn read_block(mut self, bytes: usize) -> WalkOptions {
+        self.read_block = Some(bytes);
+        self
+    }
+
+    /// sets the most text, in bytes, that **Input** can hold. All the text read is kept until a new input is set up,
+    /// since an unfinished walk could still back up to any of it, so a search over a long stream with no match goes
+    /// over the limit once that much has been read.
+    pub fn max_buffer(mut self, bytes: usize) -> WalkOptions {
+        self.max_buffer = Some(bytes);
+        self
+    }
+
+    /// adds a function to be called with each match of a unit named NAME. After each successful walk the callbacks
+    /// are called for the named units in the order they start in the text, so the matches can be processed without
+    /// going through a **Report**. They are called after the walk is over, so they can look at **Input**.
+    pub fn on_group(
+        mut self,
+        name: &str,
+        callback: impl Fn(&Matched, &str) + Send + Sync + 'static,
+    ) -> WalkOptions {
+        self.on_group.0.push((name.to_string(), Arc::new(callback)));
+        self
+    }
+
+    /// searches the input as bytes, so text that is not valid UTF-8 can be searched. Like **read_block()** this is
+    /// applied to **Input** when the options are set, but it is set every time, so options without it turn it off.
+    pub fn bytes(mut self) -> WalkOptions {
+        self.bytes = true;
+        self
+    }
+
+    /// limits the walk to matches that start before byte END, so a slice of the input can be searched by walking
+    /// from its start without copying it out. EDGE says what happens to a match that would run past END.
+    pub fn window(mut self, end: usize, edge: WindowEdge) -> WalkOptions {
+        self.window = Some((end, edge));
+        self
+    }
+
+    /// makes each step check that its match ends on a char boundary. A step that does not, which can only come from
+    /// a bug in matching a node, fails the walk with error 231 naming the node, instead of a panic later when the
+    /// match is printed. Debug builds always check, and panic if this is not set.
+    pub fn check_boundaries(mut self) -> WalkOptions {
+        self.check_boundaries = true;
+        self
+    }
+}
+
+/// calls the callbacks registered with **WalkOptions::on_group()** for the named units in the PATH of a successful
+/// walk, in the order they start in the text
+fn call_group_callbacks(path: &Path) {
+    let callbacks = WALK_OPTIONS.lock().unwrap().on_group.clone();
+    if callbacks.0.is_empty() {
+        return;
+    }
+    let mut calls = Vec::new();
+    Report::new(path).visit(|report, _| {
+        if let Some(name) = &report.name {
+            for (_, callback) in callbacks.0.iter().filter(|(key, _)| key == name) {
+                calls.push((callback.clone(), report.matched));
+            }
+        }
+    });
+    // a unit named outside a repetition can list what it contains out of order
+    calls.sort_by_key(|(_, matched)| matched.start);
+    for (callback, matched) in calls {
+        let text = Input::with_text(|text| text[matched.start..matched.end].to_string());
+        callback(&matched, &text);
+    }
+}
+
+/// The options used by the walk
+static WALK_OPTIONS: Lazy<Mutex<WalkOptions>> = Lazy::new(|| Mutex::new(WalkOptions::default()));
+
+/// Sets the options for the walks that follow, returning the previous options
+pub fn set_walk_options(options: WalkOptions) -> WalkOptions {
+    if let Some(bytes) = options.read_block {
+        Input::set_block_size(bytes);
+    }
+    Input::set_bytes_mode(options.bytes);
+    std::mem::replace(&mut *WALK_OPTIONS.lock().unwrap(), options)
+}
+
+/// the number of calls to **walk_check()** between looks at the options, so checking costs almost nothing
+const WALK_CHECK_INTERVAL: usize = 256;
+
+/// counts calls to **walk_check()**
+static WALK_CHECKS: AtomicUsize = AtomicUsize::new(0);
+
+/// the position in the text no step can go past, set from **max_match_len** for each start position
+static MATCH_END_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// sets the end limit for a walk from **start**, given the maximum match length
+fn set_match_end_limit(start: usize, max_match_len: Option<usize>) {
+    MATCH_END_LIMIT.store(
+        max_match_len.map_or(usize::MAX, |len| start.saturating_add(len)),
+        Release,
+    );
+}
+
+/// set from **WalkOptions::check_boundaries()** for each search
+static CHECK_BOUNDARIES: AtomicBool = AtomicBool::new(false);
+
+/// turns the check made by **boundary_ok()** on or off, as each search does from its **WalkOptions**
+pub(crate) fn set_check_boundaries(check: bool) {
+    CHECK_BOUNDARIES.store(check, Release);
+}
+
+/// the first step in the current walk found not ending on a char boundary, see **boundary_ok()**
+static BAD_BOUNDARY: Mutex<Option<Error>> = Mutex::new(None);
+
+/// With **WalkOptions::check_boundaries()** set, checks that the match of STEP ends on a char boundary. If it does
+/// not the step fails, and the error is kept to end the walk with. Without the option the match is trusted.
+pub(crate) fn boundary_ok<'a>(step: &impl Walker<'a>) -> bool {
+    if !CHECK_BOUNDARIES.load(Acquire) {
+        return true;
+    }
+    let matched = step.get_matched();
+    if Input::apply(|input| matched.validate(input)) {
+        return true;
+    }
+    BAD_BOUNDARY.lock().unwrap().get_or_insert_with(|| {
+        Error::make(
+            231,
+            &format!(
+                "Search abandoned: the match of {} ends at byte {}, inside a character",
+                step.node_desc().trim_end(),
+                matched.end
+            ),
+        )
+    });
+    false
+}
+
+/// fails if a step in the walk was found not to end on a char boundary
+pub(crate) fn boundary_check() -> Result<(), Error> {
+    match BAD_BOUNDARY.lock().unwrap().take() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// true if a step's match goes past the end limit, so the step fails
+fn past_match_end_limit(matched: &Matched) -> bool {
+    matched.end > MATCH_END_LIMIT.load(Acquire)
+}
+
+/// the end of the text the current walk can see, set by **walk_tree_until()**. Unlike **MATCH_END_LIMIT** the walk
+/// treats this as the end of the text, so '$' matches here.
+static WALK_TEXT_END: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// the end of the part of TEXT the walk can see
+fn walk_text_end(text: &str) -> usize {
+    WALK_TEXT_END.load(Acquire).min(text.len())
+}
+
+/// the time the current walk has to finish by, if there is a timeout
+static WALK_DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// starts the clock for the timeout, called at the start of **walk_tree()**
+fn start_walk_clock() {
+    let timeout = WALK_OPTIONS.lock().unwrap().timeout;
+    *WALK_DEADLINE.lock().unwrap() =
+        timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+}
+
+/// Called regularly during the walk (each step, each back off, and each new starting position) to see
+/// if the walk should be abandoned. The options are only looked at every **WALK_CHECK_INTERVAL** calls.
+fn walk_check() -> Result<(), Error> {
+    buffer_check()?;
+    if !WALK_CHECKS
+        .fetch_add(1, AcqRel)
+        .is_multiple_of(WALK_CHECK_INTERVAL)
+    {
+        return Ok(());
+    }
+    let options = WALK_OPTIONS.lock().unwrap();
+    if let Some(cancel) = &options.cancel {
+        if cancel.load(Acquire) {
+            return Err(Error::make(220, "Search cancelled"));
+        }
+    }
+    if let Some(deadline) = *WALK_DEADLINE.lock().unwrap() {
+        if Instant::now() >= deadline {
+            return Err(Error::make(221, "Search timed out"));
+        }
+    }
+    Ok(())
+}
+
+/// set when **Input** stops reading because the text would go over **WalkOptions::max_buffer**, cleared when a new
+/// input is set up
+static BUFFER_FULL: AtomicBool = AtomicBool::new(false);
+
+/// the largest the text in **Input** has been since the input was set up
+static PEAK_BUFFERED: AtomicUsize = AtomicUsize::new(0);
+
+/// fails if **Input** has stopped reading because its buffer is full. Since the walk cannot tell whether a match
+/// would have been found in the text that was not read, it has to give up.
+fn buffer_check() -> Result<(), Error> {
+    if !BUFFER_FULL.load(Acquire) {
+        return Ok(());
+    }
+    let max = WALK_OPTIONS
+        .lock()
+        .unwrap()
+        .max_buffer
+        .unwrap_or(usize::MAX);
+    Err(Error::make(
+        230,
+        format!(
+            "Search abandoned: the input is longer than the buffer limit of {} bytes",
+            max
+        )
+        .as_str(),
+    ))
+}
+
+/// The number of steps tried since the count was last reset. This gives a rough measure of how much work a search
+/// takes, which is useful for comparing different ways of writing (or optimizing) a RE
+static STEP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// called each time a step is tried
+fn count_step() {
+    STEP_COUNT.fetch_add(1, AcqRel);
+}
+
+/// Gets the number of steps tried since the last call to **reset_step_count()**
+pub fn step_count() -> usize {
+    STEP_COUNT.load(Acquire)
+}
+
+/// The number of times a **Path** has been backed off since the count was last reset
+static BACKTRACK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// called each time a path is backed off
+fn count_backtrack() {
+    BACKTRACK_COUNT.fetch_add(1, AcqRel);
+}
+
+/// Gets the number of backtracks since the last call to **reset_step_count()**
+pub fn backtrack_count() -> usize {
+    BACKTRACK_COUNT.load(Acquire)
+}
+
+/// The number of positions in the text a walk from the root has started from since the count was last reset
+static START_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// called each time **walk_tree()** tries a new start position
+fn count_start() {
+    START_COUNT.fetch_add(1, AcqRel);
+}
+
+/// Gets the number of start positions tried since the last call to **reset_step_count()**
+pub fn start_count() -> usize {
+    START_COUNT.load(Acquire)
+}
+
+/// The number of vectors and boxes the walk has had to allocate since the count was last reset. Ones reused from
+/// a **WalkArena** are not counted.
+static ALLOCATION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// called each time a **WalkArena** has nothing to reuse
+fn count_allocation() {
+    ALLOCATION_COUNT.fetch_add(1, AcqRel);
+}
+
+/// Gets the number of allocations since the last call to **reset_step_count()**
+pub fn allocation_count() -> usize {
+    ALLOCATION_COUNT.load(Acquire)
+}
+
+/// The number of calls to **Input::apply()**, **Input::apply_mut()**, and **Input::with_text()** since the count
+/// was last reset
+static APPLY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Gets the number of times **Input** was applied since the last call to **reset_step_count()**
+pub fn apply_count() -> usize {
+    APPLY_COUNT.load(Acquire)
+}
+
+/// Resets the step, backtrack, start, allocation, and apply counts to 0
+pub fn reset_step_count() {
+    STEP_COUNT.store(0, Release);
+    BACKTRACK_COUNT.store(0, Release);
+    START_COUNT.store(0, Release);
+    ALLOCATION_COUNT.store(0, Release);
+    APPLY_COUNT.store(0, Release);
+}
+
+/// The steps of a repeated **AND** or **OR**, which have their own paths to back off
+trait RepeatStep<'a>: Sized {
+    /// backs off the paths inside the step, see **AndStep::back_off()**
+    fn back_off(&mut self, arena: &mut WalkArena<'a>) -> Result<bool, Error>;
+    /// takes another repetition starting where this one ends
+    fn step(&mut self, arena: &mut WalkArena<'a>) -> Result<Option<Self>, Error>;
+    /// gives the step's paths back to ARENA when it is dropped
+    fn recycle(self, arena: &mut WalkArena<'a>);
+}
+
+impl<'a> RepeatStep<'a> for AndStep<'a> {
+    fn back_off(&mut self, arena: &mut WalkArena<'a>) -> Result<bool, Error> {
+        AndStep::back_off(self, arena)
+    }
+    fn step(&mut self, arena: &mut WalkArena<'a>) -> Result<Option<Self>, Error> {
+        AndStep::step(self, arena)
+    }
+    fn recycle(self, arena: &mut WalkArena<'a>) {
+        arena.recycle_and_step(self);
+    }
+}
+
+impl<'a> RepeatStep<'a> for OrStep<'a> {
+    fn back_off(&mut self, arena: &mut WalkArena<'a>) -> Result<bool, Error> {
+        OrStep::back_off(self, arena)
+    }
+    fn step(&mut self, arena: &mut WalkArena<'a>) -> Result<Option<Self>, Error> {
+        OrStep::step(self, arena)
+    }
+    fn recycle(self, arena: &mut WalkArena<'a>) {
+        arena.recycle_box(self.child_path);
+    }
+}
+
+/// Backs off the STEPS of a lazy **AND** or **OR** path. Each state of the path has already been tried with as few
+/// repetitions as it has, so first another repetition is added. If none can be, the last repetition is backed off,
+/// or dropped and the one before it backed off, and then repetitions are added again up to the minimum.
+fn back_off_lazy<'a, T: RepeatStep<'a>>(
+    steps: &mut Vec<T>,
+    limits: &Limits,
+    arena: &mut WalkArena<'a>,
+) -> Result<bool, Error> {
+    if limits.can_add_rep(steps.len() - 1) {
+        if let Some(next_step) = steps.last_mut().unwrap().step(arena)? {
+            steps.push(next_step);
+            return Ok(true);
+        }
+    }
+    // the first step is the one for no repetitions, which has nothing to back off
+    while steps.len() > 1 {
+        let mut last_step = steps.pop().unwrap();
+        if !last_step.back_off(arena)? {
+            last_step.recycle(arena);
+            continue;
+        }
+        steps.push(last_step);
+        while !limits.reps_ok(steps.len() - 1) {
+            match steps.last_mut().unwrap().step(arena)? {
+                Some(next_step) => steps.push(next_step),
+                None => break,
+            }
+        }
+        if limits.reps_ok(steps.len() - 1) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 /// Experimental: I want to use this to simplify the **impl Path ** code. It is begun but not implemented yet
 ///
-trait Walker<'a> {
+pub(crate) trait Walker<'a> {
     fn make_report(&'a self) -> Report;
     fn name_details(&self) -> (&Option<String>, bool);
     fn get_matched(&self) -> Matched;
+    fn node_desc(&self) -> String;
+}
+
+/// Makes the reports for the STEPS of a node repeated at most MAX times, which are the same for every kind of node.
+/// The first step represents 0 matches, so it is skipped if there are any others. Each repetition of a named node
+/// gets its own report, in the order they matched, and an unnamed one passes on what its children report. If the
+/// name is outside the repetition there is instead a single report for all of them, holding what each repetition
+/// reports in the order they matched. It has no branch, since each repetition of an OR can match a different one.
+fn gather_steps<'a, T: Walker<'a>>(steps: &'a [T], max: usize) -> Vec<Report> {
+    let mut reports = Vec::<Report>::new();
+    for step in steps.iter().skip(if steps.len() > 1 { 1 } else { 0 }) {
+        let mut subreport = step.make_report();
+        if subreport.name.is_none() {
+            reports.append(&mut subreport.subreports);
+        } else {
+            reports.push(subreport);
+        }
+    }
+    let (named, name_outside) = steps[0].name_details();
+    reports = Report::select_repeated(reports, max, name_outside);
+    if !name_outside {
+        return reports;
+    }
+    let mut matched = steps[0].get_matched();
+    matched.end = steps.last().unwrap().get_matched().end;
+    let mut subreports = Vec::new();
+    reports
+        .into_iter()
+        .for_each(|mut subs| subreports.append(&mut subs.subreports));
+    vec![Report {
+        matched,
+        name: named.clone(),
+        subreports,
+        branch: None,
+    }]
 }
 
 /// Trace Levels
@@ -551,20 +1175,44 @@ trait Walker<'a> {
 /// level 4: trace start and  end of walks and each new child in an AND
 /// level 10: dump out paths as they are extended
 /// prints message when entering walk (trace level 2)
-fn trace_start_walk<T: Debug>(vec: &[T]) {
+fn trace_start_walk<'a, T: Debug + Walker<'a>>(vec: &[T]) {
+    if json_tracing() {
+        let matched = vec[0].get_matched();
+        trace_event(TraceEvent::make(
+            TraceKind::WalkStart,
+            vec[0].node_desc(),
+            (matched.start, matched.end),
+        ));
+    }
     trace!(2, "Start walk for {:?}", &vec[0]);
     trace_change_indent!(2, 1);
 }
 
 /// prints message when finishing walk (trace level 3)
 fn trace_end_walk(path: Path) -> Path {
+    // checked first so a quiet walk does not build any strings or look at the text
+    if !json_tracing() && !trace_level(3) {
+        trace_change_indent!(2, -1);
+        return path;
+    }
+    if json_tracing() {
+        let mut event = TraceEvent::make(TraceKind::WalkEnd, path.node_desc(), path.range());
+        event.reps = Some(path.len() - 1);
+        event.matched = Some(path.reps_ok());
+        if let Path::Or(steps) = &path {
+            if event.matched == Some(true) && steps.len() > 1 {
+                event.branch = Some(steps.last().unwrap().which);
+            }
+        }
+        trace_event(event);
+    }
     trace_change_indent!(2, -1);
     trace!(
         3,
         "End walk for {:?}, {} steps, {}",
         path,
         path.len() - 1,
-        if path.limits().check(path.len()) == 0 {
+        if path.reps_ok() {
             format!("matches \"{}\"", path.matched_string())
         } else {
             "no match".to_string()
@@ -577,10 +1225,33 @@ fn trace_end_walk(path: Path) -> Path {
 }
 
 /// prints message when adding a Step to a Path (trace level 4)
-fn trace_pushing<T: Debug>(obj: &T, len: usize) {
+fn trace_pushing<'a, T: Debug + Walker<'a>>(obj: &T, len: usize) {
+    if json_tracing() {
+        let matched = obj.get_matched();
+        let mut event = TraceEvent::make(
+            TraceKind::Push,
+            obj.node_desc(),
+            (matched.start, matched.end),
+        );
+        event.reps = Some(len - 1);
+        trace_event(event);
+    }
     trace!(4, "Pushing {:?} rep {}", obj, len - 1);
 }
 
+/// writes a JSON event when an OR step moves on to its next branch
+fn trace_or_branch(step: &OrStep) {
+    if json_tracing() && step.which < step.node.nodes.len() {
+        let mut event = TraceEvent::make(
+            TraceKind::OrBranch,
+            step.node_desc(),
+            (step.matched.start, step.matched.end),
+        );
+        event.branch = Some(step.which);
+        trace_event(event);
+    }
+}
+
 //////////////////////////////////////////////////////////////////
 //
 // Debug implementations: used for tracing
@@ -606,6 +1277,19 @@ impl<'a> Debug for RangeStep<'a> {
 
 impl<'a> Debug for AndStep<'a> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{{{:?}}} state [{}], {:?}",
+            self.node,
+            self.child_counts(),
+            self.matched
+        )
+    }
+}
+
+impl<'a> AndStep<'a> {
+    /// the number of steps in each child path, with "-" for the children not walked yet
+    fn child_counts(&self) -> String {
         let mut child_counts: String = "".to_string();
         for p in self.child_paths.iter() {
             child_counts.push_str(&format!("{}, ", p.len()));
@@ -613,11 +1297,7 @@ impl<'a> Debug for AndStep<'a> {
         for _i in self.child_paths.len()..self.node.nodes.len() {
             child_counts.push_str("-, ");
         }
-        write!(
-            f,
-            "{{{:?}}} state [{}], {:?}",
-            self.node, child_counts, self.matched
-        )
+        child_counts
     }
 }
 
@@ -721,6 +1401,7 @@ impl<'a> Walker<'a> for CharsStep<'a> {
             matched: self.matched,
             name: self.node.named.clone(),
             subreports: Vec::<Report>::new(),
+            branch: None,
         }
     }
     fn name_details(&self) -> (&Option<String>, bool) {
@@ -729,15 +1410,24 @@ impl<'a> Walker<'a> for CharsStep<'a> {
     fn get_matched(&self) -> Matched {
         self.matched
     }
+    fn node_desc(&self) -> String {
+        format!("{:?}", self.node)
+    }
 }
 
 // Any way to make walk() generic?
 impl<'a> CharsStep<'a> {
     /// start a Path using a string of chars, matching as many times as it can subject to the matching algorithm (greedy or lazy)
-    pub fn walk(node: &'a CharsNode, matched: Matched) -> Result<Path<'a>, Error> {
-        let mut steps = vec![CharsStep { node, matched }];
+    pub fn walk(
+        node: &'a CharsNode,
+        matched: Matched,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<Path<'a>, Error> {
+        let mut steps = WalkArena::take(&mut arena.chars);
+        steps.push(CharsStep { node, matched });
         trace_start_walk(&steps);
         for _i in 1..=node.limits.initial_walk_limit() {
+            walk_check()?;
             match steps.last().unwrap().step() {
                 Some(s) => {
                     steps.push(s);
@@ -752,27 +1442,53 @@ impl<'a> CharsStep<'a> {
     // this 'a -------------------------V caused me real problems, and needed help from Stackoverflow to sort out
     /// try to take a single step over a string of regular characters
     fn step(&self) -> Option<CharsStep<'a>> {
+        count_step();
         let mut step = CharsStep {
             node: self.node,
             matched: self.matched.next(0),
         };
-        Input::extend_quiet(step.matched.start + self.node.string.len() + 1);
-        if step.matched.end == Input::len() {
-            return None;
-        }
-
-        if let Some(size) =
-            Input::apply(|input| step.node.matches(&input.full_text[step.matched.start..]))
-        {
+        // a caseless match can be longer than the string, up to 4 bytes for each char
+        let len = if self.node.limits.no_case() {
+            4 * self.node.string.chars().count()
+        } else {
+            self.node.string.len()
+        };
+        Input::extend_quiet(step.matched.start + len + 1);
+        if let Some(size) = Input::with_text(|text| {
+            let end = walk_text_end(text);
+            if step.matched.end == end {
+                None
+            } else {
+                step.node.matches(&text[step.matched.start..end])
+            }
+        }) {
             step.matched.move_end(size as isize);
+            if past_match_end_limit(&step.matched) || !boundary_ok(&step) {
+                return None;
+            }
             Some(step)
         } else {
             None
         }
     }
-    fn dump(&self, rank: usize, indent: usize) {
-        trace_indent();
-        println!("|{0:1$}{2}: {3:?}", "", 4 * indent, rank, self);
+    fn dump<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        rank: usize,
+        indent: usize,
+        input: &Input,
+    ) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "{0:1$}|{2:3$}{4}: {{{5:?}}}, {6}",
+            "",
+            trace_get_indent(),
+            "",
+            4 * indent,
+            rank,
+            self.node,
+            self.matched.display(input)
+        )
     }
 }
 
@@ -783,6 +1499,7 @@ impl<'a> Walker<'a> for SpecialStep<'a> {
             matched: self.matched,
             name: self.node.named.clone(),
             subreports: Vec::<Report>::new(),
+            branch: None,
         }
     }
     fn name_details(&self) -> (&Option<String>, bool) {
@@ -791,14 +1508,23 @@ impl<'a> Walker<'a> for SpecialStep<'a> {
     fn get_matched(&self) -> Matched {
         self.matched
     }
+    fn node_desc(&self) -> String {
+        format!("{:?}", self.node)
+    }
 }
 
 impl<'a> SpecialStep<'a> {
     /// start a Path using a string of chars, matching as many times as it can subject to the matching algorithm (greedy or lazy)
-    pub fn walk(node: &'a SpecialNode, matched: Matched) -> Result<Path<'a>, Error> {
-        let mut steps = vec![SpecialStep { node, matched }];
+    pub fn walk(
+        node: &'a SpecialNode,
+        matched: Matched,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<Path<'a>, Error> {
+        let mut steps = WalkArena::take(&mut arena.special);
+        steps.push(SpecialStep { node, matched });
         trace_start_walk(&steps);
         for _i in 1..=node.limits.initial_walk_limit() {
+            walk_check()?;
             match steps.last().unwrap().step() {
                 Some(s) => {
                     steps.push(s);
@@ -813,23 +1539,46 @@ impl<'a> SpecialStep<'a> {
     // this 'a -------------------------V caused me real problems, and needed help from Stackoverflow to sort out
     /// try to take a single step over a string of regular characters
     fn step(&self) -> Option<SpecialStep<'a>> {
+        count_step();
         let mut step = SpecialStep {
             node: self.node,
             matched: self.matched.next(0),
         };
-        if let Some(size) =
-            Input::apply(|input| step.node.matches(&input.full_text[step.matched.start..]))
-        {
+        // reads ahead if this is the end of the text read so far, so '$' only matches at the real end, and the string
+        // of an upto() is seen whole
+        Input::extend_quiet(step.matched.start + step.node.upto.as_ref().map_or(1, String::len));
+        if let Some(size) = Input::with_text(|text| {
+            step.node
+                .matches(&text[step.matched.start..walk_text_end(text)])
+        }) {
             step.matched.move_end(size as isize);
+            if past_match_end_limit(&step.matched) || !boundary_ok(&step) {
+                return None;
+            }
             Some(step)
         } else {
             None
         }
     }
 
-    fn dump(&self, rank: usize, indent: usize) {
-        trace_indent();
-        println!("|{0:1$}{2}: {3:?} ", "", 4 * indent, rank, self,);
+    fn dump<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        rank: usize,
+        indent: usize,
+        input: &Input,
+    ) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "{0:1$}|{2:3$}{4}: {{{5:?}}}, {6} ",
+            "",
+            trace_get_indent(),
+            "",
+            4 * indent,
+            rank,
+            self.node,
+            self.matched.display(input)
+        )
     }
 }
 
@@ -840,6 +1589,7 @@ impl<'a> Walker<'a> for RangeStep<'a> {
             matched: self.matched,
             name: self.node.named.clone(),
             subreports: Vec::<Report>::new(),
+            branch: None,
         }
     }
     fn name_details(&self) -> (&Option<String>, bool) {
@@ -848,14 +1598,23 @@ impl<'a> Walker<'a> for RangeStep<'a> {
     fn get_matched(&self) -> Matched {
         self.matched
     }
+    fn node_desc(&self) -> String {
+        format!("{:?}", self.node)
+    }
 }
 
 impl<'a> RangeStep<'a> {
     /// start a Path using a string of chars, matching as many times as it can subject to the matching algorithm (greedy or lazy)
-    pub fn walk(node: &'a RangeNode, matched: Matched) -> Result<Path<'a>, Error> {
-        let mut steps = vec![RangeStep { node, matched }];
+    pub fn walk(
+        node: &'a RangeNode,
+        matched: Matched,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<Path<'a>, Error> {
+        let mut steps = WalkArena::take(&mut arena.range);
+        steps.push(RangeStep { node, matched });
         trace_start_walk(&steps);
         for _i in 1..=node.limits.initial_walk_limit() {
+            walk_check()?;
             match steps.last().unwrap().step() {
                 Some(s) => {
                     steps.push(s);
@@ -870,27 +1629,48 @@ impl<'a> RangeStep<'a> {
     // this 'a -------------------------V caused me real problems, and needed help from Stackoverflow to sort out
     /// try to take a single step over a string of regular characters
     fn step(&self) -> Option<RangeStep<'a>> {
+        count_step();
         let mut step = RangeStep {
             node: self.node,
             matched: self.matched.next(0),
         };
-        if step.matched.end == Input::len() {
-            return None;
-        }
-
-        if let Some(size) =
-            Input::apply(|input| step.node.matches(&input.full_text[step.matched.start..]))
-        {
+        Input::extend_quiet(step.matched.start + 1);
+        if let Some(size) = Input::with_text(|text| {
+            let end = walk_text_end(text);
+            if step.matched.end == end {
+                None
+            } else {
+                step.node.matches(&text[step.matched.start..end])
+            }
+        }) {
             step.matched.move_end(size as isize);
+            if past_match_end_limit(&step.matched) || !boundary_ok(&step) {
+                return None;
+            }
             Some(step)
         } else {
             None
         }
     }
 
-    fn dump(&self, rank: usize, indent: usize) {
-        trace_indent();
-        println!("|{0:1$}{2}: {3:?}", "", 4 * indent, rank, self);
+    fn dump<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        rank: usize,
+        indent: usize,
+        input: &Input,
+    ) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "{0:1$}|{2:3$}{4}: {{{5:?}}}, {6}",
+            "",
+            trace_get_indent(),
+            "",
+            4 * indent,
+            rank,
+            self.node,
+            self.matched.display(input)
+        )
     }
 }
 
@@ -906,6 +1686,7 @@ impl<'a> Walker<'a> for AndStep<'a> {
             matched: self.matched,
             name: self.node.named.clone(),
             subreports: reports,
+            branch: None,
         }
     }
     fn name_details(&self) -> (&Option<String>, bool) {
@@ -914,25 +1695,33 @@ impl<'a> Walker<'a> for AndStep<'a> {
     fn get_matched(&self) -> Matched {
         self.matched
     }
+    fn node_desc(&self) -> String {
+        format!("{:?}", self.node)
+    }
 }
 
 impl<'a> AndStep<'a> {
     /// start a Path using an And node, matching as many times as it can subject to the matching algorithm (greedy or lazy)
-    pub fn walk(node: &'a AndNode, matched: Matched) -> Result<Path<'a>, Error> {
-        let mut steps = vec![AndStep {
+    pub fn walk(
+        node: &'a AndNode,
+        matched: Matched,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<Path<'a>, Error> {
+        let mut steps = WalkArena::take(&mut arena.and);
+        steps.push(AndStep {
             node,
             matched,
-            child_paths: Vec::<Path<'a>>::new(),
-        }];
+            child_paths: WalkArena::take(&mut arena.child_paths),
+        });
         trace_start_walk(&steps);
         for _i in 1..=node.limits.initial_walk_limit() {
             let len = steps.len();
             if len % 30 == 29 {
                 loop_check(&steps.last().unwrap().matched, &node.limits)?;
             }
-            match steps[len - 1].step()? {
+            match steps[len - 1].step(arena)? {
                 Some(s) => {
-                    trace_pushing::<AndStep>(&s, steps.len());
+                    trace_pushing::<AndStep>(&s, steps.len() + 1);
                     steps.push(s);
                 }
                 None => {
@@ -944,30 +1733,69 @@ impl<'a> AndStep<'a> {
     }
 
     /// try to take a single step matching an And node
-    fn step(&mut self) -> Result<Option<AndStep<'a>>, Error> {
+    fn step(&mut self, arena: &mut WalkArena<'a>) -> Result<Option<AndStep<'a>>, Error> {
+        count_step();
+        walk_check()?;
         let mut step = AndStep {
             node: self.node,
             matched: self.matched.next(0),
-            child_paths: Vec::<Path<'a>>::new(),
+            child_paths: WalkArena::take(&mut arena.child_paths),
         };
+        if !step.walk_children(arena)? {
+            arena.recycle_and_step(step);
+            return Ok(None);
+        }
+        Ok(Some(step))
+    }
+
+    /// Walks the children that do not have paths yet, one after the other, backing off the ones before when one
+    /// fails. Returns false if they cannot all match.
+    fn walk_children(&mut self, arena: &mut WalkArena<'a>) -> Result<bool, Error> {
         loop {
-            let child_len = step.child_paths.len();
-            if child_len == step.node.nodes.len() {
-                break; // all child nodes are satisfied, return success
+            let child_len = self.child_paths.len();
+            if child_len == self.node.nodes.len() {
+                return Ok(true); // all child nodes are satisfied, return success
             }
-            let child_path = step.node.nodes[child_len].walk(step.matched.next(0))?;
-            if child_path.limits().check(child_path.len()) == 0 {
-                step.child_paths.push(child_path);
+            let child = &self.node.nodes[child_len];
+            // a child that needs more text than is left cannot match, so it is not walked
+            let child_path = if Input::has_bytes(self.matched.end, child.min_len()) {
+                Some(child.walk(self.matched.next(0), arena)?)
+            } else {
+                trace!(5, "-- not enough text left for {:?}", child);
+                None
+            };
+            let child_path = match child_path {
+                Some(path) if !path.reps_ok() => {
+                    arena.recycle(path);
+                    None
+                }
+                path => path,
+            };
+            if let Some(child_path) = child_path {
+                self.child_paths.push(child_path);
                 // This could be done by removing the "else" below, but putting it here makes the trace up-to-date
-                step.matched.set_end(step.child_paths.last().unwrap().end());
-                trace!(5, "-- new child step in AND: {:?}", &step);
-            } else if !step.back_off()? {
-                return Ok(None);
+                self.matched.set_end(self.child_paths.last().unwrap().end());
+                trace!(5, "-- new child step in AND: {:?}", &self);
+            } else if !self.back_off_children(arena)? {
+                return Ok(false);
             } else {
-                step.matched.set_end(step.child_paths.last().unwrap().end());
+                self.matched.set_end(self.child_paths.last().unwrap().end());
             }
         }
-        Ok(Some(step))
+    }
+
+    /// Backs off the last child path that can be backed off, dropping the ones after it. Returns false if none can.
+    fn back_off_children(&mut self, arena: &mut WalkArena<'a>) -> Result<bool, Error> {
+        // This pops off the last child path. If the Path backs off it is restored, if not then it is already removed
+        while let Some(mut last_path) = self.child_paths.pop() {
+            let follower = self.node.nodes.get(self.child_paths.len() + 1);
+            if AndStep::back_off_child(&mut last_path, follower, arena)? {
+                self.child_paths.push(last_path);
+                return Ok(true);
+            }
+            arena.recycle(last_path);
+        }
+        Ok(false)
     }
 
     /// Back off a step after a failed match. It will back off repetitions until an untried one is found,
@@ -977,34 +1805,17 @@ impl<'a> AndStep<'a> {
     /// **back_off()** only touches the path - that is, the Vec(XXXStep). It can change Steps higher in the hierarchy
     /// or pop off the last step, but cannot change anything inside any of the *Step**s. **XXXStep::back_off()** ,
     /// on the other hand, should only change things within the current Step, that is
-    fn back_off(&mut self) -> Result<bool, Error> {
+    fn back_off(&mut self, arena: &mut WalkArena<'a>) -> Result<bool, Error> {
         trace!(6, "back off Node: {:?}", self);
         trace_change_indent!(6, 1);
-        let limits = self.node.limits;
-        let mut ret = true;
-        if limits.lazy() {
-            println!("TODO");
-            // TODO
-            ret = false;
+        // this is the same for lazy and greedy steps, which only differ in how the Path adds or removes steps
+        let ret = if self.back_off_children(arena)? {
+            // an earlier child backed off, so the ones after it have to be walked again from where it now ends
+            self.matched.set_end(self.child_paths.last().unwrap().end());
+            self.walk_children(arena)?
         } else {
-            loop {
-                // This pops off the last child path. If the Path backs off it is restored, if not then it is already removed
-                if let Some(mut last_path) = self.child_paths.pop() {
-                    if last_path.back_off()? {
-                        self.child_paths.push(last_path);
-                        break;
-                    }
-                } else {
-                    ret = false;
-                    break;
-                }
-                // backed off until reps are too few, discard
-                if limits.check(self.child_paths.len()) != 0 {
-                    ret = false;
-                    break;
-                }
-            }
-        }
+            false
+        };
         if ret {
             self.matched.set_end(self.child_paths.last().unwrap().end());
         }
@@ -1013,10 +1824,63 @@ impl<'a> AndStep<'a> {
         Ok(ret)
     }
 
-    pub fn dump(&self, rank: usize, indent: usize) {
-        trace_indent();
-        println!("|{0:1$}{2}: {3:?}", "", 4 * indent, rank, self,);
-        self.child_paths.iter().for_each(|x| x.dump(indent + 1));
+    /// Backs off PATH, a child of an And node. If it is a greedy char, special, or range path it keeps backing off
+    /// until FOLLOWER, the child after it, can start where it ends. A greedy ".*" walks to the end of the text, and
+    /// without this every place it backs off to would be tried by walking FOLLOWER there, even though one look at
+    /// the next char shows it cannot match. The result is the same, since FOLLOWER would fail at the places skipped.
+    fn back_off_child(
+        path: &mut Path<'a>,
+        follower: Option<&Node>,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<bool, Error> {
+        if !path.back_off(arena)? {
+            return Ok(false);
+        }
+        let follower = match follower {
+            Some(follower)
+                if matches!(path, Path::Chars(_) | Path::Special(_) | Path::Range(_))
+                    && !path.limits().lazy() =>
+            {
+                follower
+            }
+            _ => return Ok(true),
+        };
+        while !Input::with_text(|text| can_start(follower, text.get(path.end()..).unwrap_or(""))) {
+            trace!(
+                6,
+                "-- {:?} cannot start at {}, backing off",
+                follower,
+                path.end()
+            );
+            if !path.back_off(arena)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn dump<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        rank: usize,
+        indent: usize,
+        input: &Input,
+    ) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "{0:1$}|{2:3$}{4}: {{{5:?}}} state [{6}], {7}",
+            "",
+            trace_get_indent(),
+            "",
+            4 * indent,
+            rank,
+            self.node,
+            self.child_counts(),
+            self.matched.display(input)
+        )?;
+        self.child_paths
+            .iter()
+            .try_for_each(|x| x.dump_with(w, indent + 1, input))
     }
 }
 
@@ -1028,6 +1892,7 @@ impl<'a> Walker<'a> for OrStep<'a> {
             matched: self.matched,
             name: self.node.named.clone(),
             subreports,
+            branch: Some((self.which, self.node.nodes.len())),
         }
     }
     fn name_details(&self) -> (&Option<String>, bool) {
@@ -1036,26 +1901,34 @@ impl<'a> Walker<'a> for OrStep<'a> {
     fn get_matched(&self) -> Matched {
         self.matched
     }
+    fn node_desc(&self) -> String {
+        format!("{:?}", self.node)
+    }
 }
 
 /// OR does not have a *step()* function because it cannot have a repeat count (to repeat an OR it must be enclosed in an AND)
 impl<'a> OrStep<'a> {
     /// start a Path using an And node, matching as many times as it can subject to the matching algorithm (greedy or lazy)
-    pub fn walk(node: &'a OrNode, matched: Matched) -> Result<Path<'a>, Error> {
-        let mut steps = vec![OrStep {
+    pub fn walk(
+        node: &'a OrNode,
+        matched: Matched,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<Path<'a>, Error> {
+        let mut steps = WalkArena::take(&mut arena.or);
+        steps.push(OrStep {
             node,
             matched,
-            child_path: Box::new(Path::None),
+            child_path: arena.take_box(),
             which: 0,
-        }];
+        });
         trace_start_walk(&steps);
         for _i in 1..=node.limits.initial_walk_limit() {
-            if let Some(s) = steps.last().unwrap().step()? {
+            if let Some(s) = steps.last().unwrap().step(arena)? {
                 let len = steps.len();
                 if len % 30 == 29 {
                     loop_check(&s.matched, &node.limits)?;
                 }
-                trace_pushing::<OrStep>(&s, steps.len());
+                trace_pushing::<OrStep>(&s, steps.len() + 1);
                 steps.push(s);
             } else {
                 break;
@@ -1065,47 +1938,87 @@ impl<'a> OrStep<'a> {
     }
 
     /// try to take a single step matching an Or node
-    fn step(&self) -> Result<Option<OrStep<'a>>, Error> {
+    fn step(&self, arena: &mut WalkArena<'a>) -> Result<Option<OrStep<'a>>, Error> {
+        count_step();
+        walk_check()?;
         let mut step = OrStep {
             node: self.node,
             matched: self.matched.next(0),
             which: 0,
-            child_path: Box::new(Path::None),
+            child_path: arena.take_box(),
         };
         loop {
             if step.which == step.node.nodes.len() {
                 trace!(4, "OR step failed (exhausted)");
+                arena.recycle_box(step.child_path);
                 return Ok(None);
             }
-            step.child_path = Box::new(step.node.nodes[step.which].walk(self.matched.next(0))?);
-            if step.child_path.limits().check(step.child_path.len()) == 0 {
+            if let Some(child_path) =
+                OrStep::walk_branch(step.node, step.which, self.matched.next(0), arena)?
+            {
+                arena.recycle(std::mem::replace(&mut *step.child_path, child_path));
                 break;
             }
             step.which += 1;
+            trace_or_branch(&step);
         }
         trace!(6, "    new OR step: {:?}", step);
         step.matched.set_end(step.child_path.end());
         Ok(Some(step))
     }
 
-    fn back_off(&mut self) -> Result<bool, Error> {
+    /// Walks branch WHICH of NODE from MATCHED, returning the path if it matches. A branch that fails is remembered
+    /// (see **WalkArena::or_failures**), and is not walked from the same place again.
+    fn walk_branch(
+        node: &'a OrNode,
+        which: usize,
+        matched: Matched,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<Option<Path<'a>>, Error> {
+        let key = (node as *const OrNode as usize, which, matched.start);
+        if arena.remember_or_failures && arena.or_failures.contains(&key) {
+            trace!(
+                4,
+                "-- OR branch {} already failed at {}",
+                which + 1,
+                matched.start
+            );
+            return Ok(None);
+        }
+        let path = node.nodes[which].walk(matched, arena)?;
+        if path.reps_ok() {
+            return Ok(Some(path));
+        }
+        arena.recycle(path);
+        if arena.remember_or_failures {
+            arena.or_failures.insert(key);
+        }
+        Ok(None)
+    }
+
+    fn back_off(&mut self, arena: &mut WalkArena<'a>) -> Result<bool, Error> {
         trace!(6, "back off Node: {:?}", self);
         trace_change_indent!(6, 1);
         let ret;
         loop {
-            if self.child_path.back_off()? {
+            if self.child_path.back_off(arena)? {
                 ret = "true: child backed off";
                 break;
             }
             trace!(6, "back off (next option){:?}", self);
             self.which += 1;
             self.matched.set_end(self.matched.start);
+            trace_or_branch(self);
+            // the branch backed off is done with, and there is nothing to back off in one that fails
+            arena.recycle(std::mem::replace(&mut *self.child_path, Path::None));
             if self.which >= self.node.nodes.len() {
                 ret = "false: exhausted";
                 break;
             }
-            self.child_path = Box::new(self.node.nodes[self.which].walk(self.matched)?);
-            if self.child_path.limits().check(self.child_path.len()) == 0 {
+            if let Some(child_path) =
+                OrStep::walk_branch(self.node, self.which, self.matched, arena)?
+            {
+                *self.child_path = child_path;
                 ret = "true: next option";
                 break;
             }
@@ -1118,93 +2031,401 @@ impl<'a> OrStep<'a> {
         Ok(ret.starts_with("true"))
     }
 
-    pub fn dump(&self, rank: usize, indent: usize) {
-        trace!(
-            0,
-            "|{0:1$}{2}: {3:?} {4} of {5}",
+    pub fn dump<W: Write + ?Sized>(
+        &self,
+        w: &mut W,
+        rank: usize,
+        indent: usize,
+        input: &Input,
+    ) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "{0:1$}|{2:3$}{4}: {{{5:?}}}, branch {6} of {7}, branch reps {8}, {9}  {10} of {11}",
+            "",
+            trace_get_indent(),
             "",
             4 * indent,
             rank,
-            self,
+            self.node,
+            self.which + 1,
+            self.node.nodes.len(),
+            self.child_path.len(),
+            self.matched.display(input),
             self.which,
             self.node.nodes.len()
-        );
-        self.child_path.dump(indent + 1);
+        )?;
+        self.child_path.dump_with(w, indent + 1, input)
     }
 }
 
 /// This is the entrypoint to the phase 2, (tree walk) processing. It
 /// is put in this package to make it easier available, since
 /// logically it is part of the regexp search functionality.
+/// Like **walk_tree()**, but the walk treats END as the end of the text: no match can go past it, and '$' matches
+/// there. This is used to search a line at a time.
+pub(crate) fn walk_tree_until(
+    tree: &Node,
+    from: usize,
+    end: usize,
+) -> Result<Option<Path<'_>>, Error> {
+    WALK_TEXT_END.store(end, Release);
+    let result = walk_tree(tree, from);
+    WALK_TEXT_END.store(usize::MAX, Release);
+    result
+}
+
+/// Like **walk_tree()**, but also returns where to start looking for the next match (see **next_start()**), so
+/// callers going through all the matches do not each need their own rule for empty matches. A search from past the
+/// end of the text finds nothing.
+pub fn walk_tree_next(tree: &Node, from: usize) -> Result<Option<(Path<'_>, usize)>, Error> {
+    Ok(walk_tree(tree, from)?.map(|path| {
+        let (start, end) = path.range();
+        (path, next_start(start, end))
+    }))
+}
+
+/// Where the search for the next match starts after a match from START to END. Matches do not overlap, so this is
+/// normally the end of the match, but after an empty match it is one char further on so the same match is not found
+/// again. After an empty match at the end of the text it is past the end.
+pub fn next_start(start: usize, end: usize) -> usize {
+    if end > start {
+        return end;
+    }
+    // reads in the next char if it is not there yet
+    Input::has_bytes(end, 1);
+    Input::with_text(|text| end + text[end..].chars().next().map_or(1, char::len_utf8))
+}
+
+/// How many bytes a match of TREE starting exactly at AT takes, or None if no match starts there. This is the
+/// question a tokenizer asks at each position, so only that one start is tried, nothing is reported and group
+/// callbacks are not called. A match can be empty, so Some(0) is not the same as None.
+pub fn match_prefix_len(tree: &Node, at: usize) -> Result<Option<usize>, Error> {
+    if !start_check(at)? {
+        return Ok(None);
+    }
+    let mut search = Search::new(tree, at)?;
+    search.anchor = true;
+    search.scanner = None;
+    let mut arena = WalkArena::default();
+    let path = in_window(|| search.next(tree, &mut arena))?;
+    Ok(path.map(|path| {
+        let end = path.range().1;
+        arena.recycle(path);
+        end - at
+    }))
+}
+
+/// Finds the match that starts where **walk_tree()** would find one from FROM but ends as soon as it can, returning
+/// its byte range. The tree is walked with every repetition lazy (see **Node::all_lazy()**), so each takes as few
+/// as it can; OR branches are still tried in order, so an earlier branch is kept even if a later one would end
+/// sooner. Like **match_prefix_len()** nothing is reported and group callbacks are not called.
+pub fn shortest_match(tree: &Node, from: usize) -> Result<Option<(usize, usize)>, Error> {
+    if !start_check(from)? {
+        return Ok(None);
+    }
+    let lazy = tree.all_lazy();
+    let mut search = Search::new(&lazy, from)?;
+    let mut arena = WalkArena::default();
+    let path = in_window(|| search.next(&lazy, &mut arena))?;
+    Ok(path.map(|path| {
+        let range = path.range();
+        arena.recycle(path);
+        range
+    }))
+}
+
+/// The repetition caps a best effort search (see **walk_tree_deepening()**) tries unless it is given others
+pub const DEEPENING_CAPS: [usize; 3] = [64, 1024, 16384];
+
+/// Makes the trees for **walk_tree_deepening()**: TREE with its unlimited repetitions capped (see **Node::capped()**)
+/// at each of CAPS in turn, and then TREE itself. A cap that does not change the tree is left out, so a tree with
+/// no unlimited repetitions is searched once.
+pub fn deepening_trees(tree: &Node, caps: &[usize]) -> Vec<Node> {
+    let mut trees: Vec<Node> = Vec::new();
+    for &cap in caps {
+        let capped = tree.capped(cap);
+        if capped != *tree && trees.last() != Some(&capped) {
+            trees.push(capped);
+        }
+    }
+    trees.push(tree.clone());
+    trees
+}
+
+/// A best effort search for heavy REs, whose unlimited repetitions can take so long to fail at the early start
+/// positions that the walk times out before reaching a match. TREES (from **deepening_trees()**) are searched from
+/// FROM in turn, and the first match found is returned: with the repetitions capped at 64 the hopeless start
+/// positions are given up quickly, and a short match further on is found. Only when no capped tree finds a match is
+/// the full tree searched, so a text with no match costs more than a plain **walk_tree()**.
+///
+/// The match may not be the one **walk_tree()** would find. A match needing more repetitions than the cap is missed
+/// at that cap, so a later, shorter match can be returned in its place, and a greedy repetition stops at the cap
+/// instead of running on. Each tree gets the whole timeout, if there is one.
+pub fn walk_tree_deepening(trees: &[Node], from: usize) -> Result<Option<Path<'_>>, Error> {
+    for tree in trees {
+        if let Some(path) = walk_tree(tree, from)? {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
 /// If TEXT is non-empty then the string TEXT is searched for the RE represented by TREE. If TEXT is empty then
 /// FILE is opened and read to get the string to search. If FILE also is empty (or if FILE = "-") then the string to
 /// search is read from stdin.
-
+///
+/// FROM is a byte offset. A search from past the end of the text finds nothing, and one from the middle of a
+/// multi-byte character is error 202.
 pub fn walk_tree(tree: &Node, from: usize) -> Result<Option<Path<'_>>, Error> {
-    trace_set_indent(0);
-    let mut start_pos = from;
-    let mut char_start = Input::apply(|input| input.full_text[0..from].chars().count());
-    // hey, optimization
-    // deosn't save that much time but makes the trace debug easier to read
-    let root = {
-        if let Node::And(r) = tree {
-            r
-        } else {
-            return Err(Error::make(
-                5,
-                "Root of tree should be Node::And (should not happen)",
-            ));
+    walk_tree_opts(tree, from, &SearchOptions::default())
+}
+
+/// Like **walk_tree()**, but with the walk options in OPTIONS (if it has any) in place of the global ones
+pub fn walk_tree_opts<'a>(
+    tree: &'a Node,
+    from: usize,
+    options: &SearchOptions,
+) -> Result<Option<Path<'a>>, Error> {
+    with_walk_options(options, || {
+        if !start_check(from)? {
+            return Ok(None);
         }
-    };
-    /*
-        // If the initial node is a character this optimizes by searching for the initial string. It is commented out
-        // because it doesn't account for FILE or STDIN input
-        if !root.anchor {
-            if let Node::Chars(chars_node) = &root.nodes[0] {
-                if chars_node.limits.min > 0 {
-                    let input = INPUT.lock().unwrap();
-                    match input.full_text.find(chars_node.string.as_str()) {
-                        Some(offset) => {
-                            if offset > 0 {
-                                trace!(1, "\nOptimization: RE starts with \"{}\", skipping {} bytes", chars_node.string, offset);
-                                start_pos = offset;
-                                char_start = input.full_text[0..offset].chars().count();
-                            }
-                        },
-                        None => { return Ok(None); }
-                    }
-                }
-            }
+        let mut search = Search::new(tree, from)?;
+        let path = in_window(|| search.next(tree, &mut WalkArena::default()))?;
+        if let Some(path) = &path {
+            call_group_callbacks(path);
         }
-    */
-    loop {
-        trace!(1, "\n==== WALK \"{}\" ====", Input::abbrev(start_pos, 10));
-        let matched = Matched {
-            start: start_pos,
-            end: start_pos,
-            char_start,
-        };
-        let path = tree.walk(matched)?;
-        if path.len() > 1 {
-            trace!(1, "--- Search succeeded ---");
-            return Ok(Some(path));
+        Ok(path)
+    })
+}
+
+/// Counts the matches from FROM to the end of the text, the same ones **walk_tree_next()** would find one after
+/// another. Nothing is reported: each path is taken apart for reuse as soon as it is found, so after the first few
+/// matches the walk hardly allocates, and the scanner and the rest of the setup are shared by all the matches. Group
+/// callbacks are not called.
+pub fn count_matches(tree: &Node, from: usize) -> Result<usize, Error> {
+    if !start_check(from)? {
+        return Ok(0);
+    }
+    let mut search = Search::new(tree, from)?;
+    let mut arena = WalkArena::default();
+    in_window(|| {
+        let mut count = 0;
+        while let Some(path) = search.next(tree, &mut arena)? {
+            count += 1;
+            let (start, end) = path.range();
+            arena.recycle(path);
+            let next = next_start(start, end);
+            if search.anchor || !Input::has_bytes(0, next) {
+                break;
+            }
+            search.skip_to(next);
         }
-        trace!(
-            1,
-            "==== WALK \"{}\": no match ====",
-            Input::abbrev(start_pos, 10)
-        );
-        if root.anchor {
-            break;
+        Ok(count)
+    })
+}
+
+/// true if with the walk options in place a match ending in ".*" runs on to the end of the text: not if a limit on
+/// the match length or a clipping window can cut it short, or group callbacks need to see the whole walk
+pub(crate) fn wildcard_reaches_end() -> bool {
+    let options = WALK_OPTIONS.lock().unwrap();
+    options.max_match_len.is_none()
+        && options.on_group.0.is_empty()
+        && !matches!(options.window, Some((_, WindowEdge::Clip)))
+}
+
+/// Runs SEARCH with the end of the text moved back to the end of the window if the window clips matches
+fn in_window<T>(search: impl FnOnce() -> T) -> T {
+    let window = WALK_OPTIONS.lock().unwrap().window;
+    match window {
+        Some((end, WindowEdge::Clip)) => {
+            let outer = WALK_TEXT_END.load(Acquire);
+            WALK_TEXT_END.store(outer.min(end), Release);
+            let result = search();
+            WALK_TEXT_END.store(outer, Release);
+            result
         }
-        if let Some(ch0) = Input::apply(|input| input.full_text[start_pos..].chars().next()) {
-            start_pos += String::from(ch0).len();
-            char_start += 1;
-        } else {
-            break;
+        _ => search(),
+    }
+}
+
+/// Checks that a search can start at byte FROM, reading more input if it is not there yet. It returns false if FROM is
+/// past the end of the text, and an error if it is inside a character, which can happen when callers work out offsets
+/// themselves.
+fn start_check(from: usize) -> Result<bool, Error> {
+    if from > 0 && !Input::has_bytes(0, from) {
+        return Ok(false);
+    }
+    if Input::with_text(|text| text.is_char_boundary(from)) {
+        Ok(true)
+    } else {
+        Err(Error::make(
+            202,
+            format!("Search start {} is inside a character", from).as_str(),
+        ))
+    }
+}
+
+/// The state of a search through the text, kept between matches by **count_matches()**
+struct Search {
+    /// where the next walk from the root starts, in bytes
+    start_pos: usize,
+    /// the same, in chars
+    char_start: usize,
+    /// the shortest text the tree can match
+    min_len: usize,
+    /// true if the RE can only match at the start
+    anchor: bool,
+    /// true if the RE starts with ".*", so if it does not match at the first start it cannot match at a later one
+    wildcard_start: bool,
+    /// no match can start here or after, the end of the window if there is one
+    window_end: usize,
+    max_match_len: Option<usize>,
+    scanner: Option<Arc<Scanner>>,
+    /// true if the walks remember the OR branches that fail (see **WalkArena::or_failures**)
+    remember_or_failures: bool,
+}
+
+impl Search {
+    /// Sets up a search for TREE starting at FROM
+    fn new(tree: &Node, from: usize) -> Result<Search, Error> {
+        let (max_match_len, no_scan, no_or_memo, window) = {
+            let options = WALK_OPTIONS.lock().unwrap();
+            set_check_boundaries(options.check_boundaries);
+            (
+                options.max_match_len,
+                options.no_scan,
+                options.no_or_memo,
+                options.window,
+            )
+        };
+        // hey, optimization
+        // deosn't save that much time but makes the trace debug easier to read
+        let root = {
+            if let Node::And(r) = tree {
+                r
+            } else {
+                return Err(Error::make(
+                    5,
+                    "Root of tree should be Node::And (should not happen)",
+                ));
+            }
+        };
+        /*
+            // If the initial node is a character this optimizes by searching for the initial string. It is commented out
+            // because it doesn't account for FILE or STDIN input
+            if !root.anchor {
+                if let Node::Chars(chars_node) = &root.nodes[0] {
+                    if chars_node.limits.min > 0 {
+                        let input = INPUT.lock().unwrap();
+                        match input.full_text.find(chars_node.string.as_str()) {
+                            Some(offset) => {
+                                if offset > 0 {
+                                    trace!(1, "\nOptimization: RE starts with \"{}\", skipping {} bytes", chars_node.string, offset);
+                                    start_pos = offset;
+                                    char_start = input.full_text[0..offset].chars().count();
+                                }
+                            },
+                            None => { return Ok(None); }
+                        }
+                    }
+                }
+            }
+        */
+        Ok(Search {
+            start_pos: from,
+            char_start: Input::with_text(|text| text[0..from].chars().count()),
+            min_len: tree.min_len(),
+            anchor: root.anchor,
+            wildcard_start: !root.anchor
+                && !no_scan
+                && max_match_len.is_none()
+                && tree.leading_wildcard(),
+            window_end: window.map_or(usize::MAX, |(end, _)| end),
+            max_match_len,
+            scanner: if root.anchor || no_scan {
+                None
+            } else {
+                scanner(tree)
+            },
+            remember_or_failures: !no_or_memo,
+        })
+    }
+
+    /// Moves the start of the search on to POS, which must be past where it is now
+    fn skip_to(&mut self, pos: usize) {
+        let start_pos = self.start_pos;
+        self.char_start += Input::with_text(|text| text[start_pos..pos].chars().count());
+        self.start_pos = pos;
+    }
+
+    /// Finds the next match, leaving the search at its start
+    fn next<'a>(
+        &mut self,
+        tree: &'a Node,
+        arena: &mut WalkArena<'a>,
+    ) -> Result<Option<Path<'a>>, Error> {
+        trace_set_indent(0);
+        trace_reset_depth();
+        start_walk_clock();
+        BAD_BOUNDARY.lock().unwrap().take();
+        arena.remember_or_failures = self.remember_or_failures;
+        loop {
+            walk_check()?;
+            if let Some(scanner) = &self.scanner {
+                match Input::scan(scanner, self.start_pos) {
+                    Some(pos) => {
+                        if pos > self.start_pos {
+                            trace!(1, "\nScan: skipping {} bytes", pos - self.start_pos);
+                            self.skip_to(pos);
+                        }
+                    }
+                    None => break,
+                }
+            }
+            let start_pos = self.start_pos;
+            // no match can start this late in the text
+            if start_pos >= self.window_end
+                || !Input::has_bytes(start_pos, self.min_len)
+                || start_pos.saturating_add(self.min_len) > WALK_TEXT_END.load(Acquire)
+            {
+                break;
+            }
+            count_start();
+            set_match_end_limit(start_pos, self.max_match_len);
+            arena.forget_or_failures(start_pos, self.max_match_len.is_some());
+            trace!(1, "\n==== WALK \"{}\" ====", Input::abbrev(start_pos, 10));
+            let matched = Matched {
+                start: start_pos,
+                end: start_pos,
+                char_start: self.char_start,
+            };
+            let path = tree.walk(matched, arena)?;
+            boundary_check()?;
+            if path.len() > 1 {
+                trace!(1, "--- Search succeeded ---");
+                return Ok(Some(path));
+            }
+            arena.recycle(path);
+            trace!(
+                1,
+                "==== WALK \"{}\": no match ====",
+                Input::abbrev(start_pos, 10)
+            );
+            if self.anchor || self.wildcard_start {
+                break;
+            }
+            if let Some(ch0) = Input::with_text(|text| text[start_pos..].chars().next()) {
+                self.start_pos += String::from(ch0).len();
+                self.char_start += 1;
+            } else {
+                break;
+            }
         }
+        // the end of the text may only be where reading stopped
+        buffer_check()?;
+        Ok(None)
     }
-    Ok(None)
 }
 
 /// **Matched** is used to keep track of the state of the search
@@ -1224,19 +2445,46 @@ pub struct Matched {
 
 impl Debug for Matched {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        Input::apply_mut(|input| {
+        Input::with_text(|text| {
             write!(
                 f,
-                "match \"{}\" [{}-{})",
-                &input.full_text[self.start..self.end],
-                self.start,
-                self.end,
+                "{}",
+                MatchedDisplay {
+                    matched: *self,
+                    text: &text[self.start..self.end]
+                }
             )
         })
     }
 }
 
+/// Shows a **Matched** the way its **Debug** does, but using text borrowed from an **Input** that is already being
+/// looked at, so formatting it neither locks **Input** nor allocates
+pub struct MatchedDisplay<'b> {
+    matched: Matched,
+    text: &'b str,
+}
+
+impl std::fmt::Display for MatchedDisplay<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "match \"{}\" [{}-{})",
+            self.text, self.matched.start, self.matched.end
+        )
+    }
+}
+
 impl Matched {
+    /// Gets something to display the match with. Like **Path::match_display()** it must be called from within an
+    /// **Input::apply()** closure
+    pub fn display<'b>(&self, input: &'b Input) -> MatchedDisplay<'b> {
+        MatchedDisplay {
+            matched: *self,
+            text: &input.full_text[self.start..self.end],
+        }
+    }
+
     /// Returns the length of the match in bytes
     pub fn len_bytes(&self) -> usize {
         self.end - self.start
@@ -1244,15 +2492,28 @@ impl Matched {
 
     /// Returns the length of the match in chars
     pub fn len_chars(&self) -> usize {
-        Input::apply(|input| input.full_text[self.start..self.end].chars().count())
+        // steps start from empty matches, so this saves looking at the text for them
+        if self.start == self.end {
+            return 0;
+        }
+        Input::with_text(|text| text[self.start..self.end].chars().count())
     }
 
     pub fn string<'a>(&self, input: &'a Input) -> &'a str {
         &input.full_text[self.start..self.end]
     }
 
+    /// true if the match is a slice of the text in INPUT: it does not end before it starts or past the end of the
+    /// text, and both ends are on char boundaries
+    pub fn validate(&self, input: &Input) -> bool {
+        self.start <= self.end
+            && input.full_text.is_char_boundary(self.start)
+            && input.full_text.is_char_boundary(self.end)
+    }
+
     /// Builds a new Matched object immediately following the one pointed to by self
     fn next(&self, len: usize) -> Matched {
+        debug_assert_on_boundary(self.end + len);
         Matched {
             start: self.end,
             end: self.end + len,
@@ -1269,10 +2530,22 @@ impl Matched {
     }
     /// Moves the end of Matched to the new position
     fn set_end(&mut self, new_end: usize) {
+        debug_assert_on_boundary(new_end);
         self.end = new_end;
     }
 }
 
+/// In a debug build, panics if POS is not on a char boundary of the text, unless
+/// **WalkOptions::check_boundaries()** is set to turn it into an error
+fn debug_assert_on_boundary(pos: usize) {
+    debug_assert!(
+        CHECK_BOUNDARIES.load(Acquire)
+            || Input::read_snapshot(|snapshot| snapshot.text.is_char_boundary(pos)),
+        "match end {} is not on a char boundary",
+        pos
+    );
+}
+
 /// **Source** holds information on where the input text comes from
 #[derive(Default)]
 enum Source {
@@ -1285,39 +2558,91 @@ enum Source {
     Stdin(BufReader<std::io::Stdin>),
     /// files are read sequentlaiiy to get the input text
     File(BufReader<std::fs::File>),
+    /// text is read from a reader supplied by the caller
+    Reader(Box<dyn BufRead + Send>),
 }
 
 impl Source {
-    /// Extends by unit of BLOCK_SIZE if possible. Returns String to add along with boolean telling if the input is exhausted, or program error format
+    /// the number of times in a row a read that fails with a temporary error (like **WouldBlock**) is tried again
+    /// before giving up on the source
+    const MAX_RETRIES: usize = 3;
+
+    /// reads a line from STREAM, trying again after temporary errors. In bytes mode (see **Input::set_bytes_mode()**)
+    /// the line does not have to be UTF-8, each byte is added as a single char.
+    fn read_line<R: BufRead + ?Sized>(stream: &mut R, string: &mut String) -> Result<usize, Error> {
+        let bytes_mode = Input::bytes_mode();
+        let mut line = Vec::<u8>::new();
+        let mut failures = 0;
+        loop {
+            let read = if bytes_mode {
+                stream.read_until(b'\n', &mut line).map(|_| {
+                    string.push_str(&Input::bytes_to_text(&line));
+                    line.len()
+                })
+            } else {
+                stream.read_line(string)
+            };
+            match read {
+                Ok(bytes) => return Ok(bytes),
+                Err(error)
+                    if failures < Source::MAX_RETRIES
+                        && matches!(
+                            error.kind(),
+                            std::io::ErrorKind::Interrupted
+                                | std::io::ErrorKind::WouldBlock
+                                | std::io::ErrorKind::TimedOut
+                        ) =>
+                {
+                    failures += 1
+                }
+                // a source that is not text is given up on like any other, but is told apart so it can be counted
+                Err(error) if error.kind() == std::io::ErrorKind::InvalidData => {
+                    return Err(Error::make(
+                        212,
+                        &format!("{} (--bytes searches it as bytes)", error),
+                    ))
+                }
+                Err(error) => return Err(Error::make(210, &error.to_string())),
+            }
+        }
+    }
+
+    /// the name of the source, for error messages
+    fn name(&self, filename: Option<&str>) -> String {
+        match self {
+            Source::File(_) => filename.unwrap_or("file").to_string(),
+            Source::Stdin(_) => "stdin".to_string(),
+            Source::Reader(_) => "reader".to_string(),
+            Source::CmdLine | Source::None => "text".to_string(),
+        }
+    }
+
+    /// Extends by a block (see **Input::block_size()**) if possible. Returns String to add along with boolean telling if the input is exhausted, or program error format
     fn extend(&mut self, buf_size: Option<usize>) -> Result<(String, bool, Vec<usize>), Error> {
         let mut string = "".to_string();
         let mut more = true;
         let mut line_ends = Vec::<usize>::new();
-        let buf_size = if let Some(bsize) = buf_size { bsize } else { 0 };
+        let buf_size = buf_size.unwrap_or_default();
+        let block_size = Input::block_size();
         match self {
             Source::CmdLine => more = false,
-            Source::File(stream) => {
-                while more && string.len() < Input::BLOCK_SIZE {
-                    match stream.read_line(&mut string) {
-                        std::io::Result::Err(error) => {
-                            return Err(Error::make(210, &error.to_string()));
-                        }
-                        std::io::Result::Ok(0) => {
-                            more = false;
-                            break;
-                        }
-                        std::io::Result::Ok(_bytes) => line_ends.push(buf_size + string.len()),
+            Source::File(_) | Source::Reader(_) => {
+                while more && string.len() < block_size {
+                    let bytes = match self {
+                        Source::File(stream) => Source::read_line(stream, &mut string)?,
+                        Source::Reader(stream) => Source::read_line(stream, &mut string)?,
+                        _ => 0,
+                    };
+                    if bytes == 0 {
+                        more = false;
+                    } else {
+                        line_ends.push(buf_size + string.len());
                     }
                 }
             }
             Source::Stdin(stream) => {
-                while more && string.len() < Input::BLOCK_SIZE {
-                    match stream.read_line(&mut string) {
-                        std::io::Result::Err(error) => {
-                            return Err(Error::make(210, &error.to_string()));
-                        }
-                        std::io::Result::Ok(bytes) => more = bytes > 0,
-                    }
+                while more && string.len() < block_size {
+                    more = Source::read_line(stream, &mut string)? > 0;
                 }
             }
             Source::None => panic!("No input source has been set"),
@@ -1334,8 +2659,9 @@ impl Source {
 /// making it static the current input string is available everywhere to reference and can be updated as needed.
 #[derive(Default)]
 pub struct Input {
-    /// The text currently in the buffer
-    pub full_text: String,
+    /// The text currently in the buffer. It is shared with the snapshots walks read it through (see
+    /// **Input::with_text()**), so it is only changed by **set_text()** and **push_text()**.
+    pub full_text: Arc<String>,
     /// if this is empty just print out the matches, if non-empty it holds the line end positions, print out lines containing matches
     line_ends: Vec<usize>,
     /// The source for getting more text
@@ -1345,16 +2671,121 @@ pub struct Input {
     filenames: Option<Vec<String>>,
     /// the current file in the file list being read, 0 if input is not from file
     fileno: usize,
+    /// the errors met reading the sources, with the name of the source. A source is given up on after an error, so
+    /// there is at most one for each.
+    errors: Vec<(String, Error)>,
+    /// the files in the list that could not be opened, with the reason. They are in **errors** as well.
+    skipped: Vec<(String, Error)>,
 }
 
 /// Single static value holding input text to search. All access to this shoulld use Input::apply() or Input::apply_mut()
 static INPUT: Lazy<Mutex<Input>> = Lazy::new(|| Mutex::new(Input::default()));
 
+/// Counts changes to the text in **INPUT** (or to whether there is more to read), so a **Snapshot** can tell it is
+/// out of date
+static TEXT_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// A reference to the text in **INPUT** as of some generation. The walk reads the text for every step, and going
+/// through the lock each time is a large part of the cost of a search, so instead each thread keeps one of these and
+/// only goes back to **INPUT** after the text changes.
+struct Snapshot {
+    generation: usize,
+    text: Arc<String>,
+    more_input: bool,
+}
+
+thread_local! {
+    /// this thread's view of the text
+    static SNAPSHOT: RefCell<Option<Snapshot>> = const { RefCell::new(None) };
+}
+
+/// The size of the blocks **Input** reads, see **Input::set_block_size()**
+static BLOCK_SIZE: AtomicUsize = AtomicUsize::new(Input::DEFAULT_BLOCK_SIZE);
+
+/// If set, **Input** reads an extra block when it is close to running out, see **Input::set_read_ahead()**
+static READ_AHEAD: AtomicBool = AtomicBool::new(true);
+
+/// If set, **Input** holds the text as bytes, see **Input::set_bytes_mode()**
+static BYTES_MODE: AtomicBool = AtomicBool::new(false);
+
+/// If set, **Input::display_text()** leaves control characters as they are, see **Input::set_raw_output()**
+static RAW_OUTPUT: AtomicBool = AtomicBool::new(false);
+
 impl Input {
-    /// An indication of the block size to read in for extending input. The number is not exact since
-    /// input is read line-by-line, but it is guaranteed that each extend() call adds at least this many bytes
-    /// if they are available
-    const BLOCK_SIZE: usize = 500;
+    /// The default block size, see **set_block_size()**
+    pub const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+
+    /// Sets the block size to read in for extending input, returning the previous size. The number is not exact since
+    /// input is read line-by-line, but it is guaranteed that each extend() call adds at least this many bytes if they
+    /// are available. Small sizes are slow, but are useful for testing what happens at the ends of blocks.
+    pub fn set_block_size(bytes: usize) -> usize {
+        BLOCK_SIZE.swap(bytes.max(1), AcqRel)
+    }
+
+    /// Gets the block size, see **set_block_size()**
+    pub fn block_size() -> usize {
+        BLOCK_SIZE.load(Acquire)
+    }
+
+    /// Turns reading ahead on or off, returning the previous setting. When it is on (the default) an extend that
+    /// leaves less than half a block past what was asked for reads another block, so the walk does not have to stop
+    /// for input again right away.
+    pub fn set_read_ahead(read_ahead: bool) -> bool {
+        READ_AHEAD.swap(read_ahead, AcqRel)
+    }
+
+    /// Turns bytes mode on or off, returning the previous setting. It applies to input set up after it is changed.
+    /// In bytes mode the input does not have to be UTF-8: each byte of it is stored as the char with the same value
+    /// (so the text is read as Latin-1), which means that char positions in the text are byte positions in the
+    /// input. The specials only match ASCII characters anyway, and REs with characters that are not ASCII are
+    /// rejected when they are parsed (error 12), so a match never depends on how the high bytes are decoded.
+    pub fn set_bytes_mode(bytes_mode: bool) -> bool {
+        BYTES_MODE.swap(bytes_mode, AcqRel)
+    }
+
+    /// Gets whether bytes mode is on, see **set_bytes_mode()**
+    pub fn bytes_mode() -> bool {
+        BYTES_MODE.load(Acquire)
+    }
+
+    /// converts BYTES to the text that holds them in bytes mode, one char for each byte
+    pub fn bytes_to_text(bytes: &[u8]) -> String {
+        bytes.iter().map(|&byte| char::from(byte)).collect()
+    }
+
+    /// Turns raw output on or off, returning the previous setting. When it is off (the default) **display_text()**
+    /// shows control characters as escapes, so text with ESC sequences in it cannot garble the terminal it is
+    /// printed on, or send it commands.
+    pub fn set_raw_output(raw: bool) -> bool {
+        RAW_OUTPUT.swap(raw, AcqRel)
+    }
+
+    /// Gets TEXT from the input in a form that can be printed. Control characters other than newline and tab, like
+    /// NUL, ESC and DEL, are shown as escapes like "\x1b" unless raw output is on (see **set_raw_output()**), and in
+    /// bytes mode so are the bytes that are not ASCII, like "\xe9". Anything else, including characters that take
+    /// several bytes, is unchanged. All the printers of matched text go through this.
+    pub fn display_text(text: &str) -> std::borrow::Cow<'_, str> {
+        let bytes_mode = Input::bytes_mode();
+        let raw = RAW_OUTPUT.load(Acquire);
+        let escaped = |ch: char| {
+            (bytes_mode && !ch.is_ascii()) || (!raw && ch.is_control() && ch != '\n' && ch != '\t')
+        };
+        if !text.chars().any(escaped) {
+            return text.into();
+        }
+        text.chars()
+            .map(|ch| {
+                if !escaped(ch) {
+                    ch.to_string()
+                } else if bytes_mode || ch.is_ascii() {
+                    format!("\\x{:02x}", ch as u32)
+                } else {
+                    format!("\\u{{{:x}}}", ch as u32)
+                }
+            })
+            .collect::<String>()
+            .into()
+    }
 
     //
     // Creation
@@ -1364,34 +2795,100 @@ impl Input {
         if text.is_empty() {
             return Input::init_stdin(full_lines);
         }
+        Input::init_string(text, full_lines);
+        Ok(())
+    }
+
+    /// initializes text buffer to search TEXT. Unlike **init_text()** an empty string is searched as it is, it does
+    /// not mean stdin. In bytes mode the bytes of its UTF-8 are searched.
+    pub fn init_string(text: &str, full_lines: bool) {
+        Input::init_bytes(text.as_bytes(), full_lines);
+    }
+
+    /// initializes text buffer to search BYTES. Outside of bytes mode (see **set_bytes_mode()**) anything that is
+    /// not UTF-8 is replaced with U+FFFD.
+    pub fn init_bytes(bytes: &[u8], full_lines: bool) {
+        let text = if Input::bytes_mode() {
+            Input::bytes_to_text(bytes)
+        } else {
+            String::from_utf8_lossy(bytes).into_owned()
+        };
         let mut input = INPUT.lock().unwrap();
         input.source = Source::CmdLine;
-        input.full_text = text.to_string();
+        input.more_input = false;
+        input.set_text(text);
+        input.filenames = None;
+        input.fileno = 0;
+        input.errors.clear();
+        input.skipped.clear();
         input.result_lines(full_lines);
         // TODO: line ends vec
-        Ok(())
     }
 
     /// initializes text buffer to get the string from stdin
     pub fn init_stdin(full_lines: bool) -> Result<(), Error> {
+        Input::init_source(Source::Stdin(BufReader::new(std::io::stdin())), full_lines);
+        Ok(())
+    }
+
+    /// initializes text buffer to read the text from READER. A read error is recorded (see **errors()**) and ends the
+    /// input, the same as for files and stdin.
+    pub fn init_reader(reader: Box<dyn BufRead + Send>, full_lines: bool) {
+        Input::init_source(Source::Reader(reader), full_lines);
+    }
+
+    /// does the work for **init_stdin()** and **init_reader()**
+    fn init_source(source: Source, full_lines: bool) {
         let mut input = INPUT.lock().unwrap();
-        input.source = Source::Stdin(BufReader::new(std::io::stdin()));
+        input.source = source;
         input.more_input = true;
+        input.set_text(String::new());
+        input.filenames = None;
+        input.fileno = 0;
+        input.errors.clear();
+        input.skipped.clear();
         input.result_lines(full_lines);
-        input._extend(1)?; // any positive number forces a read
-        Ok(())
+        // any positive number forces a read. An error is recorded, and leaves the text empty
+        let _ = input._extend(1);
     }
 
-    /// initializes text buffer to get text from a list of files
-    pub fn init_files(filenames: &Vec<String>, full_lines: bool) -> Result<(), Error> {
+    /// initializes text buffer to get text from a list of files. Files that cannot be opened are skipped, and
+    /// listed by **skipped()** with the reason as they are reached. Fails with error 211 only if none of them can
+    /// be opened.
+    pub fn init_files(filenames: &[String], full_lines: bool) -> Result<(), Error> {
         if filenames.is_empty() {
             return Input::init_stdin(full_lines);
         }
         let mut input = INPUT.lock().unwrap();
         input.result_lines(full_lines);
-        input.filenames = Some(filenames.clone());
-        input.fileno = 0;
-        input.use_file(filenames[0].as_str())
+        input.filenames = Some(filenames.to_vec());
+        input.errors.clear();
+        input.skipped.clear();
+        if input.open_from(0) {
+            Ok(())
+        } else {
+            Err(Error::make(211, "No input could be read"))
+        }
+    }
+
+    /// opens the first file from number FILENO on that can be opened, recording the errors for those that cannot.
+    /// Returns false if none could be, leaving the text empty.
+    fn open_from(&mut self, fileno: usize) -> bool {
+        let filenames = self.filenames.clone().unwrap_or_default();
+        for (i, filename) in filenames.iter().enumerate().skip(fileno) {
+            self.fileno = i;
+            match self.use_file(filename) {
+                Ok(()) => return true,
+                Err(error) => {
+                    self.skipped.push((filename.clone(), error.clone()));
+                    self.errors.push((filename.clone(), error));
+                }
+            }
+        }
+        self.source = Source::CmdLine;
+        self.more_input = false;
+        self.set_text(String::new());
+        false
     }
 
     pub fn result_lines(&mut self, lines: bool) {
@@ -1402,35 +2899,124 @@ impl Input {
     /// sets up the text input to read from a new file
     fn use_file(&mut self, filename: &str) -> Result<(), Error> {
         trace!(1, "trying to open file {} for input", filename);
-        if filename == "-" {
-            let full_lines = Input::apply(|input| !input.line_ends.is_empty());
-            Input::init_stdin(full_lines)
+        // "-" is stdin, in its place in the list (INPUT is locked here, so this cannot go through init_stdin())
+        self.source = if filename == "-" {
+            Source::Stdin(BufReader::new(std::io::stdin()))
         } else {
             match std::fs::File::open(filename) {
                 Err(err) => {
                     let msg = format!("Error opening file {}: {}", filename, err);
-                    Err(Error::make(201, &msg))
-                }
-                Ok(file) => {
-                    self.source = Source::File(BufReader::new(file));
-                    self.more_input = true;
-                    self.full_text = "".to_string();
-                    self.result_lines(!self.line_ends.is_empty());
-                    self._extend(1)?; // any positive number forces a read
-                    Ok(())
+                    return Err(Error::make(201, &msg));
                 }
+                Ok(file) => Source::File(BufReader::new(file)),
             }
+        };
+        self.more_input = true;
+        self.set_text("".to_string());
+        self.result_lines(!self.line_ends.is_empty());
+        // any positive number forces a read. An error is recorded, and leaves the text empty
+        let _ = self._extend(1);
+        Ok(())
+    }
+
+    /// replaces the text, making the snapshots out of date. This starts a new input, so the buffer limit and peak
+    /// size start over too.
+    fn set_text(&mut self, text: String) {
+        PEAK_BUFFERED.store(text.len(), Release);
+        BUFFER_FULL.store(false, Release);
+        self.full_text = Arc::new(text);
+        TEXT_GENERATION.fetch_add(1, AcqRel);
+    }
+
+    /// adds to the text. This thread's snapshot is dropped first so the text is not copied just to keep it alive;
+    /// a snapshot in another thread (or one still in use further up the stack) keeps the old text until it is done
+    fn push_text(&mut self, more: &str) {
+        SNAPSHOT.with(|snapshot| {
+            if let Ok(mut snapshot) = snapshot.try_borrow_mut() {
+                *snapshot = None;
+            }
+        });
+        Arc::make_mut(&mut self.full_text).push_str(more);
+        PEAK_BUFFERED.fetch_max(self.full_text.len(), AcqRel);
+        TEXT_GENERATION.fetch_add(1, AcqRel);
+    }
+
+    /// Returns the number of bytes of text **Input** is holding in memory
+    pub fn buffered_bytes() -> usize {
+        Input::apply(|input| input.full_text.len())
+    }
+
+    /// Returns the most bytes of text **Input** has held at once since the input was set up
+    pub fn peak_buffered_bytes() -> usize {
+        PEAK_BUFFERED.load(Acquire)
+    }
+
+    /// Calls F with the current text without locking **INPUT**, unless the text has changed since the last call in
+    /// this thread. Unlike **apply()** it can be called from inside another call to it, or to **apply()**, which
+    /// brings the snapshot up to date before calling its closure.
+    pub fn with_text<T>(f: impl FnOnce(&str) -> T) -> T {
+        Input::with_snapshot(|snapshot| f(&snapshot.text))
+    }
+
+    /// makes a snapshot of the current text. This must be called with **INPUT** locked so the generation matches it.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            generation: TEXT_GENERATION.load(Acquire),
+            text: self.full_text.clone(),
+            more_input: self.more_input,
         }
     }
 
+    /// brings this thread's snapshot up to date, so **with_text()** can be used inside **apply()**
+    fn keep_snapshot(&self) {
+        SNAPSHOT.with(|cell| {
+            if let Ok(mut snapshot) = cell.try_borrow_mut() {
+                if !matches!(&*snapshot, Some(snapshot) if snapshot.generation == TEXT_GENERATION.load(Acquire)) {
+                    *snapshot = Some(self.snapshot());
+                }
+            }
+        });
+    }
+
+    /// does the work for **with_text()**, also giving whether there is more input to read
+    fn with_snapshot<T>(f: impl FnOnce(&Snapshot) -> T) -> T {
+        APPLY_COUNT.fetch_add(1, AcqRel);
+        Input::read_snapshot(f)
+    }
+
+    /// **with_snapshot()** without counting it in **apply_count()**, for checks that are only made in debug builds
+    fn read_snapshot<T>(f: impl FnOnce(&Snapshot) -> T) -> T {
+        SNAPSHOT.with(|cell| {
+            let generation = TEXT_GENERATION.load(Acquire);
+            let current =
+                matches!(&*cell.borrow(), Some(snapshot) if snapshot.generation == generation);
+            if !current {
+                let fresh = match INPUT.try_lock() {
+                    Ok(input) => input.snapshot(),
+                    Err(_) => panic!("Attempt to access INPUT when it is already locked"),
+                };
+                match cell.try_borrow_mut() {
+                    Ok(mut snapshot) => *snapshot = Some(fresh),
+                    // an older snapshot is still being read further up the stack, so this one is not kept
+                    Err(_) => return f(&fresh),
+                }
+            }
+            f(cell.borrow().as_ref().unwrap())
+        })
+    }
+
     /// Applies a mathod to the Input static instance. This is the supported way to get access to the static
     /// INPUT instance. The String full_text cannot be referred to outside this block, so passing a closure
     /// using either apply() or apply_mut() gives access to the static without having to deal explicitly
     /// with locking. The limitation of course is that the value returned cannot refer to the text.
     pub fn apply<T>(f: impl Fn(&Input) -> T) -> T {
         //        let input = &INPUT.lock().unwrap();
+        APPLY_COUNT.fetch_add(1, AcqRel);
         match INPUT.try_lock() {
-            Ok(input) => f(&input),
+            Ok(input) => {
+                input.keep_snapshot();
+                f(&input)
+            }
             // This should neve happen unless there is a programming error
             Err(_) => panic!("Attempt to access INPUT when it is already locked"),
         }
@@ -1438,8 +3024,12 @@ impl Input {
 
     /// Like Input::apply() but allows functions with muts
     pub fn apply_mut<T>(mut f: impl FnMut(&Input) -> T) -> T {
+        APPLY_COUNT.fetch_add(1, AcqRel);
         match INPUT.try_lock() {
-            Ok(input) => f(&input),
+            Ok(input) => {
+                input.keep_snapshot();
+                f(&input)
+            }
             Err(_) => panic!("Attempt to access INPUT when it is already locked"),
         }
     }
@@ -1453,17 +3043,50 @@ impl Input {
         } else {
             0
         };
-        if fileno < files_len {
-            let file = if let Some(filenames) = &input.filenames {
-                filenames[fileno].clone()
+        // files that cannot be opened are skipped, so this only fails to move on at the end of the list
+        Ok(fileno < files_len && input.open_from(fileno))
+    }
+
+    /// Returns the errors met reading the input since it was last initialized, with the names of the sources they
+    /// came from. After an error a source is given up on, so the search goes on with what was read before it.
+    pub fn errors() -> Vec<(String, Error)> {
+        Input::apply(|input| input.errors.clone())
+    }
+
+    /// Returns the files passed to **init_files()** that have been skipped so far because they could not be opened,
+    /// with the error saying why. Once the search has reached the end of the list it has all of them.
+    pub fn skipped() -> Vec<(String, Error)> {
+        Input::apply(|input| input.skipped.clone())
+    }
+
+    /// Returns the number of sources the input comes from: the number of files, or 1 for anything else
+    pub fn source_count() -> usize {
+        Input::apply(|input| {
+            input
+                .filenames
+                .as_ref()
+                .map_or(1, |filenames| filenames.len())
+        })
+    }
+
+    /// Returns the name of the source currently supplying input: the file name, or "text" or "stdin"
+    pub fn source_name() -> String {
+        Input::apply(|input| input.source.name(input.current_file()))
+    }
+
+    /// Returns the name of the source currently supplying input, with the bytes and lines read from it so far
+    pub fn source_stats() -> (String, usize, usize) {
+        Input::apply(|input| {
+            let text = &input.full_text;
+            let bytes = if Input::bytes_mode() {
+                text.chars().count()
             } else {
-                String::new()
+                text.len()
             };
-            input.use_file(file.as_str())?; //filenames[fileno].as_str())?;
-            input.fileno += 1;
-            return Ok(true);
-        }
-        Ok(false)
+            let lines =
+                text.matches('\n').count() + usize::from(!text.is_empty() && !text.ends_with('\n'));
+            (input.source.name(input.current_file()), bytes, lines)
+        })
     }
 
     /// Returns the sequence number of the file currently supplying input
@@ -1479,25 +3102,152 @@ impl Input {
     /// Checks that the input string is either fully read in or exceeds SIZE_BYTES in length
     fn _extend(&mut self, size_bytes: usize) -> Result<(), Error> {
         if self.more_input && self.full_text.len() < size_bytes {
-            let full_lines = !self.line_ends.is_empty();
-            let (string, more, mut ends) = self.source.extend(if full_lines { Some(self.full_text.len()) } else { None })?;
-            self.more_input = more;
-            self.full_text.push_str(&string);
-            if !self.line_ends.is_empty() { self.line_ends.append(&mut ends); }
+            self.read_block()?;
+            if READ_AHEAD.load(Acquire)
+                && self.more_input
+                && self.full_text.len() < size_bytes.saturating_add(Input::block_size() / 2)
+            {
+                self.read_block()?;
+            }
         }
         Ok(())
     }
 
-    /// Like Input::extend() except prints any error and continues with the current string
+    /// reads the next block from the source. After an error nothing more is read from it, so searches go on with the
+    /// text already read instead of trying again at every step.
+    fn read_block(&mut self) -> Result<(), Error> {
+        let full_lines = !self.line_ends.is_empty();
+        let (string, more, mut ends) = match self.source.extend(if full_lines {
+            Some(self.full_text.len())
+        } else {
+            None
+        }) {
+            Ok(read) => read,
+            Err(error) => {
+                let name = self.source.name(self.current_file());
+                self.errors.push((name, error.clone()));
+                self.more_input = false;
+                TEXT_GENERATION.fetch_add(1, AcqRel);
+                return Err(error);
+            }
+        };
+        let max_buffer = WALK_OPTIONS
+            .lock()
+            .unwrap()
+            .max_buffer
+            .unwrap_or(usize::MAX);
+        if self.full_text.len().saturating_add(string.len()) > max_buffer {
+            // the walk sees this and gives up, see buffer_check()
+            BUFFER_FULL.store(true, Release);
+            self.more_input = false;
+            TEXT_GENERATION.fetch_add(1, AcqRel);
+            return Err(Error::make(230, "Input buffer is full"));
+        }
+        self.more_input = more;
+        self.push_text(&string);
+        if !self.line_ends.is_empty() {
+            self.line_ends.append(&mut ends);
+        }
+        Ok(())
+    }
+
+    /// Like Input::extend() except any error is only recorded (see **errors()**), and the search continues with the
+    /// current string
     pub fn extend_quiet(size_bytes: usize) {
-        if let Err(err) = Input::extend(size_bytes) {
-            println!("Input error: {:?}", err);
+        // this is called for every step, so the lock is only taken if there is something to do
+        if Input::with_snapshot(|snapshot| {
+            !snapshot.more_input || snapshot.text.len() >= size_bytes
+        }) {
+            return;
+        }
+        // an error has been recorded, and ends the input
+        let _ = Input::extend(size_bytes);
+    }
+
+    /// Finds the first place at or after FROM where SCANNER says a match can start, reading in more input as
+    /// needed. Enough text past the place found is read in to be sure nothing starting before it was missed.
+    pub fn scan(scanner: &Scanner, from: usize) -> Option<usize> {
+        let mut input = INPUT.lock().unwrap();
+        // a walk limited to part of the text only scans that part
+        let end = WALK_TEXT_END.load(Acquire);
+        if end <= input.full_text.len() {
+            return scanner.find(&input.full_text[..end], from);
+        }
+        let mut scan_from = from;
+        loop {
+            let found = scanner.find(&input.full_text, scan_from);
+            let needed = match found {
+                Some(pos) => scanner.needed(pos),
+                None => input.full_text.len() + 1,
+            };
+            if !input.more_input || input.full_text.len() >= needed {
+                return found;
+            }
+            // anything starting before the part scanned again would have been found already
+            if found.is_none() {
+                scan_from = scanner.restart(&input.full_text).max(scan_from);
+            }
+            let before = input.full_text.len();
+            // errors are recorded, and end the input
+            if input._extend(needed).is_err() {
+                return found;
+            }
+            if input.full_text.len() == before && input.more_input {
+                return found;
+            }
+        }
+    }
+
+    /// Checks whether there are at least COUNT bytes of text from FROM on. If there are not enough yet this reads
+    /// more input, so it only returns false if the text really ends too soon.
+    pub fn has_bytes(from: usize, count: usize) -> bool {
+        let needed = from.saturating_add(count);
+        if count == 0 || Input::with_snapshot(|snapshot| snapshot.text.len() >= needed) {
+            return true;
+        }
+        let mut input = INPUT.lock().unwrap();
+        while input.more_input && input.full_text.len() < needed {
+            let before = input.full_text.len();
+            if input._extend(needed).is_err() {
+                break;
+            }
+            if input.full_text.len() == before && input.more_input {
+                break;
+            }
+        }
+        input.full_text.len() >= needed
+    }
+
+    /// Finds the end of the line containing byte position FROM: the position of the next newline, or the end of the
+    /// text if there is none. This reads more input until it finds one.
+    pub fn line_end(from: usize) -> usize {
+        let mut input = INPUT.lock().unwrap();
+        let mut searched = from;
+        loop {
+            if let Some(offset) = input
+                .full_text
+                .get(searched..)
+                .and_then(|text| text.find('\n'))
+            {
+                return searched + offset;
+            }
+            let before = input.full_text.len();
+            if !input.more_input {
+                return before;
+            }
+            searched = searched.max(before);
+            if input._extend(before + 1).is_err() {
+                return before;
+            }
+            if input.full_text.len() == before && input.more_input {
+                return before;
+            }
         }
     }
 
     /// Returns the length of the current search text. It may be there is more text that still needs to be read in.
     pub fn len() -> usize {
-        Input::apply(|input| input.full_text.len())
+        Input::with_text(|text| text.len())
     }
 
     /// For debugging, returns a String of the substring beginning at byte position FROM consisting of NUM_CHARS characters
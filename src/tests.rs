@@ -1,6 +1,6 @@
-use crate::regexp::Report;
+use crate::regexp::{parse_tree_full, parse_tree_with_warnings, Report};
 use crate::tree::*;
-use crate::walk::{walk_tree, Input};
+use crate::walk::{reset_step_count, step_count, walk_tree, Input};
 use std::io::Write;
 use std::sync::Mutex;
 
@@ -29,6 +29,11 @@ fn peekable() {
     assert_eq!(Some('x'), chars.next());
     assert_eq!(Some('c'), chars.next());
     assert_eq!((Some('d'), Some('e')), chars.peek_2());
+    assert_eq!((Some('d'), Some('e'), Some('z')), chars.peek_3());
+    assert_eq!(
+        [Some('d'), Some('e'), Some('z'), None],
+        chars.peek_array::<4>()
+    );
     let peek_4 = chars.peek_n(4);
     assert!(
         peek_4.len() == 4
@@ -45,9 +50,10 @@ fn peekable() {
 }
 
 //
-// test Limits: parse all modes, confirm check() works. Also kind of tests Peekable
+// test Limits: parse all modes, confirm the deprecated check() still works. Also kind of tests Peekable
 //
 #[test]
+#[allow(deprecated)]
 fn limits_test() {
     let mut x = LOCK.lock().unwrap();
     *x += 1;
@@ -113,6 +119,86 @@ fn limits_test() {
     assert!(chars.next().is_none(), "Failed to consume test string");
 }
 
+// the boundaries of reps_ok() and can_add_rep() for each form of repetition, which agree with check()
+#[test]
+#[allow(deprecated)]
+fn limits_reps() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let forms: [(&str, usize, usize); 16] = [
+        ("", 1, 1),
+        ("?", 0, 1),
+        ("*", 0, EFFECTIVELY_INFINITE),
+        ("+", 1, EFFECTIVELY_INFINITE),
+        ("{0}", 0, 0),
+        ("{0,0}", 0, 0),
+        ("{2}", 2, 2),
+        ("{0,}", 0, EFFECTIVELY_INFINITE),
+        ("{3,}", 3, EFFECTIVELY_INFINITE),
+        ("{0,3}", 0, 3),
+        ("{3,5}", 3, 5),
+        ("??", 0, 1),
+        ("*?", 0, EFFECTIVELY_INFINITE),
+        ("+?", 1, EFFECTIVELY_INFINITE),
+        ("{0}?", 0, 0),
+        ("{3,5}?", 3, 5),
+    ];
+    for (form, min, max) in forms {
+        let limits = if form.is_empty() {
+            Limits::default()
+        } else {
+            Limits::parse(&mut Peekable::new(form)).unwrap()
+        };
+        assert_eq!((min, max), (limits.min, limits.max), "{}", form);
+        assert_eq!(
+            form.len() > 1 && form.ends_with('?'),
+            limits.lazy(),
+            "{}",
+            form
+        );
+        if min > 0 {
+            assert!(!limits.reps_ok(min - 1), "{}: below min", form);
+        }
+        assert!(limits.reps_ok(min), "{}: min", form);
+        assert!(limits.reps_ok(max), "{}: max", form);
+        assert!(!limits.reps_ok(max + 1), "{}: above max", form);
+        assert_eq!(max > min, limits.can_add_rep(min), "{}: add to min", form);
+        assert!(!limits.can_add_rep(max), "{}: add to max", form);
+        if max > 0 {
+            assert!(limits.can_add_rep(max - 1), "{}: add up to max", form);
+        }
+        // and the walk takes exactly the counts allowed
+        let tree = parse_tree(&format!("ba{}c", form), false).unwrap();
+        for reps in 0..8 {
+            Input::init_text(&format!("b{}c", "a".repeat(reps)), false).unwrap();
+            assert_eq!(
+                limits.reps_ok(reps),
+                walk_tree(&tree, 0).unwrap().is_some(),
+                "{}: {} reps",
+                form,
+                reps
+            );
+        }
+        // the shim takes the length of the steps vector, one more than the repetitions
+        for reps in [0, 1, 2, 3, 4, 5, 6, max, max + 1] {
+            assert_eq!(
+                limits.reps_ok(reps),
+                limits.check(reps + 1) == 0,
+                "{}: {}",
+                form,
+                reps
+            );
+            assert_eq!(
+                limits.can_add_rep(reps),
+                limits.check(reps + 2) == 0,
+                "{}: {}",
+                form,
+                reps
+            );
+        }
+    }
+}
+
 //
 // parser tests
 //
@@ -122,6 +208,7 @@ fn make_chars_string(string: &str) -> Node {
         limits: Limits::default(),
         named: None,
         name_outside: false,
+        span: SourceSpan::default(),
     })
 }
 fn make_chars_single(ch: char, min: usize, max: usize, lazy: bool) -> Node {
@@ -131,6 +218,7 @@ fn make_chars_single(ch: char, min: usize, max: usize, lazy: bool) -> Node {
         limits: Limits { min, max, options },
         named: None,
         name_outside: false,
+        span: SourceSpan::default(),
     })
 }
 
@@ -147,6 +235,7 @@ fn make_and(min: usize, max: usize, lazy: bool, name: Option<&str>) -> Node {
         named,
         anchor: false,
         name_outside: false,
+        span: SourceSpan::default(),
     })
 }
 fn make_or() -> Node {
@@ -155,6 +244,7 @@ fn make_or() -> Node {
         limits: Limits::default(),
         named: None,
         name_outside: false,
+        span: SourceSpan::default(),
     })
 }
 
@@ -356,7 +446,7 @@ fn set_chars() {
     find(false, r"[a\wx-z]+", "qa x\ty\nz q", "a x\ty\nz ");
 }
 
-// #[test]
+#[test]
 fn non_set_chars() {
     let mut x = LOCK.lock().unwrap();
     *x += 1;
@@ -366,6 +456,12 @@ fn non_set_chars() {
     not_find(false, "[^abcd]+", "abcdab");
     find(false, r"[^\d\l]*", "ABCd123", "ABC");
     find(false, r"[^\d\l]*", "ABCD123", "ABCD");
+    // a leading ']' is a literal member of the set
+    find(false, r"[^]]+", "]]ab]", "ab");
+    find(false, r"x[]a]+", "x]a]b", "x]a]");
+    find(false, r"[^-]+", "--ab-", "ab");
+    find(false, r"[^你-好]+", "你好abc好", "abc");
+    find(true, r"[^]a]+", "a]bc]", "bc");
 }
 
 #[test]
@@ -532,9 +628,63 @@ fn reports() {
             assert!(second.len() == 2);
             check_report(second[0], "ef", (5, 7), (5, 7), 0);
             check_report(second[1], "ef", (7, 9), (7, 9), 0);
-            assert!(all.get("fake").is_none());
+            assert!(!all.contains_key("fake"));
+        },
+    );
+}
+
+#[test]
+fn prune_report() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    report_test(
+        r"ab\(?<first>cd\(?<second>ef\)+\)+",
+        ".abcdefefcd.",
+        false,
+        |report| {
+            // keeping the inner name keeps the unit containing it
+            let pruned = report.prune(&["second"]);
+            check_report(&pruned, "abcdefef", (1, 9), (1, 9), 1);
+            check_report(&pruned.subreports[0], "cdefef", (3, 9), (3, 9), 2);
+            check_report(&pruned.subreports[0].subreports[0], "ef", (5, 7), (5, 7), 0);
+            check_report(&pruned.subreports[0].subreports[1], "ef", (7, 9), (7, 9), 0);
+            // keeping the outer name drops what is inside it
+            let pruned = report.prune(&["first"]);
+            check_report(&pruned, "abcdefef", (1, 9), (1, 9), 1);
+            check_report(&pruned.subreports[0], "cdefef", (3, 9), (3, 9), 0);
+            assert_eq!(Some("first".to_string()), pruned.subreports[0].name);
+            // unknown names leave just the root
+            let pruned = report.prune(&["fake"]);
+            check_report(&pruned, "abcdefef", (1, 9), (1, 9), 0);
+            assert_eq!(3, report.prune(&["first", "second"]).iter().count() - 1);
+            assert_eq!(0, report.prune(&[]).subreports.len());
+        },
+    );
+    // the units in between that are not kept are dropped
+    report_test(
+        r#"and(and("x"<a> "y"<b>)<c> "z"<d>)<e>"#,
+        "xyz",
+        true,
+        |report| {
+            let pruned = report.prune(&["a"]);
+            let mut names = Vec::new();
+            pruned.visit(|report, depth| names.push((report.name.clone().unwrap(), depth)));
+            let expected = [("", 0), ("e", 1), ("c", 2), ("a", 3)];
+            assert_eq!(
+                expected
+                    .map(|(name, depth)| (name.to_string(), depth))
+                    .to_vec(),
+                names
+            );
         },
     );
+    // --only-named takes a comma separated list
+    use clap::Parser;
+    let config = crate::Config::parse_from(["regexp", "--only-named", "one,two", "x"]);
+    assert_eq!(
+        vec!["one".to_string(), "two".to_string()],
+        config.only_named
+    );
 }
 //
 // error tests
@@ -563,9 +713,435 @@ fn errors() {
     // 5 should not happen
     e_check(false, r"asd\)as", 6);
     e_check(false, r"asd{as", 7);
-    e_check(false, r"asd{4as", 7);
+    e_check(false, r"asd{4as", 13);
     e_check(false, r"asd{4,x", 8);
+    e_check(false, r"asd{4", 14);
+    e_check(false, r"asd{4,5", 14);
     e_check(false, r"abc[de", 9);
+    e_check(false, r"abc\", 15);
+    e_check(false, r"abc\c", 16);
+    e_check(false, r"abc\|", 17);
+    e_check(true, r#""asd{as""#, 7);
+    e_check(true, r#""asd"{4as"#, 13);
+    e_check(true, r#""asd"{4,x}"#, 8);
+    e_check(true, r#""asd"{4"#, 14);
+}
+
+#[test]
+fn dangling_errors() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    for (alt, re, code, msg) in [
+        (false, r"ab\", 15, "'\\' at position 2"),
+        (false, r"ab\\\", 15, "'\\' at position 4"),
+        (true, r#""ab\"#, 15, "'\\' at position 3"),
+        (false, r"ab\c", 16, "position 2"),
+        (false, r"\Cab\(\C\)", 16, "position 6"),
+        (true, r#"'ab' "\c""#, 16, "position 6"),
+        (true, r"ab \c", 16, "position 3"),
+        (false, r"ab[c-", 9, "range starting at position 2"),
+        (false, r"a\(b\(c\)", 1, "group starting at position 1"),
+        (false, r"a\(?<ab", 2, "group name starting at position 4"),
+        (false, r"a\|", 17, "position 1"),
+        (false, r"a\(b\|\)", 17, "position 4"),
+        (true, r#"'a' "bc"#, 102, "string starting at position 4"),
+        (true, r"'a' txt(bc", 102, "txt() starting at position 4"),
+        (true, r"'a' and('b'", 104, "and() starting at position 4"),
+        (true, r"'a' or('b' 'c'", 105, "or() starting at position 4"),
+        (true, r"'a'<ab", 110, "name starting at position 3"),
+        (true, r"'a' use(", 113, "use() starting at position 4"),
+        (
+            true,
+            r"'a' none('b",
+            116,
+            "none() unit starting at position 4",
+        ),
+        (
+            true,
+            r"'a' until('b'",
+            119,
+            "until() unit starting at position 4",
+        ),
+        (true, r"'a' get(ab", 121, "get() starting at position 4"),
+        (true, r"'a' def(ab", 122, "def() starting at position 4"),
+        (
+            true,
+            r"'a' def(ab: 'c'",
+            122,
+            "def() starting at position 4",
+        ),
+    ] {
+        match parse_tree(re, alt) {
+            Ok(_) => panic!("Expected error {} parsing \"{}\"", code, re),
+            Err(error) => {
+                assert_eq!(error.code, code, "\"{}\" gave \"{}\"", re, error.msg);
+                assert!(error.msg.contains(msg), "\"{}\" gave \"{}\"", re, error.msg);
+            }
+        }
+    }
+    // escaped backslashes are not dangling
+    assert!(parse_tree(r"ab\\", false).is_ok());
+    assert!(parse_tree(r"ab\\c", false).is_ok());
+    assert!(parse_tree(r#""ab\\""#, true).is_ok());
+}
+
+#[test]
+fn repetition_errors() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    for (re, code, msg) in [
+        (r"asd{as", 7, "found 'a' at position 4"),
+        (r"asd{4as", 13, "found 'a' at position 5"),
+        (r"asd{4,x", 8, "found 'x' at position 6"),
+        (r"asd{4,5", 14, "starting at position 3"),
+        (r"^asd{4", 14, "starting at position 4"),
+    ] {
+        match parse_tree(re, false) {
+            Ok(_) => panic!("Expected error {} parsing \"{}\"", code, re),
+            Err(error) => {
+                assert_eq!(error.code, code, "{}", error.msg);
+                assert!(error.msg.contains(msg), "\"{}\" gave \"{}\"", re, error.msg);
+            }
+        }
+    }
+}
+
+#[test]
+fn literal_braces() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let was = set_literal_braces(true);
+    let braces = parse_tree(r"a{x}", false);
+    let bad_count = parse_tree(r"a{2x}", false);
+    let count = parse_tree(r"a{2}", false);
+    let alt_braces = parse_tree(r#""a{x}""#, true);
+    set_literal_braces(was);
+    let tree = braces.unwrap();
+    Input::init_text("za{x}", false).unwrap();
+    let path = walk_tree(&tree, 0).unwrap().unwrap();
+    assert_eq!(path.range(), (1, 5));
+    assert_eq!(bad_count.unwrap_err().code, 13);
+    assert!(count.is_ok());
+    assert!(alt_braces.is_ok());
+    assert_eq!(parse_tree(r"a{x}", false).unwrap_err().code, 7);
+}
+
+#[test]
+fn extra_chars() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    use crate::regexp::{parse_tree_opts, SearchOptions};
+    // a closer with nothing open is an error saying where the leftover text starts
+    let error = parse_tree(r"ab\)c", false).unwrap_err();
+    assert_eq!(error.code, 6);
+    assert!(error.msg.contains(r#"position 2: "\)c""#), "{}", error.msg);
+    let error = parse_tree(r#""ab") "c""#, true).unwrap_err();
+    assert_eq!(error.code, 6);
+    assert!(
+        error.msg.contains(r#"position 4: ") "c"""#),
+        "{}",
+        error.msg
+    );
+    assert_eq!(parse_tree(r"\)c", false).unwrap_err().code, 6);
+    // leniently it is literal text, with a warning
+    let lenient = SearchOptions::new().lenient(true);
+    let (tree, warnings) = parse_tree_opts(r"ab\)c", &lenient).unwrap();
+    assert!(warnings.iter().any(|w| w.code == 8 && w.pos == Some(2)));
+    Input::init_text("xab)c", false).unwrap();
+    assert_eq!(walk_tree(&tree, 0).unwrap().unwrap().range(), (1, 5));
+    let (tree, _) = parse_tree_opts(r"^\)c", &lenient).unwrap();
+    Input::init_text(")c)c", false).unwrap();
+    assert_eq!(walk_tree(&tree, 0).unwrap().unwrap().range(), (0, 2));
+    let alt = SearchOptions::new().alt_parser(true).lenient(true);
+    let (tree, warnings) = parse_tree_opts(r#""ab") x"#, &alt).unwrap();
+    assert!(warnings.iter().any(|w| w.code == 8 && w.pos == Some(4)));
+    Input::init_text("ab) x", false).unwrap();
+    assert_eq!(walk_tree(&tree, 0).unwrap().unwrap().range(), (0, 5));
+    // the setting only lasts for the parse
+    assert_eq!(parse_tree(r"ab\)c", false).unwrap_err().code, 6);
+    // a stray '>' is an ordinary char either way
+    for options in [SearchOptions::new(), SearchOptions::new().lenient(true)] {
+        let (_, warnings) = parse_tree_opts("ab>c", &options).unwrap();
+        assert!(warnings.iter().all(|w| w.code != 8));
+        let (_, warnings) = parse_tree_opts(r#""ab" >"#, &options.alt_parser(true)).unwrap();
+        assert!(warnings.iter().all(|w| w.code != 8));
+    }
+    let tree = parse_tree(r#"and("ab")<n>> "c""#, true).unwrap();
+    Input::init_text("ab>c", false).unwrap();
+    assert_eq!(walk_tree(&tree, 0).unwrap().unwrap().range(), (0, 4));
+}
+//
+// warning tests
+//
+fn w_check(alt: bool, re: &str, wcode: usize, pos: Option<usize>) {
+    match parse_tree_with_warnings(re, alt) {
+        Err(error) => panic!("Parsing \"{}\" failed: {}", re, error),
+        Ok((_, warnings)) => assert!(
+            warnings.iter().any(|w| w.code == wcode && w.pos == pos),
+            "Parsing \"{}\", expected warning {} at {:?}, found {:?}",
+            re,
+            wcode,
+            pos,
+            warnings
+        ),
+    }
+}
+fn no_warnings(alt: bool, re: &str) {
+    match parse_tree_with_warnings(re, alt) {
+        Err(error) => panic!("Parsing \"{}\" failed: {}", re, error),
+        Ok((_, warnings)) => assert!(
+            warnings.is_empty(),
+            "Parsing \"{}\", expected no warnings, found {:?}",
+            re,
+            warnings
+        ),
+    }
+}
+
+#[test]
+fn nested_reps_warning() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    w_check(false, r"x\(a+\)*", 1, None);
+    w_check(true, r"and('a'+ 'b'?)*", 1, None);
+    w_check(true, r"or('a'+ 'b')*", 1, None);
+    no_warnings(false, r"\(a+b\)*");
+    no_warnings(true, r"and('a'+)");
+}
+
+#[test]
+fn dollar_warning() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    w_check(false, r"ab$cd", 2, Some(2));
+    w_check(false, r"^ab$cd", 2, Some(3));
+    no_warnings(false, r"abcd$");
+    no_warnings(false, r"\(ab$\)");
+    no_warnings(false, r"ab$\|cd");
+}
+
+#[test]
+fn unused_def_warning() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    w_check(true, r"def(aa: 'xyz') def(bb: 'x') w get(aa)", 3, Some(19));
+    no_warnings(true, r"def(aa: 'xyz') w get(aa)");
+    // definitions loaded from a file don't need to be used
+    no_warnings(true, r"use(src/regexp/test.re) a get(a)");
+}
+
+#[test]
+fn range_warning() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    w_check(false, r"x[abca]", 4, Some(2));
+    w_check(false, r"x[a-md]", 4, Some(2));
+    w_check(true, r"x[a-mk-z]", 4, Some(2));
+    no_warnings(false, r"x[a-mn-z]");
+}
+
+#[test]
+fn repetition_warning() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    w_check(false, r"ab{0}c", 5, Some(5));
+    w_check(false, r"ab{5,3}c", 5, Some(7));
+    w_check(true, r"and('ab'){0,0}", 5, Some(14));
+    no_warnings(false, r"ab{3,5}c");
+}
+
+//
+// optimization tests
+//
+/// flattens a Report into a list of (name, start, end) so two Reports can be compared
+fn report_spans(report: &Report, spans: &mut Vec<(Option<String>, (usize, usize))>) {
+    spans.push((report.name.clone(), report.byte_pos()));
+    report
+        .subreports
+        .iter()
+        .for_each(|r| report_spans(r, spans));
+}
+
+/// checks that the optimized and unoptimized trees give the same results
+fn same_optimized(alt: bool, re: &str, text: &str) {
+    let mut results = Vec::<Option<Vec<(Option<String>, (usize, usize))>>>::new();
+    for optimize in [false, true] {
+        let (tree, _) = parse_tree_full(re, alt, optimize)
+            .unwrap_or_else(|msg| panic!("Parse failed for re \"{}\": {}", re, msg));
+        if let Err(msg) = Input::init_text(text, false) {
+            panic!("{}", msg);
+        }
+        results.push(
+            walk_tree(&tree, 0)
+                .unwrap_or_else(|err| panic!("RE \"{}\" walk failed: {}", re, err))
+                .map(|path| {
+                    let mut spans = Vec::new();
+                    report_spans(&Report::new(&path), &mut spans);
+                    spans
+                }),
+        );
+    }
+    assert_eq!(
+        results[0], results[1],
+        "optimizing \"{}\" changed the results",
+        re
+    );
+}
+
+#[test]
+fn optimize_tree() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // adjacent strings are joined
+    let mut node = make_root(1, 1, false);
+    node.push(make_chars_string("abcd"));
+    assert_eq!(node, parse_tree("'ab' 'cd'", true).unwrap());
+    assert_ne!(node, parse_tree_full("'ab' 'cd'", true, false).unwrap().0);
+    // silent ANDs are flattened
+    let mut node = make_root(1, 1, false);
+    node.push(make_chars_string("abcdef"));
+    assert_eq!(node, parse_tree(r"ab\(?cd\)ef", false).unwrap());
+    // ... but recorded ones are not
+    let mut node = make_root(1, 1, false);
+    node.push(make_chars_string("ab"));
+    let mut and_node = make_and(1, 1, false, Some(""));
+    and_node.push(make_chars_string("cd"));
+    node.push(and_node);
+    node.push(make_chars_string("ef"));
+    assert_eq!(node, parse_tree(r"ab\(cd\)ef", false).unwrap());
+    // single child ANDs and ORs are replaced by the child
+    let mut node = make_root(1, 1, false);
+    node.push(make_chars_string("abc"));
+    assert_eq!(node, parse_tree("or('abc')", true).unwrap());
+    let mut node = make_root(1, 1, false);
+    node.push(make_chars_single('x', 0, EFFECTIVELY_INFINITE, false));
+    assert_eq!(node, parse_tree("and('x')*", true).unwrap());
+
+    same_optimized(true, "'ab' 'cd' and('ef' and('gh'))", "xabcdefghi");
+    same_optimized(true, "and('x'<n>)* y", "xxxy");
+    same_optimized(true, "and('x'{1}<n>)* y", "xxxy");
+    same_optimized(true, "or(and('ab' 'c') 'abd')+<n>", "abdabcz");
+    same_optimized(true, r"a and('bc'<n1>* '你好'*<n2>)", "xabcbc你好你好");
+    same_optimized(false, r"ab\(?cd\(ef\)+\)+", ".abcdefefcd.");
+    same_optimized(false, r"x\(abc\)+\|\(de\)*d", "xxxdededede");
+}
+
+/// parses RE (optimized or not), searches TEXT for it, and returns the number of steps taken. The literal scan is
+/// turned off, so only the tree changes the count.
+fn count_steps(alt: bool, re: &str, text: &str, optimize: bool) -> usize {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    let (tree, _) = parse_tree_full(re, alt, optimize)
+        .unwrap_or_else(|msg| panic!("Parse failed for re \"{}\": {}", re, msg));
+    if let Err(msg) = Input::init_text(text, false) {
+        panic!("{}", msg);
+    }
+    reset_step_count();
+    let options = set_walk_options(WalkOptions::new().no_scan());
+    walk_tree(&tree, 0).unwrap();
+    set_walk_options(options);
+    step_count()
+}
+
+#[test]
+fn or_prefix() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut node = make_root(1, 1, false);
+    node.push(make_chars_string("prefix_"));
+    let mut or_node = make_or();
+    or_node.push(make_chars_string("a"));
+    or_node.push(make_chars_string("b"));
+    or_node.push(make_chars_string("c"));
+    node.push(or_node);
+    assert_eq!(
+        node,
+        parse_tree("or('prefix_a' 'prefix_b' 'prefix_c')", true).unwrap()
+    );
+    // every branch keeps at least one char
+    let mut node = make_root(1, 1, false);
+    node.push(make_chars_string("a"));
+    let mut or_node = make_or();
+    or_node.push(make_chars_string("b"));
+    or_node.push(make_chars_string("bc"));
+    node.push(or_node);
+    assert_eq!(node, parse_tree("or('ab' 'abc')", true).unwrap());
+    // named branches are left alone
+    assert!(matches!(
+        &parse_tree("or('abc'<x> 'abd')", true).unwrap(),
+        Node::And(a) if matches!(a.nodes[0], Node::Or(_))
+    ));
+
+    same_optimized(true, "or('prefix_a' 'prefix_b' 'prefix_c')<n>", "xprefix_c");
+    same_optimized(
+        true,
+        "or('prefix_a' 'prefix_b' 'prefix_c')+<n>",
+        "prefix_cprefix_bprefix_",
+    );
+    same_optimized(true, "or(and('ab' 'c'<n>) 'abd' 'a'+)*", "abdabcaaz");
+    same_optimized(true, "or('你好a' '你好b')", "x你好b");
+    same_optimized(false, r"\(?abc\)\|\(?abd\)\|\(?abe\)", "xxabe");
+    // the prefix is only tried once for each position, so positions where the OR fails are cheaper
+    for text in ["prefix_x prefix_b", "some text before prefix_c"] {
+        let re = "or('prefix_a' 'prefix_b' 'prefix_c')";
+        assert!(count_steps(true, re, text, true) < count_steps(true, re, text, false));
+    }
+}
+
+//
+// canonical form tests
+//
+fn canonical_hash(alt: bool, re: &str) -> (Node, u64) {
+    use std::hash::{Hash, Hasher};
+    let mut tree =
+        parse_tree(re, alt).unwrap_or_else(|msg| panic!("Parse failed for re \"{}\": {}", re, msg));
+    tree.canonicalize();
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    tree.hash(&mut hasher);
+    (tree, hasher.finish())
+}
+
+fn same_canonical(re0: (bool, &str), re1: (bool, &str)) {
+    let (tree0, hash0) = canonical_hash(re0.0, re0.1);
+    let (tree1, hash1) = canonical_hash(re1.0, re1.1);
+    assert_eq!(
+        tree0, tree1,
+        "\"{}\" and \"{}\" should be equal",
+        re0.1, re1.1
+    );
+    assert_eq!(
+        hash0, hash1,
+        "\"{}\" and \"{}\" should hash equal",
+        re0.1, re1.1
+    );
+}
+
+#[test]
+fn canonical() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    same_canonical((true, "'ab'   'cd'"), (true, "abcd"));
+    same_canonical((true, "'ab' 'cd'"), (false, "abcd"));
+    same_canonical((false, "ab{1}cd"), (false, "abcd"));
+    same_canonical((false, "abc{2}?d"), (false, "abc{2}d"));
+    same_canonical((true, "a 'x'{1}<n>"), (true, "a 'x'<n>"));
+    same_canonical(
+        (true, "def(d: 'xyz') get(d)"),
+        (true, "def(d:'xyz')   get(d)"),
+    );
+
+    let (tree0, hash0) = canonical_hash(false, "abc*d");
+    let (tree1, hash1) = canonical_hash(false, "abc*?d");
+    assert_ne!(tree0, tree1);
+    assert_ne!(hash0, hash1);
+    // definitions with different contents are different
+    let (tree0, hash0) = canonical_hash(true, "def(d: 'xyz') get(d)");
+    let (tree1, hash1) = canonical_hash(true, "def(d: 'xyw') get(d)");
+    assert_ne!(tree0, tree1);
+    assert_ne!(hash0, hash1);
+
+    let mut cache = std::collections::HashMap::<Node, usize>::new();
+    cache.insert(canonical_hash(true, "'ab' 'cd'*").0, 1);
+    assert_eq!(cache.get(&canonical_hash(true, "ab 'cd'{0,}").0), Some(&1));
 }
 
 #[test]
@@ -640,18 +1216,202 @@ fn alt_def() {
     );
 }
 
+#[test]
+fn expanded_source() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // the alt_def expressions, with their definitions written out
+    for (re, expanded, text) in [
+        ("def(xx: 'xyz') w get(xx)", r#""w" "xyz""#, "vwxyz"),
+        (
+            "def(aa: 'xyz') w get(aa) get(aa)",
+            r#""w" "xyz" "xyz""#,
+            "vwxyzxyz",
+        ),
+        (
+            "use(src/regexp/test.re) a get(a)",
+            r#""a" "bcd""#,
+            "aabcdef",
+        ),
+        (
+            "use(src/regexp/test.re) a get(z)+",
+            r#""a" or("wx" "yz")+"#,
+            "aawxyzwx",
+        ),
+        (
+            r"def(a: x ){3} get(a) b get(a){4}",
+            r#""x"{3} "b" "x"{4}"#,
+            "zxxxxbxxxxxx",
+        ),
+    ] {
+        let (tree, _) = parse_tree_full(re, true, true).unwrap();
+        assert_eq!(expanded, tree.to_alt_source());
+        // it finds the same match without the definitions
+        let (again, _) = parse_tree_full(expanded, true, true).unwrap();
+        Input::init_text(text, false).unwrap();
+        let range = walk_tree(&tree, 0).unwrap().map(|path| path.range());
+        assert_eq!(
+            range,
+            walk_tree(&again, 0).unwrap().map(|path| path.range())
+        );
+    }
+    // the notes mark where each definition went, including ones inside others
+    let (tree, _) = parse_tree_full(
+        r"^def(n: '\d+') def(p: get(n) and('-' get(n)){2}) get(p)<ip> ' '",
+        true,
+        true,
+    )
+    .unwrap();
+    assert_eq!(
+        r#"^and("\d"+ and("-" "\d"+){2})<ip> " ""#,
+        tree.to_alt_source()
+    );
+    assert_eq!(
+        r#"^and(  # from def p
+  "\d"+  # from def n
+  and(
+    "-"
+    "\d"+  # from def n
+  ){2}
+)<ip>
+" ""#,
+        tree.to_annotated_source()
+    );
+    // traditional REs come out in the alternative syntax, escaping chars that would mean something there
+    let (tree, _) = parse_tree_full(r"a\(x\|yz\)*\n<\.", false, true).unwrap();
+    assert_eq!(
+        r#""a" and(or("x" "y") "z")<>* "\n\<\.""#,
+        tree.to_alt_source()
+    );
+}
+
+#[test]
+fn def_namespaces() {
+    use crate::regexp::Regexp;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // two libraries with the same names
+    let dir = std::env::temp_dir();
+    let net = dir.join("regexp_ns_net.re").to_string_lossy().to_string();
+    let web = dir.join("regexp_ns_web.re").to_string_lossy().to_string();
+    std::fs::write(
+        &net,
+        "def(ns_part: '\\d+')\ndef(ns_addr: get(ns_part) and('.' get(ns_part)){3})\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &web,
+        "def(ns_part: '[a-z]+')\ndef(ns_addr: get(ns_part) and('.' get(ns_part))+ '.com')\n",
+    )
+    .unwrap();
+    let re = Regexp::new_alt(&format!(
+        "use({} as net) use({} as web) get(net.ns_addr)<ip> ' ' get(web.ns_addr)<host>",
+        net, web
+    ))
+    .unwrap();
+    let found = re.captures("at 10.0.0.1 www.example.com").unwrap().unwrap();
+    assert_eq!("10.0.0.1", found.name("ip").unwrap().as_str());
+    assert_eq!("www.example.com", found.name("host").unwrap().as_str());
+    // nothing went into the flat table, and the saved text reloads to the same definition
+    assert!(get_def("ns_addr").is_none());
+    assert_eq!(
+        Some("def(web.ns_addr: get(web.ns_part) and('.' get(web.ns_part))+ '.com')".to_string()),
+        def_source("web.ns_addr")
+    );
+    let tree = get_def("net.ns_addr").unwrap();
+    delete_def("net.ns_addr");
+    load_defs(&def_source("web.ns_addr").unwrap().replace("web.", "net.")).unwrap();
+    assert_ne!(tree, get_def("net.ns_addr").unwrap());
+    delete_def("net.ns_addr");
+    load_defs("def(net.ns_addr: get(net.ns_part) and('.' get(net.ns_part)){3})").unwrap();
+    assert_eq!(tree, get_def("net.ns_addr").unwrap());
+    // unqualified names still use the flat table
+    let re = Regexp::new_alt(&format!("use({}) get(ns_addr)", web)).unwrap();
+    assert!(re.is_match("a.b.com").unwrap());
+    assert!(get_def("ns_part").is_some());
+    // a missing name lists the ones it could have meant
+    for name in ["ns_part", "ns_addr"] {
+        delete_def(name);
+    }
+    let error = parse_tree("get(ns_adr)", true).unwrap_err();
+    assert_eq!(108, error.code);
+    assert!(
+        error
+            .msg
+            .contains("did you mean net.ns_addr or web.ns_addr?"),
+        "{}",
+        error.msg
+    );
+    assert!(!parse_tree("get(ns_nothing_like_it)", true)
+        .unwrap_err()
+        .msg
+        .contains("did you mean"));
+    e_check(true, &format!("use({} as)", net), 113);
+    for name in def_names() {
+        if name.starts_with("net.") || name.starts_with("web.") {
+            delete_def(&name);
+        }
+    }
+    std::fs::remove_file(net).unwrap();
+    std::fs::remove_file(web).unwrap();
+}
+
+#[test]
+fn def_file_paths() {
+    use crate::regexp::{set_whitespace, Regexp, Whitespace};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // a quoted path is taken as it is, spaces and all
+    let dir = std::env::temp_dir().join("regexp defs with spaces");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("my defs.re").to_string_lossy().to_string();
+    std::fs::write(&path, "def(qp_digits: '\\d+')\n").unwrap();
+    for quoted in [format!("\"{}\"", path), format!("'{}'", path)] {
+        let re = Regexp::new_alt(&format!("use( {} ) get(qp_digits)", quoted)).unwrap();
+        assert!(re.is_match("a12").unwrap());
+    }
+    delete_def("qp_digits");
+    crate::regexp::tree::load_def_file(&path).unwrap();
+    assert!(get_def("qp_digits").is_some());
+    delete_def("qp_digits");
+    e_check(true, &format!("use(\"{}", path), 113);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_dir(&dir).unwrap();
+    // Windows paths are read whole, so the error is for the file not being there rather than a bad use()
+    let error = parse_tree(r"use(C:\no\such\defs.re) 'a'", true).unwrap_err();
+    assert_eq!(
+        (114, true),
+        (error.code, error.msg.contains(r"C:\no\such\defs.re")),
+        "{}",
+        error
+    );
+    // CRLF line endings are read as plain newlines, so lines can still be continued with '\'
+    let previous = set_whitespace(Whitespace::new().strict_lines());
+    let loaded = crate::regexp::tree::load_def_file("src/regexp/test_crlf.re");
+    set_whitespace(previous);
+    loaded.unwrap();
+    load_defs("def(crlf_lf: or(cat dog bird ))").unwrap();
+    assert_eq!(get_def("crlf_lf"), get_def("crlf_pet"));
+    let re = Regexp::new_alt("get(crlf_pets)").unwrap();
+    assert_eq!(
+        Some(2..10),
+        re.find("a dog, cat").unwrap().map(|found| found.range())
+    );
+    for name in ["crlf_lf", "crlf_pet", "crlf_pets"] {
+        delete_def(name);
+    }
+}
 #[test]
 fn alt_err() {
     let mut x = LOCK.lock().unwrap();
     *x += 1;
     e_check(true, "\"asd", 102);
     e_check(true, r"and(abc def)", 104);
-    // the OR reads the trailing ')' for the wrapping AND node, which is why this is not 105
-    e_check(true, r"or(abc def)", 104);
+    e_check(true, r"or(abc def)", 105);
     e_check(true, r"or(abc or(def ", 105);
     e_check(true, r"get() ", 106);
     e_check(true, r"get(a() ", 107);
-    //    e_check(true, r"get(a) ", 108);
+    e_check(true, r"get(no_such_def) ", 108);
     e_check(
         true,
         r"def(snippet_a: aa get(snippet_b)zz ) def(snippet_b: bb get(snippet_a) yy ) abcd get(snippet_a) wxyz ",
@@ -667,17 +1427,23 @@ fn alt_err() {
 fn runtime_error() {
     let mut x = LOCK.lock().unwrap();
     *x += 1;
-    // infinite loop test
-    if let Ok(tree) = parse_tree(r"and('x'*)*", true) {
-        if let Err(msg) = Input::init_text("abccc", false) {
-            panic!("{}", msg);
-        }
-        match walk_tree(&tree, 0) {
-            Err(e) if e.code == 200 => (),
-            _ => panic!("Expected infinite loop, didn't get it"),
-        }
-    } else {
-        panic!("Parse failed for infinite loop test");
+    // infinite loops are caught at parse time
+    e_check(true, r"and('x'*)*", 10);
+    e_check(true, r"or('x'* 'y')+", 10);
+    e_check(true, r"def(a: and('x'?)) get(a){2,}", 10);
+    e_check(false, r"\(x*\)*", 10);
+    e_check(false, r"$*", 10);
+    // ... but the walk still checks for them in case the tree was built some other way
+    let mut inner = make_and(0, EFFECTIVELY_INFINITE, false, None);
+    inner.push(make_chars_single('x', 0, EFFECTIVELY_INFINITE, false));
+    let mut tree = make_root(1, 1, false);
+    tree.push(inner);
+    if let Err(msg) = Input::init_text("abccc", false) {
+        panic!("{}", msg);
+    }
+    match walk_tree(&tree, 0) {
+        Err(e) if e.code == 200 => (),
+        _ => panic!("Expected infinite loop, didn't get it"),
     }
 }
 
@@ -704,24 +1470,4513 @@ fn alt_report() {
 }
 
 #[test]
-fn from_file() {
+fn trad_report() {
     let mut x = LOCK.lock().unwrap();
     *x += 1;
-    print!("RUNNING file input test");
-    std::io::stdout().flush().unwrap();
-    let tree = parse_tree("XXX", false)
-        .unwrap_or_else(|msg| panic!("Parse failed for re \"XXX\": {}", msg));
-    if let Err(msg) = Input::init_files(&vec!["README".to_string()], false) {
-        panic!("{}", msg);
-    }
-    let path = walk_tree(&tree, 0)
-        .unwrap_or_else(|err| panic!("Expected \"XXX\", got error '{}'", err))
-        .unwrap_or_else(|| panic!("Expected \"XXX\", found none"));
+    report_test(
+        r"a\(?<n1>bc\)*\(?<n2+>你好\)*",
+        "xabcbc你好你好",
+        false,
+        |report| {
+            check_report(report, "abcbc你好你好", (1, 10), (1, 18), 3);
+            check_report(&report.subreports[0], "bc", (2, 4), (2, 4), 0);
+            check_report(&report.subreports[1], "bc", (4, 6), (4, 6), 0);
+            check_report(&report.subreports[2], "你好你好", (6, 10), (6, 18), 0);
+            let n1 = report.get_by_name("n1");
+            assert_eq!(n1.len(), 2, "?<n> test failed");
+            let n2 = report.get_by_name("n2");
+            assert_eq!(n2.len(), 1, "?<n+> test failed");
+        },
+    );
+    // the same trees as the alternative syntax
     assert_eq!(
-        path.matched_string(),
-        "XXX",
-        "expected \"XXX\", found \"{}\"",
-        path.matched_string()
+        parse_tree(r"\(?<n+>ab\)+", false).unwrap(),
+        parse_tree("and('ab')+<n>", true).unwrap()
     );
-    println!("OK");
+    assert_eq!(
+        parse_tree(r"\(?<n>ab\)+", false).unwrap(),
+        parse_tree("and('ab')<n>+", true).unwrap()
+    );
+}
+
+#[test]
+// check that <NAME[N]> only reports the Nth repetition
+fn indexed_names() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // columns 2 and 4 of a 5 column record, the index counting in each repetition separately. The numbers are
+    // wrapped in and() since a name on "\d+" would pick from its digits.
+    report_test(
+        r#"and(and("\d+")<col[2]> ","){2} and(and("\d+")<col[2]> ","?)*"#,
+        "11,22,33,44,55",
+        true,
+        |report| {
+            let cols = report.get_by_name("col");
+            assert_eq!(2, cols.len());
+            Input::apply(|input| {
+                assert_eq!("22", cols[0].string(input));
+                assert_eq!("44", cols[1].string(input));
+            });
+            assert!(report.get_by_name("col[2]").is_empty());
+        },
+    );
+    report_test(
+        r#"and(and("\d+")<second[2]> ","? and("\d+")<col4[2]> ","?)*"#,
+        "11,22,33,44,55",
+        true,
+        |report| {
+            Input::apply(|input| {
+                assert_eq!("33", report.get_by_name("second")[0].string(input));
+                assert_eq!("44", report.get_by_name("col4")[0].string(input));
+            });
+        },
+    );
+    // the repetitions of a single unit
+    report_test(r#""\d"<d[3]>+"#, "12345", true, |report| {
+        let digits = report.get_by_name("d");
+        assert_eq!(1, digits.len());
+        assert_eq!((2, 3), digits[0].byte_pos());
+    });
+    // out of range indices give nothing
+    report_test(
+        r#"and(and("\d+")<col[6]> ","?)*"#,
+        "11,22,33,44,55",
+        true,
+        |report| {
+            assert!(report.get_by_name("col").is_empty());
+            assert_eq!(0, report.subreports.len());
+        },
+    );
+    report_test(r#""\d"<d[0]>+"#, "12345", true, |report| {
+        assert!(report.get_by_name("d").is_empty());
+    });
+    // named outside the repetition the index is for the level above, which here is the whole match
+    report_test(
+        r#"and("\d"<d[2]> ",")*<all[1]>"#,
+        "1,2,3,",
+        true,
+        |report| {
+            let all = report.get_by_name("all");
+            assert_eq!(1, all.len());
+            assert_eq!((0, 6), all[0].byte_pos());
+            let digits = report.get_by_name("d");
+            assert_eq!(1, digits.len());
+            assert_eq!((2, 3), digits[0].byte_pos());
+        },
+    );
+}
+
+#[test]
+fn alt_sets() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // '-', ']' and '^' are ordinary characters in a set
+    let caps = |re: &str, text: &'static str| {
+        crate::regexp::Regexp::new_alt(re)
+            .unwrap()
+            .captures(text)
+            .unwrap()
+            .map(|caps| caps[0].to_string())
+    };
+    assert_eq!(Some("-^]]".to_string()), caps("any('-]^')+", "ab-^]]cd"));
+    assert_eq!(Some("ab".to_string()), caps("none('-]^')+", "ab-^]]cd"));
+    assert_eq!(
+        Some("x\"y'".to_string()),
+        caps(r#"any("\"'xy")+"#, "ax\"y'b")
+    );
+    assert_eq!(Some("a\tb".to_string()), caps(r"'a' any('\t') 'b'", "a\tb"));
+    assert_eq!(None, caps("any('')", "abc"));
+    assert_eq!(Some("a".to_string()), caps("none('')", "abc"));
+    // the same as a range, with names and repetitions after it
+    assert_eq!(
+        parse_tree("[abc]", true).unwrap(),
+        parse_tree("any('abc')", true).unwrap()
+    );
+    assert_eq!(
+        parse_tree("'[^xyz-]'<n>+", true).unwrap(),
+        parse_tree("none(\"xyz-\")<n>+", true).unwrap()
+    );
+    report_test("'a' any('0123456789')*<num>", "xa123", true, |report| {
+        let num = report.get_by_name("num");
+        assert_eq!(1, num.len());
+        assert_eq!("123", num[0].text());
+    });
+    e_check(true, "any(abc)", 115);
+    e_check(true, "any('abc'x)", 115);
+    e_check(true, "none('abc", 116);
+    e_check(true, r"any('ab\d')", 117);
+    w_check(true, "any('abca')", 4, Some(4));
+    w_check(true, r"any('a\q')", 6, Some(6));
+}
+
+#[test]
+fn escapes() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let found = |alt: bool, re: &str, text: &str| {
+        let (tree, _) = parse_tree_full(re, alt, true)
+            .unwrap_or_else(|msg| panic!("Parse failed for re \"{}\": {}", re, msg));
+        Input::init_text(text, false).unwrap();
+        walk_tree(&tree, 0).unwrap().map(|path| path.range())
+    };
+    // each escape means the same char in every context, in both syntaxes
+    for (code, ch) in [
+        ('n', '\n'),
+        ('t', '\t'),
+        ('r', '\r'),
+        ('0', '\0'),
+        ('\\', '\\'),
+    ] {
+        let e = format!("\\{}", code);
+        let text = format!("a{}b", ch);
+        let twice = format!("a{}{}b", ch, ch);
+        for (alt, re, text, expected) in [
+            (false, format!("a{}b", e), &text, Some((0, 3))),
+            (false, format!("a[{}]b", e), &text, Some((0, 3))),
+            (false, format!("a[^{}]b", e), &text, None),
+            (false, format!("a{}+b", e), &twice, Some((0, 4))),
+            (false, format!("{}{{2}}", e), &twice, Some((1, 3))),
+            (true, format!("\"a{}b\"", e), &text, Some((0, 3))),
+            (true, format!("'a{}b'", e), &text, Some((0, 3))),
+            (true, format!("txt(a{}b)", e), &text, Some((0, 3))),
+            (true, format!("a{}b ", e), &text, Some((0, 3))),
+            (true, format!("'a{}+b'", e), &twice, Some((0, 4))),
+            (true, format!("'a[{}]b'", e), &text, Some((0, 3))),
+            (true, format!("'a' any('{}') 'b'", e), &text, Some((0, 3))),
+            (true, format!("'a' none('{}') 'b'", e), &text, None),
+            (true, format!("'a' upto('{}') 'b'", e), &text, None),
+            (true, format!("'a' until('{}')", e), &text, Some((0, 1))),
+        ] {
+            assert_eq!(expected, found(alt, &re, text), "{} in {:?}", re, text);
+        }
+        // the letter itself is not matched
+        if code != '\\' {
+            let letter = format!("a{}b", code);
+            assert_eq!(None, found(false, &format!("a{}b", e), &letter));
+            assert_eq!(None, found(false, &format!("a[{}]b", e), &letter));
+            assert_eq!(None, found(true, &format!("'a' any('{}') 'b'", e), &letter));
+        }
+    }
+    // other escapes are the char itself, and are written back out the same way
+    assert_eq!(Some((0, 4)), found(false, r"a\.+", "a..."));
+    assert_eq!(Some((0, 4)), found(false, r"a\.{3}", "a..."));
+    assert_eq!(Some((0, 3)), found(true, r"'a\.+'", "a.."));
+    let (tree, _) = parse_tree_full(r"[\r\0x]", false, true).unwrap();
+    assert_eq!(
+        Some(r"[\r\0x]".to_string()),
+        tree.to_ast().children.unwrap()[0].literal
+    );
+}
+
+#[test]
+fn alt_upto() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let bodies = |re: &str, text: &str| {
+        let tree = parse_tree(re, true).unwrap();
+        Input::init_text(text, false).unwrap();
+        crate::regexp::find_iter(&tree)
+            .map(|report| report.unwrap().get_by_name("body")[0].text())
+            .collect::<Vec<String>>()
+    };
+    let comment = r#""/\*" until('*/')<body> "*/""#;
+    assert_eq!(
+        bodies(comment, "a /* one */ b /** two * / **/ c /**/"),
+        vec![" one ", "* two * / *", ""]
+    );
+    // an unterminated comment does not match, unless the end of the text will do
+    assert!(bodies(comment, "a /* open").is_empty());
+    assert_eq!(
+        bodies(
+            r#""/\*" until('*/')<body> or("*/" "$")"#,
+            "/* one */ /* open"
+        ),
+        vec![" one ", " open"]
+    );
+    // upto() matches a single character, and can be repeated like one
+    report_test("upto('ab')+", "xxaab", true, |report| {
+        assert_eq!("xxa", report.text())
+    });
+    report_test("upto('\\'')*<q> '\\''", "it's", true, |report| {
+        assert_eq!("it", report.get_by_name("q")[0].text())
+    });
+    assert_eq!(
+        parse_tree("until('*/')", true).unwrap(),
+        parse_tree("upto('*/')*", true).unwrap()
+    );
+    // "until" is still ordinary text without the '('
+    report_test("until ", "go until done", true, |report| {
+        assert_eq!("until", report.text())
+    });
+    e_check(true, "upto(*/)", 118);
+    e_check(true, "until('')", 118);
+    e_check(true, "upto('*/'x)", 118);
+    e_check(true, "until('*/", 119);
+    e_check(true, r"upto('\d')", 120);
+}
+
+#[test]
+fn from_file() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    print!("RUNNING file input test");
+    std::io::stdout().flush().unwrap();
+    let tree = parse_tree("XXX", false)
+        .unwrap_or_else(|msg| panic!("Parse failed for re \"XXX\": {}", msg));
+    if let Err(msg) = Input::init_files(&["src/tests.rs".to_string()], false) {
+        panic!("{}", msg);
+    }
+    let path = walk_tree(&tree, 0)
+        .unwrap_or_else(|err| panic!("Expected \"XXX\", got error '{}'", err))
+        .unwrap_or_else(|| panic!("Expected \"XXX\", found none"));
+    assert_eq!(
+        path.matched_string(),
+        "XXX",
+        "expected \"XXX\", found \"{}\"",
+        path.matched_string()
+    );
+    println!("OK");
+}
+
+#[test]
+fn check_patterns() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let dir = std::env::temp_dir();
+    let patterns = dir
+        .join("regexp_check_patterns.txt")
+        .to_string_lossy()
+        .to_string();
+    let defs = dir
+        .join("regexp_check_defs.re")
+        .to_string_lossy()
+        .to_string();
+    std::fs::write(&patterns, "# good and bad REs\nab+c\n\na{x}\n[a-z]*\\q\n").unwrap();
+    std::fs::write(&defs, "def(ok: \"abc\")\ndef(bad: \"x\"\n").unwrap();
+    let check = |args: &[&str]| {
+        let mut all = vec!["regexp", "--check"];
+        all.extend(args);
+        crate::regexp::check_patterns(&crate::Config::parse_from(all))
+    };
+    assert!(check(&["ab+c"]).is_empty());
+    let problems = check(&[
+        "a\\(b",
+        "--patterns-check",
+        &patterns,
+        "--defs-check",
+        &defs,
+    ]);
+    assert_eq!(problems.len(), 4, "{:?}", problems);
+    assert!(problems[0].starts_with("RE: Error:"));
+    assert!(problems[1].starts_with(&format!("{}:4: Error:7:", patterns)));
+    assert!(problems[2].starts_with(&format!("{}:5: Warning:6:", patterns)));
+    assert!(problems[3].starts_with(&format!("{}: Error:", defs)));
+    // the warning is dropped, or made an error
+    assert_eq!(
+        check(&["--no-warnings", "--patterns-check", &patterns]).len(),
+        1
+    );
+    let problems = check(&["--strict", "--patterns-check", &patterns]);
+    assert!(problems[1].starts_with(&format!("{}:5: Error:11:", patterns)));
+    assert!(check(&["--patterns-check", "/no/such/file"])[0].contains("Error reading pattern file"));
+    std::fs::remove_file(patterns).unwrap();
+    std::fs::remove_file(defs).unwrap();
+    delete_def("ok");
+}
+
+#[test]
+fn interactive_file_search() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let dir = std::env::temp_dir();
+    let file1 = dir
+        .join("regexp_search_1.txt")
+        .to_string_lossy()
+        .to_string();
+    let file2 = dir
+        .join("regexp_search_2.txt")
+        .to_string_lossy()
+        .to_string();
+    std::fs::write(&file1, "first abb line\nno match\nsecond ab line\n").unwrap();
+    std::fs::write(&file2, "nothing here\nabbb\n").unwrap();
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i", "ab+"]));
+    assert!(session.do_command(&format!("search file {} {}", file1, file2)));
+    assert_eq!(session.search_files(0), 3);
+    // changing the RE and searching again uses the saved file list
+    assert!(session.do_command("regexp traditional line"));
+    assert!(session.do_command("search file"));
+    assert_eq!(session.search_files(0), 2);
+    // an empty match at every position must not loop forever
+    assert!(session.do_command("regexp traditional x*"));
+    assert_eq!(session.search_files(0), 59);
+    std::fs::remove_file(file1).unwrap();
+    std::fs::remove_file(file2).unwrap();
+}
+
+#[test]
+fn interactive_walk() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp", "-i", "b+c", "-t", "abbbcd",
+    ]));
+    assert!(session.do_command("walk"));
+    assert!(session.do_command("walk 1"));
+    assert!(session.walk(0));
+    assert!(session.do_command("regexp traditional x+"));
+    assert!(session.do_command("walk"));
+    assert!(!session.walk(0));
+}
+
+#[test]
+fn interactive_quit() {
+    use crate::interactive::confirm_exit;
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i"]));
+    assert!(!session.do_command("quit"));
+    assert!(!session.do_command("q"));
+    assert!(session.do_command("help"));
+    assert!(confirm_exit(&mut "yes\n".as_bytes()));
+    assert!(confirm_exit(&mut "y\n".as_bytes()));
+    assert!(confirm_exit(&mut "\n".as_bytes()));
+    assert!(!confirm_exit(&mut "no\n".as_bytes()));
+    assert!(!confirm_exit(&mut "maybe\nn\n".as_bytes()));
+}
+
+#[test]
+fn config_parser() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let alt_parser = |parser: &str| {
+        crate::Config::parse_from(["regexp", "--parser", parser, "ab", "-t", "ab"]).alt_parser()
+    };
+    assert!(alt_parser("alt"));
+    assert!(alt_parser("alternative"));
+    assert!(!alt_parser("trad"));
+    assert!(!alt_parser("traditional"));
+}
+
+#[test]
+fn interactive_persist() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let dir = std::env::temp_dir();
+    let session1 = dir
+        .join("regexp_session_1.json")
+        .to_string_lossy()
+        .to_string();
+    let session2 = dir
+        .join("regexp_session_2.json")
+        .to_string_lossy()
+        .to_string();
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp", "-i", "ab+", "-t", "xabb",
+    ]));
+    assert!(session.do_command("regexp alternative def(persist_word: 'w'+) get(persist_word)"));
+    assert!(session.do_command("text set some more words"));
+    // definitions are made when the RE is parsed
+    assert!(session.do_command("search"));
+    assert!(session.do_command(&format!("save {}", session1)));
+    let mut reloaded =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i"]));
+    assert_eq!(reloaded.load(&session1), Ok(Vec::<String>::new()));
+    reloaded.save(&session2).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&session1).unwrap(),
+        std::fs::read_to_string(&session2).unwrap()
+    );
+    assert!(std::fs::read_to_string(&session1)
+        .unwrap()
+        .contains("def(persist_word: 'w'+)"));
+    // REs that do not parse any more are dropped
+    std::fs::write(
+        &session1,
+        r#"{"res": [{"re": "a{2", "alt_parser": false}, {"re": "b+", "alt_parser": false}],
+            "texts": [{"text": "text"}], "defs": []}"#,
+    )
+    .unwrap();
+    let dropped = reloaded.load(&session1).unwrap();
+    assert_eq!(dropped.len(), 1);
+    reloaded.save(&session2).unwrap();
+    assert!(!std::fs::read_to_string(&session2).unwrap().contains("a{2"));
+    assert!(reloaded.load("/nonexistent/session").is_err());
+    std::fs::remove_file(session1).unwrap();
+    std::fs::remove_file(session2).unwrap();
+}
+
+#[test]
+fn interactive_defs() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let lib = std::env::temp_dir()
+        .join("regexp_defs_lib.re")
+        .to_string_lossy()
+        .to_string();
+    std::fs::write(
+        &lib,
+        "# test library\ndef(lib_one: 'one'+)\ndef(lib_two: or('a' 'b'))\n",
+    )
+    .unwrap();
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i"]));
+    assert!(session.do_command(&format!("defs load {}", lib)));
+    assert!(def_names().contains(&"lib_one".to_string()));
+    assert_eq!(
+        def_source("lib_two"),
+        Some("def(lib_two: or('a' 'b'))".to_string())
+    );
+    assert!(get_def("lib_two").is_some());
+    assert!(session.do_command("defs list"));
+    assert!(session.do_command("defs show lib_two"));
+    assert!(session.do_command("defs delete lib_one"));
+    assert!(get_def("lib_one").is_none());
+    assert!(get_def("lib_two").is_some());
+    // every missing name is reported, not just the first
+    let err = parse_tree("get(lib_one) get(lib_two) get(lib_three)", true).unwrap_err();
+    assert_eq!(err.code, 108);
+    assert!(err.msg.contains("lib_one, lib_three"), "{}", err.msg);
+    assert!(session.do_command("defs clear"));
+    assert!(def_names().is_empty());
+    std::fs::remove_file(lib).unwrap();
+}
+
+#[test]
+fn preserve_case() {
+    use crate::regexp::{preserve_case, replace_all_with};
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    assert_eq!(preserve_case("COLOUR", "color"), "COLOR");
+    assert_eq!(preserve_case("Colour", "color"), "Color");
+    assert_eq!(preserve_case("colour", "Color"), "color");
+    // mixed case, or nothing with case, leaves the replacement alone
+    assert_eq!(preserve_case("cOLOUR", "Color"), "Color");
+    assert_eq!(preserve_case("ColOur", "color"), "color");
+    assert_eq!(preserve_case("1234", "Color"), "Color");
+    // a single capital is a capitalized word, and only letters count
+    assert_eq!(preserve_case("A", "the"), "The");
+    assert_eq!(preserve_case("NO. 1", "number"), "NUMBER");
+    assert_eq!(preserve_case("Éclair", "ÉCLAIR"), "Éclair");
+    // title case is not always upper case
+    assert_eq!(preserve_case("Džungla", "džem"), "Džem");
+    assert_eq!(preserve_case("Word", "ǆem"), "ǅem");
+    assert_eq!(preserve_case("Word", "ßtraße"), "Sstraße");
+    assert_eq!(preserve_case("WORD", "ǆem"), "ǄEM");
+
+    let tree = parse_tree(r#""\ccolou?r""#, true).unwrap();
+    Input::init_text("Colour, COLOUR or colour", false).unwrap();
+    let (result, count) = replace_all_with(&tree, |report, input| {
+        preserve_case(report.string(input), &report.expand("hue", input))
+    })
+    .unwrap();
+    assert_eq!((result.as_str(), count), ("Hue, HUE or hue", 3));
+    let config = crate::Config::parse_from([
+        "regexp",
+        "colou?r",
+        "-t",
+        "Colour and colour",
+        "--replace",
+        "hue",
+        "--replace-preserve-case",
+    ]);
+    assert_eq!(crate::regexp::regexp(&config).unwrap().matches, 1);
+}
+
+#[test]
+fn replace() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let tree = parse_tree(r"\(?<num>[0-9]+\)-\(x\)", false).unwrap();
+    Input::init_text("a1-x b22-x c-x", false).unwrap();
+    let (result, count) = crate::regexp::replace_all(&tree, "<$num>").unwrap();
+    assert_eq!((result.as_str(), count), ("a<1> b<22> c-x", 2));
+    let (result, _) = crate::regexp::replace_all(&tree, "[$0|$1|${2}|$$|$none|${num]").unwrap();
+    assert_eq!(result, "a[1-x|1|x|$||${num] b[22-x|22|x|$||${num] c-x");
+    // empty matches are replaced once at each position
+    let tree = parse_tree("y*", false).unwrap();
+    Input::init_text("ab", false).unwrap();
+    let (result, count) = crate::regexp::replace_all(&tree, "-").unwrap();
+    assert_eq!((result.as_str(), count), ("-a-b-", 3));
+
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp",
+        "-i",
+        r"\(?<num>[0-9]+\)-x",
+        "-t",
+        "a1-x b22-x c-x",
+    ]));
+    assert!(session.do_command("replace <$num>"));
+    assert_eq!(session.text(), Some(&"a1-x b22-x c-x".to_string()));
+    assert!(session.do_command("replace! <$num>"));
+    assert_eq!(session.text(), Some(&"a<1> b<22> c-x".to_string()));
+    // no matches leaves the text alone
+    assert!(session.do_command("replace! <$num>"));
+    assert_eq!(session.text(), Some(&"a<1> b<22> c-x".to_string()));
+}
+
+#[test]
+fn search_all() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp",
+        "-i",
+        r"\(?<num>[0-9]+\)",
+        "-t",
+        "one 1, two 22, three 333",
+    ]));
+    assert!(session.do_command("search all"));
+    assert!(session.do_command("search 0 count"));
+    assert_eq!(session.search_all(0, false), 3);
+    assert_eq!(session.search_all(0, true), 3);
+    // a pattern matching the empty string matches at every position, and must stop
+    assert!(session.do_command("regexp traditional x*"));
+    assert_eq!(session.search_all(0, true), 25);
+}
+
+#[test]
+fn text_from_match() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp", "-i", r"[0-9]+",
+    ]));
+    // nothing to take from before a search
+    assert!(session.do_command("text from-match"));
+    assert_eq!(session.text(), None);
+    assert!(session.do_command("text set one 1\ntwo 22 end\nthree 333"));
+    assert!(session.do_command("search all"));
+    assert!(session.do_command("text from-match 2"));
+    assert_eq!(session.text(), Some(&"22".to_string()));
+    assert!(session.do_command("text from-line 3"));
+    assert_eq!(session.text(), Some(&"three 333".to_string()));
+    // the results stay after searching the new text, since it was not searched with "search all"
+    assert!(session.do_command("search"));
+    assert!(session.do_command("text from-line 2"));
+    assert_eq!(session.text(), Some(&"two 22 end".to_string()));
+    // out of range leaves the text alone
+    assert!(session.do_command("text from-match 4"));
+    assert_eq!(session.text(), Some(&"two 22 end".to_string()));
+    // a new "search all" replaces them
+    assert!(session.do_command("search all"));
+    assert!(session.do_command("text from-match"));
+    assert_eq!(session.text(), Some(&"22".to_string()));
+    assert!(session.do_command("text from-match 2"));
+    assert_eq!(session.text(), Some(&"22".to_string()));
+}
+
+#[test]
+fn bench() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i", "a*ab"]));
+    // no text yet
+    assert!(session.bench(5).is_none());
+    assert!(session.do_command("text set xxaaab"));
+    assert!(session.do_command("bench 3"));
+    let bench = session.bench(5).unwrap();
+    assert!(bench.parse.min > std::time::Duration::ZERO);
+    assert!(bench.walk.min > std::time::Duration::ZERO);
+    assert!(bench.parse.min <= bench.parse.median && bench.walk.min <= bench.walk.mean);
+    assert!(bench.steps > 0);
+    assert!(bench.backtracks > 0);
+    assert_eq!(6, bench.peak_buffered);
+}
+
+#[test]
+fn named_slots() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp", "-i", "a+", "-t", "first",
+    ]));
+    let current_text = |session: &crate::interactive::Interactive| session.text().cloned();
+    assert!(session.do_command("regexp name as"));
+    assert!(session.do_command("text name one"));
+    assert!(session.do_command("regexp traditional b+"));
+    assert!(session.do_command("text set second"));
+    assert!(session.do_command("text set third"));
+    assert!(session.do_command("regexp list"));
+    // recall by name
+    assert!(session.do_command("text use one"));
+    assert_eq!(current_text(&session), Some("first".to_string()));
+    assert!(session.do_command("regexp use as"));
+    assert!(session.do_command("text set xaay"));
+    assert_eq!(session.search_all(0, true), 1);
+    // names follow their entries when the history order changes
+    assert!(session.do_command("text 1"));
+    assert_eq!(current_text(&session), Some("first".to_string()));
+    assert!(session.do_command("text use one"));
+    assert_eq!(current_text(&session), Some("first".to_string()));
+    // a name can only be used once: naming another entry takes it away from the first
+    assert!(session.do_command("text 3"));
+    assert_eq!(current_text(&session), Some("second".to_string()));
+    assert!(session.do_command("text name one"));
+    assert!(session.do_command("text 1"));
+    assert!(session.do_command("text use one"));
+    assert_eq!(current_text(&session), Some("second".to_string()));
+    // popping a named entry removes the name
+    assert!(session.do_command("text pop"));
+    assert!(session.do_command("text use one"));
+    assert_ne!(current_text(&session), Some("second".to_string()));
+    // names are saved with the session
+    let file = std::env::temp_dir()
+        .join("regexp_named_slots.json")
+        .to_string_lossy()
+        .to_string();
+    session.save(&file).unwrap();
+    let mut reloaded =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i"]));
+    reloaded.load(&file).unwrap();
+    assert!(reloaded.do_command("text set xaay"));
+    assert!(reloaded.do_command("regexp use as"));
+    assert_eq!(reloaded.search_all(0, true), 1);
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn numeric_args() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp", "-i", "a+", "-t", "first",
+    ]));
+    let current_text = |session: &crate::interactive::Interactive| session.text().cloned();
+    // a number with no such history entry is the text itself
+    assert!(session.do_command("text 42"));
+    assert_eq!(current_text(&session), Some("42".to_string()));
+    // more than a bare number is always text
+    assert!(session.do_command("text 1 abc"));
+    assert_eq!(current_text(&session), Some("1 abc".to_string()));
+    // a bare number in range is a history index
+    assert!(session.do_command("text 2"));
+    assert_eq!(current_text(&session), Some("first".to_string()));
+    // with only one RE stored "regexp 1" is neither an index nor a new RE
+    assert!(session.do_command("text set aa b a"));
+    assert!(session.do_command("regexp 1"));
+    assert_eq!(session.search_all(0, true), 2);
+}
+
+#[test]
+fn interactive_diff() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp",
+        "-i",
+        "a+",
+        "-t",
+        "aaa b aa a",
+    ]));
+    assert!(session.do_command("regexp name plus"));
+    assert!(session.do_command("regexp traditional a{2,}"));
+    assert!(session.do_command("diff plus 0"));
+    let diff = session.diff("1", "0").unwrap();
+    assert_eq!(diff.only_first, vec![(9, 10)]);
+    assert!(diff.only_second.is_empty());
+    assert_eq!(diff.both, vec![(0, 3), (6, 8)]);
+    assert!(diff.group_differences.is_empty());
+    let diff = session.diff("0", "plus").unwrap();
+    assert!(diff.only_first.is_empty());
+    assert_eq!(diff.only_second, vec![(9, 10)]);
+    assert!(session.diff("0", "nosuch").is_none());
+}
+
+#[test]
+fn interactive_set() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp", "-i", "ab", "-t", "AB ab aB",
+    ]));
+    assert_eq!(session.search_all(0, true), 1);
+    assert!(session.do_command("set caseless on"));
+    assert!(session.options().caseless);
+    assert_eq!(session.search_all(0, true), 3);
+    assert!(session.do_command("set parser alt"));
+    assert_eq!(session.options().parser, "alternative");
+    // with the parser set an RE without a keyword is not guessed at
+    assert!(session.do_command("regexp 'b'"));
+    assert_eq!(session.search_all(0, true), 3);
+    assert!(session.do_command("set trace 3"));
+    assert_eq!(session.options().trace, 3);
+    assert!(session.do_command("set"));
+    // invalid options and values are rejected and leave things as they were
+    assert!(session.set_option("caseless", "maybe").is_err());
+    assert!(session.set_option("parser", "fancy").is_err());
+    assert!(session.set_option("color", "on").is_err());
+    assert!(session.set_option("trace", "x").is_err());
+    assert!(session.do_command("set trace lots"));
+    assert_eq!(session.options().trace, 3);
+    assert!(session.options().caseless);
+    assert!(session.do_command("set timeout 0.5"));
+    assert_eq!(session.options().timeout, Some(0.5));
+    assert!(session.set_option("timeout", "x").is_err());
+    assert!(session.set_option("timeout", "-1").is_err());
+    assert_eq!(session.options().timeout, Some(0.5));
+    // with multiline off a match cannot run from one line into the next
+    let mut lines = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp", "-i", "b.a", "-t", "ab\nab",
+    ]));
+    assert_eq!(lines.search_all(0, true), 1);
+    assert!(lines.do_command("set multiline off"));
+    assert!(!lines.options().multiline);
+    assert_eq!(lines.search_all(0, true), 0);
+    assert!(lines.do_command("regexp traditional ^a"));
+    assert_eq!(lines.search_all(0, true), 2);
+    assert!(lines.do_command("set multiline on"));
+    assert_eq!(lines.search_all(0, true), 1);
+    assert!(session.do_command("set multiline off"));
+    // options are saved with the session
+    let file = std::env::temp_dir()
+        .join("regexp_interactive_set.json")
+        .to_string_lossy()
+        .to_string();
+    session.save(&file).unwrap();
+    let mut reloaded =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i"]));
+    reloaded.load(&file).unwrap();
+    assert_eq!(reloaded.options(), session.options());
+    assert_eq!(reloaded.options().timeout, Some(0.5));
+    assert!(!reloaded.options().multiline);
+    assert!(reloaded.do_command("set timeout off"));
+    assert_eq!(reloaded.options().timeout, None);
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn command_splitting() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    use crate::interactive::split_command;
+    let words = |line: &str| -> Vec<String> {
+        split_command(line)
+            .into_iter()
+            .map(|(word, _)| word)
+            .collect()
+    };
+    assert_eq!(words("text set  a   b"), vec!["text", "set", "a", "b"]);
+    assert_eq!(words("text\tset\t\tab"), vec!["text", "set", "ab"]);
+    assert_eq!(
+        words(r"regexp traditional \(a\|b\)"),
+        vec!["regexp", "traditional", "(a|b)"]
+    );
+    assert_eq!(
+        words("search file \"my  file\" 'it''s' x\\ y"),
+        vec!["search", "file", "my  file", "its", "x y"]
+    );
+    assert_eq!(words(r#"a "b\"c" 'd\e'"#), vec!["a", "b\"c", r"d\e"]);
+    // an unclosed quote runs to the end of the line
+    assert_eq!(words("text don't stop"), vec!["text", "dont stop"]);
+    assert!(words(" \t ").is_empty());
+    // the ranges cover the words as typed, so REs and texts can be taken from the line unchanged
+    let line = "regexp alternative and('a'  \"b\")";
+    let ranges: Vec<&str> = split_command(line)
+        .into_iter()
+        .map(|(_, range)| &line[range])
+        .collect();
+    assert_eq!(ranges, vec!["regexp", "alternative", "and('a'", "\"b\")"]);
+    // the old way, parsing the command with an RE, is still available
+    let old = crate::interactive::self_hosted_split("text  set ab").unwrap();
+    assert_eq!(
+        old.into_iter().map(|(word, _)| word).collect::<Vec<_>>(),
+        vec!["text", "set", "ab"]
+    );
+}
+
+#[test]
+fn quoted_commands() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i", "a+"]));
+    // the text is taken as typed, including its spacing
+    assert!(session.do_command("text set x  \t aa ( a"));
+    assert_eq!(session.text(), Some(&"x  \t aa ( a".to_string()));
+    assert_eq!(session.search_all(0, true), 2);
+    assert!(session.do_command("text name \"two words\""));
+    assert!(session.do_command("text set other"));
+    assert!(session.do_command("text use 'two words'"));
+    assert_eq!(session.text(), Some(&"x  \t aa ( a".to_string()));
+    assert!(session.do_command("regexp traditional \\(a\\|x\\)"));
+    assert_eq!(session.search_all(0, true), 4);
+}
+
+#[test]
+fn completion() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    use crate::interactive::{complete, CompletionNames};
+    let names = CompletionNames {
+        res: vec!["date".to_string(), "digits".to_string()],
+        texts: vec!["log".to_string()],
+    };
+    let at_end = |line: &str| complete(line, line.len(), &names);
+    // commands and subcommands
+    assert_eq!(
+        at_end("re"),
+        (
+            0,
+            vec![
+                "regexp".to_string(),
+                "replace".to_string(),
+                "replace!".to_string(),
+                "results".to_string()
+            ]
+        )
+    );
+    assert_eq!(at_end("regexp tr"), (7, vec!["traditional".to_string()]));
+    assert_eq!(at_end("text fi"), (5, vec!["file".to_string()]));
+    assert_eq!(
+        at_end("text fr"),
+        (5, vec!["from-line".to_string(), "from-match".to_string()])
+    );
+    assert_eq!(at_end("search 2 c"), (9, vec!["count".to_string()]));
+    assert_eq!(at_end("set pa"), (4, vec!["parser".to_string()]));
+    assert_eq!(
+        at_end("set parser a"),
+        (11, vec!["alternative".to_string()])
+    );
+    assert_eq!(
+        at_end("set caseless "),
+        (13, vec!["off".to_string(), "on".to_string()])
+    );
+    // completion can start in the middle of the line
+    assert_eq!(
+        complete("tex set abc", 3, &names),
+        (0, vec!["text".to_string()])
+    );
+    // stored names
+    assert_eq!(
+        at_end("regexp use d"),
+        (11, vec!["date".to_string(), "digits".to_string()])
+    );
+    assert_eq!(at_end("text use "), (9, vec!["log".to_string()]));
+    assert_eq!(at_end("diff date di"), (10, vec!["digits".to_string()]));
+    // definition names
+    load_defs("def(completion_word: 'w'+) def(completion_num: 'd'+)").unwrap();
+    assert_eq!(
+        at_end("regexp alternative 'a' get(completion_"),
+        (
+            27,
+            vec!["completion_num".to_string(), "completion_word".to_string()]
+        )
+    );
+    assert_eq!(
+        at_end("defs show completion_w"),
+        (10, vec!["completion_word".to_string()])
+    );
+    assert!(at_end("regexp alternative get(completion_num) x")
+        .1
+        .is_empty());
+    // file paths
+    let dir = std::env::temp_dir().join("regexp_completion");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("subdir")).unwrap();
+    std::fs::write(dir.join("words.txt"), "").unwrap();
+    std::fs::write(dir.join(".hidden"), "").unwrap();
+    let path = format!("{}/", dir.to_string_lossy());
+    let file_line = format!("text file {}", path);
+    assert_eq!(
+        at_end(&file_line),
+        (
+            10,
+            vec![format!("{}subdir/", path), format!("{}words.txt", path)]
+        )
+    );
+    assert_eq!(
+        at_end(&format!("search file a {}w", path)).1,
+        vec![format!("{}words.txt", path)]
+    );
+    assert_eq!(
+        at_end(&format!("regexp use({}w", path)),
+        (11, vec![format!("{}words.txt", path)])
+    );
+    assert_eq!(
+        at_end(&format!("load {}.h", path)).1,
+        vec![format!("{}.hidden", path)]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+    clear_defs();
+}
+
+/// a trace writer the test can read back after the trace is done
+#[derive(Clone, Default)]
+struct TraceSink(std::sync::Arc<Mutex<Vec<u8>>>);
+
+impl Write for TraceSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn tree_ast() {
+    use crate::regexp::{AstKind, AstNode};
+    use clap::Parser;
+    use std::collections::BTreeSet;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // collects the field names used by each kind of node
+    fn fields(value: &serde_json::Value, found: &mut Vec<(String, BTreeSet<String>)>) {
+        let object = value.as_object().unwrap();
+        let kind = object["kind"].as_str().unwrap().to_string();
+        found.push((kind, object.keys().cloned().collect()));
+        if let Some(children) = object.get("children") {
+            children
+                .as_array()
+                .unwrap()
+                .iter()
+                .for_each(|child| fields(child, found));
+        }
+    }
+    let schema = |re: &str, alt: bool| {
+        let ast = parse_tree(re, alt).unwrap().to_ast();
+        let mut found = Vec::new();
+        fields(&serde_json::to_value(&ast).unwrap(), &mut found);
+        found
+            .iter()
+            .map(|(kind, keys)| {
+                format!(
+                    "{}: {}",
+                    kind,
+                    keys.iter().cloned().collect::<Vec<String>>().join(" ")
+                )
+            })
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(
+        schema(r#"^"ab"<x> "\d*?" "[^a-c]" or("\ccat" "dog")*<pet>"#, true),
+        vec![
+            "and: anchor children kind limits name",
+            "chars: kind limits literal name",
+            "special: kind limits literal",
+            "range: kind limits literal",
+            "or: children kind limits name name_all_reps",
+            "chars: kind limits literal",
+            "chars: kind limits literal",
+        ]
+    );
+    assert_eq!(
+        schema(r"a\(?<x>[0-9]\|.\)", false),
+        vec![
+            "and: children kind limits name",
+            "chars: kind limits literal",
+            "and: children kind limits name",
+            "or: children kind limits",
+            "range: kind limits literal",
+            "special: kind limits literal",
+        ]
+    );
+    let limits = serde_json::to_value(
+        parse_tree("a{2,}?", false)
+            .unwrap()
+            .to_ast()
+            .children
+            .unwrap()[0]
+            .limits,
+    )
+    .unwrap();
+    assert_eq!(
+        limits,
+        serde_json::json!({"min": 2, "max": null, "lazy": true, "no_case": false})
+    );
+
+    // definitions are expanded, marked with the name they came from
+    let ast = parse_tree(r#"def(two: "xy") get(two) get(two){2}"#, true)
+        .unwrap()
+        .to_ast();
+    let children = ast.children.as_ref().unwrap();
+    assert_eq!(children.len(), 2);
+    assert_eq!(children[0].kind, AstKind::Chars);
+    assert_eq!(children[0].literal.as_deref(), Some("xy"));
+    assert_eq!(children[0].def.as_deref(), Some("two"));
+    assert_eq!(children[1].kind, AstKind::And);
+    assert_eq!(children[1].def.as_deref(), Some("two"));
+    assert_eq!(children[1].limits.min, 2);
+    assert_eq!(
+        children[1].children.as_ref().unwrap()[0].literal.as_deref(),
+        Some("xy")
+    );
+    delete_def("two");
+    // it reads back the same
+    let json = ast.to_json();
+    assert_eq!(serde_json::from_str::<AstNode>(&json).unwrap(), ast);
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i", "ab+"]));
+    assert!(session.do_command("tree json"));
+}
+
+#[test]
+fn json_trace() {
+    use crate::regexp::trace::{attempt_tree, read_events, TraceKind};
+    use crate::regexp::{set_trace, set_trace_format, set_trace_writer, TraceFormat};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let sink = TraceSink::default();
+    let tree = parse_tree(r"a\(b\|c\)", false).unwrap();
+    Input::init_text("xac", false).unwrap();
+    let previous = set_trace_writer(Some(Box::new(sink.clone())));
+    set_trace_format(TraceFormat::Json);
+    set_trace(1);
+    // without the literal scan the walk is tried at every position, including the "x" that fails
+    let options = crate::regexp::set_walk_options(crate::regexp::WalkOptions::new().no_scan());
+    let found = walk_tree(&tree, 0).unwrap().is_some();
+    crate::regexp::set_walk_options(options);
+    set_trace(0);
+    set_trace_format(TraceFormat::Text);
+    set_trace_writer(previous);
+    assert!(found);
+    let output = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+    // every line is an event with all the required fields
+    for line in output.lines() {
+        let value: serde_json::Value = serde_json::from_str(line).unwrap();
+        for field in ["event", "node", "start", "end", "depth"] {
+            assert!(value.get(field).is_some(), "{} missing in {}", field, line);
+        }
+    }
+    let events = read_events(&output);
+    assert_eq!(events.len(), output.lines().count());
+    assert_eq!(events[0].event, TraceKind::WalkStart);
+    assert_eq!(events[0].depth, 0);
+    assert_eq!(events.last().unwrap().event, TraceKind::WalkEnd);
+    assert_eq!(events.last().unwrap().matched, Some(true));
+    // starts and ends pair up at the same depth
+    let mut depths = Vec::new();
+    for event in events.iter() {
+        match event.event {
+            TraceKind::WalkStart => depths.push(event.depth),
+            TraceKind::WalkEnd => assert_eq!(depths.pop(), Some(event.depth)),
+            _ => (),
+        }
+    }
+    assert!(depths.is_empty());
+    // the OR tries "b", fails, switches to branch 1 and then tries "c"
+    let position = |kind: TraceKind, node: &str| {
+        events
+            .iter()
+            .position(|event| event.event == kind && event.node.contains(node))
+            .unwrap()
+    };
+    let branch = position(TraceKind::OrBranch, "OrNode");
+    assert_eq!(events[branch].branch, Some(1));
+    assert!(position(TraceKind::WalkStart, "\"b\"") < branch);
+    assert!(branch < position(TraceKind::WalkStart, "\"c\""));
+    // and the end of the OR's walk says which branch matched
+    assert_eq!(
+        events[position(TraceKind::WalkEnd, "OrNode")].branch,
+        Some(1)
+    );
+    // one walk from each starting position, only the second matches
+    let attempts = attempt_tree(&events);
+    assert_eq!(attempts.len(), 2);
+    assert_eq!(attempts[0].matched, Some(false));
+    assert_eq!(attempts[1].matched, Some(true));
+    assert_eq!((attempts[1].start, attempts[1].end), (1, 3));
+    // the OR is inside the AND made by the parens
+    let or_attempt = &attempts[1].children[1].children[0];
+    assert!(or_attempt.node.contains("OrNode"));
+    assert_eq!(or_attempt.branches, vec![1]);
+    assert_eq!(or_attempt.children.len(), 2);
+}
+
+#[test]
+fn interactive_walk_json() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let file = std::env::temp_dir()
+        .join("regexp_walk.json")
+        .to_string_lossy()
+        .to_string();
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp", "-i", "a*ab", "-t", "xaab",
+    ]));
+    assert!(session.do_command(&format!("walk json {}", file)));
+    let attempts = session.walk_json(&file).unwrap();
+    assert_eq!(attempts.last().unwrap().matched, Some(true));
+    // the greedy a* has to give back an "a"
+    assert!(attempts.last().unwrap().children[0].back_offs > 0);
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn cancelled_walk() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // this takes several seconds to fail without the cancel
+    let (tree, _) = parse_tree_full("a*a*a*a*b", false, true).unwrap();
+    Input::init_text(&"a".repeat(5000), false).unwrap();
+    let cancel = Arc::new(AtomicBool::new(false));
+    set_walk_options(WalkOptions::new().cancel(cancel.clone()));
+    let flag = cancel.clone();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        flag.store(true, Ordering::Release);
+    });
+    let started = Instant::now();
+    let result = walk_tree(&tree, 0);
+    set_walk_options(WalkOptions::new());
+    canceller.join().unwrap();
+    match result {
+        Err(error) => assert_eq!(error.code, 220),
+        Ok(_) => panic!("walk was not cancelled"),
+    }
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn walk_timeout() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    use std::time::{Duration, Instant};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let (tree, _) = parse_tree_full("a*a*a*a*b", false, true).unwrap();
+    Input::init_text(&"a".repeat(5000), false).unwrap();
+    set_walk_options(WalkOptions::new().timeout(Duration::from_millis(50)));
+    let started = Instant::now();
+    let result = walk_tree(&tree, 0);
+    set_walk_options(WalkOptions::new());
+    match result {
+        Err(error) => assert_eq!(error.code, 221),
+        Ok(_) => panic!("walk did not time out"),
+    }
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(50));
+    assert!(elapsed < Duration::from_secs(1));
+    // a quick search is not affected
+    set_walk_options(WalkOptions::new().timeout(Duration::from_millis(50)));
+    Input::init_text("xxab", false).unwrap();
+    let result = walk_tree(&tree, 0);
+    set_walk_options(WalkOptions::new());
+    assert!(result.unwrap().is_some());
+}
+
+#[test]
+fn max_match_len() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let (tree, _) = parse_tree_full("\".*\"", false, true).unwrap();
+    let long = format!("\"{}\"", "x".repeat(10_000));
+    // without a limit the whole 10KB string matches
+    Input::init_text(&long, false).unwrap();
+    let (start, end) = walk_tree(&tree, 0).unwrap().unwrap().range();
+    assert_eq!(10_002, end - start);
+    // with the limit there is no match
+    set_walk_options(WalkOptions::new().max_match_len(1024));
+    let result = walk_tree(&tree, 0);
+    assert!(result.unwrap().is_none());
+    // a shorter match is found instead, starting at the closing quote of the long string
+    Input::init_text(&format!("{} \"short\"", long), false).unwrap();
+    let result = walk_tree(&tree, 0);
+    set_walk_options(WalkOptions::new());
+    let path = result.unwrap().unwrap();
+    assert_eq!((10_001, 10_010), path.range());
+    assert_eq!("\" \"short\"", path.matched_string());
+}
+
+#[test]
+fn min_len() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let min = |re: &str| parse_tree_full(re, false, true).unwrap().0.min_len();
+    assert_eq!(8, min("abcdefgh"));
+    assert_eq!(6, min("ab\\(cd\\){2}x*"));
+    assert_eq!(3, min("abc\\|d"));
+    assert_eq!(1, min("\\(abc\\)\\|d"));
+    assert_eq!(0, min("a*$"));
+    assert_eq!(3, min("[a-z]\\d+."));
+}
+
+#[test]
+fn min_len_pruning() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // the literal scan would skip the whole text, so it is turned off to see the start positions the walk tries
+    set_walk_options(WalkOptions::new().no_scan());
+    let (tree, _) = parse_tree_full("abcdefgh", false, true).unwrap();
+    // starts stop once fewer than 8 bytes are left, so the partial match at the end is never tried
+    let text = format!("{}abcdefg", "x".repeat(1000));
+    Input::init_text(&text, false).unwrap();
+    reset_step_count();
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    assert_eq!(1000, crate::walk::start_count());
+    // the same search still finds a match that just fits
+    let text = format!("{}abcdefgh", "x".repeat(1000));
+    Input::init_text(&text, false).unwrap();
+    reset_step_count();
+    assert_eq!((1000, 1008), walk_tree(&tree, 0).unwrap().unwrap().range());
+    assert_eq!(1001, crate::walk::start_count());
+    set_walk_options(WalkOptions::new());
+    // a child of an AND that cannot fit is not walked, but the ones before it still match
+    let (tree, _) = parse_tree_full("\\(x*\\)\\(abcd\\)", false, true).unwrap();
+    Input::init_text("xxxabc", false).unwrap();
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    Input::init_text("xxxabcd", false).unwrap();
+    assert_eq!((0, 7), walk_tree(&tree, 0).unwrap().unwrap().range());
+}
+
+/// A reader that counts the bytes it gives out
+#[cfg(test)]
+struct CountingRead {
+    text: std::io::Cursor<Vec<u8>>,
+    read: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(test)]
+impl std::io::Read for CountingRead {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.text.read(buf)?;
+        self.read
+            .fetch_add(count, std::sync::atomic::Ordering::AcqRel);
+        Ok(count)
+    }
+}
+
+#[test]
+fn greedy_back_off_skip() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // backing off ".*" goes straight to where "x" can start instead of walking "x" at each place on the way
+    let (tree, _) = parse_tree_full("^.*x", false, true).unwrap();
+    let text = format!("axb{}", "y".repeat(1000));
+    Input::init_text(&text, false).unwrap();
+    reset_step_count();
+    assert_eq!((0, 2), walk_tree(&tree, 0).unwrap().unwrap().range());
+    assert!(step_count() < 1100, "{} steps", step_count());
+    // the results are the same as trying every place
+    for (alt, re, text, expected) in [
+        (false, "^.*x", "axbxc", vec![(0, 4)]),
+        (false, r"\(.*\)\(a[bc]\)", "xxabyyacz", vec![(0, 8)]),
+        (false, "[a-z]*[0-9]", "ab1cd2ef", vec![(0, 3), (3, 6)]),
+        (false, ".*y*", "abc", vec![(0, 3), (3, 3)]),
+        (false, ".*$", "abc", vec![(0, 3), (3, 3)]),
+        (false, "a.*?b", "aabab", vec![(0, 3), (3, 5)]),
+        (true, r"'.*' '\cX'", "axbxc", vec![(0, 4)]),
+        (true, "'.*' or('b' 'c')", "abcabd", vec![(0, 5)]),
+        (true, "'[a-z]+' and('1' '2')", "ab12cd13", vec![(0, 4)]),
+    ] {
+        let (tree, _) = parse_tree_full(re, alt, true).unwrap();
+        Input::init_text(text, false).unwrap();
+        assert_eq!(expected, all_ranges(&tree), "{} in {}", re, text);
+    }
+    // greedy matching has to see all the text to find the last "x", so a streamed search still reads it all, but
+    // gets the same match
+    let text = format!("axb{}", "y".repeat(5000));
+    let read = Arc::new(AtomicUsize::new(0));
+    let reader = CountingRead {
+        text: std::io::Cursor::new(text.clone().into_bytes()),
+        read: read.clone(),
+    };
+    let block = Input::set_block_size(64);
+    Input::init_reader(Box::new(std::io::BufReader::new(reader)), false);
+    let (tree, _) = parse_tree_full("^.*x", false, true).unwrap();
+    reset_step_count();
+    assert_eq!((0, 2), walk_tree(&tree, 0).unwrap().unwrap().range());
+    assert!(step_count() < 5100, "{} steps", step_count());
+    assert_eq!(text.len(), read.load(Ordering::Acquire));
+    Input::set_block_size(block);
+}
+
+/// all the matches of TREE in the current text, as ranges
+fn all_ranges(tree: &Node) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut from = 0;
+    while let Some(path) = walk_tree(tree, from).unwrap() {
+        let (start, end) = path.range();
+        ranges.push((start, end));
+        from = if end > start { end } else { end + 1 };
+        if from > Input::apply(|input| input.full_text.len()) {
+            break;
+        }
+    }
+    ranges
+}
+
+#[test]
+fn literal_scan() {
+    use crate::regexp::scan::{leading_strings, LiteralScanner};
+    use crate::regexp::{set_walk_options, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // the scanner finds where the first string starts, not where the first one ends
+    let scanner = LiteralScanner::new(vec!["abcd".to_string(), "c".to_string(), "he".to_string()]);
+    assert_eq!(Some(1), scanner.find("xabcd", 0));
+    assert_eq!(Some(3), scanner.find("xabcd", 2));
+    assert_eq!(Some(1), scanner.find("she", 0));
+    assert_eq!(None, scanner.find("abd", 0));
+    assert_eq!(Some(3), scanner.find("你c", 0));
+    // the REs that have leading strings
+    let strings =
+        |alt: bool, re: &str| leading_strings(&parse_tree_full(re, alt, false).unwrap().0);
+    assert_eq!(
+        Some(vec!["ab".to_string(), "d".to_string()]),
+        strings(true, "or(and('ab' 'c') 'd'<n>) 'x'")
+    );
+    assert_eq!(Some(vec!["ab".to_string()]), strings(false, "abc*"));
+    assert_eq!(None, strings(true, "or('a' '[b-c]')"));
+    assert_eq!(None, strings(true, "or('a' 'b')? 'c'"));
+    assert_eq!(None, strings(true, "'\\cabc'"));
+
+    // a blocklist of words, searched for in a long text with and without the scan
+    let mut seed = 12345u32;
+    let mut word = || {
+        (0..6)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (b'a' + ((seed >> 16) % 26) as u8) as char
+            })
+            .collect::<String>()
+    };
+    let words: Vec<String> = (0..200).map(|_| word()).collect();
+    let re = format!(
+        "or({})",
+        words
+            .iter()
+            .map(|w| format!("'{}'", w))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    let (tree, _) = parse_tree_full(&re, true, true).unwrap();
+    let mut text = "0123456789 ".repeat(1_000);
+    text.insert_str(5_000, &words[150]);
+    text.push_str(&words[7]);
+    Input::init_text(&text, false).unwrap();
+    reset_step_count();
+    let scanned = all_ranges(&tree);
+    let scanned_starts = crate::walk::start_count();
+    set_walk_options(WalkOptions::new().no_scan());
+    reset_step_count();
+    let naive = all_ranges(&tree);
+    let naive_starts = crate::walk::start_count();
+    set_walk_options(WalkOptions::new());
+    assert_eq!(vec![(5_000, 5_006), (11_006, 11_012)], scanned);
+    assert_eq!(naive, scanned);
+    assert_eq!(2, scanned_starts);
+    assert!(naive_starts > 10_000);
+
+    // the walk still decides which branch matched
+    let (tree, _) = parse_tree_full("or('cat'<c> 'dog'<d>)<pet>", true, true).unwrap();
+    Input::init_text("hotdog", false).unwrap();
+    let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+    assert_eq!((3, 6), report.byte_pos());
+    assert!(report.get_by_name("d").len() == 1 && report.get_by_name("c").is_empty());
+    // anchored REs are only tried at the start
+    let (tree, _) = parse_tree_full("^abc", false, true).unwrap();
+    Input::init_text("xabc", false).unwrap();
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+
+    // in a file, strings crossing the blocks it is read in are found
+    let file = std::env::temp_dir()
+        .join("regexp_literal_scan.txt")
+        .to_string_lossy()
+        .to_string();
+    let mut contents = String::new();
+    while contents.len() < 2_000 {
+        contents.push_str("a line of text\n");
+    }
+    contents.insert_str(997, "needle");
+    std::fs::write(&file, &contents).unwrap();
+    let (tree, _) = parse_tree_full("or('needle' 'haystack')", true, true).unwrap();
+    let files = [file];
+    Input::init_files(&files, false).unwrap();
+    assert_eq!(vec![(997, 1003)], all_ranges(&tree));
+    // and with tiny blocks
+    let block = Input::set_block_size(20);
+    Input::init_files(&files, false).unwrap();
+    assert_eq!(vec![(997, 1003)], all_ranges(&tree));
+    Input::set_block_size(block);
+}
+
+#[test]
+fn quiet_trace() {
+    use crate::regexp::{set_trace, set_trace_writer};
+    use crate::walk::{apply_count, start_count};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let tree = parse_tree(r"\(ab\|b\)+c", false).unwrap();
+    Input::init_text("xxabd abbab abc", false).unwrap();
+    // searches once, returning the number of times Input was applied
+    let count_applies = || {
+        reset_step_count();
+        let path = walk_tree(&tree, 0).unwrap().unwrap();
+        assert_eq!((12, 15), path.range());
+        // the dump looks at Input once for the whole path
+        let before = apply_count();
+        path.dump(0);
+        assert_eq!(before + 1, apply_count());
+        before
+    };
+    // a quiet search only looks at the text to take steps, to check there is enough of it left, and to move to the
+    // next start position, a few times for each
+    let quiet = count_applies();
+    assert!(quiet <= 3 * (1 + step_count() + start_count()));
+    // tracing formats the matches, which looks at the text a lot more
+    let sink = TraceSink::default();
+    let previous = set_trace_writer(Some(Box::new(sink.clone())));
+    set_trace(3);
+    let traced = count_applies();
+    set_trace(0);
+    set_trace_writer(previous);
+    assert!(traced > quiet);
+    assert!(!sink.0.lock().unwrap().is_empty());
+    assert_eq!(quiet, count_applies());
+}
+
+#[test]
+fn walk_arena() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    use crate::walk::{allocation_count, start_count};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    set_walk_options(WalkOptions::new().no_scan());
+    // searches TEXT for RE, returning the match with the allocation and start counts
+    let search = |re: &str, text: &str| {
+        let (tree, _) = parse_tree_full(re, false, true).unwrap();
+        Input::init_text(text, false).unwrap();
+        reset_step_count();
+        let found = walk_tree(&tree, 0).unwrap().map(|path| path.range());
+        (found, allocation_count(), start_count())
+    };
+    // the failed walks at each start position reuse what the first ones allocated, so a longer text allocates no more
+    for re in [r"[a-c]+\d", r"\(ab\|b\)+c", r"\(?<x>a*b\){2}d"] {
+        let (found, short, short_starts) = search(re, &"abd ab ".repeat(10));
+        assert!(found.is_none(), "{}", re);
+        let (found, long, long_starts) = search(re, &"abd ab ".repeat(1000));
+        assert!(found.is_none(), "{}", re);
+        assert!(short_starts < 70 && long_starts > 6_000);
+        assert_eq!(short, long, "{}", re);
+    }
+    // matches are the same as before
+    let (found, _, _) = search(r"\(ab\|b\)+c", &format!("{}abc", "abd ab ".repeat(100)));
+    assert_eq!(Some((700, 703)), found);
+    set_walk_options(WalkOptions::new());
+}
+
+#[test]
+fn count_only() {
+    use crate::regexp::{count_matches, walk_tree_next};
+    use crate::walk::allocation_count;
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // counts the matches one at a time the way regexp() goes through them, returning the count and allocations
+    let count_by_walking = |tree: &Node| {
+        reset_step_count();
+        let (mut count, mut start) = (0, 0);
+        while let Some((path, next)) = walk_tree_next(tree, start).unwrap() {
+            let _ = Report::new(&path);
+            count += 1;
+            start = next;
+        }
+        (count, allocation_count())
+    };
+    let text = format!("{}xyz", "ab12 abb3 b4 ".repeat(200));
+    for (re, alt) in [
+        (r"ab*\d", false),
+        (r"\(?<x>a\|b\)+\d", false),
+        (r"b*", false),
+        (r"^ab", false),
+        (r"x\|q", false),
+        (r"no match", false),
+        (r#"or("ab"<a> "b4"<b>)"#, true),
+    ] {
+        let (tree, _) = parse_tree_full(re, alt, true).unwrap();
+        Input::init_text(&text, false).unwrap();
+        let (walked, walk_allocations) = count_by_walking(&tree);
+        Input::init_text(&text, false).unwrap();
+        reset_step_count();
+        assert_eq!(walked, count_matches(&tree, 0).unwrap(), "{}", re);
+        // the paths are taken apart and reused, so there are only as many allocations as one match needs
+        if walked > 100 {
+            assert!(
+                allocation_count() * 20 < walk_allocations,
+                "{}: {} allocations counting, {} walking",
+                re,
+                allocation_count(),
+                walk_allocations
+            );
+        }
+    }
+    // from the middle of the text, and past its end
+    let (tree, _) = parse_tree_full("b4", false, true).unwrap();
+    Input::init_text(&text, false).unwrap();
+    assert_eq!(100, count_matches(&tree, text.len() / 2).unwrap());
+    assert_eq!(0, count_matches(&tree, text.len() + 1).unwrap());
+    // and from the command line, where -c is ignored
+    let count = |args: &[&str]| {
+        crate::regexp::regexp(&crate::Config::parse_from(args))
+            .unwrap()
+            .matches
+    };
+    assert_eq!(
+        200,
+        count(&["regexp", "--count-only", "-c", "3", "b4", "-t", &text])
+    );
+    assert_eq!(
+        count(&["regexp", "-a", "-q", "b*", "-t", "abbcb"]),
+        count(&["regexp", "--count-only", "b*", "-t", "abbcb"])
+    );
+}
+
+#[test]
+fn walk_start_checked() {
+    use crate::regexp::{count_matches, walk_tree_next};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let text = "héllo wörld";
+    let tree = parse_tree("l+", false).unwrap();
+    Input::init_text(text, false).unwrap();
+    // inside the 'é' and the 'ö'
+    for from in [2, 9] {
+        assert_eq!(202, walk_tree(&tree, from).unwrap_err().code);
+        assert_eq!(202, walk_tree_next(&tree, from).unwrap_err().code);
+        assert_eq!(202, count_matches(&tree, from).unwrap_err().code);
+    }
+    // on either side of them is fine
+    assert_eq!((3, 5), walk_tree(&tree, 1).unwrap().unwrap().range());
+    assert_eq!((11, 12), walk_tree(&tree, 10).unwrap().unwrap().range());
+    // past the end there is nothing, but an empty match can still be found at the end
+    for from in [text.len() + 1, text.len() + 100, usize::MAX] {
+        assert!(walk_tree(&tree, from).unwrap().is_none());
+        assert!(walk_tree_next(&tree, from).unwrap().is_none());
+        assert_eq!(0, count_matches(&tree, from).unwrap());
+    }
+    assert!(walk_tree(&tree, text.len()).unwrap().is_none());
+    let tree = parse_tree("d*$", false).unwrap();
+    assert_eq!(
+        (text.len(), text.len()),
+        walk_tree(&tree, text.len()).unwrap().unwrap().range()
+    );
+}
+
+#[test]
+fn walk_window() {
+    use crate::regexp::{count_matches, set_walk_options, WalkOptions, WindowEdge};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let text = "aaaa bbbb aaaa bb";
+    // searches the window of TEXT from FROM to END, returning the first match
+    let search = |re: &str, from: usize, end: usize, edge: WindowEdge| {
+        let tree = parse_tree(re, false).unwrap();
+        Input::init_text(text, false).unwrap();
+        let old = set_walk_options(WalkOptions::new().window(end, edge));
+        let found = walk_tree(&tree, from).unwrap().map(|path| path.range());
+        set_walk_options(old);
+        found
+    };
+    // "bbbb" straddles the end of the window at 7
+    assert_eq!(Some((5, 9)), search("b+", 0, 7, WindowEdge::Extend));
+    assert_eq!(Some((5, 7)), search("b+", 0, 7, WindowEdge::Clip));
+    assert_eq!(Some((5, 9)), search("b{4}", 0, 7, WindowEdge::Extend));
+    assert_eq!(None, search("b{4}", 0, 7, WindowEdge::Clip));
+    // '$' is the end of the window only when it clips
+    assert_eq!(Some((5, 7)), search("b+$", 0, 7, WindowEdge::Clip));
+    assert_eq!(Some((15, 17)), search("b+$", 0, 17, WindowEdge::Extend));
+    assert_eq!(None, search("b+$", 0, 7, WindowEdge::Extend));
+    // matches must start inside the window, even empty ones
+    assert_eq!(None, search("b+", 0, 5, WindowEdge::Extend));
+    assert_eq!(None, search("b+", 10, 15, WindowEdge::Extend));
+    assert_eq!(Some((15, 17)), search("b+", 10, 16, WindowEdge::Extend));
+    assert_eq!(None, search("x*$", 0, 17, WindowEdge::Extend));
+    assert_eq!(None, search("x*$", 0, 7, WindowEdge::Clip));
+    // counting in chunks that do not cut a match finds the same matches as the whole text
+    let tree = parse_tree("a+", false).unwrap();
+    Input::init_text(text, false).unwrap();
+    let count = |chunks: &[(usize, usize)]| {
+        let mut total = 0;
+        for (from, end) in chunks {
+            let old = set_walk_options(WalkOptions::new().window(*end, WindowEdge::Extend));
+            total += count_matches(&tree, *from).unwrap();
+            set_walk_options(old);
+        }
+        total
+    };
+    assert_eq!(2, count(&[(0, 7), (7, text.len())]));
+    // one that does finds the rest of the cut match again
+    assert_eq!(3, count(&[(0, 2), (2, text.len())]));
+    // and the window is gone when the options are replaced
+    assert_eq!(2, count_matches(&tree, 0).unwrap());
+    assert_eq!(Some((5, 9)), search("b+", 0, usize::MAX, WindowEdge::Clip));
+}
+
+#[test]
+fn render_to_string() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let tree = parse_tree(r"a\(?<x>b+\)\|z", false).unwrap();
+    Input::init_text("xxabbby", false).unwrap();
+    let path = walk_tree(&tree, 0).unwrap().unwrap();
+    let report = Report::new(&path);
+    assert_eq!(
+        report.to_string(),
+        concat!(
+            "\"abbb\" <> chars start 2, length 4; bytes start 2, length 4\n",
+            "    \"bbb\" <x> chars start 3, length 3; bytes start 3, length 3\n",
+        )
+    );
+    let mut out = Vec::new();
+    report.display_to(&mut out, 2).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .zip(report.to_string().lines())
+        .all(|(indented, line)| indented == format!("  {}", line)));
+    assert_eq!(
+        tree.to_string(),
+        concat!(
+            "AndNode(2)<>{1,1}\n",
+            "    CharsNode: \"a\"{1,1}\n",
+            "    OrNode(2) {1,1} \n",
+            "        AndNode(1)<x>{1,1}\n",
+            "            CharsNode: \"b\"{1,99999999}\n",
+            "        CharsNode: \"z\"{1,1}\n",
+        )
+    );
+    assert_eq!(
+        path.to_string(),
+        concat!(
+            "PATH And ------------\n",
+            "|    0: {AndNode(2)<>{1,1}} state [-, -, ], match \"\" [2-2)\n",
+            "|    1: {AndNode(2)<>{1,1}} state [2, 2, ], match \"abbb\" [2-6)\n",
+            "|        0: {CharsNode: \"a\"{1,1}}, match \"\" [2-2)\n",
+            "|        1: {CharsNode: \"a\"{1,1}}, match \"a\" [2-3)\n",
+            "|        0: {OrNode(2) {1,1} }, branch 1 of 2, branch reps 0, match \"\" [3-3)  0 of 2\n",
+            "|            0: NONE \"\"\n",
+            "|        1: {OrNode(2) {1,1} }, branch 1 of 2, branch reps 2, match \"bbb\" [3-6)  0 of 2\n",
+            "|            0: {AndNode(1)<x>{1,1}} state [-, ], match \"\" [3-3)\n",
+            "|            1: {AndNode(1)<x>{1,1}} state [4, ], match \"bbb\" [3-6)\n",
+            "|                0: {CharsNode: \"b\"{1,99999999}}, match \"\" [3-3)\n",
+            "|                1: {CharsNode: \"b\"{1,99999999}}, match \"b\" [3-4)\n",
+            "|                2: {CharsNode: \"b\"{1,99999999}}, match \"b\" [4-5)\n",
+            "|                3: {CharsNode: \"b\"{1,99999999}}, match \"b\" [5-6)\n",
+            "PATH And ------------\n",
+        )
+    );
+}
+
+#[test]
+fn shift_or_sequence() {
+    use crate::regexp::scan::ShiftOr;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let len = |alt: bool, re: &str| {
+        ShiftOr::sequence(&parse_tree_full(re, alt, true).unwrap().0).map(|classes| classes.len())
+    };
+    assert_eq!(Some(10), len(false, r"\d\d\d\d-\d\d-\d\d"));
+    assert_eq!(
+        Some(10),
+        len(true, r"'\d{4}-'<year> '\d{2}'<month> '-' '\d\d'<day>")
+    );
+    assert_eq!(Some(7), len(false, r"\(?<y>[0-9]{4}\)-.{2}"));
+    assert_eq!(Some(64), len(false, &"x".repeat(64)));
+    // repetitions that are not fixed, anchors, ORs, and caseless chars are left to the walk
+    assert_eq!(None, len(false, r"\d+-\d"));
+    assert_eq!(None, len(false, r"a\d?"));
+    assert_eq!(None, len(false, r"^\d\d"));
+    assert_eq!(None, len(false, r"ab\|cd"));
+    assert_eq!(None, len(false, r"ab$"));
+    assert_eq!(None, len(true, r"'\cabc'"));
+    assert_eq!(None, len(false, &"x".repeat(65)));
+    assert_eq!(None, len(false, r"x{65}"));
+}
+
+#[test]
+fn shift_or_matcher() {
+    use crate::regexp::scan::ShiftOr;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let matcher =
+        |re: &str| ShiftOr::new(ShiftOr::sequence(&parse_tree(re, false).unwrap()).unwrap());
+    let date = matcher(r"\d\d\d\d-\d\d-\d\d");
+    assert_eq!(Some(4), date.find("on: 2024-01-31.", 0));
+    assert_eq!(Some(6), date.find("1999-12024-01-31", 0));
+    assert_eq!(None, date.find("2024-1-31", 0));
+    assert_eq!(Some(11), date.find("2024-01-31 2024-02-01", 1));
+    // positions are in bytes, and ranges and specials work on any char
+    assert_eq!(Some(6), matcher("a[^b].").find("你好aéz", 0));
+    assert_eq!(Some(1), matcher("aab").find("aaab", 0));
+    assert_eq!(Some(0), matcher("x").find("x", 0));
+}
+
+#[test]
+fn shift_or_dates() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let (tree, _) = parse_tree_full(
+        r"\(?<year>\d\d\d\d\)-\(?<month>\d\d\)-\(?<day>\d\d\)",
+        false,
+        true,
+    )
+    .unwrap();
+    let mut text = String::new();
+    for i in 0..300 {
+        text.push_str(&format!(
+            "entry {} at 20{:02}-0{}-1{} ok, id 12-34 ",
+            i,
+            i % 100,
+            i % 9 + 1,
+            i % 10
+        ));
+    }
+    Input::init_text(&text, false).unwrap();
+    reset_step_count();
+    let scanned = all_ranges(&tree);
+    let scanned_steps = step_count();
+    set_walk_options(WalkOptions::new().no_scan());
+    reset_step_count();
+    let naive = all_ranges(&tree);
+    let naive_steps = step_count();
+    set_walk_options(WalkOptions::new());
+    assert_eq!(300, scanned.len());
+    assert_eq!(naive, scanned);
+    // the walk is only started where there is a date, so it never fails
+    assert!(scanned_steps * 5 < naive_steps);
+    // the Report is still built by the walk
+    let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+    Input::apply(|input| {
+        assert_eq!("2000-01-10", report.string(input));
+        assert_eq!("01", report.get_by_name("month")[0].string(input));
+    });
+}
+
+//
+// Property tests, using the random REs and texts from fuzz.rs
+//
+
+#[cfg(test)]
+/// parses RE, writes the tree back out in the alternative syntax, and checks that parsing that gives the same tree
+fn check_round_trip(re: &str, alt: bool) -> Result<(), proptest::test_runner::TestCaseError> {
+    if let Ok((mut tree, _)) = parse_tree_full(re, alt, false) {
+        tree.canonicalize();
+        let source = tree.to_alt_source();
+        let (mut again, _) = parse_tree_full(&source, true, false).map_err(|error| {
+            proptest::test_runner::TestCaseError::fail(format!("{} -> {}: {}", re, source, error))
+        })?;
+        again.canonicalize();
+        proptest::prop_assert_eq!(tree, again, "{} -> {}", re, source);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+/// searches TEXT from every position, checking that every step of every match is a slice of the text (see
+/// **Matched::validate()**) and that its char position agrees with its byte position. Random REs can have nested repetitions that take a very long time
+/// to fail, so the caller should set a timeout; searches that return an error are not checked.
+fn check_walk(re: &str, alt: bool, text: &str) -> Result<(), proptest::test_runner::TestCaseError> {
+    if let Ok((tree, _)) = parse_tree_full(re, alt, true) {
+        Input::init_text(text, false).unwrap();
+        let starts = text.char_indices().map(|(i, _)| i).chain([text.len()]);
+        for start in starts {
+            let path = match walk_tree(&tree, start) {
+                Ok(Some(path)) => path,
+                Ok(None) => continue,
+                Err(_) => break,
+            };
+            for matched in path.all_matched() {
+                proptest::prop_assert!(
+                    start <= matched.start && Input::apply(|input| matched.validate(input)),
+                    "{} on \"{}\" from {}: bad match {}-{}",
+                    re,
+                    text,
+                    start,
+                    matched.start,
+                    matched.end
+                );
+                proptest::prop_assert_eq!(
+                    matched.char_start,
+                    text[..matched.start].chars().count()
+                );
+            }
+            let _ = Report::new(&path);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn fuzz_parsers() {
+    use crate::fuzz::*;
+    use proptest::prelude::*;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    proptest!(|(re in junk_re())| {
+        let _ = parse_tree_full(&re, false, true);
+        let _ = parse_tree_full(&re, true, true);
+    });
+    proptest!(|(re in traditional_re())| {
+        check_round_trip(&re, false)?;
+    });
+    proptest!(|(re in alternative_re())| {
+        check_round_trip(&re, true)?;
+    });
+}
+
+#[test]
+fn fuzz_walks() {
+    use crate::fuzz::*;
+    use crate::regexp::{set_walk_options, WalkOptions};
+    use proptest::prelude::*;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    set_walk_options(WalkOptions::new().timeout(std::time::Duration::from_millis(20)));
+    proptest!(|(re in traditional_re(), text in text())| {
+        check_walk(&re, false, &text)?;
+    });
+    proptest!(|(re in alternative_re(), text in text())| {
+        check_walk(&re, true, &text)?;
+    });
+    set_walk_options(WalkOptions::new());
+}
+
+#[cfg(test)]
+/// searches TEXT for RE with and without the literal scan, and checks that the results are the same
+fn check_literal_scan(
+    re: &str,
+    alt: bool,
+    text: &str,
+) -> Result<(), proptest::test_runner::TestCaseError> {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    if let Ok((tree, _)) = parse_tree_full(re, alt, true) {
+        Input::init_text(text, false).unwrap();
+        let timeout = std::time::Duration::from_millis(20);
+        set_walk_options(WalkOptions::new().timeout(timeout));
+        let scanned = walk_tree(&tree, 0).map(|path| path.map(|p| p.range()));
+        set_walk_options(WalkOptions::new().timeout(timeout).no_scan());
+        let naive = walk_tree(&tree, 0).map(|path| path.map(|p| p.range()));
+        if let (Ok(scanned), Ok(naive)) = (scanned, naive) {
+            proptest::prop_assert_eq!(naive, scanned, "{} on \"{}\"", re, text);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn fuzz_literal_scan() {
+    use crate::fuzz::*;
+    use crate::regexp::{set_walk_options, WalkOptions};
+    use proptest::prelude::*;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    proptest!(|(re in traditional_re(), text in text())| {
+        check_literal_scan(&re, false, &text)?;
+    });
+    proptest!(|(re in alternative_re(), text in text())| {
+        check_literal_scan(&re, true, &text)?;
+    });
+    set_walk_options(WalkOptions::new());
+}
+
+#[test]
+fn captures() {
+    use crate::regexp::Regexp;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let re = Regexp::new(r"\(?<user>\l+\)@\(?<host>[a-z.]+\)").unwrap();
+    let caps = re.captures("mail bob@example.com now").unwrap().unwrap();
+    assert_eq!(3, caps.len());
+    assert_eq!("bob@example.com", &caps[0]);
+    assert_eq!("bob", &caps[1]);
+    assert_eq!("bob", &caps["user"]);
+    assert_eq!("example.com", &caps["host"]);
+    let host = caps.name("host").unwrap();
+    assert_eq!((9, 20), (host.start(), host.end()));
+    assert_eq!(Some("host"), host.name());
+    assert_eq!(None, caps.get(0).unwrap().name());
+    assert!(caps.get(3).is_none());
+    assert!(caps.name("domain").is_none());
+    assert!(re.captures("nobody here").unwrap().is_none());
+    // a repeated group is recorded each time it matches
+    let re = Regexp::new_alt("and('a' '[b-z]'<letter>)+").unwrap();
+    let caps = re.captures("xacadae").unwrap().unwrap();
+    let letters: Vec<&str> = caps.iter().skip(1).map(|m| m.as_str()).collect();
+    assert_eq!(vec!["c", "d", "e"], letters);
+    assert_eq!("c", &caps["letter"]);
+    // an empty text can be searched
+    let re = Regexp::new("x*").unwrap();
+    assert_eq!("", &re.captures("").unwrap().unwrap()[0]);
+}
+
+#[test]
+fn captures_bytes_mode() {
+    use crate::regexp::{set_walk_options, Regexp, SearchOptions, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // with bytes mode on for every search the text is searched as bytes, but positions are still in the str
+    let previous = set_walk_options(WalkOptions::new().bytes());
+    let found = Regexp::new("a").unwrap().find("é a").unwrap().unwrap();
+    assert_eq!((3, 4, "a"), (found.start(), found.end(), found.as_str()));
+    let re = Regexp::new(r"\(?<x>.a\)").unwrap();
+    let caps = re.captures("é a").unwrap().unwrap();
+    let group = caps.name("x").unwrap();
+    assert_eq!((2, 4, " a"), (group.start(), group.end(), group.as_str()));
+    // a single byte of "é" cannot be given as a str
+    let re = Regexp::new(".").unwrap();
+    assert_eq!(232, re.find("é").unwrap_err().code);
+    assert_eq!(232, re.captures("é").unwrap_err().code);
+    set_walk_options(previous);
+    // a Regexp asking for bytes mode gets it, and only for its own searches
+    let options = SearchOptions::new().walk(WalkOptions::new().bytes());
+    let re = Regexp::with_options("..a", options).unwrap();
+    assert_eq!("éa", re.find("xéa").unwrap().unwrap().as_str());
+    assert_eq!("éa", &re.captures("xéa").unwrap().unwrap()[0]);
+    assert!(!Input::bytes_mode());
+    assert!(Regexp::new("..a").unwrap().find("éa").unwrap().is_none());
+}
+
+#[test]
+fn search_options() {
+    use crate::regexp::{parse_tree_opts, set_literal_braces, Regexp, SearchOptions, WalkOptions};
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // the default options are the same as the plain calls
+    let (tree, _) = parse_tree_opts("ab+", &SearchOptions::default()).unwrap();
+    assert_eq!(parse_tree("ab+", false).unwrap(), tree);
+    // options are kept with a Regexp and used for its searches
+    let options = SearchOptions::new().alt_parser(true).caseless(true);
+    let re = Regexp::with_options("'hello' '\\d'", options).unwrap();
+    assert!(re.options().caseless);
+    assert_eq!("HeLLo1", &re.captures("say HeLLo1").unwrap().unwrap()[0]);
+    let re = Regexp::with_options(
+        "a+",
+        SearchOptions::new().walk(WalkOptions::new().max_match_len(2)),
+    )
+    .unwrap();
+    assert_eq!("aa", &re.captures("aaaa").unwrap().unwrap()[0]);
+    // the walk options only apply to that search
+    assert_eq!(
+        "aaaa",
+        &Regexp::new("a+")
+            .unwrap()
+            .captures("aaaa")
+            .unwrap()
+            .unwrap()[0]
+    );
+    // parse options with a global setting only replace it for the call
+    let braces = set_literal_braces(false);
+    let literal = SearchOptions::new().literal_braces(true);
+    assert!(parse_tree_opts("a{x}", &literal).is_ok());
+    assert_eq!(
+        7,
+        parse_tree_opts("a{x}", &SearchOptions::new())
+            .unwrap_err()
+            .code
+    );
+    set_literal_braces(braces);
+    assert_eq!(
+        11,
+        parse_tree_opts("a\\qb", &SearchOptions::new().strict(true))
+            .unwrap_err()
+            .code
+    );
+    // the command line flags go through the options, as --ignore-case shows
+    let run = |args: &[&str]| {
+        crate::regexp::regexp(&crate::Config::parse_from(args))
+            .unwrap()
+            .matches
+    };
+    assert_eq!(
+        1,
+        run(&["regexp", "-q", "--ignore-case", "hello", "-t", "say HeLLo"])
+    );
+    assert_eq!(0, run(&["regexp", "-q", "hello", "-t", "say HeLLo"]));
+    let config =
+        crate::Config::parse_from(["regexp", "--ignore-case", "--strict", "-p", "alt", "x"]);
+    let options = config.search_options();
+    assert!(options.caseless && options.strict && options.alt_parser && options.optimize);
+}
+
+#[test]
+fn input_snapshot() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let tree = parse_tree(r"b+c", false).unwrap();
+    Input::init_text("abbbcd", false).unwrap();
+    let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+    assert_eq!("bbbc", report.text());
+    // a match can be formatted while Input is in use
+    let shown = Input::apply(|input| format!("{} {:?}", report.string(input), report.matched));
+    assert!(shown.starts_with("bbbc match \"bbbc\""));
+    // the snapshot follows new text
+    Input::init_text("xbc", false).unwrap();
+    assert_eq!(3, Input::with_text(|text| text.len()));
+    // and text read in from a file after the walk starts
+    let block = Input::set_block_size(500);
+    let file = std::env::temp_dir()
+        .join("regexp_input_snapshot.txt")
+        .to_string_lossy()
+        .to_string();
+    let mut contents = "aaaaaaaaa\n".repeat(180);
+    contents.push_str("bbc");
+    std::fs::write(&file, &contents).unwrap();
+    Input::init_files(&[file], false).unwrap();
+    let before = Input::with_text(|text| text.len());
+    assert!(before < contents.len());
+    let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+    assert_eq!("bbc", report.text());
+    assert_eq!((1_800, 1_803), report.byte_pos());
+    assert!(Input::with_text(|text| text.len()) > before);
+    Input::set_block_size(block);
+}
+
+#[test]
+fn parse_large_re() {
+    use std::time::{Duration, Instant};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // a definition library, like one loaded with use(), made up of about 100KB of definitions
+    let mut re = String::new();
+    let mut count = 0;
+    while re.len() < 100_000 {
+        re.push_str(&format!(
+            "def(d{}: and('abc' [a-z]+ \"x\\d\"? or('def' txt(\\w+) 'ghé'))) ",
+            count
+        ));
+        count += 1;
+    }
+    re.push_str(&format!("get(d{})", count - 1));
+    let started = Instant::now();
+    let tree = parse_tree(&re, true).unwrap();
+    let elapsed = started.elapsed();
+    assert_eq!(
+        Some("def(d7: and('abc' [a-z]+ \"x\\d\"? or('def' txt(\\w+) 'ghé')))".to_string()),
+        crate::tree::def_source("d7")
+    );
+    crate::tree::clear_defs();
+    assert!(matches!(tree, Node::And(_)));
+    assert!(
+        elapsed < Duration::from_secs(5),
+        "parsed {} bytes in {:?}",
+        re.len(),
+        elapsed
+    );
+}
+
+#[test]
+fn end_at_block_boundary() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let file = std::env::temp_dir()
+        .join("regexp_end_at_block_boundary.txt")
+        .to_string_lossy()
+        .to_string();
+    // the first block read ends just after "xxx\n"
+    let block = Input::set_block_size(500);
+    let mut contents = "aaaaaaaaa\n".repeat(49);
+    contents.push_str("bbbbbbbxxx\n");
+    let (tree, _) = parse_tree_full(r"xxx\n$", false, true).unwrap();
+    let (range_tree, _) = parse_tree_full(r"xxx[\n]$", false, true).unwrap();
+    let files = [file.clone()];
+    // when more text follows '$' does not match there
+    std::fs::write(&file, format!("{}more\n", contents)).unwrap();
+    Input::init_files(&files, false).unwrap();
+    assert_eq!(contents.len(), Input::len());
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    Input::init_files(&files, false).unwrap();
+    assert!(walk_tree(&range_tree, 0).unwrap().is_none());
+    // but it does at the end of the file
+    std::fs::write(&file, &contents).unwrap();
+    Input::init_files(&files, false).unwrap();
+    let path = walk_tree(&tree, 0).unwrap().unwrap();
+    assert_eq!((497, 501), path.range());
+    Input::init_files(&files, false).unwrap();
+    assert!(walk_tree(&range_tree, 0).unwrap().is_some());
+    Input::set_block_size(block);
+}
+
+#[test]
+fn search_lines() {
+    use crate::regexp::search_lines;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // returns the line numbers, ranges, and matched strings for each line with a match
+    let lines = |re: &str, text: &str, multiline: bool| {
+        let (tree, _) = parse_tree_full(re, false, true).unwrap();
+        Input::init_string(text, false);
+        let iter = search_lines(&tree);
+        let iter = if multiline { iter.multiline() } else { iter };
+        iter.map(|line| {
+            let (number, range, reports) = line.unwrap();
+            let strings: Vec<String> = reports.iter().map(|report| report.text()).collect();
+            (number, range, strings)
+        })
+        .collect::<Vec<_>>()
+    };
+    let text = "no match\nab once\nab ab and ab\n\nlast ab";
+    assert_eq!(
+        vec![
+            (2, 9..16, vec!["ab".to_string()]),
+            (
+                3,
+                17..29,
+                vec!["ab".to_string(), "ab".to_string(), "ab".to_string()]
+            ),
+            (5, 31..38, vec!["ab".to_string()]),
+        ],
+        lines("ab", text, false)
+    );
+    assert!(lines("xyz", text, false).is_empty());
+    // a match is not allowed to cross a newline, so a shorter one is found instead
+    assert_eq!(
+        vec![(1, 0..3, vec!["abc".to_string()])],
+        lines(r"a.*", "abc\nde\n", false)
+    );
+    assert!(lines(r"b\nc", "ab\ncd", false).is_empty());
+    // '$' matches at the end of each line
+    assert_eq!(
+        vec![
+            (1, 0..2, vec!["b".to_string()]),
+            (2, 3..5, vec!["b".to_string()])
+        ],
+        lines(r"b$", "ab\nab\nba", false)
+    );
+    // unless the search is multiline, when matches can run on into the following lines
+    assert_eq!(
+        vec![(1, 0..2, vec!["b\nc".to_string()])],
+        lines(r"b\nc", "ab\ncd", true)
+    );
+    // the next match can start in the line the last one ended in
+    assert_eq!(
+        vec![
+            (1, 0..2, vec!["b\nc".to_string()]),
+            (2, 3..5, vec!["b\nc".to_string()])
+        ],
+        lines(r"b\nc", "ab\ncb\ncd", true)
+    );
+    // empty texts have no lines, and a final newline does not start one
+    assert!(lines("x*", "", false).is_empty());
+    assert_eq!(2, lines("x*", "a\nb\n", false).len());
+    // files are read in as the lines are needed
+    let file = std::env::temp_dir()
+        .join("regexp_search_lines.txt")
+        .to_string_lossy()
+        .to_string();
+    std::fs::write(&file, format!("{}here\nnot\n", "line\n".repeat(200))).unwrap();
+    Input::init_files(&[file], false).unwrap();
+    let (tree, _) = parse_tree_full("her", false, true).unwrap();
+    let found: Vec<_> = search_lines(&tree).map(|line| line.unwrap()).collect();
+    assert_eq!(1, found.len());
+    assert_eq!((201, 1000..1004), (found[0].0, found[0].1.clone()));
+}
+
+#[test]
+fn named_order() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // names come back in the order they appear, not sorted or hashed
+    report_test(
+        r"\(?<zeta>a\)\(?<alpha>b+\)\(?<mid>c\)\(?<zeta>d\)",
+        "xabbcd",
+        false,
+        |report| {
+            let named = report.get_named();
+            assert_eq!(
+                vec!["", "zeta", "alpha", "mid"],
+                named.keys().collect::<Vec<&str>>()
+            );
+            assert_eq!(2, named.get("zeta").unwrap().len());
+            assert_eq!(1, named.get("alpha").unwrap().len());
+            assert!(named.get("beta").is_none());
+            let names: Vec<&str> = named.into_iter().map(|(name, _)| name).collect();
+            assert_eq!(vec!["", "zeta", "alpha", "mid"], names);
+        },
+    );
+    report_test("and('a'<one> 'b'<two> 'c'<three>)", "abc", true, |report| {
+        let names: Vec<&str> = report.get_named().keys().collect();
+        assert_eq!(vec!["", "one", "two", "three"], names);
+    });
+}
+
+#[test]
+fn report_iter() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // the same structure the reports test checks piece by piece
+    report_test(
+        r"ab\(cd\(?<x>ef\)+\)+",
+        ".abcdefefcd.",
+        false,
+        |report: &Report| {
+            let spans: Vec<(usize, usize)> = report.iter().map(|r| r.char_pos()).collect();
+            assert_eq!(vec![(1, 9), (3, 9), (5, 7), (7, 9)], spans);
+            let named: Vec<(usize, usize)> = report.iter_named().map(|r| r.char_pos()).collect();
+            assert_eq!(vec![(5, 7), (7, 9)], named);
+            let mut depths = Vec::new();
+            report.visit(|r, depth| depths.push((r.text(), depth)));
+            assert_eq!(
+                vec![
+                    ("abcdefef".to_string(), 0),
+                    ("cdefef".to_string(), 1),
+                    ("ef".to_string(), 2),
+                    ("ef".to_string(), 2)
+                ],
+                depths
+            );
+            assert_eq!(3, report.groups().len());
+        },
+    );
+    // very deep trees do not use up the stack
+    let matched = crate::walk::Matched {
+        start: 0,
+        end: 0,
+        char_start: 0,
+    };
+    let mut report = Report {
+        matched,
+        name: None,
+        subreports: Vec::new(),
+        branch: None,
+    };
+    for _ in 0..100_000 {
+        report = Report {
+            matched,
+            name: Some("".to_string()),
+            subreports: vec![report],
+            branch: None,
+        };
+    }
+    assert_eq!(100_001, report.iter().count());
+    let mut deepest = 0;
+    report.visit(|_, depth| deepest = deepest.max(depth));
+    assert_eq!(100_000, deepest);
+    // taken apart a level at a time, since dropping it all at once would recurse
+    let mut next = Some(report);
+    while let Some(mut report) = next {
+        next = report.subreports.pop();
+    }
+}
+
+#[test]
+fn read_block() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let file = std::env::temp_dir()
+        .join("regexp_read_block.txt")
+        .to_string_lossy()
+        .to_string();
+    std::fs::write(&file, "0123456789\n".repeat(100)).unwrap();
+    let files = [file];
+    // whole lines are read until there is at least a block
+    let block = Input::set_block_size(100);
+    let read_ahead = Input::set_read_ahead(false);
+    Input::init_files(&files, false).unwrap();
+    assert_eq!(110, Input::len());
+    Input::extend(200).unwrap();
+    assert_eq!(220, Input::len());
+    // reading ahead takes another block when what was asked for is close to the end
+    Input::set_read_ahead(true);
+    Input::init_files(&files, false).unwrap();
+    assert_eq!(110, Input::len());
+    Input::extend(200).unwrap();
+    assert_eq!(330, Input::len());
+    // the walk options can set it too
+    let previous = set_walk_options(WalkOptions::new().read_block(1));
+    assert_eq!(1, Input::block_size());
+    set_walk_options(previous);
+    // searches work the same with any size
+    for size in [1, 7, 100, Input::DEFAULT_BLOCK_SIZE] {
+        Input::set_block_size(size);
+        let (tree, _) = parse_tree_full(r"9\n0", false, true).unwrap();
+        Input::init_files(&files, false).unwrap();
+        assert_eq!(99, crate::regexp::find_iter(&tree).count());
+        let (tree, _) = parse_tree_full(r"89\n$", false, true).unwrap();
+        Input::init_files(&files, false).unwrap();
+        let path = walk_tree(&tree, 0).unwrap().unwrap();
+        assert_eq!((1097, 1100), path.range());
+    }
+    Input::set_block_size(block);
+    Input::set_read_ahead(read_ahead);
+}
+
+/// A reader that fails every time it is read, counting the tries
+#[cfg(test)]
+struct FailingRead {
+    tries: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    kind: std::io::ErrorKind,
+}
+
+#[cfg(test)]
+impl std::io::Read for FailingRead {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        self.tries.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+        Err(std::io::Error::new(self.kind, "disk on fire"))
+    }
+}
+
+#[test]
+fn read_errors() {
+    use clap::Parser;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // a source that gives a couple of lines and then fails
+    let failing = |kind: std::io::ErrorKind| {
+        let tries = Arc::new(AtomicUsize::new(0));
+        let reader = std::io::Cursor::new("abc\ndef\n").chain(FailingRead {
+            tries: tries.clone(),
+            kind,
+        });
+        Input::init_reader(Box::new(std::io::BufReader::new(reader)), false);
+        tries
+    };
+    let block = Input::set_block_size(4);
+    let (tree, _) = parse_tree_full("zzz", false, true).unwrap();
+    let tries = failing(std::io::ErrorKind::Other);
+    // the walk gives up on the source after the first error, and searches what it has
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    assert_eq!(1, tries.load(Ordering::Acquire));
+    assert_eq!("abc\ndef\n", Input::with_text(|text| text.to_string()));
+    let errors = Input::errors();
+    assert_eq!(1, errors.len());
+    assert_eq!(("reader", 210), (errors[0].0.as_str(), errors[0].1.code));
+    // matches in the text read before the error are still found
+    let tries = failing(std::io::ErrorKind::Other);
+    let (tree, _) = parse_tree_full("def", false, true).unwrap();
+    assert_eq!((4, 7), walk_tree(&tree, 0).unwrap().unwrap().range());
+    assert_eq!(0, tries.load(Ordering::Acquire));
+    // temporary errors are tried again a few times first
+    let tries = failing(std::io::ErrorKind::WouldBlock);
+    let (tree, _) = parse_tree_full("zzz", false, true).unwrap();
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    assert_eq!(4, tries.load(Ordering::Acquire));
+    assert_eq!(1, Input::errors().len());
+    Input::set_block_size(block);
+
+    // files that cannot be read are skipped and listed, and it is an error if none can be
+    let dir = std::env::temp_dir();
+    let file = dir.join("regexp_read_errors.txt");
+    std::fs::write(&file, "abc\n").unwrap();
+    let missing = dir.join("regexp_read_errors_missing.txt");
+    let _ = std::fs::remove_file(&missing);
+    let run = |files: Vec<&std::path::Path>| {
+        let mut args = vec!["regexp", "-q", "-a", "abc"];
+        args.extend(files.iter().map(|file| file.to_str().unwrap()));
+        crate::regexp::regexp(&crate::Config::parse_from(args)).map(|stats| stats.matches)
+    };
+    assert_eq!(1, run(vec![&missing, &dir, &file]).unwrap());
+    let errors = Input::errors();
+    assert_eq!(
+        vec![201, 210],
+        errors
+            .iter()
+            .map(|(_, error)| error.code)
+            .collect::<Vec<usize>>()
+    );
+    assert_eq!(missing.to_str().unwrap(), errors[0].0);
+    assert_eq!(211, run(vec![&missing, &dir]).unwrap_err().code);
+    assert_eq!(1, run(vec![&file, &missing]).unwrap());
+}
+
+#[test]
+fn in_node_names() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let digits = |re: &str, text: &str| {
+        let (tree, _) = parse_tree_full(re, true, true).unwrap();
+        Input::init_text(text, false).unwrap();
+        let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+        report
+            .get_by_name("num")
+            .iter()
+            .map(|report| report.text())
+            .collect::<Vec<String>>()
+    };
+    // after the repetition the name covers all of it
+    assert_eq!(vec!["123"], digits("'ab\\d+<num>cd'", "xab123cd"));
+    assert_eq!(vec!["333"], digits("'ab3+<num>cd'", "ab333cd"));
+    assert_eq!(vec!["12"], digits("'ab[0-9]{2}<num>'", "ab123"));
+    // before it each char is named separately
+    assert_eq!(vec!["1", "2", "3"], digits("'ab\\d<num>+cd'", "xab123cd"));
+    // anywhere else '<' is just a char
+    assert_eq!(Vec::<String>::new(), digits("'a<num>\\d+'", "a<num>12"));
+    let (tree, _) = parse_tree_full("'<b>'", true, true).unwrap();
+    Input::init_text("a<b>c", false).unwrap();
+    assert_eq!(
+        (1, 4),
+        Report::new(&walk_tree(&tree, 0).unwrap().unwrap()).byte_pos()
+    );
+    // there must be something to name
+    assert_eq!(114, parse_tree("'<num>+ab'", true).unwrap_err().code);
+}
+
+#[test]
+fn group_alternation() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let found = |re: &str, text: &str| {
+        let tree = parse_tree(re, false).unwrap();
+        Input::init_text(text, false).unwrap();
+        walk_tree(&tree, 0)
+            .unwrap()
+            .map(|path| path.matched_string())
+    };
+    for (text, single, group) in [
+        ("abc", None, Some("abc")),
+        ("def", None, Some("def")),
+        ("abcef", Some("abcef"), Some("abc")),
+        ("xabdefx", Some("abdef"), Some("def")),
+        ("abef", None, None),
+    ] {
+        let single = single.map(String::from);
+        let group = group.map(String::from);
+        assert_eq!(single, found(r"abc\|def", text), "{}", text);
+        assert_eq!(group, found(r"(?g)abc\|def", text), "{}", text);
+    }
+    // longer chains, groups and anchors
+    assert_eq!(Some("ghi".to_string()), found(r"(?g)abc\|def\|ghi", "xghi"));
+    assert_eq!(
+        Some("xdefy".to_string()),
+        found(r"(?g)x\(abc\|def\)y", "xdefy")
+    );
+    assert_eq!(None, found(r"(?g)^abc\|def", "xabc"));
+    assert_eq!(Some("def".to_string()), found(r"(?g)^abc\|def", "def"));
+    // a repetition still takes just the char before it
+    assert_eq!(Some("abccc".to_string()), found(r"(?g)abc+\|def", "abccc"));
+    // without an OR it changes nothing
+    assert_eq!(
+        parse_tree(r"abc+\(d\)", false).unwrap(),
+        parse_tree(r"(?g)abc+\(d\)", false).unwrap()
+    );
+}
+
+#[test]
+fn unknown_escapes() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let escapes = |re: &str, alt: bool| {
+        let (_, warnings) = parse_tree_full(re, alt, true).unwrap();
+        warnings
+            .iter()
+            .filter(|w| w.code == 6)
+            .map(|w| w.pos.unwrap())
+            .collect::<Vec<usize>>()
+    };
+    // unknown escapes are still taken as the plain char, with a warning giving where they are
+    assert_eq!(vec![2], escapes(r"ab\qc", false));
+    assert_eq!(vec![3], escapes(r"^ab\q", false));
+    assert_eq!(vec![2, 6], escapes(r"a[\q]b\3", false));
+    assert_eq!(vec![3], escapes(r"'ab\qc'", true));
+    assert_eq!(vec![2], escapes(r"'[\Q]'", true));
+    find(false, r"a\qc", "xaqc", "aqc");
+    // documented escapes and escaped punctuation never warn
+    for re in [
+        r"\\\.\*\+\?\[\]\{\$\^",
+        r"\d\l\u\a\w\x\o\n\t",
+        r"\cab\(c\|d\)",
+        r"[\d\n\]\\-]",
+        r"(?g)ab\|cd",
+    ] {
+        assert!(escapes(re, false).is_empty(), "{}", re);
+    }
+    for re in [r"'\\\'\[\*\d\n'", r#""\ca\"b""#, r"txt(a\)b)"] {
+        assert!(escapes(re, true).is_empty(), "{}", re);
+    }
+    // strict makes them an error
+    let (_, warnings) = parse_tree_full(r"ab\qc", false, true).unwrap();
+    let error = check_escapes(&warnings).unwrap_err();
+    assert_eq!(11, error.code);
+    assert!(
+        error.msg.contains("\\q") && error.msg.contains("at 2"),
+        "{}",
+        error
+    );
+    let run = |args: &[&str]| {
+        crate::regexp::regexp(&crate::Config::parse_from(
+            ["regexp", "-q"].iter().chain(args.iter()),
+        ))
+        .map(|stats| stats.matches)
+    };
+    assert_eq!(1, run(&[r"a\qc", "-t", "aqc"]).unwrap());
+    assert_eq!(
+        11,
+        run(&["--strict", r"a\qc", "-t", "aqc"]).unwrap_err().code
+    );
+    assert_eq!(1, run(&["--strict", r"a\.c", "-t", "a.c"]).unwrap());
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i", r"a\qc"]));
+    assert!(session.do_command("text aqc"));
+    assert_eq!(1, session.search_all(0, true));
+    assert!(session.do_command("set strict on"));
+    assert_eq!(0, session.search_all(0, true));
+}
+
+#[test]
+fn max_buffer() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let block = Input::set_block_size(1000);
+    // a long stream with no match in it
+    let stream = |lines: usize| {
+        let text = "abcdefghij\n".repeat(lines);
+        Input::init_reader(Box::new(std::io::Cursor::new(text)), false);
+    };
+    let (tree, _) = parse_tree_full("zzz", false, true).unwrap();
+    stream(1000);
+    assert!(Input::buffered_bytes() < 3000);
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    assert_eq!(11_000, Input::buffered_bytes());
+    assert_eq!(11_000, Input::peak_buffered_bytes());
+    // with a limit the search gives up once the text would go over it
+    let old = set_walk_options(WalkOptions::new().max_buffer(5_000));
+    stream(1000);
+    let error = walk_tree(&tree, 0).unwrap_err();
+    assert_eq!(230, error.code);
+    assert!(error.msg.contains("5000"), "{}", error);
+    assert!(Input::buffered_bytes() <= 5_000);
+    assert_eq!(Input::buffered_bytes(), Input::peak_buffered_bytes());
+    // the same happens without the scan, and when the walk is stepping through the text
+    set_walk_options(WalkOptions::new().max_buffer(5_000).no_scan());
+    stream(1000);
+    assert_eq!(230, walk_tree(&tree, 0).unwrap_err().code);
+    // matches found before the limit are still found, and a stream that fits is searched as usual
+    set_walk_options(WalkOptions::new().max_buffer(5_000));
+    stream(1000);
+    assert_eq!(
+        (3, 6),
+        walk_tree(&parse_tree("def", false).unwrap(), 0)
+            .unwrap()
+            .unwrap()
+            .range()
+    );
+    stream(100);
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    assert_eq!(1_100, Input::peak_buffered_bytes());
+    // a new input starts over
+    Input::init_string("abc", false);
+    assert_eq!(3, Input::peak_buffered_bytes());
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    set_walk_options(old);
+    Input::set_block_size(block);
+}
+
+#[test]
+fn walk_next() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let all = |re: &str, text: &str| {
+        let tree = parse_tree(re, false).unwrap();
+        Input::init_text(text, false).unwrap();
+        let mut found = Vec::new();
+        let mut from = 0;
+        while let Some((path, next)) = crate::walk::walk_tree_next(&tree, from).unwrap() {
+            found.push((path.range(), next));
+            from = next;
+        }
+        found
+    };
+    // empty matches move on a char, up to one at the very end
+    assert_eq!(
+        vec![((0, 0), 1), ((1, 1), 2), ((2, 2), 3), ((3, 3), 4)],
+        all("x*", "abc")
+    );
+    assert_eq!(vec![((0, 0), 2), ((2, 2), 3), ((3, 3), 4)], all("x*", "éa"));
+    // otherwise the next search starts at the end of the match
+    assert_eq!(
+        vec![
+            ((0, 0), 1),
+            ((1, 3), 3),
+            ((3, 3), 4),
+            ((4, 6), 6),
+            ((6, 6), 7)
+        ],
+        all("x*", "axxbxx")
+    );
+    assert_eq!(vec![((0, 2), 2), ((3, 5), 5)], all("ab", "ab ab"));
+    // the same rule is used by everything that goes through the matches
+    Input::init_text("abc", false).unwrap();
+    let tree = parse_tree("x*", false).unwrap();
+    assert_eq!(4, crate::regexp::find_iter(&tree).count());
+    assert_eq!(
+        ("-a-b-c-".to_string(), 4),
+        crate::regexp::replace_all(&tree, "-").unwrap()
+    );
+    let config = crate::Config::parse_from(["regexp", "-q", "-a", "x*", "-t", "abc"]);
+    assert_eq!(4, crate::regexp::regexp(&config).unwrap().matches);
+}
+
+#[test]
+fn group_callbacks() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    use std::sync::Arc;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let calls = Arc::new(Mutex::new(Vec::<(String, usize, String)>::new()));
+    let (digits, letters) = (calls.clone(), calls.clone());
+    let old = set_walk_options(
+        WalkOptions::new()
+            .on_group("d", move |matched, text| {
+                digits
+                    .lock()
+                    .unwrap()
+                    .push(("d".to_string(), matched.start, text.to_string()))
+            })
+            .on_group("l", move |matched, text| {
+                letters
+                    .lock()
+                    .unwrap()
+                    .push(("l".to_string(), matched.start, text.to_string()))
+            }),
+    );
+    let tree = parse_tree(r"\(?<d>\d\)+\(?<l>\l\)*", false).unwrap();
+    Input::init_text("x123ab!", false).unwrap();
+    let path = walk_tree(&tree, 0).unwrap().unwrap();
+    let report = Report::new(&path);
+    let found = calls.lock().unwrap().clone();
+    let expected = [
+        ("d", 1, "1"),
+        ("d", 2, "2"),
+        ("d", 3, "3"),
+        ("l", 4, "a"),
+        ("l", 5, "b"),
+    ];
+    assert_eq!(
+        expected
+            .map(|(name, start, text)| (name.to_string(), start, text.to_string()))
+            .to_vec(),
+        found
+    );
+    // the same matches as the report
+    for name in ["d", "l"] {
+        let reports = report.get_by_name(name);
+        let calls: Vec<&(String, usize, String)> =
+            found.iter().filter(|call| call.0 == name).collect();
+        assert_eq!(reports.len(), calls.len());
+        for (report, call) in reports.iter().zip(calls) {
+            assert_eq!(report.byte_pos().0, call.1);
+            assert_eq!(report.text(), call.2);
+        }
+    }
+    // a failed walk calls nothing, and each successful one calls them again
+    calls.lock().unwrap().clear();
+    Input::init_text("xyz", false).unwrap();
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    assert!(calls.lock().unwrap().is_empty());
+    Input::init_text("1 22", false).unwrap();
+    let reports: Vec<Report> = crate::regexp::find_iter(&tree)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(2, reports.len());
+    let named: usize = reports
+        .iter()
+        .map(|report| report.get_by_name("d").len() + report.get_by_name("l").len())
+        .sum();
+    assert_eq!(named, calls.lock().unwrap().len());
+    set_walk_options(old);
+}
+
+#[test]
+fn self_test() {
+    use crate::regexp::selftest::{self_test, Expected, SelfTestCase, SELF_TEST_CASES};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // the same table as --self-test, so the two cannot drift apart
+    for case in SELF_TEST_CASES {
+        if let Err(msg) = case.run() {
+            panic!("self test case {:?} on {:?}: {}", case.re, case.text, msg);
+        }
+    }
+    let mut out = Vec::new();
+    assert_eq!(0, self_test(&mut out).unwrap());
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(SELF_TEST_CASES.len() + 1, out.lines().count());
+    assert!(out.ends_with(&format!("{0} of {0} passed\n", SELF_TEST_CASES.len())));
+    // a case that does not do what it expects says what happened instead
+    let wrong = SelfTestCase {
+        alt: false,
+        re: "abc",
+        text: "xabc",
+        expected: Expected::Span(0, 3),
+    };
+    assert_eq!(
+        Err("expected Span(0, 3), got Span(1, 4)".to_string()),
+        wrong.run()
+    );
+    let wrong = SelfTestCase {
+        re: "abc[de",
+        ..wrong
+    };
+    assert!(wrong
+        .run()
+        .unwrap_err()
+        .starts_with("expected Span(0, 3), got error"));
+    for name in ["selftest_num", "selftest_word"] {
+        delete_def(name);
+    }
+}
+
+#[test]
+fn prefix_and_shortest() {
+    use crate::regexp::{match_prefix_len, shortest_match};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let range = |tree: &Node, from: usize| walk_tree(tree, from).unwrap().map(|path| path.range());
+    // at every position the prefix length is what a full search finds if its match starts there
+    for (re, text) in [
+        ("ab*", "xabbbc ab a"),
+        ("ab*?", "xabbbc ab a"),
+        (r"\(ab\)+c\|d", "ababc abd ab"),
+        ("x*", "axxb"),
+        (r"\d{2,3}?5", "1234 125 0015"),
+    ] {
+        let tree = parse_tree(re, false).unwrap();
+        Input::init_text(text, false).unwrap();
+        for at in 0..=text.len() {
+            let expected = range(&tree, at)
+                .filter(|(start, _)| *start == at)
+                .map(|(start, end)| end - start);
+            assert_eq!(
+                expected,
+                match_prefix_len(&tree, at).unwrap(),
+                "\"{}\" at {} in \"{}\"",
+                re,
+                at,
+                text
+            );
+        }
+        assert_eq!(None, match_prefix_len(&tree, text.len() + 1).unwrap());
+    }
+    // the shortest match starts where the full one does, and ends where the same RE written lazily does
+    for (re, lazy, text) in [
+        ("ab*", "ab*?", "xabbbc"),
+        ("a.*c", "a.*?c", "xabcbcx"),
+        (r"\(ab\)+", r"\(ab\)+?", "xababab"),
+        (r"\d{2,4}", r"\d{2,4}?", "ab 12345"),
+        ("ab*?c", "ab*?c", "xabbbc"),
+    ] {
+        let tree = parse_tree(re, false).unwrap();
+        let lazy_tree = parse_tree(lazy, false).unwrap();
+        Input::init_text(text, false).unwrap();
+        let full = range(&tree, 0).unwrap();
+        let shortest = shortest_match(&tree, 0).unwrap().unwrap();
+        assert_eq!(full.0, shortest.0, "\"{}\" in \"{}\"", re, text);
+        assert_eq!(
+            range(&lazy_tree, 0),
+            Some(shortest),
+            "\"{}\" in \"{}\"",
+            re,
+            text
+        );
+    }
+    let tree = parse_tree("ab*", false).unwrap();
+    Input::init_text("xabbb abb", false).unwrap();
+    assert_eq!(Some((6, 7)), shortest_match(&tree, 2).unwrap());
+    assert_eq!(None, shortest_match(&tree, 7).unwrap());
+}
+
+#[test]
+fn complexity_score() {
+    use crate::regexp::{parse_tree_opts, Regexp, SearchOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let score = |re: &str, alt: bool| parse_tree(re, alt).unwrap().complexity_score();
+    // simple REs score low, and each unlimited repetition inside another multiplies the score
+    for (re, alt, low, high) in [
+        ("abc", false, 3, 6),
+        (r"\d+-\d+", false, 20, 30),
+        ("'x' or('cat' 'dog' 'bird')", true, 8, 16),
+        (r"a[^;]*b[^;]*c[^;]*d", false, 30, 50),
+        (
+            r"^\(?<date>\d{4}-\d\d-\d\d\) \(?<level>\(INFO\)\|\(WARN\)\|\(ERROR\)\) .*user=\(?<user>\w+\).*$",
+            false,
+            50,
+            100,
+        ),
+        (r"\(a+\)+", false, 50, 70),
+        (r"\(\(a+\)+\)+", false, 200, 300),
+        (r"\(\(\(a+\)+\)+\)+b", false, 800, 1100),
+        ("and(and(and(and('a'+)+)+)+)+", true, 3000, 5000),
+    ] {
+        let found = score(re, alt);
+        assert!(
+            (low..=high).contains(&found),
+            "\"{}\" scores {}, expected {} to {}",
+            re,
+            found,
+            low,
+            high
+        );
+    }
+    // the same RE scores the same in either syntax, and a definition counts where it is used
+    assert_eq!(score(r"\(?ab\)+c", false), score("and('ab')+ 'c'", true));
+    assert_eq!(
+        score("'a' and('b'+)*", true),
+        score("def(cs_bs: and('b'+)*) 'a' get(cs_bs)", true)
+    );
+    delete_def("cs_bs");
+    // over the limit parsing fails, at or under it the RE is parsed as usual
+    let limited = |max: usize| SearchOptions::new().max_complexity(max);
+    let error = Regexp::with_options(r"\(\(a+\)+\)+", limited(150)).unwrap_err();
+    assert_eq!(18, error.code);
+    assert!(error.msg.contains("236"), "{}", error);
+    assert!(Regexp::with_options(r"\(a+\)+", limited(150)).is_ok());
+    let plain = score(r"\d+-\d+", false);
+    assert!(parse_tree_opts(r"\d+-\d+", &limited(plain)).is_ok());
+    assert_eq!(
+        18,
+        parse_tree_opts(r"\d+-\d+", &limited(plain - 1))
+            .unwrap_err()
+            .code
+    );
+}
+
+#[test]
+fn syntax_summary() {
+    use crate::regexp::syntax::{syntax_summary, SYNTAX_EXAMPLES};
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    for alt in [false, true] {
+        let summary = syntax_summary(alt);
+        // every escape the parser knows about is described
+        for code in SpecialNode::ESCAPE_CODES
+            .chars()
+            .chain(CHAR_ESCAPES.iter().map(|(code, _)| *code))
+        {
+            assert!(
+                summary
+                    .lines()
+                    .any(|line| line.trim_start().starts_with(&format!("\\{} ", code))),
+                "\\{} missing from the summary",
+                code
+            );
+        }
+        assert_eq!(alt, summary.contains("and(U U ...)"));
+        assert_eq!(!alt, summary.contains("\\(?<NAME>...\\)"));
+    }
+    // the examples work
+    for example in SYNTAX_EXAMPLES {
+        let re = crate::regexp::Regexp::with_parser(example.re, example.alt).unwrap();
+        assert!(
+            re.captures(example.text).unwrap().is_some(),
+            "example {} does not match \"{}\"",
+            example.re,
+            example.text
+        );
+    }
+    let syntax = |args: &[&str]| crate::Config::parse_from(args).syntax_alt();
+    assert_eq!(None, syntax(&["regexp", "x"]));
+    assert_eq!(Some(false), syntax(&["regexp", "--syntax"]));
+    assert_eq!(Some(true), syntax(&["regexp", "--syntax", "alt"]));
+    assert_eq!(Some(true), syntax(&["regexp", "-p", "alt", "--syntax"]));
+    assert_eq!(
+        Some(false),
+        syntax(&["regexp", "-p", "alt", "--syntax", "trad"])
+    );
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i", "ab+"]));
+    assert!(session.do_command("syntax"));
+    assert!(session.do_command("syntax alternative"));
+    assert!(session.do_command("syntax bogus"));
+}
+
+#[test]
+fn builtin_defs() {
+    use crate::regexp::builtin::{builtin_summary, BUILTIN_DEFS, BUILTIN_PREFIX};
+    use crate::regexp::Regexp;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    load_builtin_defs().unwrap();
+    for def in BUILTIN_DEFS {
+        assert!(
+            def.name.starts_with(BUILTIN_PREFIX),
+            "{} has no prefix",
+            def.name
+        );
+        assert!(
+            !def.matches.is_empty() && !def.misses.is_empty(),
+            "{} needs examples",
+            def.name
+        );
+        // matched against the whole text, so a miss cannot succeed by matching part of it
+        let re = Regexp::new_alt(&format!("^get({}) '$'", def.name)).unwrap();
+        for text in def.matches {
+            assert!(
+                re.is_match(text).unwrap(),
+                "{} does not match \"{}\"",
+                def.name,
+                text
+            );
+        }
+        for text in def.misses {
+            assert!(
+                !re.is_match(text).unwrap(),
+                "{} matches \"{}\"",
+                def.name,
+                text
+            );
+        }
+        // the short name finds it too
+        let short = &def.name[BUILTIN_PREFIX.len()..];
+        let re = Regexp::new_alt(&format!("^get({}) '$'", short)).unwrap();
+        assert!(
+            re.is_match(def.matches[0]).unwrap(),
+            "get({}) failed",
+            short
+        );
+        assert!(builtin_summary().contains(def.name));
+    }
+    // they load cleanly from an RE
+    match parse_tree_with_warnings("use(builtin) get(ipv4)", true) {
+        Ok((_, warnings)) => assert!(warnings.is_empty(), "{:?}", warnings),
+        Err(error) => panic!("use(builtin) failed: {}", error),
+    }
+    // a user definition comes before the builtin one, and does not replace it
+    let re = Regexp::new_alt("def(ipv4: 'x') get(ipv4)").unwrap();
+    assert!(re.is_match("x").unwrap());
+    assert!(!re.is_match("1.2.3.4").unwrap());
+    let re = Regexp::new_alt("get(std.ipv4)").unwrap();
+    assert_eq!(
+        "10.0.0.1",
+        re.find("at 10.0.0.1 now").unwrap().unwrap().as_str()
+    );
+    delete_def("ipv4");
+    // searching running text
+    let re = Regexp::new_alt("get(iso_date)<date> 'T' get(iso_time)<time>").unwrap();
+    let found = re
+        .captures("logged 2024-06-01T09:30:00Z ok")
+        .unwrap()
+        .unwrap();
+    assert_eq!("2024-06-01", found.name("date").unwrap().as_str());
+    assert_eq!("09:30:00Z", found.name("time").unwrap().as_str());
+    for def in BUILTIN_DEFS {
+        delete_def(def.name);
+    }
+    assert!(get_def("ipv4").is_none());
+}
+
+#[test]
+fn parse_once() {
+    use crate::regexp::parse_count;
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // a Regexp is parsed once, and keeps its definitions when the def table changes
+    let re = crate::regexp::Regexp::new_alt("def(parse_once: 'ab') get(parse_once)+").unwrap();
+    parse_tree("def(parse_once: 'xy')", true).unwrap();
+    let before = parse_count();
+    let found = re.find("xxababyy").unwrap().unwrap();
+    assert_eq!((2, 6), (found.start(), found.end()));
+    assert_eq!("abab", found.as_str());
+    assert!(!re.is_match("xyxy").unwrap());
+    assert!(re.captures("ab").unwrap().is_some());
+    assert_eq!(before, parse_count());
+    crate::regexp::tree::delete_def("parse_once");
+    // the interactive session keeps the tree with the RE
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i", "ab+"]));
+    assert!(session.do_command("text set xabbb"));
+    let before = parse_count();
+    assert!(session.do_command("search"));
+    assert_eq!(before + 1, parse_count());
+    assert!(session.do_command("search"));
+    assert!(session.do_command("search all"));
+    assert_eq!(before + 1, parse_count());
+    // and parses again when something that changes the tree changes
+    assert!(session.do_command("set caseless on"));
+    assert!(session.do_command("search"));
+    assert_eq!(before + 2, parse_count());
+    assert!(session.do_command("defs delete parse_once"));
+    assert!(session.do_command("search"));
+    assert_eq!(before + 3, parse_count());
+    assert!(session.do_command("regexp traditional ab+c"));
+    assert!(session.do_command("search"));
+    assert_eq!(before + 4, parse_count());
+}
+
+#[test]
+fn bytes_mode() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let find = |re: &str| {
+        let tree = parse_tree(re, false).unwrap();
+        Report::new(&walk_tree(&tree, 0).unwrap().unwrap())
+    };
+    let old = set_walk_options(WalkOptions::new().bytes());
+    assert!(Input::bytes_mode());
+    // stray high bytes, which are not UTF-8
+    let data = b"ab\xff\xfe error: x\x80y\n\xc3(ok\n".to_vec();
+    Input::init_reader(Box::new(std::io::Cursor::new(data.clone())), false);
+    let report = find("error: x.y");
+    assert_eq!((5, 15), report.offsets());
+    assert_eq!(b"error: x\x80y".to_vec(), report.bytes());
+    assert_eq!("error: x\\x80y", Input::display_text(&report.text()));
+    // positions are in bytes, not chars
+    Input::init_bytes(&data, false);
+    let report = find("ok");
+    assert_eq!((18, 20), report.offsets());
+    // the specials only match ASCII, anything else matches a high byte as a single char
+    Input::init_bytes(b"\xe9ab\xe9", false);
+    let report = find(r"\a+");
+    assert_eq!((1, 3), report.offsets());
+    let report = find("b[^a]");
+    assert_eq!((2, 4), report.offsets());
+    assert_eq!(b"b\xe9".to_vec(), report.bytes());
+    // REs that are not ASCII are rejected
+    assert_eq!(12, parse_tree("caf\u{e9}", false).unwrap_err().code);
+    assert_eq!(12, parse_tree("[\u{e0}-\u{ff}]", false).unwrap_err().code);
+    assert_eq!(12, parse_tree("any('\u{e9}')", true).unwrap_err().code);
+    // outside bytes mode the same RE is fine, and text that is not UTF-8 is replaced
+    set_walk_options(WalkOptions::new());
+    assert!(!Input::bytes_mode());
+    assert!(parse_tree("caf\u{e9}", false).is_ok());
+    Input::init_bytes(&data, false);
+    let report = find("error: x.y");
+    assert_eq!("error: x\u{fffd}y", report.text());
+    assert_eq!((9, 21), report.offsets());
+    // from the command line, where the text is searched as the bytes of its UTF-8
+    let config = crate::Config::parse_from(["regexp", "-q", "--bytes", "x.y", "-t", "ax\u{e9}y"]);
+    set_walk_options(config.walk_options());
+    assert_eq!(0, crate::regexp::regexp(&config).unwrap().matches);
+    let config = crate::Config::parse_from(["regexp", "-q", "--bytes", "x..y", "-t", "ax\u{e9}y"]);
+    assert_eq!(1, crate::regexp::regexp(&config).unwrap().matches);
+    let config = crate::Config::parse_from(["regexp", "-q", "--bytes", "\u{e9}", "-t", "\u{e9}"]);
+    assert_eq!(12, crate::regexp::regexp(&config).unwrap_err().code);
+    set_walk_options(old);
+}
+
+#[test]
+fn context_chars() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // a single 10KB line, with the match in the middle
+    let text = format!("{}needle{}", "é-".repeat(2500), "x".repeat(5000));
+    Input::init_string(&text, false);
+    let tree = parse_tree("needle", false).unwrap();
+    let path = walk_tree(&tree, 0).unwrap().unwrap();
+    let context = |chars| Input::apply(|input| path.match_context(input, chars));
+    assert_eq!(
+        (
+            "\u{2026}é-é-".to_string(),
+            "needle".to_string(),
+            "xxxx\u{2026}".to_string()
+        ),
+        context(4)
+    );
+    assert_eq!(
+        (
+            "\u{2026}".to_string(),
+            "needle".to_string(),
+            "\u{2026}".to_string()
+        ),
+        context(0)
+    );
+    // no ellipsis when the context reaches the end of the text
+    let (before, _, after) = context(6000);
+    assert_eq!("é-".repeat(2500), before);
+    assert_eq!("x".repeat(5000), after);
+    // the text after the match is read in if it comes from a file
+    let file = std::env::temp_dir().join("regexp_context_chars.txt");
+    std::fs::write(&file, &text).unwrap();
+    let block = Input::set_block_size(100);
+    let config = crate::Config::parse_from([
+        "regexp",
+        "--context-chars",
+        "500",
+        "needle",
+        file.to_str().unwrap(),
+    ]);
+    assert_eq!(1, crate::regexp::regexp(&config).unwrap().matches);
+    assert!(Input::buffered_bytes() >= 7_506);
+    let path = walk_tree(&tree, 0).unwrap().unwrap();
+    let (before, _, after) = Input::apply(|input| path.match_context(input, 500));
+    assert_eq!(format!("\u{2026}{}", "é-".repeat(250)), before);
+    assert_eq!(format!("{}\u{2026}", "x".repeat(500)), after);
+    Input::set_block_size(block);
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn or_branch() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let tree = parse_tree("or('cat' 'dog' 'bird')<animal>", true).unwrap();
+    for (text, branch) in [("a cat", 0), ("hotdog", 1), ("bird", 2)] {
+        Input::init_text(text, false).unwrap();
+        let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+        let animal = report.get_by_name("animal");
+        assert_eq!(Some((branch, 3)), animal[0].branch, "{}", text);
+    }
+    // each repetition has its own branch, unless the name covers them all
+    Input::init_text("dogcat", false).unwrap();
+    let tree = parse_tree("or('cat' 'dog')<animal>*", true).unwrap();
+    let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+    let branches: Vec<_> = report
+        .get_by_name("animal")
+        .iter()
+        .map(|r| r.branch)
+        .collect();
+    assert_eq!(vec![Some((1, 2)), Some((0, 2))], branches);
+    let tree = parse_tree("or('cat' 'dog')*<animal>", true).unwrap();
+    let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+    assert_eq!(None, report.get_by_name("animal")[0].branch);
+    // only ORs have branches
+    let tree = parse_tree("and('dog')<animal>", true).unwrap();
+    let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+    assert_eq!(None, report.get_by_name("animal")[0].branch);
+}
+
+#[test]
+fn auto_preview() {
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    for (partial, alt, closing) in [
+        (r#"and("a" or("b"#, true, r#""))"#),
+        (r#"and('x' "it's"<na"#, true, ">)"),
+        ("and(txt(a(b", true, "))"),
+        (r#"any("[a-"#, true, "]\")"),
+        (r#"'a'{2,"#, true, "}"),
+        (r#"and("a" "b")"#, true, ""),
+        (r"\(a\(?<name>b", false, r"\)\)"),
+        (r"\(a[)\]", false, r"]\)"),
+        (r"(a\(b", false, r"\)"),
+    ] {
+        assert_eq!(closing, auto_close(partial, alt), "{}", partial);
+        let closed = format!("{}{}", partial, closing);
+        assert!(parse_tree(&closed, alt).is_ok(), "{}", closed);
+    }
+    // an empty group does not parse, but a name is still closed
+    assert_eq!(r">\)", auto_close(r"\(?<na", false));
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp",
+        "-i",
+        "-t",
+        "a dog and a cat",
+    ]));
+    assert!(!session.options().auto);
+    session.set_option("auto", "on").unwrap();
+    assert!(session.options().auto);
+    assert_eq!(
+        Some(r#"preview (closed with ')): match "do" [2-4)"#.to_string()),
+        session.preview("regexp alternative or('do")
+    );
+    assert_eq!(
+        Some(r#"preview: match "cat" [12-15)"#.to_string()),
+        session.preview("regexp alt or('cat' 'cow')\n")
+    );
+    assert_eq!(
+        Some("preview (closed with )): No match".to_string()),
+        session.preview("regexp alternative and('a' 'dog' 'x'")
+    );
+    assert!(session
+        .preview("regexp alternative 'a'{x,")
+        .unwrap()
+        .starts_with("preview: Error parsing RE"));
+    assert_eq!(None, session.preview("text set and("));
+    // a partial definition does not stay in the table
+    let before = def_sources();
+    assert!(session
+        .preview("regexp alternative def(preview_def: 'c'")
+        .is_some());
+    assert_eq!(before, def_sources());
+    assert!(get_def("preview_def").is_none());
+}
+
+#[test]
+fn leading_wildcard() {
+    use crate::regexp::{set_walk_options, Regexp, SearchOptions, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let no_match = "abcdefghij ".repeat(20);
+    for (re, alt) in [
+        (r".*foo", false),
+        (r".*?foo", false),
+        (r".*\(o+\)b", false),
+        (r#"".*" "o" any("xyz")"#, true),
+        (r#"and(".*?" "f")+ "o""#, true),
+    ] {
+        let tree = parse_tree(re, alt).unwrap();
+        assert!(tree.leading_wildcard(), "{}", re);
+        for text in [
+            "a foo and foo",
+            "foo\nbar foob",
+            "fox",
+            "oy",
+            no_match.as_str(),
+        ] {
+            Input::init_text(text, false).unwrap();
+            let fast = all_ranges(&tree);
+            let fast_report = walk_tree(&tree, 0).unwrap().map(|path| Report::new(&path));
+            let options = set_walk_options(WalkOptions::new().no_scan());
+            let slow = all_ranges(&tree);
+            let slow_report = walk_tree(&tree, 0).unwrap().map(|path| Report::new(&path));
+            set_walk_options(options);
+            assert_eq!(slow, fast, "{} in {:?}", re, text);
+            assert_eq!(
+                format!("{:?}", slow_report),
+                format!("{:?}", fast_report),
+                "{} in {:?}",
+                re,
+                text
+            );
+        }
+    }
+    // a failed search stops after the first start instead of trying them all
+    let tree = parse_tree(".*zq", false).unwrap();
+    Input::init_text(&no_match, false).unwrap();
+    reset_step_count();
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    let fast = step_count();
+    let options = set_walk_options(WalkOptions::new().no_scan());
+    reset_step_count();
+    assert!(walk_tree(&tree, 0).unwrap().is_none());
+    let slow = step_count();
+    set_walk_options(options);
+    assert!(fast * 50 < slow, "{} {}", fast, slow);
+    // a named or optional wildcard is left alone
+    for (re, alt) in [(r#"".*"<pre> "foo""#, true), (r#"".*"? "foo""#, true)] {
+        assert!(!parse_tree(re, alt).unwrap().leading_wildcard(), "{}", re);
+    }
+    // the lint
+    let lint = |re: &str, alt: bool| {
+        parse_tree_with_warnings(re, alt)
+            .unwrap()
+            .1
+            .iter()
+            .any(|warning| warning.code == 7)
+    };
+    assert!(lint(".*foo", false));
+    assert!(lint(".*?foo", false));
+    assert!(lint(r#"".*" "foo""#, true));
+    assert!(!lint("^.*foo", false));
+    assert!(!lint(r#"".*"<pre> "foo""#, true));
+    assert!(!lint(".*", false));
+    assert!(!lint("a.*foo", false));
+    // a trailing wildcard is not walked when only the whole match is needed
+    for re in ["fo+.*", "fo+.*?", r"\(?<f>fo+\).*", "x*.*"] {
+        let regexp = Regexp::new(re).unwrap();
+        assert!(
+            regexp.tree().without_trailing_wildcard().is_some(),
+            "{}",
+            re
+        );
+        for text in ["a foo bar", "foo", "bar", ""] {
+            let whole = regexp
+                .captures(text)
+                .unwrap()
+                .map(|caps| caps.get(0).unwrap().range());
+            let found = regexp.find(text).unwrap().map(|found| found.range());
+            assert_eq!(whole, found, "{} in {:?}", re, text);
+            assert_eq!(whole.is_some(), regexp.is_match(text).unwrap());
+        }
+    }
+    // except where the options can cut the match short
+    let regexp = Regexp::with_options(
+        "fo+.*",
+        SearchOptions::new().walk(WalkOptions::new().max_match_len(5)),
+    )
+    .unwrap();
+    assert_eq!(
+        Some(2..7),
+        regexp.find("a foo bar").unwrap().map(|found| found.range())
+    );
+}
+
+#[test]
+fn interactive_source() {
+    use crate::interactive::ScriptEnd;
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let script = std::env::temp_dir()
+        .join("regexp_source_script.txt")
+        .to_string_lossy()
+        .to_string();
+    let mut session =
+        crate::interactive::Interactive::new(crate::Config::parse_from(["regexp", "-i"]));
+    // commands that cannot do what they are asked are marked as failed
+    for (command, failed) in [
+        ("search", true),
+        ("text set abcd", false),
+        ("regexp traditional b\\(c", false),
+        ("search", true),
+        ("regexp traditional bc", false),
+        ("search", false),
+        ("regexp traditional x", false),
+        ("search", false),
+        ("tree x", true),
+        ("no_such_command", true),
+    ] {
+        assert!(session.do_command(command));
+        assert_eq!(session.command_failed(), failed, "{}", command);
+    }
+    std::fs::write(
+        &script,
+        "# comment\ntext set xyz\nregexp traditional \\\ny\nsearch\ntree x\ntext set after\n",
+    )
+    .unwrap();
+    assert_eq!(session.source(&script, false), ScriptEnd::Failed);
+    assert_eq!(session.text(), Some(&"xyz".to_string()));
+    assert_eq!(session.source(&script, true), ScriptEnd::Finished);
+    assert_eq!(session.text(), Some(&"after".to_string()));
+    assert!(!session.command_failed());
+    std::fs::write(&script, "text set abc\nquit\ntext set after\n").unwrap();
+    assert!(!session.do_command(&format!("source {}", script)));
+    assert_eq!(session.text(), Some(&"abc".to_string()));
+    // a script that sources itself is stopped
+    std::fs::write(&script, format!("source {}\n", script)).unwrap();
+    assert_eq!(session.source(&script, false), ScriptEnd::Failed);
+    assert_eq!(
+        session.source("/nonexistent/script", false),
+        ScriptEnd::Failed
+    );
+    std::fs::remove_file(script).unwrap();
+}
+
+#[test]
+fn or_failures() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    use crate::regexp::{set_walk_options, WalkOptions};
+    // the OR branches that fail are remembered and not walked again, which saves steps but finds the same matches
+    for (re, alt, text) in [
+        (
+            r"\(?\(?[0-9]+x\|[0-9]+y\|[0-9]+\.\)\)+z",
+            false,
+            "123456789 123456789 123456789. 123.z",
+        ),
+        (
+            r#"and("\d+" or('x' "\d+") or('y' "\d+") '.')+ 'z'"#,
+            true,
+            "123456789 123456789 123456789. 123.z",
+        ),
+    ] {
+        let tree = parse_tree(re, alt).unwrap();
+        Input::init_text(text, false).unwrap();
+        let mut counts = Vec::new();
+        let mut results = Vec::new();
+        for options in [
+            WalkOptions::new().no_scan(),
+            WalkOptions::new().no_scan().no_or_memo(),
+        ] {
+            let previous = set_walk_options(options);
+            reset_step_count();
+            results.push(all_ranges(&tree));
+            counts.push(step_count());
+            set_walk_options(previous);
+        }
+        assert_eq!(results[0], results[1], "{}", re);
+        assert!(!results[0].is_empty(), "{}", re);
+        assert!(counts[0] < counts[1], "{}: {:?}", re, counts);
+        // with a longest match the failures are kept only for one start position, but the matches are the same
+        let previous = set_walk_options(WalkOptions::new().max_match_len(100));
+        assert_eq!(all_ranges(&tree), results[0], "{}", re);
+        set_walk_options(previous);
+    }
+    // an AND that cannot back off its last child fails, rather than backing off an earlier one and keeping the
+    // later ones where they were
+    for (re, text, expected) in [
+        (r"\([^a]?.x\)c", "cbx ", None),
+        (r"\([ab]+?[ab]{2}\)+b+?", "bbb aaxcb a", None),
+        (
+            r"\(b*?[^a]{2}\(c*?[ab]{1,2}[^a]*?\){1,2}[ab]{1,2}\)?[ab]",
+            "b1b bc b1",
+            Some((0, 1)),
+        ),
+    ] {
+        for optimize in [true, false] {
+            let (tree, _) = parse_tree_full(re, false, optimize).unwrap();
+            Input::init_text(text, false).unwrap();
+            let found = walk_tree(&tree, 0).unwrap().map(|path| path.range());
+            assert_eq!(expected, found, "{} on {:?}", re, text);
+        }
+    }
+}
+
+#[test]
+fn run_stats() {
+    use crate::regexp::SourceStats;
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let dir = std::env::temp_dir();
+    let files = [
+        dir.join("regexp_run_stats_1.txt"),
+        dir.join("regexp_run_stats_2.txt"),
+        dir.join("regexp_run_stats_3.bin"),
+    ];
+    std::fs::write(&files[0], "abc\nxabc abc\n").unwrap();
+    std::fs::write(&files[1], "no match\nabc").unwrap();
+    std::fs::write(&files[2], b"abc\xff\xfe\n").unwrap();
+    let names: Vec<&str> = files.iter().map(|file| file.to_str().unwrap()).collect();
+    let run = |flags: &[&str], count: usize| {
+        let mut args = vec!["regexp", "-q", "abc"];
+        args.extend_from_slice(flags);
+        args.extend_from_slice(&names[..count]);
+        crate::regexp::regexp(&crate::Config::parse_from(args)).unwrap()
+    };
+    let stats = run(&["-a"], 2);
+    assert_eq!(4, stats.matches);
+    assert_eq!(
+        vec![
+            SourceStats {
+                name: names[0].to_string(),
+                matches: 3,
+                bytes: 13,
+                lines: 2
+            },
+            SourceStats {
+                name: names[1].to_string(),
+                matches: 1,
+                bytes: 12,
+                lines: 2
+            },
+        ],
+        stats.files
+    );
+    assert_eq!((25, 4), (stats.bytes_scanned, stats.lines_scanned));
+    let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+    assert_eq!(4, json["matches"]);
+    assert_eq!(names[1], json["files"][1]["name"]);
+    assert_eq!(3, json["files"][0]["matches"]);
+    assert_eq!(12, json["files"][1]["bytes"]);
+    assert_eq!(2, json["files"][1]["lines"]);
+    assert_eq!(25, json["bytes_scanned"]);
+    assert_eq!(4, json["lines_scanned"]);
+    assert_eq!(serde_json::json!([]), json["files_skipped_binary"]);
+    assert!(json["elapsed_secs"].as_f64().unwrap() >= 0.0);
+    // a file that is not UTF-8 is given up on and listed
+    let stats = run(&["-a"], 3);
+    assert_eq!(vec![names[2].to_string()], stats.files_skipped_binary);
+    assert_eq!(212, Input::errors()[0].1.code);
+    assert_eq!((3, 0), (stats.files.len(), stats.files[2].matches));
+    // a search that stops at a count stops in the middle of the first file
+    let stats = run(&["-c", "2"], 2);
+    assert_eq!(2, stats.matches);
+    assert_eq!(1, stats.files.len());
+    // the counts are the same for --count-only and --replace
+    for flags in [&["--count-only"][..], &["--replace", "x"][..]] {
+        let stats = run(flags, 2);
+        assert_eq!(
+            vec![3, 1],
+            stats
+                .files
+                .iter()
+                .map(|source| source.matches)
+                .collect::<Vec<usize>>()
+        );
+    }
+    files
+        .iter()
+        .for_each(|file| std::fs::remove_file(file).unwrap());
+}
+
+#[test]
+fn display_text_escapes() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    assert!(!Input::bytes_mode());
+    // text with nothing to escape is not copied
+    assert!(matches!(
+        Input::display_text("plain text"),
+        std::borrow::Cow::Borrowed(_)
+    ));
+    for (text, shown) in [
+        ("a\0b", "a\\x00b"),
+        ("\x1b[31mred\x1b[0m", "\\x1b[31mred\\x1b[0m"),
+        ("del\x7f", "del\\x7f"),
+        ("back\rover", "back\\x0dover"),
+        ("csi\u{9b}", "csi\\u{9b}"),
+        // tabs and newlines are kept, and so are characters that take several bytes
+        ("a\tb\nc", "a\tb\nc"),
+        (
+            "caf\u{e9} \u{1f600} \u{4e2d}",
+            "caf\u{e9} \u{1f600} \u{4e2d}",
+        ),
+        ("\u{e9}\x1b\u{e9}", "\u{e9}\\x1b\u{e9}"),
+    ] {
+        assert_eq!(shown, Input::display_text(text), "{:?}", text);
+    }
+    // raw output leaves them alone
+    let old = Input::set_raw_output(true);
+    assert_eq!("a\0\x1b\x7f", Input::display_text("a\0\x1b\x7f"));
+    // in bytes mode the bytes that are not ASCII are escaped whether or not the output is raw
+    let options = set_walk_options(WalkOptions::new().bytes());
+    assert_eq!("\\xe9\x1b", Input::display_text("\u{e9}\x1b"));
+    Input::set_raw_output(false);
+    assert_eq!("\\xe9\\x1b\\x9b", Input::display_text("\u{e9}\x1b\u{9b}"));
+    set_walk_options(options);
+    Input::set_raw_output(old);
+    // the match printers use it
+    Input::init_text("say \x1b[1mhi", false).unwrap();
+    let tree = parse_tree("y..\\[", false).unwrap();
+    let report = Report::new(&walk_tree(&tree, 0).unwrap().unwrap());
+    let mut out = Vec::new();
+    report.display_to(&mut out, 0).unwrap();
+    assert!(String::from_utf8(out).unwrap().starts_with("\"y \\x1b[\""));
+}
+
+#[test]
+fn search_driver_events() {
+    use crate::regexp::{MatchEvent, SearchDriver};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let dir = std::env::temp_dir();
+    let files = [
+        dir.join("regexp_driver_1.txt"),
+        dir.join("regexp_driver_missing.txt"),
+        dir.join("regexp_driver_2.txt"),
+    ];
+    std::fs::write(&files[0], "cat\n\nsat cat\n").unwrap();
+    let _ = std::fs::remove_file(&files[1]);
+    std::fs::write(&files[2], "a bat\n").unwrap();
+    let names: Vec<String> = files
+        .iter()
+        .map(|file| file.to_str().unwrap().to_string())
+        .collect();
+    let tree = parse_tree("[a-z]at", false).unwrap();
+    let run = |driver: SearchDriver| {
+        Input::init_files(&names, false).unwrap();
+        let mut events = Vec::new();
+        let stats = driver.run(&mut |event: &MatchEvent| {
+            events.push(match event {
+                MatchEvent::FileStarted(name) => format!("start {}", name),
+                MatchEvent::Match(found) => format!(
+                    "match {} {} line {} {:?}",
+                    found.file.unwrap(),
+                    found.report.text(),
+                    found.line,
+                    found.path.range()
+                ),
+                MatchEvent::FileErrored(name, error) => format!("error {} {}", name, error.code),
+                MatchEvent::SearchFailed(error) => format!("failed {}", error.code),
+                MatchEvent::FileFinished(source) => {
+                    format!("finish {} {}", source.name, source.matches)
+                }
+                MatchEvent::Finished(stats) => format!("done {}", stats.matches),
+            })
+        });
+        (stats, events)
+    };
+    let (stats, events) = run(SearchDriver::new(&tree).all());
+    assert_eq!(4, stats.matches);
+    assert_eq!(
+        vec![
+            format!("start {}", names[0]),
+            format!("match {} cat line 1 (0, 3)", names[0]),
+            format!("match {} sat line 3 (5, 8)", names[0]),
+            format!("match {} cat line 3 (9, 12)", names[0]),
+            format!("finish {} 3", names[0]),
+            // the missing file is found when the driver moves on past the first one
+            format!("error {} 201", names[1]),
+            format!("start {}", names[2]),
+            format!("match {} bat line 1 (2, 5)", names[2]),
+            format!("finish {} 1", names[2]),
+            "done 4".to_string(),
+        ],
+        events
+    );
+    // a limit stops the run in the source where it is reached
+    let (stats, events) = run(SearchDriver::new(&tree).limit(2));
+    assert_eq!(2, stats.matches);
+    assert_eq!(format!("finish {} 2", names[0]), events[events.len() - 2]);
+    // counting sends no matches
+    let (stats, events) = run(SearchDriver::new(&tree).count_only());
+    assert_eq!(4, stats.matches);
+    assert!(!events.iter().any(|event| event.starts_with("match")));
+    assert_eq!(format!("finish {} 1", names[2]), events[events.len() - 2]);
+    std::fs::remove_file(&files[0]).unwrap();
+    std::fs::remove_file(&files[2]).unwrap();
+}
+
+#[test]
+fn regexp_set_scan_lines() {
+    use crate::regexp::{parse_tree_opts, scan_lines, search_lines, RegexpSet, SearchOptions};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let text = "2024-01-02 ERROR disk full on /dev/sda1\n\
+                2024-01-02 INFO started in 120ms\n\
+                \n\
+                warn: retry 3 of 5\n\
+                2024-01-03 error: timeout after 5000ms\n\
+                cat and dog\n\
+                the end";
+    // literals, sequences, and REs with no prefilter, anchored and not
+    let res = [
+        "ERROR",
+        "\\d\\d\\d\\d-\\d\\d-\\d\\d",
+        "\\(ERROR\\)\\|\\(INFO\\)\\|\\(warn\\)",
+        "[0-9]+ms$",
+        "^warn",
+        "\\cerror",
+        "^$",
+        "o.*o",
+        "end$",
+        "zebra",
+    ];
+    for optimize in [true, false] {
+        let options = SearchOptions::new().optimize(optimize);
+        let set = RegexpSet::new(&res, options.clone()).unwrap();
+        let hits: Vec<_> = scan_lines(&set, text.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        // each RE run on its own finds the same lines
+        for (pattern, re) in res.iter().enumerate() {
+            let tree = parse_tree_opts(re, &options).unwrap().0;
+            Input::init_string(text, false);
+            let expected: Vec<usize> = search_lines(&tree).map(|line| line.unwrap().0).collect();
+            let found: Vec<usize> = hits
+                .iter()
+                .filter(|line| line.patterns.contains(&pattern))
+                .map(|line| line.number)
+                .collect();
+            assert_eq!(expected, found, "RE {}", re);
+        }
+        assert_eq!(
+            vec![1, 2, 3, 4, 5, 7],
+            hits.iter().map(|line| line.number).collect::<Vec<usize>>()
+        );
+        assert_eq!("warn: retry 3 of 5", hits[3].text);
+        assert_eq!(vec![2, 4], hits[3].patterns);
+        assert_eq!(
+            vec![0, 1, 2, 5, 7],
+            set.matches("2024-01-02 ERROR no no").unwrap()
+        );
+    }
+    let set = RegexpSet::new(&["abc"], SearchOptions::new()).unwrap();
+    let mut scan = scan_lines(&set, &b"abc\nab\xffc\nabc\n"[..]);
+    assert_eq!(1, scan.next().unwrap().unwrap().number);
+    assert_eq!(212, scan.next().unwrap().unwrap_err().code);
+    assert!(scan.next().is_none());
+    assert_eq!((2, 9), (scan.lines_read(), scan.bytes_read()));
+}
+
+#[test]
+fn whitespace_sets() {
+    use crate::regexp::{parse_tree_opts, set_whitespace, Regexp, SearchOptions, Whitespace};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // by default a non-breaking space is text, so it has to be in the match
+    let re = "\"a\" \u{a0} \"b\"\r\n";
+    let default = Regexp::with_parser(re, true).unwrap();
+    assert!(default.is_match("a\u{a0}b").unwrap());
+    assert!(!default.is_match("ab").unwrap());
+    // with Unicode whitespace it separates the units, as do carriage returns
+    let unicode = Regexp::with_options(
+        re,
+        SearchOptions::new()
+            .alt_parser(true)
+            .whitespace(Whitespace::new().unicode()),
+    )
+    .unwrap();
+    assert!(unicode.is_match("ab").unwrap());
+    assert!(Whitespace::new().unicode().contains('\u{2003}'));
+    let tabs = Whitespace::new().chars("\t");
+    assert!(!tabs.contains(' ') && tabs.contains('\t'));
+    // the setting only lasts for the parse it was given to
+    assert!(!Regexp::with_parser(re, true)
+        .unwrap()
+        .is_match("ab")
+        .unwrap());
+
+    let path = std::env::temp_dir().join("regexp_whitespace_defs.re");
+    let path = path.to_str().unwrap();
+    let load = |defs: &str, whitespace: Whitespace| {
+        std::fs::write(path, defs).unwrap();
+        let previous = set_whitespace(whitespace);
+        let loaded = crate::regexp::tree::load_def_file(path);
+        set_whitespace(previous);
+        loaded
+    };
+    let one_a_line = "# one to a line\ndef(ws_one: 'a' 'b')\n\n  def(ws_two: or('c' 'd'))  \n";
+    let continued = "def(ws_three: 'a' \\\n    'b')\n";
+    let split = "def(ws_one: 'a')\ndef(ws_four: 'a'\n    'b')\n";
+    let split_inside = "def(ws_five: or('a'\n'b'))\n";
+    for defs in [one_a_line, continued, split, split_inside] {
+        assert!(load(defs, Whitespace::new()).is_ok(), "{}", defs);
+    }
+    assert!(load(one_a_line, Whitespace::new().strict_lines()).is_ok());
+    assert!(load(continued, Whitespace::new().strict_lines()).is_ok());
+    let error = load(split, Whitespace::new().strict_lines()).unwrap_err();
+    assert_eq!(
+        (123, true),
+        (error.code, error.msg.contains("line 2")),
+        "{}",
+        error
+    );
+    assert_eq!(
+        123,
+        load(split_inside, Whitespace::new().strict_lines())
+            .unwrap_err()
+            .code
+    );
+    // strict lines only apply to def files, not to the RE
+    let options = SearchOptions::new()
+        .alt_parser(true)
+        .whitespace(Whitespace::new().strict_lines());
+    assert!(parse_tree_opts("'a'\n'b'", &options).is_ok());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn anchored_all() {
+    use crate::regexp::walk::count_matches;
+    use crate::regexp::{search_lines, MatchEvent, SearchDriver};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let (tree, _) = parse_tree_full("^a", false, true).unwrap();
+    assert!(tree.anchored());
+    assert!(!parse_tree_full("a^", false, true).unwrap().0.anchored());
+    // over the whole text '^' only matches at its start, however many times the walk could match later
+    Input::init_string("aaa\nab\n", false);
+    assert_eq!(1, crate::regexp::find_iter(&tree).count());
+    assert_eq!(1, count_matches(&tree, 0).unwrap());
+    Input::init_string("aaa\nab\n", false);
+    let mut found = 0;
+    let stats = SearchDriver::new(&tree)
+        .all()
+        .run(&mut |event: &MatchEvent| {
+            if let MatchEvent::Match(_) = event {
+                found += 1;
+            }
+        });
+    assert_eq!((1, 1), (found, stats.matches));
+    Input::init_string("baa", false);
+    assert_eq!(0, crate::regexp::find_iter(&tree).count());
+    // a line at a time it matches once at the start of each line, with or without multiline
+    let lines = |re: &str, text: &str, multiline: bool| {
+        let (tree, _) = parse_tree_full(re, false, true).unwrap();
+        Input::init_string(text, false);
+        let iter = search_lines(&tree);
+        let iter = if multiline { iter.multiline() } else { iter };
+        iter.map(|line| {
+            let (number, _, reports) = line.unwrap();
+            let strings: Vec<String> = reports.iter().map(|report| report.text()).collect();
+            (number, strings)
+        })
+        .collect::<Vec<_>>()
+    };
+    let expected = vec![(1, vec!["a".to_string()]), (2, vec!["a".to_string()])];
+    assert_eq!(expected, lines("^a", "aaa\naaa\nbaa\n", false));
+    assert_eq!(expected, lines("^a", "aaa\naaa\nbaa\n", true));
+    // in multiline mode a line a match ran on into is not searched again, as its start has been passed
+    assert_eq!(
+        vec![(1, vec!["a\na".to_string()]), (3, vec!["a\na".to_string()])],
+        lines(r"^a\na", "a\na\na\na\n", true)
+    );
+}
+
+#[test]
+fn explain_syntaxes() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // the same RE written in either syntax is explained the same way
+    let explain = |re: &str, alt: bool| {
+        let (tree, warnings) = parse_tree_with_warnings(re, alt).unwrap();
+        tree.explain(&warnings)
+    };
+    let expected = "match \"ab\"\nthen 2 to 4 digits, captured as 'num'\nthen the end of the text";
+    assert_eq!(expected, explain(r"ab\(?<num>\d{2,4}\)$", false));
+    assert_eq!(expected, explain(r#""ab" "\d"{2,4}<num> "$""#, true));
+    assert_eq!(
+        "match one of these, 1 or more times:\n    either \"x\"\n    or a character matching [^y]",
+        explain(r#"or("x" "[^y]")+"#, true)
+    );
+    assert_eq!("match the empty string", explain("", false));
+    assert!(explain(r"a\q", false)
+        .ends_with("Notes:\n  - Unknown escape \"\\q\" is taken as 'q' (at position 1)"));
+}
+
+#[test]
+fn deepening_search() {
+    use crate::regexp::walk::{
+        deepening_trees, set_walk_options, walk_tree_deepening, WalkOptions, DEEPENING_CAPS,
+    };
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let (tree, _) = parse_tree_full(r"a[^;]*b[^;]*c[^;]*d", false, true).unwrap();
+    // the only match is at the end, and every start position before it takes the full walk a long time to fail
+    let text = format!("a{};abcd", "bc".repeat(3000));
+    Input::init_string(&text, false);
+    let previous =
+        set_walk_options(WalkOptions::new().timeout(std::time::Duration::from_millis(200)));
+    assert_eq!(221, walk_tree(&tree, 0).unwrap_err().code);
+    let trees = deepening_trees(&tree, &DEEPENING_CAPS);
+    assert_eq!(4, trees.len());
+    let path = walk_tree_deepening(&trees, 0).unwrap().unwrap();
+    assert_eq!((6002, 6006), path.range());
+    // with no match anywhere the full tree is searched last, so the result is the same as without deepening
+    Input::init_string("abc;abd", false);
+    assert!(walk_tree_deepening(&trees, 0).unwrap().is_none());
+    set_walk_options(previous);
+
+    // only the unlimited repetitions are capped, and never below their minimum
+    let (tree, _) = parse_tree_full(r"a*b{2,5}c{100,}", false, true).unwrap();
+    let capped = tree.capped(64);
+    let Node::And(and_node) = &capped else {
+        panic!("root is not an AND");
+    };
+    let maxes: Vec<usize> = and_node
+        .nodes
+        .iter()
+        .map(|node| node.limits().max)
+        .collect();
+    assert_eq!(vec![64, 5, 100], maxes);
+    // a tree with nothing to cap is searched once
+    let (tree, _) = parse_tree_full("abc", false, true).unwrap();
+    assert_eq!(1, deepening_trees(&tree, &DEEPENING_CAPS).len());
+}
+
+#[test]
+fn report_positions_absolute() {
+    use crate::regexp::walk::{set_walk_options, WalkOptions, WindowEdge};
+    use crate::regexp::RelativePos;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let (tree, _) = parse_tree_full(r"a\(?<mid>b\)c", false, true).unwrap();
+    // "ééé abc " is 8 chars but 11 bytes, so the window starting after it starts at a different char and byte
+    let text = "ééé abc ééé abc tail";
+    Input::init_string(text, false);
+    let old = set_walk_options(WalkOptions::new().window(22, WindowEdge::Clip));
+    let report = Report::new(&walk_tree(&tree, 11).unwrap().unwrap());
+    set_walk_options(old);
+    // positions count from the start of the text, not from where the walk started
+    assert_eq!(((18, 21), (12, 15)), (report.byte_pos(), report.char_pos()));
+    assert_eq!("abc", report.text());
+    let mid = report.get_by_name("mid")[0];
+    assert_eq!(((19, 20), (13, 14)), (mid.byte_pos(), mid.char_pos()));
+    // relative to the window they count from its start
+    assert_eq!(
+        Some(RelativePos {
+            bytes: (7, 10),
+            chars: (4, 7)
+        }),
+        report.relative_to(11)
+    );
+    assert_eq!(
+        Some(RelativePos {
+            bytes: (8, 9),
+            chars: (5, 6)
+        }),
+        mid.relative_to(11)
+    );
+    assert_eq!((0, 3), report.relative_to(18).unwrap().chars);
+    // a window that starts after the match, or inside a char, has no position for it
+    assert_eq!(None, report.relative_to(19));
+    assert_eq!(None, report.relative_to(1));
+    // the same match found by searching the whole text has the same positions
+    let all: Vec<Report> = crate::regexp::find_iter(&tree)
+        .map(|report| report.unwrap())
+        .collect();
+    assert_eq!(report.byte_pos(), all[1].byte_pos());
+    assert_eq!(report.char_pos(), all[1].char_pos());
+}
+
+#[test]
+fn get_overrides() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // each get() of the same definition takes its own name, laziness and case, and a plain one takes the definition's
+    report_test(
+        r#"def(gow: "abc"+<x>) get(gow)<lower> " " get(gow)\c<any> " " get(gow)\c+?<few> get(gow)"#,
+        "abc ABC abcabcabc",
+        true,
+        |report| {
+            let found: Vec<_> = report
+                .subreports
+                .iter()
+                .map(|sub| (sub.name.clone().unwrap(), sub.char_pos()))
+                .collect();
+            assert_eq!(
+                vec![
+                    ("lower".to_string(), (0, 3)),
+                    ("any".to_string(), (4, 7)),
+                    ("few".to_string(), (8, 11)),
+                    ("x".to_string(), (11, 17)),
+                ],
+                found
+            );
+        },
+    );
+    // "\C" makes a caseless definition match case, in that get() only
+    report_test(
+        r#"def(goc: "\cab") get(goc)\C<s> get(goc)<t>"#,
+        "ABabAB",
+        true,
+        |report| {
+            check_report(report, "abAB", (2, 6), (2, 6), 2);
+            check_report(&report.subreports[0], "ab", (2, 4), (2, 4), 0);
+            check_report(&report.subreports[1], "AB", (4, 6), (4, 6), 0);
+        },
+    );
+    delete_def("gow");
+    delete_def("goc");
+}
+
+#[test]
+fn tree_view() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let tree = parse_tree(
+        r#"def(tv_digits: and("\d"<first> "\d")) and(or("a" "b")<ab>+ get(tv_digits)<pair> and("x" and("y")<n>)<n>)"#,
+        true,
+    )
+    .unwrap();
+    let view = |view: TreeView| {
+        let mut out = Vec::new();
+        view.write_to(&tree, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    // below the depth each node's children are counted rather than printed
+    assert_eq!(
+        concat!("AndNode(3)<>{1,1}\n", "    \u{2026} 11 more nodes\n"),
+        view(TreeView::new().depth(1))
+    );
+    assert_eq!(
+        concat!(
+            "AndNode(3)<>{1,1}\n",
+            "    OrNode(2) <ab>{1,99999999} \n",
+            "        \u{2026} 2 more nodes\n",
+            "    DefNode 'tv_digits'<pair>{1,1} \n",
+            "        \u{2026} 3 more nodes\n",
+            "    AndNode(2)<n>{1,1}\n",
+            "        \u{2026} 3 more nodes\n",
+        ),
+        view(TreeView::new().depth(2))
+    );
+    assert_eq!(tree.to_string(), view(TreeView::new().depth(5)));
+    // a filter prints the subtree under the name, from the left margin, and a name inside it only once
+    assert_eq!(
+        concat!(
+            "DefNode 'tv_digits'<pair>{1,1} \n",
+            "    AndNode(2)<pair>{1,1}\n",
+            "        \u{2026} 2 more nodes\n",
+        ),
+        view(TreeView::new().filter("pair").depth(2))
+    );
+    assert_eq!(
+        concat!(
+            "AndNode(2)<n>{1,1}\n",
+            "    CharsNode: \"x\"{1,1}\n",
+            "    AndNode(1)<n>{1,1}\n",
+            "        CharsNode: \"y\"{1,1}\n",
+        ),
+        view(TreeView::new().filter("n"))
+    );
+    assert_eq!("(no unit named zz)\n", view(TreeView::new().filter("zz")));
+    delete_def("tv_digits");
+}
+
+#[test]
+fn skipped_files() {
+    use crate::regexp::{MatchEvent, SearchDriver};
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let dir = std::env::temp_dir();
+    let good = [
+        dir.join("regexp_skipped_1.txt"),
+        dir.join("regexp_skipped_2.txt"),
+    ];
+    std::fs::write(&good[0], "one cat\n").unwrap();
+    std::fs::write(&good[1], "two cats\nand a cat\n").unwrap();
+    let missing = [
+        dir.join("regexp_skipped_none_1.txt"),
+        dir.join("regexp_skipped_none_2.txt"),
+        dir.join("regexp_skipped_none_3.txt"),
+    ];
+    let names: Vec<String> = [&missing[0], &good[0], &missing[1], &missing[2], &good[1]]
+        .iter()
+        .map(|file| file.to_string_lossy().to_string())
+        .collect();
+    // the files that open are searched, and the others listed in order
+    let (tree, _) = parse_tree_full("cat", false, true).unwrap();
+    Input::init_files(&names, false).unwrap();
+    assert_eq!(vec![names[0].clone()], skipped_names());
+    let stats = SearchDriver::new(&tree).all().run(&mut |_: &MatchEvent| ());
+    assert_eq!(3, stats.matches);
+    let searched: Vec<&str> = stats.files.iter().map(|file| file.name.as_str()).collect();
+    assert_eq!(vec![&names[1], &names[4]], searched);
+    assert_eq!(
+        vec![names[0].clone(), names[2].clone(), names[3].clone()],
+        skipped_names()
+    );
+    assert!(Input::skipped().iter().all(|(_, error)| error.code == 201));
+    assert_eq!(
+        vec![names[0].clone(), names[2].clone(), names[3].clone()],
+        stats
+            .files_skipped
+            .iter()
+            .map(|file| file.name.clone())
+            .collect::<Vec<_>>()
+    );
+    assert!(stats.files_skipped[1].reason.contains(&names[2]));
+    // the same from the command line, and in the JSON summary
+    let mut args = vec!["regexp", "-a", "-q", "cat"];
+    args.extend(names.iter().map(String::as_str));
+    let stats = crate::regexp::regexp(&crate::Config::parse_from(args)).unwrap();
+    assert_eq!((3, 3), (stats.matches, stats.files_skipped.len()));
+    let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+    assert_eq!(names[3], json["files_skipped"][2]["name"]);
+    // it is only an error if none of the files can be opened
+    let missing: Vec<String> = [&names[0], &names[2]]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    assert_eq!(211, Input::init_files(&missing, false).unwrap_err().code);
+    assert_eq!(missing, skipped_names());
+    let mut args = vec!["regexp", "cat"];
+    args.extend(missing.iter().map(String::as_str));
+    assert_eq!(
+        211,
+        crate::regexp::regexp(&crate::Config::parse_from(args))
+            .unwrap_err()
+            .code
+    );
+    // and starting again clears the list
+    Input::init_files(&names[1..2], false).unwrap();
+    assert!(Input::skipped().is_empty());
+}
+
+/// the names of the files in **Input::skipped()**
+fn skipped_names() -> Vec<String> {
+    Input::skipped().into_iter().map(|(name, _)| name).collect()
+}
+
+#[test]
+fn source_spans() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // the text each node was parsed from
+    let spans = |re: &str, alt: bool| -> Vec<String> {
+        let (tree, _) = parse_tree_full(re, alt, false).unwrap();
+        let mut found = Vec::new();
+        collect_spans(&tree, re, &mut found);
+        found
+    };
+    let re = r"^x\(?<n>[0-9]+\)\.\|ab*c$";
+    assert_eq!(
+        vec![
+            re,
+            "x",
+            r"\(?<n>[0-9]+\)",
+            "[0-9]+",
+            r"\.\|a",
+            r"\.",
+            "a",
+            "b*",
+            "c",
+            "$",
+        ],
+        spans(re, false)
+    );
+    let re = "'ab'+ or(any('xy') upto(';'))<o> 'q\\dr' \"z\"";
+    assert_eq!(
+        vec![
+            re,
+            "'ab'+",
+            "or(any('xy') upto(';'))<o>",
+            "any('xy')",
+            "upto(';')",
+            "'q\\dr'",
+            "'q\\dr'",
+            "'q\\dr'",
+            "'q\\dr'",
+            "\"z\"",
+        ],
+        spans(re, true)
+    );
+    // a definition is shown as the get() that brought it in
+    let re = "def(spans_word: 'w' or('a' 'b')) 'x' get(spans_word)<w>+";
+    assert_eq!(
+        vec![
+            re,
+            "'x'",
+            "get(spans_word)<w>+",
+            "get(spans_word)<w>+",
+            "get(spans_word)<w>+",
+            "get(spans_word)<w>+",
+            "get(spans_word)<w>+",
+            "get(spans_word)<w>+",
+        ],
+        spans(re, true)
+    );
+    // joined strings cover both, and nodes made by the optimizer have the span of what they replace
+    let (tree, _) = parse_tree_full(r"\(?ab\)c", false, true).unwrap();
+    assert_eq!(Some((0, 8)), tree.source_span());
+    if let Node::And(a) = &tree {
+        assert_eq!(Some((3, 8)), a.nodes[0].source_span());
+    }
+    // spans are not part of equality
+    assert_eq!(
+        parse_tree("ab", false).unwrap(),
+        parse_tree(" 'ab' ", true).unwrap()
+    );
+    assert_eq!(None, Node::None.source_span());
+    // warnings and errors about a unit quote it, with its span
+    let (_, warnings) = parse_tree_with_warnings(r"x\(a+\)*", false).unwrap();
+    let warning = warnings.iter().find(|w| w.code == 1).unwrap();
+    assert_eq!(Some((3, 5)), warning.span);
+    assert!(
+        warning.msg.contains(r#""a+" inside "\(a+\)*""#),
+        "{}",
+        warning.msg
+    );
+    let error = parse_tree(r"ab\(x*\)*", false).unwrap_err();
+    assert_eq!((10, Some((2, 9))), (error.code, error.span));
+    assert!(error.msg.contains(r#""\(x*\)*""#), "{}", error.msg);
+}
+
+/// the text of the spans in TREE, in RE, parents before children
+fn collect_spans(tree: &Node, re: &str, found: &mut Vec<String>) {
+    if let Some((start, end)) = tree.source_span() {
+        found.push(re[start..end].to_string());
+    }
+    match tree {
+        Node::And(a) => a.nodes.iter().for_each(|x| collect_spans(x, re, found)),
+        Node::Or(a) => a.nodes.iter().for_each(|x| collect_spans(x, re, found)),
+        Node::Def(a) => collect_spans(&a.node, re, found),
+        _ => (),
+    }
+}
+
+#[test]
+fn result_pages() {
+    use crate::interactive::{PagerKey, Pages};
+    use clap::Parser;
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // a screenful at a time, the last one short
+    let pages: Vec<_> = Pages::new(10, 4).collect();
+    assert_eq!(vec![0..4, 4..8, 8..10], pages);
+    assert_eq!(vec![0..3], Pages::new(3, 24).collect::<Vec<_>>());
+    assert_eq!(0, Pages::new(0, 24).count());
+    // a height of 0 still makes progress
+    assert_eq!(5, Pages::new(5, 0).count());
+    // jumping goes to the match with that number, forward or back
+    let mut pages = Pages::new(100, 10);
+    assert_eq!(Some(0..10), pages.next());
+    pages.jump(55);
+    assert_eq!(Some(54..64), pages.next());
+    pages.jump(3);
+    assert_eq!(Some(2..12), pages.next());
+    pages.jump(1000);
+    assert_eq!(Some(99..100), pages.next());
+    assert!(pages.done());
+    assert_eq!(None, pages.next());
+    // what can be typed at the prompt
+    assert_eq!(Some(PagerKey::Next), PagerKey::parse(""));
+    assert_eq!(Some(PagerKey::Quit), PagerKey::parse("q"));
+    assert_eq!(Some(PagerKey::Jump(12)), PagerKey::parse(" 12 "));
+    assert_eq!(None, PagerKey::parse("0"));
+    assert_eq!(None, PagerKey::parse("next"));
+    // the results of a file search are kept for the results command
+    let file = std::env::temp_dir()
+        .join("regexp_result_pages.txt")
+        .to_string_lossy()
+        .to_string();
+    std::fs::write(&file, "x1\nx22\nnone\nx333\n").unwrap();
+    let mut session = crate::interactive::Interactive::new(crate::Config::parse_from([
+        "regexp",
+        "-i",
+        r"x\(?<n>\d+\)",
+    ]));
+    assert!(session.do_command("results"));
+    assert!(session.command_failed());
+    assert!(session.do_command(&format!("search file {}", file)));
+    assert!(!session.command_failed());
+    for command in ["results", "results 3"] {
+        assert!(session.do_command(command));
+        assert!(!session.command_failed());
+    }
+    for command in ["results 4", "results 0", "results x", "results 1 2"] {
+        assert!(session.do_command(command));
+        assert!(session.command_failed(), "{}", command);
+    }
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn lazy_block_boundary() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    let file = std::env::temp_dir()
+        .join("regexp_lazy_block.txt")
+        .to_string_lossy()
+        .to_string();
+    // each line is a block, so the lazy matches below all cross one
+    std::fs::write(&file, "xxxxa\nbbbbb\nbbbbc\nd12\n345;\nzzz\n").unwrap();
+    let files = [file];
+    let block = Input::set_block_size(1);
+    let read_ahead = Input::set_read_ahead(false);
+    for (alt, re, expected) in [
+        (false, "a.+?$", (4, 31)),
+        (false, r"a\(b\|\n\)+?c", (4, 17)),
+        (false, "ab*?c", (0, 0)),
+        (false, "a[b\n]+?c", (4, 17)),
+        (false, r"d[\d\n]+?;", (18, 26)),
+        (false, r"4\n*?5;", (23, 26)),
+        (true, "'a' '.'+? 'c'", (4, 17)),
+        (false, r"\(bb\)+?\n", (7, 12)),
+        (false, r"\(b\n?\)+?c", (6, 17)),
+        (true, "and('b' '\\n'?)+? 'c'", (6, 17)),
+        (true, "'d' '\\d'+? '\\n' '3'", (18, 23)),
+    ] {
+        let (tree, _) = parse_tree_full(re, alt, true).unwrap();
+        Input::init_files(&files, false).unwrap();
+        let found = walk_tree(&tree, 0).unwrap().map(|path| path.range());
+        if expected == (0, 0) {
+            assert!(found.is_none(), "{}: {:?}", re, found);
+        } else {
+            assert_eq!(Some(expected), found, "{}", re);
+        }
+    }
+    // and all the matches are the same as with the text read in at once
+    let text = std::fs::read_to_string(&files[0]).unwrap();
+    for re in [
+        r"b+?\nb",
+        r"[bc\n]+?c",
+        r"\d+?\n3",
+        r".+?\n",
+        r"\(b\nb\)+?",
+        r"\(b{1,2}\)+?c",
+    ] {
+        let (tree, _) = parse_tree_full(re, false, true).unwrap();
+        Input::init_text(&text, false).unwrap();
+        let expected = all_ranges(&tree);
+        Input::init_files(&files, false).unwrap();
+        assert_eq!(expected, all_ranges(&tree), "{}", re);
+    }
+    Input::set_block_size(block);
+    Input::set_read_ahead(read_ahead);
+    // lazy groups back off like greedy ones, adding repetitions instead of dropping them
+    for (re, text, expected) in [
+        (r"\(ab\)+?c", "xababc abc", vec![(1, 6), (7, 10)]),
+        (r"x\(ab\)+?", "xababx", vec![(0, 3)]),
+        (r"\([ab]b?\)+?c", "abbac", vec![(0, 5)]),
+        (r"\(a.\)*?b", "aaab", vec![(1, 4)]),
+    ] {
+        let (tree, _) = parse_tree_full(re, false, true).unwrap();
+        Input::init_text(text, false).unwrap();
+        assert_eq!(expected, all_ranges(&tree), "{}", re);
+    }
+}
+
+/// a logger keeping the level, target and message of each record, for **log_trace**
+#[cfg(feature = "log")]
+struct TraceLogger(Mutex<Vec<(log::Level, String, String)>>);
+
+#[cfg(feature = "log")]
+impl log::Log for TraceLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push((
+            record.level(),
+            record.target().to_string(),
+            record.args().to_string(),
+        ));
+    }
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "log")]
+#[test]
+fn log_trace() {
+    use crate::regexp::{set_trace, set_trace_backend, set_trace_writer, TraceBackend};
+    static LOGGER: TraceLogger = TraceLogger(Mutex::new(Vec::new()));
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+    let tree = parse_tree("ab+c", false).unwrap();
+    Input::init_text("xabbc", false).unwrap();
+    let sink = TraceSink::default();
+    let previous = set_trace_writer(Some(Box::new(sink.clone())));
+    assert_eq!(TraceBackend::Writer, set_trace_backend(TraceBackend::Log));
+    set_trace(4);
+    assert_eq!((1, 5), walk_tree(&tree, 0).unwrap().unwrap().range());
+    set_trace(0);
+    assert_eq!(TraceBackend::Log, set_trace_backend(TraceBackend::Writer));
+    // nothing went to the writer
+    assert!(sink.0.lock().unwrap().is_empty());
+    let records = std::mem::take(&mut *LOGGER.0.lock().unwrap());
+    let find = |text: &str| {
+        records
+            .iter()
+            .find(|(_, _, msg)| msg.contains(text))
+            .map(|(level, target, _)| (*level, target.as_str()))
+    };
+    // level 1 and 2 lines are debug, the rest trace
+    assert_eq!(Some((log::Level::Debug, "regexp::walk")), find("==== WALK"));
+    assert_eq!(
+        Some((log::Level::Debug, "regexp::walk")),
+        find("Start walk for")
+    );
+    assert_eq!(
+        Some((log::Level::Trace, "regexp::walk")),
+        find("End walk for")
+    );
+    assert_eq!(Some((log::Level::Trace, "regexp::walk")), find("Pushing"));
+    // and with the writer back nothing more is logged
+    set_trace(4);
+    walk_tree(&tree, 0).unwrap();
+    set_trace(0);
+    set_trace_writer(previous);
+    assert!(LOGGER.0.lock().unwrap().is_empty());
+    assert!(!sink.0.lock().unwrap().is_empty());
+}
+
+#[test]
+fn char_boundaries() {
+    use crate::regexp::{set_walk_options, WalkOptions};
+    use crate::walk::{boundary_check, boundary_ok, set_check_boundaries, Matched, Walker};
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    Input::init_text("a\u{e9}b", false).unwrap();
+    let matched = |start, end| Matched {
+        start,
+        end,
+        char_start: 0,
+    };
+    assert!(Input::apply(|input| matched(0, 1).validate(input)));
+    assert!(Input::apply(|input| matched(1, 4).validate(input)));
+    assert!(Input::apply(|input| matched(4, 4).validate(input)));
+    assert!(!Input::apply(|input| matched(1, 2).validate(input)));
+    assert!(!Input::apply(|input| matched(2, 3).validate(input)));
+    assert!(!Input::apply(|input| matched(3, 1).validate(input)));
+    assert!(!Input::apply(|input| matched(0, 5).validate(input)));
+
+    // a mock step standing in for a node that gets its length wrong, matching 1 byte of the 2 in "\u{e9}"
+    struct MockStep(Matched);
+    impl<'a> Walker<'a> for MockStep {
+        fn make_report(&'a self) -> Report {
+            Report {
+                matched: self.0,
+                name: None,
+                subreports: Vec::new(),
+                branch: None,
+            }
+        }
+        fn name_details(&self) -> (&Option<String>, bool) {
+            (&None, false)
+        }
+        fn get_matched(&self) -> Matched {
+            self.0
+        }
+        fn node_desc(&self) -> String {
+            "MockNode".to_string()
+        }
+    }
+    // without the option the match is trusted
+    set_check_boundaries(false);
+    assert!(boundary_ok(&MockStep(matched(1, 2))));
+    assert!(boundary_check().is_ok());
+    set_check_boundaries(true);
+    assert!(boundary_ok(&MockStep(matched(1, 4))));
+    assert!(!boundary_ok(&MockStep(matched(1, 2))));
+    // only the first bad step is reported
+    assert!(!boundary_ok(&MockStep(matched(0, 2))));
+    let error = boundary_check().unwrap_err();
+    assert_eq!(231, error.code);
+    assert!(error.msg.contains("MockNode ends at byte 2"), "{}", error);
+    assert!(boundary_check().is_ok());
+    set_check_boundaries(false);
+
+    // a caseless "k" matches the 3 bytes of the Kelvin sign, so a walk with the check on ends on char boundaries
+    let tree = parse_tree(r"\cky", false).unwrap();
+    Input::init_text("x\u{212a}y", false).unwrap();
+    let previous = set_walk_options(WalkOptions::new().check_boundaries());
+    assert_eq!((1, 5), walk_tree(&tree, 0).unwrap().unwrap().range());
+    set_walk_options(previous);
+}
+
+#[test]
+fn repeated_reports() {
+    let mut x = LOCK.lock().unwrap();
+    *x += 1;
+    // named before the repetition an OR reports each repetition with its branch
+    report_test(r#"or("a"<x> "b"<y>)<v>+"#, "abb", true, |report| {
+        check_report(report, "abb", (0, 3), (0, 3), 3);
+        let branches: Vec<_> = report.subreports.iter().map(|v| v.branch).collect();
+        assert_eq!(vec![Some((0, 2)), Some((1, 2)), Some((1, 2))], branches);
+        check_report(&report.subreports[0].subreports[0], "a", (0, 1), (0, 1), 0);
+        check_report(&report.subreports[2].subreports[0], "b", (2, 3), (2, 3), 0);
+    });
+    // named after it there is one report holding the repetitions in the order they matched, for an OR and an AND alike
+    report_test(r#"or("a"<x> "b"<y>)+<v>"#, "abb", true, |report| {
+        let v = &report.subreports[0];
+        check_report(v, "abb", (0, 3), (0, 3), 3);
+        assert_eq!(None, v.branch);
+        let starts: Vec<_> = v
+            .subreports
+            .iter()
+            .map(|sub| (sub.name.clone().unwrap(), sub.char_pos().0))
+            .collect();
+        assert_eq!(
+            vec![
+                ("x".to_string(), 0),
+                ("y".to_string(), 1),
+                ("y".to_string(), 2)
+            ],
+            starts
+        );
+    });
+    report_test(r#"and("a"<x> "b"<y>)+<v>"#, "abab", true, |report| {
+        let v = &report.subreports[0];
+        check_report(v, "abab", (0, 4), (0, 4), 4);
+        let starts: Vec<_> = v.subreports.iter().map(|sub| sub.char_pos().0).collect();
+        assert_eq!(vec![0, 1, 2, 3], starts);
+    });
+    // last_only() keeps the last repetition, as other engines do
+    report_test(r"\(?<g>x\(?<v>a\|b\)\)+", "xaxb", false, |report| {
+        check_report(report, "xaxb", (0, 4), (0, 4), 2);
+        let last = report.last_only(&["g"]);
+        check_report(&last, "xaxb", (0, 4), (0, 4), 1);
+        check_report(&last.subreports[0], "xb", (2, 4), (2, 4), 1);
+        check_report(&last.subreports[0].subreports[0], "b", (3, 4), (3, 4), 0);
+        // a name inside another keeps only its last match in the whole report
+        let last = report.last_only(&["v"]);
+        check_report(&last.subreports[0], "xa", (0, 2), (0, 2), 0);
+        check_report(&last.subreports[1], "xb", (2, 4), (2, 4), 1);
+        assert_eq!(
+            report.iter().count(),
+            report.last_only(&["fake"]).iter().count()
+        );
+    });
 }
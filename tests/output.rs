@@ -0,0 +1,168 @@
+//! Runs searches through the program with a range of output flags and compares what it prints, and its exit status,
+//! with the saved output in tests/output/NAME.out. The searches are run from that directory, so the file names in
+//! the output are the plain names of the files there. Running the tests with REGEXP_BLESS set saves the output
+//! instead, for when it is meant to change.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// the searches: the name of the saved output, the arguments, and the text given on stdin
+const CASES: &[(&str, &[&str], &str)] = &[
+    ("text", &["at", "-t", "the cat sat"], ""),
+    ("text_all", &["-a", "[cs]at", "-t", "the cat sat"], ""),
+    ("files", &["-a", "[a-z]at", "a.txt", "b.txt"], ""),
+    ("count", &["-c", "3", "[a-z]at", "a.txt", "b.txt"], ""),
+    (
+        "named",
+        &["-a", "-n", r"\(?<first>[a-z]\)\(?<rest>at\)", "a.txt"],
+        "",
+    ),
+    (
+        "only_named",
+        &[
+            "--only-named",
+            "rest",
+            r"\(?<first>[a-z]\)\(?<rest>at\)",
+            "a.txt",
+        ],
+        "",
+    ),
+    (
+        "last_only",
+        &["--last-only", "g", r"\(?<g>x\(?<v>a\|b\)\)+", "-t", "xaxb"],
+        "",
+    ),
+    (
+        "tree_depth",
+        &[
+            "-p",
+            "alt",
+            "--tree-depth",
+            "2",
+            r#"and(or("a" "b")<ab>+ and("1" and("2")<two>)<nums>)"#,
+            "-t",
+            "ab12",
+        ],
+        "",
+    ),
+    ("quiet", &["-q", "-a", "[a-z]at", "a.txt", "b.txt"], ""),
+    ("lines", &["-l", "-a", "[a-z]at", "a.txt", "b.txt"], ""),
+    (
+        "context",
+        &["--context-chars", "4", "-a", "cat", "a.txt"],
+        "",
+    ),
+    ("walk", &["-w", "ca\\(t\\)", "-t", "a cat"], ""),
+    (
+        "count_only",
+        &["--count-only", "[a-z]at", "a.txt", "b.txt"],
+        "",
+    ),
+    (
+        "replace",
+        &["--replace", "<$0>", "[a-z]at", "a.txt", "b.txt"],
+        "",
+    ),
+    (
+        "replace_case",
+        &[
+            "--replace",
+            "dog",
+            "--replace-preserve-case",
+            r"\c cat",
+            "a.txt",
+        ],
+        "",
+    ),
+    ("missing", &["-a", "cat", "missing.txt", "a.txt"], ""),
+    ("all_missing", &["-a", "cat", "missing.txt"], ""),
+    ("no_match", &["dog", "a.txt", "b.txt"], ""),
+    ("stdin", &["-a", "cat"], "piped cat\nand another cat\n"),
+    (
+        "stdin_dash",
+        &["-q", "-a", "cat", "-", "a.txt"],
+        "piped cat\n",
+    ),
+    (
+        "set_count",
+        &[
+            "--set-count",
+            "patterns.txt",
+            "a.txt",
+            "b.txt",
+            "missing.txt",
+        ],
+        "",
+    ),
+    (
+        "set_count_stdin",
+        &["--set-count", "patterns.txt"],
+        "a cat\n\n",
+    ),
+    (
+        "alt",
+        &["-p", "alt", "-a", "-n", r#"any("bh")<first> "at""#, "b.txt"],
+        "",
+    ),
+    (
+        "explain",
+        &["--explain", r"^\(?<id>\d+\)-\(\(cat\)\|\(dog\)\)?[a-z]*? $"],
+        "",
+    ),
+    (
+        "explain_alt",
+        &[
+            "-p",
+            "alt",
+            "--explain",
+            r#"def(num: "\d"+) get(num)<whole> "." get(num)? and(" " until(";"))<rest>{0,3} "\cok""#,
+        ],
+        "",
+    ),
+    ("explain_notes", &["--explain", r"\(a+\)*[aab]\q"], ""),
+];
+
+/// the directory the searches are run in
+fn dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/output")
+}
+
+/// runs the program with ARGS and STDIN, returning its output in the form it is saved in
+fn run(args: &[&str], stdin: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_regexp"))
+        .args(args)
+        .current_dir(dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not run regexp");
+    std::io::Write::write_all(child.stdin.as_mut().unwrap(), stdin.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    format!(
+        "--- stdout\n{}--- stderr\n{}--- status {}\n",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+        output.status.code().unwrap_or(-1)
+    )
+}
+
+#[test]
+fn output_unchanged() {
+    for (name, args, stdin) in CASES {
+        let found = run(args, stdin);
+        let path = dir().join(format!("{}.out", name));
+        if std::env::var_os("REGEXP_BLESS").is_some() {
+            std::fs::write(&path, &found).unwrap();
+            continue;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            found,
+            expected,
+            "output of {} differs from {}.out",
+            args.join(" "),
+            name
+        );
+    }
+}
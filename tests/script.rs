@@ -0,0 +1,77 @@
+//! Runs the scripts in tests/scripts through the program with --script and compares what it prints with the saved
+//! transcripts. Each script NAME.txt has its transcript in NAME.transcript. The scripts are run from that directory,
+//! so a script can source another one by its plain name.
+
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// runs the program in the scripts directory with ARGS, giving it STDIN as its input
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_regexp"))
+        .args(args)
+        .current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not run regexp");
+    std::io::Write::write_all(child.stdin.as_mut().unwrap(), stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// runs SCRIPT with the extra ARGS and checks the output against TRANSCRIPT and the exit status against STATUS
+fn check(script: &str, args: &[&str], transcript: &str, status: i32) {
+    let mut all_args = vec!["--script", script];
+    all_args.extend_from_slice(args);
+    let output = run(&all_args, "");
+    let expected = std::fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/scripts")
+            .join(transcript),
+    )
+    .unwrap();
+    let found = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        found, expected,
+        "output of {} differs from {}",
+        script, transcript
+    );
+    assert_eq!(
+        output.status.code(),
+        Some(status),
+        "exit status of {}",
+        script
+    );
+}
+
+#[test]
+fn script_transcripts() {
+    check("basic.txt", &[], "basic.transcript", 0);
+    check("quit.txt", &[], "quit.transcript", 0);
+}
+
+#[test]
+fn script_stops_on_error() {
+    check("stop.txt", &[], "stop.transcript", 1);
+    check("stop.txt", &["-k"], "stop_keep_going.transcript", 0);
+    // a failed script stops the one that sourced it too
+    check("nested.txt", &[], "nested.transcript", 1);
+    let output = run(&["--script", "no_such_script.txt"], "");
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Error reading no_such_script.txt"));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn script_interactive() {
+    // the prompt reads from stdin once the script is done
+    let output = run(
+        &["--script", "stop.txt", "-k", "--script-interactive"],
+        "regexp\nquit\n",
+    );
+    let found = String::from_utf8_lossy(&output.stdout);
+    assert!(found.contains("> current RE: traditional"), "{}", found);
+    assert!(found.ends_with("exit\n"), "{}", found);
+    // the flags only go with --script
+    let output = run(&["-i", "--script-interactive"], "");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("can only be given with --script"));
+}
@@ -0,0 +1,125 @@
+//! Benchmark for --set-count: the 80 patterns in tests/bench/patterns.txt counted in a generated service log, once
+//! with --set-count and once with a separate --count-only run for each pattern. It is ignored by default since it
+//! takes a while; run it with
+//!
+//!     cargo test --release --test set_count -- --ignored --nocapture
+//!
+//! REGEXP_BENCH_LINES sets the size of the log (default 200000 lines). Along with the timings it checks that the two
+//! agree on which patterns occur: --count-only counts matches rather than lines, so it can only be higher. The
+//! patterns anchored to the start or end of a line, or with ".*" in them, are not checked, since the separate runs
+//! search the log as one text.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+
+/// writes LINES lines of a made up service log to PATH. The lines are built from a simple generator so every run
+/// gets the same log.
+fn write_log(path: &Path, lines: usize) {
+    let services = ["auth", "billing", "search", "cart", "gateway", "mailer"];
+    let levels = ["INFO", "INFO", "INFO", "DEBUG", "WARN", "ERROR"];
+    let messages = [
+        "request done",
+        "cache miss",
+        "cache hit",
+        "retry 2 of 5",
+        "timeout after 3000ms",
+        "connection reset by peer",
+        "slow query",
+        "upstream failed",
+        "token expired",
+        "disk full",
+    ];
+    let mut seed: u64 = 12345;
+    let mut next = |limit: usize| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % limit
+    };
+    let mut log = std::io::BufWriter::new(std::fs::File::create(path).unwrap());
+    for line in 0..lines {
+        writeln!(
+            log,
+            "2026-10-{:02}T{:02}:{:02}:{:02}.{:03}Z host-{:02} service={} level={} status={} latency={}ms user=u{:05} \
+             path=/api/v{}/orders ip=10.0.{}.{} msg=\"{}\"",
+            1 + line * 30 / lines,
+            next(24),
+            next(60),
+            next(60),
+            next(1000),
+            next(20),
+            services[next(services.len())],
+            levels[next(levels.len())],
+            [200, 200, 200, 404, 500][next(5)],
+            next(5000),
+            next(100000),
+            1 + next(2),
+            next(256),
+            next(256),
+            messages[next(messages.len())],
+        )
+        .unwrap();
+    }
+}
+
+/// runs the program with ARGS, returning its stdout and how long it took
+fn run(args: &[&str]) -> (String, f64) {
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_regexp"))
+        .args(args)
+        .output()
+        .expect("could not run regexp");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        start.elapsed().as_secs_f64(),
+    )
+}
+
+#[test]
+#[ignore]
+fn set_count_benchmark() {
+    let lines = std::env::var("REGEXP_BENCH_LINES")
+        .ok()
+        .and_then(|lines| lines.parse().ok())
+        .unwrap_or(200000);
+    let log = std::env::temp_dir().join("regexp_set_count_bench.log");
+    write_log(&log, lines);
+    let log = log.to_str().unwrap();
+    let patterns = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/bench/patterns.txt");
+    let (output, set_secs) = run(&["--set-count", patterns.to_str().unwrap(), log]);
+    let mut separate_secs = 0.0;
+    for line in output.lines() {
+        let (count, re) = line.split_once('\t').unwrap();
+        let (matches, secs) = run(&["--count-only", re, log]);
+        separate_secs += secs;
+        let matches: usize = matches.trim().parse().unwrap();
+        let count: usize = count.parse().unwrap();
+        // outside of a line by line search '^' and '$' are only at the ends of the whole log, and ".*" runs on
+        // past the end of a line
+        if re.starts_with('^') || re.ends_with('$') || re.contains(".*") {
+            continue;
+        }
+        assert!(
+            matches >= count && (matches == 0) == (count == 0),
+            "{}: {} lines, {} matches",
+            re,
+            count,
+            matches
+        );
+    }
+    println!(
+        "{} lines, {} patterns: --set-count {:.2}s, separate runs {:.2}s",
+        lines,
+        output.lines().count(),
+        set_secs,
+        separate_secs
+    );
+    std::fs::remove_file(log).unwrap();
+}
@@ -0,0 +1,104 @@
+//! Benchmark for the walk phase: REs that take many steps for each match, counted in text given on the command
+//! line, so the time goes into matching rather than reading input. It is ignored by default since it takes a while;
+//! run it with
+//!
+//!     cargo test --release --test walk_speed -- --ignored --nocapture
+//!
+//! REGEXP_BENCH_ROUNDS sets how many times each RE is counted (default 5), and the best time is reported.
+//! REGEXP_BENCH_BASELINE can name another build of regexp, for example one built from an earlier commit, to time
+//! alongside this one. The two must agree on the counts.
+
+use std::process::Command;
+use std::time::Instant;
+
+/// REs that back off a lot on the text from **make_text()**
+const PATTERNS: [&str; 6] = [
+    r"\([a-z]+ \){3}[0-9]+x",
+    r"\([a-z]+ \)+?[0-9]+;",
+    r"\([ab]+ \)+b2b",
+    r"\(ab\|b\)+c",
+    r"[a-z]*?[0-9]{2,}[a-z]*x",
+    r"\(\w\|[a-z]\)*z",
+];
+
+/// makes about SIZE bytes of words, numbers and punctuation. The text is built from a simple generator so every run
+/// gets the same text. It is passed as an argument, which limits its size.
+fn make_text(size: usize) -> String {
+    let words = [
+        "ab", "abb", "bab", "word", "abc", "x", "12", "345x", "9;", "zz", "az", "b2b",
+    ];
+    let mut seed: u64 = 54321;
+    let mut text = String::new();
+    while text.len() < size {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        text.push_str(words[(seed >> 33) as usize % words.len()]);
+        text.push(if (seed >> 20).is_multiple_of(7) {
+            '\n'
+        } else {
+            ' '
+        });
+    }
+    text
+}
+
+/// counts the matches of RE in TEXT with the program at PROGRAM, returning the count and how long it took
+fn count(program: &str, re: &str, text: &str) -> (usize, f64) {
+    let start = Instant::now();
+    let output = Command::new(program)
+        .args(["--all", re, "-t", text])
+        .output()
+        .expect("could not run regexp");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // --all rather than --count-only, which older builds do not have. The count goes to stderr
+    let found = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix("Found ")?
+                .strip_suffix(" instances")?
+                .parse()
+                .ok()
+        })
+        .unwrap();
+    (found, start.elapsed().as_secs_f64())
+}
+
+/// counts RE ROUNDS times, returning the count and the best time
+fn best(program: &str, re: &str, text: &str, rounds: usize) -> (usize, f64) {
+    (0..rounds)
+        .map(|_| count(program, re, text))
+        .fold((0, f64::MAX), |(_, best), (matches, secs)| {
+            (matches, best.min(secs))
+        })
+}
+
+#[test]
+#[ignore]
+fn walk_speed_benchmark() {
+    let rounds = std::env::var("REGEXP_BENCH_ROUNDS")
+        .ok()
+        .and_then(|rounds| rounds.parse().ok())
+        .unwrap_or(5);
+    let baseline = std::env::var("REGEXP_BENCH_BASELINE").ok();
+    let text = make_text(100000);
+    for re in PATTERNS {
+        let (matches, secs) = best(env!("CARGO_BIN_EXE_regexp"), re, &text, rounds);
+        assert!(matches > 0, "{}: no matches", re);
+        match &baseline {
+            Some(program) => {
+                let (base_matches, base_secs) = best(program, re, &text, rounds);
+                assert_eq!(base_matches, matches, "{}", re);
+                println!(
+                    "{}: {} matches in {:.3}s, baseline {:.3}s",
+                    re, matches, secs, base_secs
+                );
+            }
+            None => println!("{}: {} matches in {:.3}s", re, matches, secs),
+        }
+    }
+}